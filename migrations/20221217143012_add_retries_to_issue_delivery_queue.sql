@@ -0,0 +1,2 @@
+ALTER TABLE issue_delivery_queue ADD COLUMN n_retries INT NOT NULL DEFAULT 0;
+ALTER TABLE issue_delivery_queue ADD COLUMN execute_after timestamptz NOT NULL DEFAULT now();
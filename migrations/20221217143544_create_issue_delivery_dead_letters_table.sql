@@ -0,0 +1,9 @@
+-- Delivery tasks which failed too many times end up here
+CREATE TABLE issue_delivery_dead_letters(
+  newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues(id),
+  subscriber_email TEXT NOT NULL,
+  n_retries INT NOT NULL,
+  last_error TEXT NOT NULL,
+  failed_at timestamptz NOT NULL,
+  PRIMARY KEY (newsletter_issue_id, subscriber_email)
+);
@@ -0,0 +1,3 @@
+ALTER TABLE newsletter_issues ADD COLUMN status TEXT NOT NULL DEFAULT 'published';
+ALTER TABLE newsletter_issues ADD COLUMN scheduled_for timestamptz NULL;
+ALTER TABLE newsletter_issues ALTER COLUMN published_at DROP NOT NULL;
@@ -0,0 +1,9 @@
+CREATE TABLE newsletter_deliveries(
+  newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues(id),
+  subscriber_email TEXT NOT NULL,
+  outcome TEXT NOT NULL,
+  n_attempts INT NOT NULL,
+  response_body TEXT NULL,
+  recorded_at timestamptz NOT NULL,
+  PRIMARY KEY(newsletter_issue_id, subscriber_email)
+);
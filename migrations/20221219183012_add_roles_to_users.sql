@@ -0,0 +1,14 @@
+-- Every admin has a role; the existing admins become owners
+BEGIN;
+  ALTER TABLE users ADD COLUMN role TEXT NULL;
+  UPDATE users SET role = 'owner';
+  ALTER TABLE users ALTER COLUMN role SET NOT NULL;
+  ALTER TABLE users ADD CONSTRAINT users_role_check CHECK (role IN ('owner', 'editor', 'viewer'));
+
+  ALTER TABLE users ADD COLUMN disabled_at timestamptz NULL;
+
+  -- Deleting an admin also deletes its saved idempotent responses
+  ALTER TABLE idempotency DROP CONSTRAINT idempotency_user_id_fkey;
+  ALTER TABLE idempotency ADD CONSTRAINT idempotency_user_id_fkey
+    FOREIGN KEY (user_id) REFERENCES users(user_id) ON DELETE CASCADE;
+COMMIT;
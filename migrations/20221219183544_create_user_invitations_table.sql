@@ -0,0 +1,11 @@
+-- Only the hash of an invitation token is stored, the token itself is only in the link emailed.
+CREATE TABLE user_invitations(
+  token_hash TEXT NOT NULL,
+  email TEXT NOT NULL,
+  role TEXT NOT NULL CHECK (role IN ('owner', 'editor', 'viewer')),
+  invited_by uuid NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+  created_at timestamptz NOT NULL,
+  expires_at timestamptz NOT NULL,
+  accepted_at timestamptz NULL,
+  PRIMARY KEY (token_hash)
+);
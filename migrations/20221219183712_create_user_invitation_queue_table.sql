@@ -0,0 +1,11 @@
+-- Invitation emails are sent by the worker, which generates the token, stores its hash in
+-- user_invitations and sends the link in one transaction.
+CREATE TABLE user_invitation_queue(
+  id uuid NOT NULL,
+  email TEXT NOT NULL,
+  role TEXT NOT NULL CHECK (role IN ('owner', 'editor', 'viewer')),
+  invited_by uuid NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+  n_retries INT NOT NULL,
+  execute_after timestamptz NOT NULL,
+  PRIMARY KEY (id)
+);
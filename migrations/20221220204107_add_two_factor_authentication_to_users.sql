@@ -0,0 +1,12 @@
+-- TOTP second factor, the secret is base32 encoded
+ALTER TABLE users ADD COLUMN totp_secret TEXT NULL;
+-- The time step of the last accepted code, to prevent replaying a code
+ALTER TABLE users ADD COLUMN totp_last_used_step BIGINT NULL;
+
+CREATE TABLE user_recovery_codes(
+  id uuid NOT NULL,
+  user_id uuid NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+  code_hash TEXT NOT NULL,
+  used_at timestamptz NULL,
+  PRIMARY KEY (id)
+);
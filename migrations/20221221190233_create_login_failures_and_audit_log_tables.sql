@@ -0,0 +1,20 @@
+-- Failed login attempts, counted per username and per client IP address
+CREATE TABLE login_failures(
+  counter TEXT NOT NULL CHECK (counter IN ('username', 'client_ip')),
+  key TEXT NOT NULL,
+  n_failures INT NOT NULL,
+  last_failure_at timestamptz NOT NULL,
+  locked_until timestamptz NULL,
+  PRIMARY KEY (counter, key)
+);
+
+CREATE TABLE audit_log(
+  id uuid NOT NULL,
+  occurred_at timestamptz NOT NULL,
+  event TEXT NOT NULL,
+  username TEXT NULL,
+  client_ip TEXT NULL,
+  details TEXT NOT NULL,
+  PRIMARY KEY (id)
+);
+CREATE INDEX audit_log_occurred_at_idx ON audit_log(occurred_at);
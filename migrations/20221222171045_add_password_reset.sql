@@ -0,0 +1,18 @@
+-- Password reset links are sent to the email address of the user
+ALTER TABLE users ADD COLUMN email TEXT NULL UNIQUE;
+
+CREATE TABLE password_reset_tokens(
+  token_hash TEXT NOT NULL,
+  user_id uuid NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+  created_at timestamptz NOT NULL,
+  expires_at timestamptz NOT NULL,
+  used_at timestamptz NULL,
+  PRIMARY KEY (token_hash)
+);
+
+-- Sessions are linked to their user so that they can all be invalidated at once
+ALTER TABLE sessions ADD COLUMN user_id uuid NULL;
+UPDATE sessions
+SET user_id = ((convert_from(state, 'UTF8')::jsonb ->> 'user_id')::jsonb #>> '{}')::uuid
+WHERE convert_from(state, 'UTF8')::jsonb ? 'user_id';
+CREATE INDEX sessions_user_id_idx ON sessions(user_id);
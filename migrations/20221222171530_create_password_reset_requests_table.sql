@@ -0,0 +1,12 @@
+-- Password reset links are emailed by the worker, so that responding to a request doesn't
+-- depend on whether an account exists. Recent requests are kept to throttle them.
+CREATE TABLE password_reset_requests(
+  id uuid NOT NULL,
+  email TEXT NOT NULL,
+  client_ip TEXT NOT NULL,
+  requested_at timestamptz NOT NULL,
+  processed_at timestamptz NULL,
+  PRIMARY KEY (id)
+);
+CREATE INDEX password_reset_requests_email_idx ON password_reset_requests(email);
+CREATE INDEX password_reset_requests_client_ip_idx ON password_reset_requests(client_ip);
@@ -0,0 +1,6 @@
+-- Describe sessions so that users can recognize and revoke them
+ALTER TABLE sessions ADD COLUMN user_agent TEXT NULL;
+ALTER TABLE sessions ADD COLUMN ip_address TEXT NULL;
+ALTER TABLE sessions ADD COLUMN last_seen_at timestamptz NULL;
+UPDATE sessions SET last_seen_at = created_at;
+ALTER TABLE sessions ALTER COLUMN last_seen_at SET NOT NULL;
@@ -0,0 +1,10 @@
+-- Bearer tokens of the JSON API; only a hash of each token is stored
+CREATE TABLE api_tokens(
+  id uuid NOT NULL,
+  user_id uuid NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+  name TEXT NOT NULL,
+  token_hash TEXT NOT NULL UNIQUE,
+  created_at timestamptz NOT NULL,
+  last_used_at timestamptz NULL,
+  PRIMARY KEY (id)
+);
@@ -0,0 +1,9 @@
+-- Confirmation emails are sent by the worker, so that signing up takes as long whether the
+-- address is new, pending or already confirmed.
+CREATE TABLE confirmation_email_queue(
+  subscriber_id uuid NOT NULL REFERENCES subscriptions(id) ON DELETE CASCADE,
+  subscription_token TEXT NOT NULL,
+  n_retries INT NOT NULL,
+  execute_after timestamptz NOT NULL,
+  PRIMARY KEY (subscriber_id)
+);
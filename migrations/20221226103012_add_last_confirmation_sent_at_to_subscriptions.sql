@@ -0,0 +1,3 @@
+-- When the last confirmation email was sent to a subscriber, to rate limit the resends.
+-- NULL until a confirmation is resent, the first one is sent when subscribing.
+ALTER TABLE subscriptions ADD COLUMN last_confirmation_sent_at timestamptz NULL;
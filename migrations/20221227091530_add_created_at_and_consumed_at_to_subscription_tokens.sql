@@ -0,0 +1,12 @@
+-- Confirmation tokens expire, and can only be used once.
+-- The existing tokens are considered created now so that they don't all expire at once, and the
+-- ones of subscribers who are no longer pending are consumed so that they can't confirm them again.
+BEGIN;
+  ALTER TABLE subscription_tokens ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
+  ALTER TABLE subscription_tokens ALTER COLUMN created_at DROP DEFAULT;
+  ALTER TABLE subscription_tokens ADD COLUMN consumed_at timestamptz NULL;
+  UPDATE subscription_tokens t
+  SET consumed_at = now()
+  FROM subscriptions s
+  WHERE s.id = t.subscriber_id AND s.status <> 'pending_confirmation';
+COMMIT;
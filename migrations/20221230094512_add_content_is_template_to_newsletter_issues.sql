@@ -0,0 +1,6 @@
+-- The contents of the issues are rendered as templates; the ones written before are sent as they
+-- are, since they could contain `{{` or `{%` which aren't meant as template syntax.
+BEGIN;
+  ALTER TABLE newsletter_issues ADD COLUMN content_is_template BOOLEAN NOT NULL DEFAULT false;
+  ALTER TABLE newsletter_issues ALTER COLUMN content_is_template SET DEFAULT true;
+COMMIT;
@@ -0,0 +1,44 @@
+use anyhow::Context;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// A security relevant event, kept in the `audit_log` table.
+pub struct AuditEvent<'a> {
+    pub event: &'static str,
+    pub username: Option<&'a str>,
+    pub client_ip: Option<&'a str>,
+    pub details: String,
+}
+
+#[tracing::instrument(
+    name = "Record audit event",
+    skip(executor, event),
+    fields(event = event.event, username = event.username, client_ip = event.client_ip)
+)]
+pub async fn record_audit_event<'a, E>(
+    executor: E,
+    event: AuditEvent<'_>,
+) -> Result<(), anyhow::Error>
+where
+    E: sqlx::PgExecutor<'a>,
+{
+    tracing::warn!(details = %event.details, "audit event");
+
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_log(id, occurred_at, event, username, client_ip, details)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        Uuid::new_v4(),
+        OffsetDateTime::now_utc(),
+        event.event,
+        event.username,
+        event.client_ip,
+        event.details,
+    )
+    .execute(executor)
+    .await
+    .context("Failed to record the audit event")?;
+
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use crate::authentication::{hash_token, Role};
+use anyhow::Context;
+use rand::distributions::{Alphanumeric, DistString};
+use secrecy::{ExposeSecret, Secret};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// Every token starts with this prefix, which makes them easy to spot in a leaked file.
+const API_TOKEN_PREFIX: &str = "z2p_";
+
+/// How precisely the last use of a token is recorded.
+const LAST_USED_AT_TOUCH_INTERVAL: time::Duration = time::Duration::minutes(1);
+
+/// Creates a new API token for a user, returning it; this is the only time it is known.
+#[tracing::instrument(skip(pool))]
+pub async fn create_api_token(
+    pool: &sqlx::PgPool,
+    user_id: Uuid,
+    name: &str,
+) -> Result<Secret<String>, anyhow::Error> {
+    let token = Secret::new(format!(
+        "{}{}",
+        API_TOKEN_PREFIX,
+        Alphanumeric.sample_string(&mut rand::thread_rng(), 40)
+    ));
+
+    sqlx::query!(
+        r#"
+        INSERT INTO api_tokens(id, user_id, name, token_hash, created_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        Uuid::new_v4(),
+        user_id,
+        name,
+        hash_token(token.expose_secret()),
+        OffsetDateTime::now_utc(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the API token")?;
+
+    Ok(token)
+}
+
+/// Returns the user owning an API token and their role, unless the token doesn't exist or the
+/// user has been disabled.
+#[tracing::instrument(skip(pool, token))]
+pub(super) async fn authenticate_api_token(
+    pool: &sqlx::PgPool,
+    token: &str,
+) -> Result<Option<(Uuid, Role)>, anyhow::Error> {
+    if !token.starts_with(API_TOKEN_PREFIX) {
+        return Ok(None);
+    }
+
+    let row = sqlx::query!(
+        r#"
+        SELECT api_tokens.id, api_tokens.last_used_at, users.user_id, users.role
+        FROM api_tokens
+        INNER JOIN users ON users.user_id = api_tokens.user_id
+        WHERE api_tokens.token_hash = $1 AND users.disabled_at IS NULL
+        "#,
+        hash_token(token),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to authenticate the API token")?;
+
+    let row = match row {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+
+    // Most API calls only read: don't write to the database on each of them
+    let now = OffsetDateTime::now_utc();
+    let recently_used = row
+        .last_used_at
+        .is_some_and(|last_used_at| now - last_used_at < LAST_USED_AT_TOUCH_INTERVAL);
+    if !recently_used {
+        sqlx::query!(
+            "UPDATE api_tokens SET last_used_at = $2 WHERE id = $1",
+            row.id,
+            now,
+        )
+        .execute(pool)
+        .await
+        .context("Failed to record the use of the API token")?;
+    }
+
+    Ok(Some((row.user_id, Role::parse(&row.role)?)))
+}
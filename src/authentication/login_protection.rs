@@ -0,0 +1,462 @@
+use crate::audit_log::{record_audit_event, AuditEvent};
+use actix_web::HttpRequest;
+use anyhow::Context;
+use std::net::{IpAddr, SocketAddr};
+use time::{Duration, OffsetDateTime};
+
+#[derive(Clone, Copy, Debug)]
+pub struct CounterLimits {
+    /// Failed attempts allowed before the next attempts are delayed.
+    pub free_attempts: i32,
+    /// Failed attempts after which further attempts are locked out.
+    pub lockout_threshold: i32,
+}
+
+/// Limits the rate of failed login attempts, both per username and per client IP address.
+///
+/// After `free_attempts` failures every new attempt must wait for a delay which doubles with
+/// each failure, and after `lockout_threshold` failures every attempt is refused for
+/// `lockout_duration`. Refused attempts don't count as failures, otherwise anyone knowing a
+/// username could keep it locked out.
+#[derive(Clone, Debug)]
+pub struct LoginProtectionPolicy {
+    pub username: CounterLimits,
+    pub client_ip: CounterLimits,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub lockout_duration: Duration,
+    pub failure_window: Duration,
+    pub trust_forwarded_for: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum LoginThrottle {
+    Allowed,
+    Delayed { until: OffsetDateTime },
+    LockedOut { until: OffsetDateTime },
+}
+
+impl LoginThrottle {
+    fn severity(&self) -> (u8, Option<OffsetDateTime>) {
+        match self {
+            LoginThrottle::Allowed => (0, None),
+            LoginThrottle::Delayed { until } => (1, Some(*until)),
+            LoginThrottle::LockedOut { until } => (2, Some(*until)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Counter {
+    Username,
+    ClientIp,
+}
+
+impl Counter {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Counter::Username => "username",
+            Counter::ClientIp => "client_ip",
+        }
+    }
+
+    fn parse(s: &str) -> Result<Self, anyhow::Error> {
+        match s {
+            "username" => Ok(Counter::Username),
+            "client_ip" => Ok(Counter::ClientIp),
+            _ => Err(anyhow::anyhow!("invalid login failure counter {:?}", s)),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct FailureCounter {
+    n_failures: i32,
+    last_failure_at: OffsetDateTime,
+    locked_until: Option<OffsetDateTime>,
+}
+
+impl LoginProtectionPolicy {
+    fn limits(&self, counter: Counter) -> CounterLimits {
+        match counter {
+            Counter::Username => self.username,
+            Counter::ClientIp => self.client_ip,
+        }
+    }
+
+    fn delay_after(&self, limits: CounterLimits, n_failures: i32) -> Duration {
+        if n_failures < limits.free_attempts {
+            return Duration::ZERO;
+        }
+
+        let exponent = (n_failures - limits.free_attempts).min(30) as u32;
+        std::cmp::min(self.base_delay * 2i32.pow(exponent), self.max_delay)
+    }
+
+    /// A counter is forgotten once its lockout is over or when its last failure is too old.
+    fn is_forgotten(&self, counter: &FailureCounter, now: OffsetDateTime) -> bool {
+        match counter.locked_until {
+            Some(locked_until) => locked_until <= now,
+            None => counter.last_failure_at + self.failure_window <= now,
+        }
+    }
+
+    fn throttle(
+        &self,
+        limits: CounterLimits,
+        counter: &FailureCounter,
+        now: OffsetDateTime,
+    ) -> LoginThrottle {
+        if self.is_forgotten(counter, now) {
+            return LoginThrottle::Allowed;
+        }
+        if let Some(until) = counter.locked_until {
+            return LoginThrottle::LockedOut { until };
+        }
+
+        let until = counter.last_failure_at + self.delay_after(limits, counter.n_failures);
+        if until > now {
+            LoginThrottle::Delayed { until }
+        } else {
+            LoginThrottle::Allowed
+        }
+    }
+
+    /// Returns the counter after one more failure, and whether that failure triggered a lockout.
+    fn next_counter(
+        &self,
+        limits: CounterLimits,
+        previous: Option<FailureCounter>,
+        now: OffsetDateTime,
+    ) -> (FailureCounter, bool) {
+        let previous = previous.filter(|counter| !self.is_forgotten(counter, now));
+
+        let n_failures = previous.as_ref().map_or(0, |c| c.n_failures) + 1;
+        let already_locked_until = previous.and_then(|c| c.locked_until);
+        let lockout = already_locked_until.is_none() && n_failures >= limits.lockout_threshold;
+
+        let counter = FailureCounter {
+            n_failures,
+            last_failure_at: now,
+            locked_until: if lockout {
+                Some(now + self.lockout_duration)
+            } else {
+                already_locked_until
+            },
+        };
+
+        (counter, lockout)
+    }
+
+    /// Returns the IP address of the client, without the port.
+    pub fn client_ip(&self, req: &HttpRequest) -> String {
+        let connection_info = req.connection_info();
+        let address = if self.trust_forwarded_for {
+            connection_info.realip_remote_addr()
+        } else {
+            connection_info.peer_addr()
+        };
+
+        match address {
+            Some(address) => match address.parse::<SocketAddr>() {
+                Ok(socket_address) => socket_address.ip().to_string(),
+                Err(_) => address
+                    .parse::<IpAddr>()
+                    .map(|ip| ip.to_string())
+                    .unwrap_or_else(|_| address.to_string()),
+            },
+            None => "unknown".to_string(),
+        }
+    }
+}
+
+/// Checks whether a login attempt for this username from this client is allowed right now.
+#[tracing::instrument(name = "Check login attempt", skip(pool, policy))]
+pub async fn check_login_attempt(
+    pool: &sqlx::PgPool,
+    policy: &LoginProtectionPolicy,
+    username: &str,
+    client_ip: &str,
+) -> Result<LoginThrottle, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT counter, n_failures, last_failure_at, locked_until
+        FROM login_failures
+        WHERE (counter = 'username' AND key = $1) OR (counter = 'client_ip' AND key = $2)
+        "#,
+        username,
+        client_ip,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the login failure counters")?;
+
+    let now = OffsetDateTime::now_utc();
+
+    let mut result = LoginThrottle::Allowed;
+    for row in rows {
+        let counter = Counter::parse(&row.counter)?;
+        let throttle = policy.throttle(
+            policy.limits(counter),
+            &FailureCounter {
+                n_failures: row.n_failures,
+                last_failure_at: row.last_failure_at,
+                locked_until: row.locked_until,
+            },
+            now,
+        );
+
+        if throttle.severity() > result.severity() {
+            result = throttle;
+        }
+    }
+
+    Ok(result)
+}
+
+/// Counts a failed login attempt against both the username and the client IP address.
+///
+/// Lockouts are written to the audit log.
+#[tracing::instrument(name = "Record login failure", skip(pool, policy))]
+pub async fn record_login_failure(
+    pool: &sqlx::PgPool,
+    policy: &LoginProtectionPolicy,
+    username: &str,
+    client_ip: &str,
+) -> Result<(), anyhow::Error> {
+    let now = OffsetDateTime::now_utc();
+
+    let mut transaction = pool.begin().await?;
+
+    for (counter, key) in [
+        (Counter::Username, username),
+        (Counter::ClientIp, client_ip),
+    ] {
+        let previous = sqlx::query_as!(
+            FailureCounter,
+            r#"
+            SELECT n_failures, last_failure_at, locked_until
+            FROM login_failures
+            WHERE counter = $1 AND key = $2
+            FOR UPDATE
+            "#,
+            counter.as_str(),
+            key,
+        )
+        .fetch_optional(&mut transaction)
+        .await
+        .context("Failed to fetch the login failure counter")?;
+
+        let (next, lockout) = policy.next_counter(policy.limits(counter), previous, now);
+
+        sqlx::query!(
+            r#"
+            INSERT INTO login_failures(counter, key, n_failures, last_failure_at, locked_until)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (counter, key) DO UPDATE
+            SET n_failures = EXCLUDED.n_failures,
+                last_failure_at = EXCLUDED.last_failure_at,
+                locked_until = EXCLUDED.locked_until
+            "#,
+            counter.as_str(),
+            key,
+            next.n_failures,
+            next.last_failure_at,
+            next.locked_until,
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to store the login failure counter")?;
+
+        if lockout {
+            record_audit_event(
+                &mut transaction,
+                AuditEvent {
+                    event: "login_lockout",
+                    username: Some(username),
+                    client_ip: Some(client_ip),
+                    details: format!(
+                        "{} locked out after {} failed login attempts until {}",
+                        counter.as_str(),
+                        next.n_failures,
+                        next.locked_until.unwrap_or(now)
+                    ),
+                },
+            )
+            .await?;
+        }
+    }
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Deletes the counters which have been forgotten, since they don't throttle anything anymore.
+/// Returns how many have been deleted.
+///
+/// This runs outside of [`record_login_failure`], whose transaction locks the counters it
+/// updates.
+#[tracing::instrument(skip(pool, policy), level = "debug")]
+pub async fn purge_forgotten_login_failures(
+    pool: &sqlx::PgPool,
+    policy: &LoginProtectionPolicy,
+) -> Result<u64, anyhow::Error> {
+    let now = OffsetDateTime::now_utc();
+
+    let purged = sqlx::query!(
+        r#"
+        DELETE FROM login_failures
+        WHERE (locked_until IS NULL AND last_failure_at <= $2) OR locked_until <= $1
+        "#,
+        now,
+        now - policy.failure_window,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to prune the login failure counters")?
+    .rows_affected();
+
+    Ok(purged)
+}
+
+/// Forgets the failed attempts of a username after a successful login.
+///
+/// The counter of the client IP address is kept, otherwise logging in with any valid account
+/// would allow guessing the passwords of the other accounts faster.
+#[tracing::instrument(name = "Reset login failures", skip(pool))]
+pub async fn reset_login_failures(
+    pool: &sqlx::PgPool,
+    username: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM login_failures
+        WHERE counter = 'username' AND key = $1
+        "#,
+        username,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to reset the login failure counter")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CounterLimits, FailureCounter, LoginProtectionPolicy, LoginThrottle};
+    use claim::assert_none;
+    use time::macros::datetime;
+    use time::{Duration, OffsetDateTime};
+
+    const LIMITS: CounterLimits = CounterLimits {
+        free_attempts: 3,
+        lockout_threshold: 6,
+    };
+
+    const NOW: OffsetDateTime = datetime!(2022-12-21 19:00 UTC);
+
+    fn policy() -> LoginProtectionPolicy {
+        LoginProtectionPolicy {
+            username: LIMITS,
+            client_ip: LIMITS,
+            base_delay: Duration::seconds(1),
+            max_delay: Duration::seconds(10),
+            lockout_duration: Duration::minutes(15),
+            failure_window: Duration::hours(1),
+            trust_forwarded_for: false,
+        }
+    }
+
+    fn counter(n_failures: i32, last_failure_at: OffsetDateTime) -> FailureCounter {
+        FailureCounter {
+            n_failures,
+            last_failure_at,
+            locked_until: None,
+        }
+    }
+
+    #[test]
+    fn the_delay_doubles_after_the_free_attempts_and_is_capped() {
+        let policy = policy();
+
+        let delays: Vec<i64> = (0..10)
+            .map(|n| policy.delay_after(LIMITS, n).whole_seconds())
+            .collect();
+        assert_eq!(delays, vec![0, 0, 0, 1, 2, 4, 8, 10, 10, 10]);
+
+        assert_eq!(policy.delay_after(LIMITS, i32::MAX), Duration::seconds(10));
+    }
+
+    #[test]
+    fn attempts_are_allowed_during_the_free_attempts() {
+        let policy = policy();
+
+        assert_eq!(
+            policy.throttle(LIMITS, &counter(2, NOW), NOW),
+            LoginThrottle::Allowed
+        );
+    }
+
+    #[test]
+    fn attempts_are_delayed_after_the_free_attempts() {
+        let policy = policy();
+        let counter = counter(4, NOW);
+
+        assert_eq!(
+            policy.throttle(LIMITS, &counter, NOW + Duration::seconds(1)),
+            LoginThrottle::Delayed {
+                until: NOW + Duration::seconds(2)
+            }
+        );
+        assert_eq!(
+            policy.throttle(LIMITS, &counter, NOW + Duration::seconds(2)),
+            LoginThrottle::Allowed
+        );
+    }
+
+    #[test]
+    fn reaching_the_threshold_locks_out_until_the_lockout_is_over() {
+        let policy = policy();
+
+        let (counter, lockout) = policy.next_counter(LIMITS, Some(counter(5, NOW)), NOW);
+        assert!(lockout);
+        assert_eq!(counter.n_failures, 6);
+
+        let until = NOW + Duration::minutes(15);
+        assert_eq!(
+            policy.throttle(LIMITS, &counter, NOW + Duration::minutes(14)),
+            LoginThrottle::LockedOut { until }
+        );
+        assert_eq!(
+            policy.throttle(LIMITS, &counter, until),
+            LoginThrottle::Allowed
+        );
+
+        // Failing during the lockout doesn't extend it nor lock out again
+        let (counter, lockout) = policy.next_counter(LIMITS, Some(counter), NOW);
+        assert!(!lockout);
+        assert_eq!(counter.locked_until, Some(until));
+
+        // After the lockout the counter starts over
+        let (counter, lockout) = policy.next_counter(LIMITS, Some(counter), until);
+        assert!(!lockout);
+        assert_eq!(counter.n_failures, 1);
+        assert_none!(counter.locked_until);
+    }
+
+    #[test]
+    fn old_failures_are_forgotten() {
+        let policy = policy();
+        let an_hour_later = NOW + Duration::hours(1);
+
+        assert_eq!(
+            policy.throttle(LIMITS, &counter(5, NOW), an_hour_later),
+            LoginThrottle::Allowed
+        );
+
+        let (counter, _) = policy.next_counter(LIMITS, Some(counter(5, NOW)), an_hour_later);
+        assert_eq!(counter.n_failures, 1);
+    }
+}
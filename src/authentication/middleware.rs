@@ -1,12 +1,17 @@
-use crate::routes::{e500, see_other};
+use crate::authentication::api_token::authenticate_api_token;
+use crate::authentication::Role;
+use crate::routes::{e500, see_other, ApiError};
 use crate::sessions::TypedSession;
 use actix_web::body::MessageBody;
 use actix_web::dev::{ServiceRequest, ServiceResponse};
 use actix_web::error::InternalError;
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::http::StatusCode;
+use actix_web::web;
 use actix_web::FromRequest;
 use actix_web::HttpMessage;
 use actix_web_lab::middleware::Next;
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
 use std::ops::Deref;
 use uuid::Uuid;
 
@@ -26,6 +31,10 @@ impl Deref for UserId {
     }
 }
 
+/// Rejects requests without a logged in user, or whose user has since been disabled or deleted.
+///
+/// On success both the [`UserId`] and the [`Role`] of the user are available to the handlers
+/// and to the role checks of [`require_editor_role`] and [`require_owner_role`].
 pub async fn reject_anonymous_users(
     mut req: ServiceRequest,
     next: Next<impl MessageBody>,
@@ -36,16 +45,130 @@ pub async fn reject_anonymous_users(
     };
     let session = session_result?;
 
-    let user_id_result = session.get_user_id().map_err(e500)?;
-    match user_id_result {
-        Some(user_id) => {
+    let user_id = match session.get_user_id().map_err(e500)? {
+        Some(user_id) => user_id,
+        None => {
+            let response = see_other("/login");
+            let err = anyhow!("The user has not logged in");
+            return Err(InternalError::from_response(err, response).into());
+        }
+    };
+
+    let pool = req
+        .app_data::<web::Data<sqlx::PgPool>>()
+        .context("No database pool in the application data")
+        .map_err(e500)?;
+
+    match get_active_user_role(pool, user_id).await.map_err(e500)? {
+        Some(role) => {
             req.extensions_mut().insert(UserId(user_id));
+            req.extensions_mut().insert(role);
             next.call(req).await
         }
         None => {
+            session.logout();
+
             let response = see_other("/login");
-            let err = anyhow!("The user has not logged in");
+            let err = anyhow!("The user has been disabled or deleted");
             Err(InternalError::from_response(err, response).into())
         }
     }
 }
+
+/// Rejects requests without a valid API token in their `Authorization: Bearer` header.
+///
+/// Like [`reject_anonymous_users`] the [`UserId`] and the [`Role`] of the owner of the token are
+/// then available to the handlers and the role checks.
+pub async fn require_api_token(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let token = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+        .map(|(_, token)| token.trim().to_string());
+    let token = match token {
+        Some(token) => token,
+        None => return Err(ApiError::Unauthorized("Missing bearer API token").into()),
+    };
+
+    let pool = req
+        .app_data::<web::Data<sqlx::PgPool>>()
+        .context("No database pool in the application data")
+        .map_err(e500)?;
+
+    match authenticate_api_token(pool, &token).await.map_err(e500)? {
+        Some((user_id, role)) => {
+            req.extensions_mut().insert(UserId(user_id));
+            req.extensions_mut().insert(role);
+            next.call(req).await
+        }
+        None => Err(ApiError::Unauthorized("Invalid API token").into()),
+    }
+}
+
+/// Rejects requests from users who are not at least editors.
+///
+/// Must be wrapped by [`reject_anonymous_users`] or [`require_api_token`].
+pub async fn require_editor_role(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    require_role(Role::Editor, req, next).await
+}
+
+/// Rejects requests from users who are not owners.
+///
+/// Must be wrapped by [`reject_anonymous_users`] or [`require_api_token`].
+pub async fn require_owner_role(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    require_role(Role::Owner, req, next).await
+}
+
+async fn require_role(
+    required_role: Role,
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let role = req
+        .extensions()
+        .get::<Role>()
+        .copied()
+        .context("No role in the request, is the route authenticated ?")
+        .map_err(e500)?;
+
+    if role < required_role {
+        let err = anyhow!(
+            "This action requires the {} role, you are {}",
+            required_role,
+            role
+        );
+        return Err(InternalError::new(err, StatusCode::FORBIDDEN).into());
+    }
+
+    next.call(req).await
+}
+
+#[tracing::instrument(name = "Get active user role", skip(pool))]
+async fn get_active_user_role(
+    pool: &sqlx::PgPool,
+    user_id: Uuid,
+) -> Result<Option<Role>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT role FROM users
+        WHERE user_id = $1 AND disabled_at IS NULL
+        "#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve the user role")?;
+
+    row.map(|row| Role::parse(&row.role)).transpose()
+}
@@ -1,4 +1,28 @@
 mod password;
-pub use password::{change_password, validate_credentials, AuthError, Credentials};
+pub use password::{
+    change_password, compute_password_hash, validate_credentials, AuthError, Credentials,
+    PasswordHashing,
+};
+mod password_policy;
+pub use password_policy::{validate_new_password, NewPasswordError};
+mod login_protection;
+pub use login_protection::{
+    check_login_attempt, purge_forgotten_login_failures, record_login_failure,
+    reset_login_failures, CounterLimits, LoginProtectionPolicy, LoginThrottle,
+};
 mod middleware;
-pub use middleware::{reject_anonymous_users, UserId};
+pub use middleware::{
+    reject_anonymous_users, require_api_token, require_editor_role, require_owner_role, UserId,
+};
+mod api_token;
+pub use api_token::create_api_token;
+mod token_hash;
+pub(crate) use token_hash::hash_token;
+mod role;
+pub use role::Role;
+mod totp;
+pub use totp::TotpSecret;
+mod two_factor;
+pub use two_factor::{
+    count_unused_recovery_codes, disable_totp, enable_totp, get_totp_secret, verify_second_factor,
+};
@@ -13,23 +13,105 @@ pub enum AuthError {
     Unexpected(#[from] anyhow::Error),
 }
 
+/// How passwords are hashed: the Argon2id cost parameters and an optional pepper.
+///
+/// The pepper is a secret mixed into every hash which isn't stored in the database, so a leaked
+/// database alone isn't enough to brute force the passwords. Hashes computed with the pepper are
+/// marked with the `keyid` parameter of their PHC string.
+#[derive(Clone)]
+pub struct PasswordHashing {
+    params: argon2::Params,
+    pepper: Option<Secret<String>>,
+    /// Verified when the username is unknown, so that it takes as long as for a known one.
+    dummy_hash: Secret<String>,
+}
+
+/// The `keyid` of the hashes computed with the pepper.
+const PEPPER_KEY_ID: &[u8] = b"pepper";
+
+impl PasswordHashing {
+    pub fn new(
+        memory_cost_kib: u32,
+        iterations: u32,
+        parallelism: u32,
+        pepper: Option<Secret<String>>,
+    ) -> Result<Self, anyhow::Error> {
+        let mut builder = argon2::ParamsBuilder::new();
+        builder
+            .m_cost(memory_cost_kib)
+            .and_then(|builder| builder.t_cost(iterations))
+            .and_then(|builder| builder.p_cost(parallelism))
+            .map_err(|err| anyhow!("Invalid Argon2 parameters: {}", err))?;
+        if pepper.is_some() {
+            builder
+                .keyid(PEPPER_KEY_ID)
+                .map_err(|err| anyhow!("Invalid Argon2 key id: {}", err))?;
+        }
+        let params = builder
+            .params()
+            .map_err(|err| anyhow!("Invalid Argon2 parameters: {}", err))?;
+
+        let mut hashing = Self {
+            params,
+            pepper,
+            dummy_hash: Secret::new(String::new()),
+        };
+        hashing.dummy_hash =
+            compute_password_hash(&hashing, Secret::new(Uuid::new_v4().to_string()))?;
+
+        Ok(hashing)
+    }
+
+    fn argon2(&self, params: argon2::Params, peppered: bool) -> Result<Argon2<'_>, anyhow::Error> {
+        match (&self.pepper, peppered) {
+            (Some(pepper), true) => Argon2::new_with_secret(
+                pepper.expose_secret().as_bytes(),
+                argon2::Algorithm::Argon2id,
+                argon2::Version::V0x13,
+                params,
+            )
+            .map_err(|err| anyhow!("Invalid pepper: {}", err)),
+            (None, true) => Err(anyhow!("The pepper is needed to verify this password hash")),
+            (_, false) => Ok(Argon2::new(
+                argon2::Algorithm::Argon2id,
+                argon2::Version::V0x13,
+                params,
+            )),
+        }
+    }
+
+    /// Returns true if the hash is weaker than what would be computed now.
+    fn needs_rehash(&self, password_hash: &PasswordHash) -> bool {
+        let params = match argon2::Params::try_from(password_hash) {
+            Ok(params) => params,
+            Err(_) => return true,
+        };
+
+        password_hash.algorithm != argon2::Algorithm::Argon2id.ident()
+            || password_hash.version != Some(argon2::Version::V0x13 as u32)
+            || params.m_cost() < self.params.m_cost()
+            || params.t_cost() < self.params.t_cost()
+            || params.p_cost() < self.params.p_cost()
+            || (self.pepper.is_some() && params.keyid() != PEPPER_KEY_ID)
+    }
+}
+
 pub struct Credentials {
     pub username: String,
     pub password: Secret<String>,
 }
 
-#[tracing::instrument(name = "Validate credentials", skip(pool, credentials))]
+/// Returns the id of the user if the credentials are valid.
+///
+/// A stored hash weaker than the current hashing settings is replaced by a new one.
+#[tracing::instrument(name = "Validate credentials", skip(pool, hashing, credentials))]
 pub async fn validate_credentials(
     pool: &sqlx::PgPool,
+    hashing: &PasswordHashing,
     credentials: Credentials,
 ) -> Result<Uuid, AuthError> {
     let mut user_id = None;
-    let mut expected_password_hash = Secret::new(
-        "$argon2id$v=19$m=15000,t=2,p=1$\
-gZiV/M1gPc22ElAH/Jh1Hw$\
-CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
-            .into(),
-    );
+    let mut expected_password_hash = hashing.dummy_hash.clone();
 
     if let Some(stored_credentials) = get_stored_credentials(pool, &credentials.username)
         .await
@@ -41,33 +123,98 @@ CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
 
     //
 
-    let verify_result = spawn_blocking_with_tracing(move || {
-        verify_password_hash(expected_password_hash, credentials.password)
-    })
-    .await
-    .context("Failed to spawn blocking task")
-    .map_err(AuthError::Unexpected)?;
+    let verify_result = {
+        let hashing = hashing.clone();
+        let expected_password_hash = expected_password_hash.clone();
+        let password = credentials.password.clone();
+        spawn_blocking_with_tracing(move || {
+            verify_password_hash(&hashing, expected_password_hash, password)
+        })
+        .await
+        .context("Failed to spawn blocking task")
+        .map_err(AuthError::Unexpected)?
+    };
 
     verify_result?;
 
     //
 
-    user_id
+    let user_id = user_id
         .ok_or_else(|| anyhow!("Unknown username"))
-        .map_err(AuthError::InvalidCredentials)
+        .map_err(AuthError::InvalidCredentials)?;
+
+    let needs_rehash = PasswordHash::new(expected_password_hash.expose_secret())
+        .map(|password_hash| hashing.needs_rehash(&password_hash))
+        .unwrap_or(true);
+    if needs_rehash {
+        // The credentials are valid whether the upgrade works or not
+        if let Err(err) = upgrade_password_hash(
+            pool,
+            hashing,
+            user_id,
+            expected_password_hash,
+            credentials.password,
+        )
+        .await
+        {
+            tracing::warn!(error = ?err, "failed to upgrade the password hash");
+        }
+    }
+
+    Ok(user_id)
 }
 
-#[tracing::instrument(name = "Change password", skip(pool, password))]
-pub async fn change_password(
+#[tracing::instrument(
+    name = "Upgrade password hash",
+    skip(pool, hashing, previous_password_hash, password)
+)]
+async fn upgrade_password_hash(
     pool: &sqlx::PgPool,
+    hashing: &PasswordHashing,
     user_id: Uuid,
+    previous_password_hash: Secret<String>,
     password: Secret<String>,
 ) -> Result<(), anyhow::Error> {
+    let hashing = hashing.clone();
+    let password_hash =
+        spawn_blocking_with_tracing(move || compute_password_hash(&hashing, password))
+            .await
+            .context("Failed to spawn blocking task")??;
+
+    // Don't overwrite a password changed in the meantime
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET password_hash = $1
+        WHERE user_id = $2 AND password_hash = $3
+        "#,
+        password_hash.expose_secret(),
+        user_id,
+        previous_password_hash.expose_secret(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to update the users password hash")?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Change password", skip(executor, hashing, password))]
+pub async fn change_password<'a, E>(
+    executor: E,
+    hashing: &PasswordHashing,
+    user_id: Uuid,
+    password: Secret<String>,
+) -> Result<(), anyhow::Error>
+where
+    E: sqlx::PgExecutor<'a>,
+{
     // Compute the new hash
-    let password_hash_result = spawn_blocking_with_tracing(move || compute_password_hash(password))
-        .await
-        .context("Failed to spawn blocking task")
-        .map_err(Into::<anyhow::Error>::into)?;
+    let hashing = hashing.clone();
+    let password_hash_result =
+        spawn_blocking_with_tracing(move || compute_password_hash(&hashing, password))
+            .await
+            .context("Failed to spawn blocking task")?;
     let password_hash = password_hash_result?;
 
     // Store it
@@ -80,20 +227,19 @@ pub async fn change_password(
         password_hash.expose_secret(),
         user_id,
     )
-    .execute(pool)
+    .execute(executor)
     .await
     .context("Failed to update the users password")?;
 
     Ok(())
 }
 
-pub fn compute_password_hash(password: Secret<String>) -> Result<Secret<String>, anyhow::Error> {
+pub fn compute_password_hash(
+    hashing: &PasswordHashing,
+    password: Secret<String>,
+) -> Result<Secret<String>, anyhow::Error> {
     let salt = SaltString::generate(&mut rand::thread_rng());
-    let hasher = Argon2::new(
-        argon2::Algorithm::Argon2id,
-        argon2::Version::V0x13,
-        argon2::Params::new(15000, 2, 1, None).unwrap(),
-    );
+    let hasher = hashing.argon2(hashing.params.clone(), hashing.pepper.is_some())?;
 
     let password_hash = hasher.hash_password(password.expose_secret().as_bytes(), &salt)?;
     let password_hash_string = password_hash.to_string();
@@ -103,9 +249,10 @@ pub fn compute_password_hash(password: Secret<String>) -> Result<Secret<String>,
 
 #[tracing::instrument(
     name = "Verify password hash",
-    skip(expected_password_hash, password_candidate)
+    skip(hashing, expected_password_hash, password_candidate)
 )]
-fn verify_password_hash(
+pub(crate) fn verify_password_hash(
+    hashing: &PasswordHashing,
     expected_password_hash: Secret<String>,
     password_candidate: Secret<String>,
 ) -> Result<(), AuthError> {
@@ -113,7 +260,15 @@ fn verify_password_hash(
         .context("Failed to parse hash in PHC string format")
         .map_err(AuthError::Unexpected)?;
 
-    Argon2::default()
+    // The parameters used come from the hash itself, only the pepper comes from the settings
+    let params = argon2::Params::try_from(&expected_password_hash)
+        .context("Failed to parse the parameters of the hash")
+        .map_err(AuthError::Unexpected)?;
+    let peppered = params.keyid() == PEPPER_KEY_ID;
+
+    hashing
+        .argon2(params, peppered)
+        .map_err(AuthError::Unexpected)?
         .verify_password(
             password_candidate.expose_secret().as_bytes(),
             &expected_password_hash,
@@ -131,7 +286,7 @@ async fn get_stored_credentials(
         r#"
         SELECT user_id, password_hash
         FROM users
-        WHERE username = $1
+        WHERE username = $1 AND disabled_at IS NULL
         "#,
         username,
     )
@@ -147,3 +302,94 @@ async fn get_stored_credentials(
         None => Ok(None),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{compute_password_hash, verify_password_hash, PasswordHashing};
+    use argon2::PasswordHash;
+    use claim::{assert_err, assert_ok};
+    use secrecy::{ExposeSecret, Secret};
+
+    /// Cheap parameters, the tests don't need to be slow.
+    fn hashing(memory_cost_kib: u32, pepper: Option<&str>) -> PasswordHashing {
+        PasswordHashing::new(
+            memory_cost_kib,
+            1,
+            1,
+            pepper.map(|pepper| Secret::new(pepper.to_string())),
+        )
+        .unwrap()
+    }
+
+    fn needs_rehash(hashing: &PasswordHashing, password_hash: &Secret<String>) -> bool {
+        hashing.needs_rehash(&PasswordHash::new(password_hash.expose_secret()).unwrap())
+    }
+
+    fn password() -> Secret<String> {
+        Secret::new("hunter2".to_string())
+    }
+
+    #[test]
+    fn a_hash_with_the_current_parameters_is_not_rehashed() {
+        let hashing = hashing(64, None);
+        let password_hash = compute_password_hash(&hashing, password()).unwrap();
+
+        assert!(!needs_rehash(&hashing, &password_hash));
+    }
+
+    #[test]
+    fn a_hash_with_weaker_parameters_is_rehashed() {
+        let password_hash = compute_password_hash(&hashing(64, None), password()).unwrap();
+
+        assert!(needs_rehash(&hashing(128, None), &password_hash));
+        // A stronger hash is kept as is
+        assert!(!needs_rehash(&hashing(32, None), &password_hash));
+    }
+
+    #[test]
+    fn a_hash_with_another_algorithm_is_rehashed() {
+        let password_hash = Secret::new(
+            "$argon2i$v=19$m=4096,t=3,p=1$\
+gZiV/M1gPc22ElAH/Jh1Hw$\
+CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
+                .to_string(),
+        );
+
+        assert!(needs_rehash(&hashing(64, None), &password_hash));
+    }
+
+    #[test]
+    fn a_peppered_hash_needs_the_pepper() {
+        let peppered = hashing(64, Some("pepper"));
+        let password_hash = compute_password_hash(&peppered, password()).unwrap();
+
+        assert_ok!(verify_password_hash(
+            &peppered,
+            password_hash.clone(),
+            password()
+        ));
+        assert_err!(verify_password_hash(
+            &hashing(64, Some("another pepper")),
+            password_hash.clone(),
+            password()
+        ));
+        assert_err!(verify_password_hash(
+            &hashing(64, None),
+            password_hash,
+            password()
+        ));
+    }
+
+    #[test]
+    fn a_hash_without_pepper_is_verified_then_rehashed_once_a_pepper_is_set() {
+        let password_hash = compute_password_hash(&hashing(64, None), password()).unwrap();
+        let peppered = hashing(64, Some("pepper"));
+
+        assert_ok!(verify_password_hash(
+            &peppered,
+            password_hash.clone(),
+            password()
+        ));
+        assert!(needs_rehash(&peppered, &password_hash));
+    }
+}
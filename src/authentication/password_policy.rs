@@ -0,0 +1,394 @@
+use secrecy::{ExposeSecret, Secret};
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use unicode_segmentation::UnicodeSegmentation;
+
+const MIN_GRAPHEMES: usize = 12;
+const MAX_GRAPHEMES: usize = 128;
+/// Also bounded in bytes since a grapheme can be arbitrarily long.
+const MAX_BYTES: usize = 512;
+/// About 10^12 guesses, out of reach of an online attack and slow offline with Argon2id.
+const MIN_ENTROPY_BITS: f64 = 40.0;
+
+/// Words which are likely to be part of a password chosen for this site.
+const SITE_WORDS: [&str; 4] = ["zero2prod", "newsletter", "admin", "subscriber"];
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum NewPasswordError {
+    #[error("You entered two different new passwords - the field values must match.")]
+    Mismatch,
+    #[error("New password is too short, it must be at least {MIN_GRAPHEMES} characters long")]
+    TooShort,
+    #[error("New password is too long")]
+    TooLong,
+    #[error("New password can't be your username")]
+    SameAsUsername,
+    #[error("New password is too easy to guess, try a longer passphrase")]
+    TooWeak,
+    #[error("New password appears in a list of breached passwords, choose another one")]
+    Breached,
+}
+
+/// Validates a new password typed twice in a form by the user `username`.
+pub fn validate_new_password(
+    password: &Secret<String>,
+    password_check: &Secret<String>,
+    username: &str,
+) -> Result<(), NewPasswordError> {
+    let password = password.expose_secret();
+    if password != password_check.expose_secret() {
+        return Err(NewPasswordError::Mismatch);
+    }
+
+    let n_graphemes = password.graphemes(true).count();
+    if n_graphemes < MIN_GRAPHEMES {
+        return Err(NewPasswordError::TooShort);
+    }
+    if n_graphemes > MAX_GRAPHEMES || password.len() > MAX_BYTES {
+        return Err(NewPasswordError::TooLong);
+    }
+
+    if password.trim().to_lowercase() == username.trim().to_lowercase() {
+        return Err(NewPasswordError::SameAsUsername);
+    }
+
+    if is_breached(password) {
+        return Err(NewPasswordError::Breached);
+    }
+
+    if estimate_entropy_bits(password, &[username]) < MIN_ENTROPY_BITS {
+        return Err(NewPasswordError::TooWeak);
+    }
+
+    Ok(())
+}
+
+/// Estimates the number of bits of entropy of a password, in the style of zxcvbn.
+///
+/// The password is split in the sequence of patterns which is the cheapest to guess: words of
+/// the dictionary (possibly capitalized, reversed or with l33t substitutions), repeated
+/// characters, sequences like `abcd` or `4321`, runs of keyboard keys, and single characters
+/// guessed by brute force.
+fn estimate_entropy_bits(password: &str, user_inputs: &[&str]) -> f64 {
+    let chars: Vec<char> = password.chars().collect();
+
+    // best[i] is the cheapest estimate for the first i characters
+    let mut best = vec![0.0; chars.len() + 1];
+    for end in 1..=chars.len() {
+        best[end] = best[end - 1] + bruteforce_bits(chars[end - 1]);
+
+        for start in 0..end.saturating_sub(2) {
+            if let Some(bits) = pattern_bits(&chars[start..end], user_inputs) {
+                best[end] = f64::min(best[end], best[start] + bits);
+            }
+        }
+    }
+
+    best[chars.len()]
+}
+
+fn bruteforce_bits(c: char) -> f64 {
+    let cardinality: f64 = if c.is_ascii_digit() {
+        10.0
+    } else if c.is_ascii_lowercase() || c.is_ascii_uppercase() {
+        26.0
+    } else if c.is_ascii() {
+        33.0
+    } else {
+        // Any other Unicode character, counted like a block of 100 characters
+        100.0
+    };
+    cardinality.log2()
+}
+
+/// Returns the cost of the cheapest pattern matching the whole segment, if any.
+fn pattern_bits(segment: &[char], user_inputs: &[&str]) -> Option<f64> {
+    [
+        repeat_bits(segment),
+        sequence_bits(segment),
+        keyboard_bits(segment),
+        dictionary_bits(segment, user_inputs),
+    ]
+    .into_iter()
+    .flatten()
+    .reduce(f64::min)
+}
+
+fn repeat_bits(segment: &[char]) -> Option<f64> {
+    if segment.iter().all(|c| *c == segment[0]) {
+        Some(bruteforce_bits(segment[0]) + (segment.len() as f64).log2())
+    } else {
+        None
+    }
+}
+
+fn sequence_bits(segment: &[char]) -> Option<f64> {
+    let delta = segment[1] as i64 - segment[0] as i64;
+    if delta.abs() != 1 {
+        return None;
+    }
+    if !segment
+        .windows(2)
+        .all(|pair| pair[1] as i64 - pair[0] as i64 == delta)
+    {
+        return None;
+    }
+
+    let descending_bits = if delta < 0 { 1.0 } else { 0.0 };
+    Some(bruteforce_bits(segment[0]) + (segment.len() as f64).log2() + descending_bits)
+}
+
+fn keyboard_bits(segment: &[char]) -> Option<f64> {
+    const ROWS: [&str; 5] = [
+        "1234567890",
+        "qwertyuiop",
+        "asdfghjkl",
+        "zxcvbnm",
+        "azertyuiop",
+    ];
+
+    let lowercase: String = segment.iter().flat_map(|c| c.to_lowercase()).collect();
+    let reversed: String = lowercase.chars().rev().collect();
+
+    ROWS.iter()
+        .any(|row| row.contains(&lowercase) || row.contains(&reversed))
+        .then(|| (ROWS.len() as f64 * 2.0).log2() + (segment.len() as f64).log2())
+}
+
+fn dictionary_bits(segment: &[char], user_inputs: &[&str]) -> Option<f64> {
+    const MAX_WORD_LENGTH: usize = 32;
+    if segment.len() > MAX_WORD_LENGTH {
+        return None;
+    }
+
+    let lowercase: String = segment.iter().flat_map(|c| c.to_lowercase()).collect();
+    let uppercase_bits = uppercase_bits(segment);
+
+    let mut candidates = vec![(lowercase.clone(), 0.0)];
+    candidates.push((lowercase.chars().rev().collect(), 1.0));
+    let (unleeted, n_substitutions) = unleet(&lowercase);
+    if n_substitutions > 0 {
+        candidates.push((unleeted, n_substitutions as f64));
+    }
+
+    candidates
+        .into_iter()
+        .filter_map(|(word, extra_bits)| {
+            word_rank(&word, user_inputs).map(|rank| (rank as f64).log2() + extra_bits)
+        })
+        .reduce(f64::min)
+        .map(|bits| bits + uppercase_bits)
+}
+
+/// Capitalizing only the first letter or every letter is what most people do, other
+/// combinations cost one bit per uppercase letter.
+fn uppercase_bits(segment: &[char]) -> f64 {
+    let n_uppercase = segment.iter().filter(|c| c.is_uppercase()).count();
+    let n_letters = segment.iter().filter(|c| c.is_alphabetic()).count();
+
+    if n_uppercase == 0 {
+        0.0
+    } else if n_uppercase == n_letters || (n_uppercase == 1 && segment[0].is_uppercase()) {
+        1.0
+    } else {
+        n_uppercase as f64
+    }
+}
+
+fn unleet(word: &str) -> (String, usize) {
+    let mut n_substitutions = 0;
+    let unleeted = word
+        .chars()
+        .map(|c| {
+            let substitute = match c {
+                '4' | '@' => 'a',
+                '8' => 'b',
+                '3' => 'e',
+                '1' | '!' => 'i',
+                '0' => 'o',
+                '5' | '$' => 's',
+                '7' | '+' => 't',
+                _ => return c,
+            };
+            n_substitutions += 1;
+            substitute
+        })
+        .collect();
+    (unleeted, n_substitutions)
+}
+
+/// Returns the rank of a word in the dictionary, starting at 1; the words given by the user and
+/// those of the site come first.
+fn word_rank(word: &str, user_inputs: &[&str]) -> Option<usize> {
+    if let Some(position) = user_inputs
+        .iter()
+        .chain(SITE_WORDS.iter())
+        .position(|input| input.to_lowercase() == word)
+    {
+        return Some(position + 1);
+    }
+
+    common_passwords()
+        .get(word)
+        .map(|rank| user_inputs.len() + SITE_WORDS.len() + rank)
+}
+
+fn common_passwords() -> &'static HashMap<String, usize> {
+    static COMMON_PASSWORDS: OnceLock<HashMap<String, usize>> = OnceLock::new();
+
+    COMMON_PASSWORDS.get_or_init(|| {
+        let mut words = HashMap::new();
+        for (rank, word) in include_str!("../../data/common_passwords.txt")
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .enumerate()
+        {
+            // Keep the best rank of words differing only by their case
+            words.entry(word.to_lowercase()).or_insert(rank + 1);
+        }
+        words
+    })
+}
+
+/// Checks the password against the bundled list of breached passwords.
+///
+/// The lookup works like the k-anonymity range API of Have I Been Pwned: the first 5 characters
+/// of the SHA-1 hash select a range of candidates, then only the rest of the hash is compared.
+fn is_breached(password: &str) -> bool {
+    let hash = hex::encode_upper(Sha1::digest(password.as_bytes()));
+    let (prefix, suffix) = hash.split_at(5);
+
+    breached_hashes_range(prefix)
+        .iter()
+        .any(|candidate| &candidate[prefix.len()..] == suffix)
+}
+
+/// Returns the breached hashes starting with `prefix`.
+fn breached_hashes_range(prefix: &str) -> &'static [&'static str] {
+    let hashes = breached_hashes();
+    let start = hashes.partition_point(|hash| &hash[..prefix.len()] < prefix);
+    let end = start + hashes[start..].partition_point(|hash| hash.starts_with(prefix));
+
+    &hashes[start..end]
+}
+
+fn breached_hashes() -> &'static [&'static str] {
+    static BREACHED_HASHES: OnceLock<Vec<&'static str>> = OnceLock::new();
+
+    BREACHED_HASHES.get_or_init(|| {
+        let mut hashes: Vec<&'static str> = include_str!("../../data/breached_passwords.sha1")
+            .lines()
+            .map(str::trim)
+            .filter(|line| line.len() == 40)
+            .collect();
+        // The lookup relies on the order, don't trust the file for it
+        hashes.sort_unstable();
+        hashes
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{estimate_entropy_bits, is_breached, validate_new_password, NewPasswordError};
+    use claim::assert_ok;
+    use secrecy::Secret;
+
+    fn validate(password: &str, username: &str) -> Result<(), NewPasswordError> {
+        validate_new_password(
+            &Secret::new(password.to_string()),
+            &Secret::new(password.to_string()),
+            username,
+        )
+    }
+
+    #[test]
+    fn both_passwords_must_match() {
+        assert_eq!(
+            validate_new_password(
+                &Secret::new("correct-horse-battery-staple".to_string()),
+                &Secret::new("correct-horse-battery-stapler".to_string()),
+                "jane",
+            ),
+            Err(NewPasswordError::Mismatch)
+        );
+    }
+
+    #[test]
+    fn the_length_is_counted_in_graphemes() {
+        // 11 graphemes but many more bytes
+        assert_eq!(
+            validate("ñandú🦀🦀éàço", "jane"),
+            Err(NewPasswordError::TooShort)
+        );
+        assert_eq!(
+            validate(&"🦀".repeat(129), "jane"),
+            Err(NewPasswordError::TooLong)
+        );
+    }
+
+    #[test]
+    fn the_username_is_rejected() {
+        assert_eq!(
+            validate("Jane.Doe.Of.Zero2prod", "jane.doe.of.zero2prod"),
+            Err(NewPasswordError::SameAsUsername)
+        );
+    }
+
+    #[test]
+    fn predictable_passwords_are_too_weak() {
+        for password in [
+            "aaaaaaaaaaaaaaaa",
+            "abcdefghijklmnop",
+            "qwertyuiop123456",
+            "Password12345678",
+            "P@ssw0rdP@ssw0rd",
+            "iloveyouiloveyou",
+            "zero2prodnewsletter",
+        ] {
+            assert_eq!(
+                validate(password, "jane"),
+                Err(NewPasswordError::TooWeak),
+                "{}",
+                password
+            );
+        }
+    }
+
+    #[test]
+    fn the_username_makes_a_password_weaker() {
+        let username = "kowalczykowski";
+        let password = "kowalczykowski2022";
+
+        assert!(
+            estimate_entropy_bits(password, &[]) > estimate_entropy_bits(password, &[username])
+        );
+        assert_eq!(validate(password, username), Err(NewPasswordError::TooWeak));
+    }
+
+    #[test]
+    fn passphrases_and_random_passwords_are_accepted() {
+        for password in [
+            "correct-horse-battery-staple",
+            "my cat sleeps on the keyboard",
+            "xK9#mQ2$vL7!",
+            "7b0d1b4e-3f8a-4c5e-9a1f-5d2c8e6b4a90",
+        ] {
+            assert_ok!(validate(password, "jane"), "{}", password);
+        }
+    }
+
+    #[test]
+    fn breached_passwords_are_found_by_their_hash_prefix() {
+        assert!(is_breached("password"));
+        assert!(is_breached("Password123"));
+        assert!(is_breached("qwertyuiop!"));
+        assert!(!is_breached("correct-horse-battery-staple"));
+
+        assert_eq!(
+            validate("Password1234", "jane"),
+            Err(NewPasswordError::Breached)
+        );
+    }
+}
@@ -0,0 +1,66 @@
+use std::fmt;
+
+/// The role of an admin, ordered from the least to the most privileged.
+///
+/// * a viewer can look at everything in the admin area but can't change anything
+/// * an editor can also write, schedule and publish newsletter issues
+/// * an owner can also manage the other admins
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Viewer,
+    Editor,
+    Owner,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::Owner, Role::Editor, Role::Viewer];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Owner => "owner",
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self, anyhow::Error> {
+        match s {
+            "viewer" => Ok(Role::Viewer),
+            "editor" => Ok(Role::Editor),
+            "owner" => Ok(Role::Owner),
+            _ => Err(anyhow::anyhow!("invalid role {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Role;
+    use claim::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn roles_are_ordered_by_privilege() {
+        assert!(Role::Viewer < Role::Editor);
+        assert!(Role::Editor < Role::Owner);
+    }
+
+    #[test]
+    fn a_role_round_trips_through_its_string_representation() {
+        for role in Role::ALL {
+            assert_ok_eq!(Role::parse(role.as_str()), role);
+        }
+    }
+
+    #[test]
+    fn an_unknown_role_is_rejected() {
+        assert_err!(Role::parse("admin"));
+        assert_err!(Role::parse("Owner"));
+    }
+}
@@ -0,0 +1,9 @@
+use sha2::{Digest, Sha256};
+
+/// Hashes a random token before storing it or looking it up.
+///
+/// Only the hash of a token is stored, so that whoever reads the database can't use the tokens
+/// in it. The tokens are long random strings, a fast hash is enough.
+pub(crate) fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
@@ -0,0 +1,190 @@
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use secrecy::{ExposeSecret, Secret};
+use sha1::Sha1;
+use time::OffsetDateTime;
+
+/// Duration of a time step in seconds, the default of RFC 6238.
+const TIME_STEP: i64 = 30;
+/// Number of digits of a code.
+const DIGITS: u32 = 6;
+/// Number of time steps before and after the current one for which a code is still accepted,
+/// to account for clock drift and for the time it takes to type the code.
+const ALLOWED_DRIFT: i64 = 1;
+
+/// The shared secret of a TOTP (RFC 6238) second factor, using HMAC-SHA1 like every
+/// authenticator app expects.
+pub struct TotpSecret(Secret<Vec<u8>>);
+
+impl TotpSecret {
+    pub fn generate() -> Self {
+        let mut key = vec![0u8; 20];
+        rand::thread_rng().fill_bytes(&mut key);
+        Self(Secret::new(key))
+    }
+
+    pub fn from_base32(s: &str) -> Result<Self, anyhow::Error> {
+        let key = data_encoding::BASE32_NOPAD
+            .decode(s.as_bytes())
+            .context("Failed to decode the TOTP secret")?;
+        Ok(Self(Secret::new(key)))
+    }
+
+    pub fn to_base32(&self) -> String {
+        data_encoding::BASE32_NOPAD.encode(self.0.expose_secret())
+    }
+
+    /// Returns the `otpauth://` URI to give to an authenticator app, usually as a QR code.
+    pub fn otpauth_uri(&self, issuer: &str, account_name: &str) -> String {
+        let issuer = percent_encode(issuer);
+        format!(
+            "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+            issuer,
+            percent_encode(account_name),
+            self.to_base32(),
+            issuer,
+            DIGITS,
+            TIME_STEP
+        )
+    }
+
+    /// Returns the time step of the code if it is valid at `now`.
+    ///
+    /// Codes from `last_used_step` or earlier are rejected so that a code can't be replayed.
+    pub fn verify(
+        &self,
+        code: &str,
+        now: OffsetDateTime,
+        last_used_step: Option<i64>,
+    ) -> Option<i64> {
+        if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+
+        let current_step = now.unix_timestamp().div_euclid(TIME_STEP);
+
+        ((current_step - ALLOWED_DRIFT)..=(current_step + ALLOWED_DRIFT))
+            // None is lower than any Some, so every step is allowed if no code has been used yet
+            .filter(|step| last_used_step < Some(*step))
+            .find(|step| self.code_at_step(*step) == code)
+    }
+
+    /// Returns the code an authenticator app would show at `now`.
+    pub fn code_at(&self, now: OffsetDateTime) -> String {
+        self.code_at_step(now.unix_timestamp().div_euclid(TIME_STEP))
+    }
+
+    fn code_at_step(&self, step: i64) -> String {
+        let mut mac = Hmac::<Sha1>::new_from_slice(self.0.expose_secret()).unwrap();
+        mac.update(&step.to_be_bytes());
+        let digest = mac.finalize().into_bytes();
+
+        // Dynamic truncation, see section 5.3 of RFC 4226
+        let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([
+            digest[offset] & 0x7f,
+            digest[offset + 1],
+            digest[offset + 2],
+            digest[offset + 3],
+        ]);
+
+        format!(
+            "{:0width$}",
+            binary % 10u32.pow(DIGITS),
+            width = DIGITS as usize
+        )
+    }
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            result.push(b as char);
+        } else {
+            result.push_str(&format!("%{:02X}", b));
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TotpSecret;
+    use claim::{assert_none, assert_some_eq};
+    use secrecy::Secret;
+    use time::OffsetDateTime;
+
+    /// The SHA1 secret of the test vectors in appendix B of RFC 6238.
+    fn rfc_secret() -> TotpSecret {
+        TotpSecret(Secret::new(b"12345678901234567890".to_vec()))
+    }
+
+    fn at(timestamp: i64) -> OffsetDateTime {
+        OffsetDateTime::from_unix_timestamp(timestamp).unwrap()
+    }
+
+    #[test]
+    fn codes_match_the_rfc_test_vectors() {
+        // The RFC uses 8 digits codes, ours are the last 6 digits of those
+        let vectors = [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+        ];
+
+        let secret = rfc_secret();
+        for (timestamp, code) in vectors {
+            let step = timestamp / 30;
+            assert_some_eq!(secret.verify(code, at(timestamp), None), step);
+        }
+    }
+
+    #[test]
+    fn a_code_from_the_previous_or_next_time_step_is_accepted() {
+        let secret = rfc_secret();
+
+        assert_some_eq!(secret.verify("287082", at(59 + 30), None), 1);
+        assert_some_eq!(secret.verify("287082", at(59 - 30), None), 1);
+        assert_none!(secret.verify("287082", at(59 + 60), None));
+    }
+
+    #[test]
+    fn a_code_can_not_be_replayed() {
+        let secret = rfc_secret();
+
+        assert_none!(secret.verify("287082", at(59), Some(1)));
+        assert_none!(secret.verify("287082", at(59), Some(2)));
+    }
+
+    #[test]
+    fn malformed_codes_are_rejected() {
+        let secret = rfc_secret();
+
+        assert_none!(secret.verify("", at(59), None));
+        assert_none!(secret.verify("28708", at(59), None));
+        assert_none!(secret.verify("2870820", at(59), None));
+        assert_none!(secret.verify("28708a", at(59), None));
+    }
+
+    #[test]
+    fn the_secret_round_trips_through_base32() {
+        let secret = TotpSecret::generate();
+        let decoded = TotpSecret::from_base32(&secret.to_base32()).unwrap();
+        assert_eq!(decoded.to_base32(), secret.to_base32());
+    }
+
+    #[test]
+    fn the_otpauth_uri_contains_the_secret_and_the_encoded_label() {
+        let secret = rfc_secret();
+
+        let uri = secret.otpauth_uri("zero2prod", "jane doe");
+        assert_eq!(
+            uri,
+            "otpauth://totp/zero2prod:jane%20doe?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=zero2prod&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+}
@@ -0,0 +1,286 @@
+use crate::authentication::password::{compute_password_hash, verify_password_hash};
+use crate::authentication::PasswordHashing;
+use crate::authentication::TotpSecret;
+use crate::telemetry::spawn_blocking_with_tracing;
+use anyhow::Context;
+use rand::distributions::{Alphanumeric, DistString};
+use secrecy::{ExposeSecret, Secret};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// Number of recovery codes generated when enabling the second factor.
+const RECOVERY_CODES_COUNT: usize = 10;
+
+/// Returns the TOTP secret of the user, if the second factor is enabled.
+#[tracing::instrument(name = "Get TOTP secret", skip(pool))]
+pub async fn get_totp_secret(
+    pool: &sqlx::PgPool,
+    user_id: Uuid,
+) -> Result<Option<TotpSecret>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT totp_secret FROM users
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to perform a query to retrieve the TOTP secret")?;
+
+    row.totp_secret
+        .map(|secret| TotpSecret::from_base32(&secret))
+        .transpose()
+}
+
+/// Enables the second factor with this secret.
+///
+/// Returns the new recovery codes; they are only stored hashed so this is the only time they
+/// can be shown to the user.
+#[tracing::instrument(name = "Enable TOTP", skip(pool, hashing, secret))]
+pub async fn enable_totp(
+    pool: &sqlx::PgPool,
+    hashing: &PasswordHashing,
+    user_id: Uuid,
+    secret: &TotpSecret,
+) -> Result<Vec<Secret<String>>, anyhow::Error> {
+    let recovery_codes: Vec<Secret<String>> = (0..RECOVERY_CODES_COUNT)
+        .map(|_| Secret::new(generate_recovery_code()))
+        .collect();
+
+    let codes_to_hash = recovery_codes.clone();
+    let hashing = hashing.clone();
+    let recovery_code_hashes = spawn_blocking_with_tracing(move || {
+        codes_to_hash
+            .into_iter()
+            .map(|code| compute_password_hash(&hashing, code))
+            .collect::<Result<Vec<_>, _>>()
+    })
+    .await
+    .context("Failed to spawn blocking task")??;
+
+    let mut transaction = pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE users SET totp_secret = $1, totp_last_used_step = NULL
+        WHERE user_id = $2
+        "#,
+        secret.to_base32(),
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to store the TOTP secret")?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM user_recovery_codes
+        WHERE user_id = $1
+        "#,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete the previous recovery codes")?;
+
+    for code_hash in recovery_code_hashes {
+        sqlx::query!(
+            r#"
+            INSERT INTO user_recovery_codes(id, user_id, code_hash)
+            VALUES ($1, $2, $3)
+            "#,
+            Uuid::new_v4(),
+            user_id,
+            code_hash.expose_secret(),
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to store a recovery code")?;
+    }
+
+    transaction.commit().await?;
+
+    Ok(recovery_codes)
+}
+
+#[tracing::instrument(name = "Disable TOTP", skip(pool))]
+pub async fn disable_totp(pool: &sqlx::PgPool, user_id: Uuid) -> Result<(), anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE users SET totp_secret = NULL, totp_last_used_step = NULL
+        WHERE user_id = $1
+        "#,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to remove the TOTP secret")?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM user_recovery_codes
+        WHERE user_id = $1
+        "#,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete the recovery codes")?;
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Returns the number of recovery codes the user can still use.
+#[tracing::instrument(name = "Count unused recovery codes", skip(pool))]
+pub async fn count_unused_recovery_codes(
+    pool: &sqlx::PgPool,
+    user_id: Uuid,
+) -> Result<i64, anyhow::Error> {
+    let count = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM user_recovery_codes
+        WHERE user_id = $1 AND used_at IS NULL
+        "#,
+        user_id,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to count the unused recovery codes")?;
+
+    Ok(count)
+}
+
+/// Verifies a second factor, either a TOTP code or one of the recovery codes.
+///
+/// A code is consumed on success, it can't be used again.
+#[tracing::instrument(name = "Verify second factor", skip(pool, hashing, code))]
+pub async fn verify_second_factor(
+    pool: &sqlx::PgPool,
+    hashing: &PasswordHashing,
+    user_id: Uuid,
+    code: Secret<String>,
+) -> Result<bool, anyhow::Error> {
+    let code: String = code
+        .expose_secret()
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+
+    if code.bytes().all(|b| b.is_ascii_digit()) {
+        verify_totp_code(pool, user_id, &code).await
+    } else {
+        verify_recovery_code(pool, hashing, user_id, Secret::new(code.to_lowercase())).await
+    }
+}
+
+async fn verify_totp_code(
+    pool: &sqlx::PgPool,
+    user_id: Uuid,
+    code: &str,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+
+    // Lock the row so that the same code can't be accepted by two concurrent requests
+    let row = sqlx::query!(
+        r#"
+        SELECT totp_secret, totp_last_used_step FROM users
+        WHERE user_id = $1
+        FOR UPDATE
+        "#,
+        user_id,
+    )
+    .fetch_one(&mut transaction)
+    .await
+    .context("Failed to fetch the TOTP secret")?;
+
+    let secret = match row.totp_secret {
+        Some(secret) => TotpSecret::from_base32(&secret)?,
+        None => return Ok(false),
+    };
+
+    let step = match secret.verify(code, OffsetDateTime::now_utc(), row.totp_last_used_step) {
+        Some(step) => step,
+        None => return Ok(false),
+    };
+
+    sqlx::query!(
+        r#"
+        UPDATE users SET totp_last_used_step = $1
+        WHERE user_id = $2
+        "#,
+        step,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to store the last used TOTP step")?;
+
+    transaction.commit().await?;
+
+    Ok(true)
+}
+
+async fn verify_recovery_code(
+    pool: &sqlx::PgPool,
+    hashing: &PasswordHashing,
+    user_id: Uuid,
+    code: Secret<String>,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT id, code_hash FROM user_recovery_codes
+        WHERE user_id = $1 AND used_at IS NULL
+        FOR UPDATE
+        "#,
+        user_id,
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("Failed to fetch the recovery codes")?;
+
+    let hashing = hashing.clone();
+    let matching_id = spawn_blocking_with_tracing(move || {
+        rows.into_iter()
+            .find(|row| {
+                verify_password_hash(&hashing, Secret::new(row.code_hash.clone()), code.clone())
+                    .is_ok()
+            })
+            .map(|row| row.id)
+    })
+    .await
+    .context("Failed to spawn blocking task")?;
+
+    let id = match matching_id {
+        Some(id) => id,
+        None => return Ok(false),
+    };
+
+    sqlx::query!(
+        r#"
+        UPDATE user_recovery_codes SET used_at = now()
+        WHERE id = $1
+        "#,
+        id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to mark the recovery code as used")?;
+
+    transaction.commit().await?;
+
+    Ok(true)
+}
+
+/// Generates a recovery code like `k3x9a-0pq2m`.
+fn generate_recovery_code() -> String {
+    let mut rng = rand::thread_rng();
+    let code = Alphanumeric.sample_string(&mut rng, 10).to_lowercase();
+    format!("{}-{}", &code[..5], &code[5..])
+}
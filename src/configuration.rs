@@ -5,8 +5,26 @@ use secrecy::{ExposeSecret, Secret};
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSetttings,
-    pub tem: TEMSettings,
+    pub email: EmailSettings,
     pub session: SessionSettings,
+    pub worker: WorkerSettings,
+    pub login_protection: LoginProtectionSettings,
+    pub password_hashing: PasswordHashingSettings,
+    pub subscriptions: SubscriptionSettings,
+}
+
+impl Settings {
+    /// Checks what can't be expressed by the types of the settings.
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        self.email.validate()?;
+        self.session.validate()?;
+        self.worker.validate()?;
+        self.login_protection.validate()?;
+        self.password_hashing.validate()?;
+        self.subscriptions.validate()?;
+
+        Ok(())
+    }
 }
 
 #[derive(Clone, serde::Deserialize)]
@@ -15,6 +33,14 @@ pub struct ApplicationSetttings {
     pub port: u16,
     pub base_url: String,
     pub hmac_secret: Secret<String>,
+    pub shutdown_timeout_seconds: u64,
+}
+
+impl ApplicationSetttings {
+    /// How long in-flight requests are given to complete when shutting down.
+    pub fn shutdown_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.shutdown_timeout_seconds)
+    }
 }
 
 #[derive(Clone, serde::Deserialize)]
@@ -39,45 +65,364 @@ impl DatabaseSettings {
     }
 }
 
+#[derive(Clone, Copy, Debug, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionBackend {
+    Postgres,
+    Memory,
+    Redis,
+}
+
 #[derive(Clone, serde::Deserialize)]
 pub struct SessionSettings {
+    pub backend: SessionBackend,
     pub cleanup_enabled: bool,
     pub cleanup_interval_milliseconds: i64,
-    pub ttl: i64,
+    /// Sessions unused for this long are closed.
+    pub idle_timeout_seconds: i64,
+    /// Sessions are closed this long after the login, even if they are in use.
+    pub max_lifetime_seconds: i64,
+    /// How often the last time a session has been seen is updated. The idle timeout is only
+    /// as precise as this, which avoids a write on every request.
+    pub touch_interval_seconds: i64,
+    pub redis: Option<RedisSettings>,
 }
 
 impl SessionSettings {
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        if self.idle_timeout_seconds <= 0 || self.max_lifetime_seconds <= 0 {
+            anyhow::bail!("the session idle timeout and max lifetime must be positive");
+        }
+        if self.idle_timeout_seconds > self.max_lifetime_seconds {
+            anyhow::bail!("the session idle timeout is greater than the max lifetime");
+        }
+        if self.touch_interval_seconds < 0
+            || self.touch_interval_seconds >= self.idle_timeout_seconds
+        {
+            anyhow::bail!(
+                "the session touch interval must be positive and lower than the idle timeout"
+            );
+        }
+        if matches!(self.backend, SessionBackend::Redis) && self.redis.is_none() {
+            anyhow::bail!("the redis session backend is selected but its settings are missing");
+        }
+
+        Ok(())
+    }
+
     pub fn cleanup_interval(&self) -> time::Duration {
         time::Duration::milliseconds(self.cleanup_interval_milliseconds)
     }
-    pub fn ttl(&self) -> time::Duration {
-        time::Duration::milliseconds(self.ttl)
+
+    pub fn limits(&self) -> crate::sessions::SessionLimits {
+        crate::sessions::SessionLimits {
+            idle_timeout: time::Duration::seconds(self.idle_timeout_seconds),
+            max_lifetime: time::Duration::seconds(self.max_lifetime_seconds),
+            touch_interval: time::Duration::seconds(self.touch_interval_seconds),
+        }
     }
 }
 
 #[derive(Clone, serde::Deserialize)]
-pub struct TEMSettings {
-    pub base_url: String,
-    pub auth_key: Secret<String>,
-    pub project_id: String,
+pub struct RedisSettings {
+    pub host: String,
+    pub port: u16,
+    pub password: Option<Secret<String>>,
+    pub database: u32,
+    pub timeout_milliseconds: u64,
+}
+
+impl RedisSettings {
+    pub fn connection_info(&self) -> redis::ConnectionInfo {
+        redis::ConnectionInfo {
+            addr: redis::ConnectionAddr::Tcp(self.host.clone(), self.port),
+            redis: redis::RedisConnectionInfo {
+                db: self.database as i64,
+                username: None,
+                password: self
+                    .password
+                    .as_ref()
+                    .map(|password| password.expose_secret().clone()),
+            },
+        }
+    }
+
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct WorkerSettings {
+    pub max_retries: i32,
+    pub retry_base_delay_milliseconds: u64,
+    pub retry_max_delay_milliseconds: u64,
+    /// Number of tasks consuming the delivery queue concurrently.
+    pub concurrency: usize,
+    /// Emails sent per second across all the consumers, unlimited if unset.
+    pub max_emails_per_second: Option<f64>,
+    pub rate_limit_burst: u32,
+    pub poll_min_interval_milliseconds: u64,
+    pub poll_max_interval_milliseconds: u64,
+}
+
+impl WorkerSettings {
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        if self.concurrency == 0 {
+            anyhow::bail!("the worker concurrency must be at least 1");
+        }
+        if matches!(self.max_emails_per_second, Some(rate) if !crate::token_bucket::is_valid_rate(rate))
+        {
+            anyhow::bail!("the worker max emails per second must be a finite positive number");
+        }
+        if self.poll_min_interval_milliseconds > self.poll_max_interval_milliseconds {
+            anyhow::bail!("the worker min poll interval is greater than the max poll interval");
+        }
+
+        Ok(())
+    }
+
+    pub fn retry_policy(&self) -> crate::issue_delivery_worker::RetryPolicy {
+        crate::issue_delivery_worker::RetryPolicy::new(
+            self.max_retries,
+            std::time::Duration::from_millis(self.retry_base_delay_milliseconds),
+            std::time::Duration::from_millis(self.retry_max_delay_milliseconds),
+        )
+    }
+
+    pub fn rate_limiter(&self) -> Result<crate::token_bucket::RateLimiter, anyhow::Error> {
+        match self.max_emails_per_second {
+            Some(rate) => crate::token_bucket::RateLimiter::new(rate, self.rate_limit_burst),
+            None => Ok(crate::token_bucket::RateLimiter::unlimited()),
+        }
+    }
+
+    pub fn poll_interval(&self) -> crate::issue_delivery_worker::PollInterval {
+        crate::issue_delivery_worker::PollInterval::new(
+            std::time::Duration::from_millis(self.poll_min_interval_milliseconds),
+            std::time::Duration::from_millis(self.poll_max_interval_milliseconds),
+        )
+    }
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct LoginProtectionSettings {
+    pub username: LoginCounterSettings,
+    pub client_ip: LoginCounterSettings,
+    pub base_delay_milliseconds: u64,
+    pub max_delay_milliseconds: u64,
+    pub lockout_duration_seconds: u64,
+    /// Failed attempts older than this are forgotten.
+    pub failure_window_seconds: u64,
+    /// Take the client IP address from the `Forwarded` or `X-Forwarded-For` headers.
+    /// Only enable this behind a reverse proxy which sets them.
+    pub trust_forwarded_for: bool,
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct LoginCounterSettings {
+    /// Failed attempts allowed before the next attempts are delayed.
+    pub free_attempts: i32,
+    /// Failed attempts after which further attempts are locked out.
+    pub lockout_threshold: i32,
+}
+
+impl LoginCounterSettings {
+    fn limits(&self) -> crate::authentication::CounterLimits {
+        crate::authentication::CounterLimits {
+            free_attempts: self.free_attempts,
+            lockout_threshold: self.lockout_threshold,
+        }
+    }
+}
+
+impl LoginProtectionSettings {
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        for (name, counter) in [("username", &self.username), ("client_ip", &self.client_ip)] {
+            if counter.free_attempts < 0 || counter.free_attempts >= counter.lockout_threshold {
+                anyhow::bail!(
+                    "the {} login free attempts must be positive and lower than the lockout threshold",
+                    name
+                );
+            }
+        }
+        if self.base_delay_milliseconds > self.max_delay_milliseconds {
+            anyhow::bail!("the login base delay is greater than the max delay");
+        }
+
+        Ok(())
+    }
+
+    pub fn policy(&self) -> crate::authentication::LoginProtectionPolicy {
+        crate::authentication::LoginProtectionPolicy {
+            username: self.username.limits(),
+            client_ip: self.client_ip.limits(),
+            base_delay: time::Duration::milliseconds(self.base_delay_milliseconds as i64),
+            max_delay: time::Duration::milliseconds(self.max_delay_milliseconds as i64),
+            lockout_duration: time::Duration::seconds(self.lockout_duration_seconds as i64),
+            failure_window: time::Duration::seconds(self.failure_window_seconds as i64),
+            trust_forwarded_for: self.trust_forwarded_for,
+        }
+    }
+}
+
+/// The Argon2id cost parameters of new password hashes.
+///
+/// Stored hashes with weaker parameters are upgraded the next time their user logs in.
+#[derive(Clone, serde::Deserialize)]
+pub struct PasswordHashingSettings {
+    pub memory_cost_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+    /// Mixed into every new hash but not stored in the database. Once set, it can't be changed
+    /// without invalidating the passwords hashed with it.
+    pub pepper: Option<Secret<String>>,
+}
+
+impl PasswordHashingSettings {
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        argon2::Params::new(
+            self.memory_cost_kib,
+            self.iterations,
+            self.parallelism,
+            None,
+        )
+        .map_err(|err| anyhow::anyhow!("invalid password hashing parameters: {}", err))?;
+
+        Ok(())
+    }
+
+    pub fn hashing(&self) -> Result<crate::authentication::PasswordHashing, anyhow::Error> {
+        crate::authentication::PasswordHashing::new(
+            self.memory_cost_kib,
+            self.iterations,
+            self.parallelism,
+            self.pepper.clone(),
+        )
+    }
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct SubscriptionSettings {
+    /// Signing up again with a pending address resends a confirmation email at most this often.
+    pub confirmation_resend_interval_seconds: i64,
+    /// How long a confirmation link is valid. Pending subscribers who haven't been sent a link
+    /// for this long are purged.
+    pub confirmation_window_seconds: i64,
+    pub purge_interval_seconds: u64,
+}
+
+impl SubscriptionSettings {
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        if self.confirmation_resend_interval_seconds < 0 {
+            anyhow::bail!("the confirmation resend interval can't be negative");
+        }
+        if self.confirmation_window_seconds <= 0 {
+            anyhow::bail!("the confirmation window must be positive");
+        }
+        if self.purge_interval_seconds == 0 {
+            anyhow::bail!("the purge interval of pending subscribers must be positive");
+        }
+
+        Ok(())
+    }
+
+    pub fn policy(&self) -> crate::routes::SubscriptionPolicy {
+        crate::routes::SubscriptionPolicy {
+            confirmation_resend_interval: time::Duration::seconds(
+                self.confirmation_resend_interval_seconds,
+            ),
+            confirmation_window: time::Duration::seconds(self.confirmation_window_seconds),
+        }
+    }
+
+    pub fn purge_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.purge_interval_seconds)
+    }
+}
+
+#[derive(Clone, Copy, Debug, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailBackend {
+    Tem,
+    Smtp,
+    Spool,
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct EmailSettings {
+    pub backend: EmailBackend,
     pub sender_email: String,
     pub timeout_milliseconds: u64,
+    pub tem: Option<TEMSettings>,
+    pub smtp: Option<SMTPSettings>,
+    pub spool: Option<SpoolSettings>,
 }
 
-impl TEMSettings {
+impl EmailSettings {
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        self.sender().map_err(anyhow::Error::msg)?;
+
+        let has_backend_settings = match self.backend {
+            EmailBackend::Tem => self.tem.is_some(),
+            EmailBackend::Smtp => self.smtp.is_some(),
+            EmailBackend::Spool => self.spool.is_some(),
+        };
+        if !has_backend_settings {
+            anyhow::bail!(
+                "the {:?} email backend is selected but its settings are missing",
+                self.backend
+            );
+        }
+
+        Ok(())
+    }
+
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
 
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct TEMSettings {
+    pub base_url: String,
+    pub auth_key: Secret<String>,
+    pub project_id: String,
+}
+
+impl TEMSettings {
     pub fn project_id(&self) -> crate::tem::ProjectId {
         crate::tem::ProjectId::new(self.project_id.clone())
     }
+}
 
-    pub fn timeout(&self) -> std::time::Duration {
-        std::time::Duration::from_millis(self.timeout_milliseconds)
+#[derive(Clone, serde::Deserialize)]
+pub struct SMTPSettings {
+    pub host: String,
+    pub port: u16,
+    pub security: crate::smtp::Security,
+    pub username: Option<String>,
+    pub password: Option<Secret<String>>,
+}
+
+impl SMTPSettings {
+    pub fn credentials(&self) -> Option<(String, Secret<String>)> {
+        match (&self.username, &self.password) {
+            (Some(username), Some(password)) => Some((username.clone(), password.clone())),
+            _ => None,
+        }
     }
 }
 
+#[derive(Clone, serde::Deserialize)]
+pub struct SpoolSettings {
+    pub path: std::path::PathBuf,
+}
+
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
     let settings = config::Config::builder()
         .add_source(
@@ -95,3 +440,86 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
 
     settings.try_deserialize::<Settings>()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{get_configuration, EmailBackend, SessionBackend};
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn the_default_configuration_is_valid() {
+        let configuration = get_configuration().unwrap();
+
+        assert_ok!(configuration.validate());
+    }
+
+    #[test]
+    fn an_email_backend_without_its_settings_is_rejected() {
+        let mut configuration = get_configuration().unwrap();
+        configuration.email.backend = EmailBackend::Smtp;
+        configuration.email.smtp = None;
+
+        assert_err!(configuration.validate());
+    }
+
+    #[test]
+    fn an_invalid_sender_email_is_rejected() {
+        let mut configuration = get_configuration().unwrap();
+        configuration.email.sender_email = "vincent".to_string();
+
+        assert_err!(configuration.validate());
+    }
+
+    #[test]
+    fn invalid_worker_settings_are_rejected() {
+        let mut configuration = get_configuration().unwrap();
+        configuration.worker.concurrency = 0;
+        assert_err!(configuration.validate());
+
+        let mut configuration = get_configuration().unwrap();
+        configuration.worker.max_emails_per_second = Some(0.0);
+        assert_err!(configuration.validate());
+
+        let mut configuration = get_configuration().unwrap();
+        configuration.worker.max_emails_per_second = Some(f64::NAN);
+        assert_err!(configuration.validate());
+
+        let mut configuration = get_configuration().unwrap();
+        configuration.worker.max_emails_per_second = Some(f64::INFINITY);
+        assert_err!(configuration.validate());
+    }
+
+    #[test]
+    fn a_login_lockout_threshold_lower_than_the_free_attempts_is_rejected() {
+        let mut configuration = get_configuration().unwrap();
+        configuration.login_protection.username.lockout_threshold =
+            configuration.login_protection.username.free_attempts;
+
+        assert_err!(configuration.validate());
+    }
+
+    #[test]
+    fn an_idle_timeout_longer_than_the_session_lifetime_is_rejected() {
+        let mut configuration = get_configuration().unwrap();
+        configuration.session.idle_timeout_seconds = configuration.session.max_lifetime_seconds + 1;
+
+        assert_err!(configuration.validate());
+    }
+
+    #[test]
+    fn a_redis_session_backend_without_its_settings_is_rejected() {
+        let mut configuration = get_configuration().unwrap();
+        configuration.session.backend = SessionBackend::Redis;
+        configuration.session.redis = None;
+
+        assert_err!(configuration.validate());
+    }
+
+    #[test]
+    fn invalid_password_hashing_parameters_are_rejected() {
+        let mut configuration = get_configuration().unwrap();
+        configuration.password_hashing.parallelism = 0;
+
+        assert_err!(configuration.validate());
+    }
+}
@@ -0,0 +1,204 @@
+use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName, SubscriberStatus};
+use crate::email_client::EmailSender;
+use crate::issue_delivery_worker::{ExecutionOutcome, RetryPolicy};
+use crate::startup::ApplicationBaseUrl;
+use crate::token_bucket::RateLimiter;
+use askama::Template;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, event, info, warn, Level};
+use uuid::Uuid;
+
+#[derive(askama::Template)]
+#[template(path = "html_content.html")]
+struct HtmlContentTemplate<'a> {
+    confirmation_link: &'a str,
+}
+
+#[derive(askama::Template)]
+#[template(path = "text_content.txt")]
+struct TextContentTemplate<'a> {
+    confirmation_link: &'a str,
+}
+
+/// Enqueues a confirmation email with a new token for a pending subscriber, replacing the one
+/// still waiting to be sent to them if any.
+#[tracing::instrument(skip(transaction, subscription_token))]
+pub(crate) async fn enqueue_confirmation_email(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    subscriber_id: Uuid,
+    subscription_token: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO confirmation_email_queue(subscriber_id, subscription_token, n_retries, execute_after)
+        VALUES ($1, $2, 0, now())
+        ON CONFLICT (subscriber_id) DO UPDATE
+        SET subscription_token = EXCLUDED.subscription_token,
+            n_retries = EXCLUDED.n_retries,
+            execute_after = EXCLUDED.execute_after
+        "#,
+        subscriber_id,
+        subscription_token,
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(())
+}
+
+/// Sends one of the enqueued confirmation emails, if any.
+///
+/// Subscribers who have been confirmed in the meantime are skipped, failed emails are retried
+/// like the deliveries of the issues.
+#[tracing::instrument(skip_all, level = "debug")]
+pub async fn try_send_confirmation_email(
+    pool: &sqlx::PgPool,
+    email_client: &dyn EmailSender,
+    retry_policy: &RetryPolicy,
+    rate_limiter: &RateLimiter,
+    shutdown: &CancellationToken,
+    base_url: &ApplicationBaseUrl,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let task = sqlx::query!(
+        r#"
+        SELECT q.subscriber_id, q.subscription_token, q.n_retries, s.email, s.name, s.status
+        FROM confirmation_email_queue q
+        INNER JOIN subscriptions s ON s.id = q.subscriber_id
+        WHERE q.execute_after <= now()
+        FOR UPDATE OF q
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+
+    let task = match task {
+        Some(task) => task,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+
+    if task.status == SubscriberStatus::Confirmed.as_str() {
+        info!("Skipping a subscriber who has been confirmed in the meantime");
+        return delete_task(transaction, task.subscriber_id).await;
+    }
+
+    let subscriber = match parse_subscriber(task.email, task.name) {
+        Ok(subscriber) => subscriber,
+        Err(err) => {
+            error!(
+                error.cause_chain = ?err,
+                error.message = %err,
+                "Skipping a pending subscriber, their stored contact details are invalid",
+            );
+            return delete_task(transaction, task.subscriber_id).await;
+        }
+    };
+
+    if !rate_limiter.acquire_unless_cancelled(shutdown).await {
+        return Ok(ExecutionOutcome::Cancelled);
+    }
+
+    let send_result =
+        send_confirmation_email(base_url, email_client, subscriber, &task.subscription_token).await;
+
+    match send_result {
+        Ok(()) => {}
+        Err(err) if task.n_retries >= retry_policy.max_retries() => {
+            error!(
+                error.cause_chain = ?err,
+                error.message = %err,
+                n_retries = task.n_retries,
+                "Failed to send a confirmation email, giving up",
+            );
+        }
+        Err(err) => {
+            let delay = retry_policy.backoff(task.n_retries);
+
+            warn!(
+                error.cause_chain = ?err,
+                error.message = %err,
+                n_retries = task.n_retries,
+                retry_in = ?delay,
+                "Failed to send a confirmation email, retrying later",
+            );
+
+            sqlx::query!(
+                r#"
+                UPDATE confirmation_email_queue
+                SET n_retries = n_retries + 1, execute_after = $2
+                WHERE subscriber_id = $1
+                "#,
+                task.subscriber_id,
+                time::OffsetDateTime::now_utc() + delay,
+            )
+            .execute(&mut transaction)
+            .await?;
+            transaction.commit().await?;
+
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    }
+
+    delete_task(transaction, task.subscriber_id).await
+}
+
+fn parse_subscriber(email: String, name: String) -> Result<NewSubscriber, anyhow::Error> {
+    Ok(NewSubscriber {
+        email: SubscriberEmail::parse(email).map_err(anyhow::Error::msg)?,
+        name: SubscriberName::parse(name).map_err(anyhow::Error::msg)?,
+    })
+}
+
+#[tracing::instrument(skip(transaction))]
+async fn delete_task(
+    mut transaction: sqlx::Transaction<'static, sqlx::Postgres>,
+    subscriber_id: Uuid,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    sqlx::query!(
+        r#"DELETE FROM confirmation_email_queue WHERE subscriber_id = $1"#,
+        subscriber_id,
+    )
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+#[tracing::instrument(name = "Send confirmation email", skip(base_url, email_client))]
+async fn send_confirmation_email(
+    base_url: &ApplicationBaseUrl,
+    email_client: &dyn EmailSender,
+    new_subscriber: NewSubscriber,
+    subscription_token: &str,
+) -> Result<(), anyhow::Error> {
+    let confirmation_link = format!(
+        "{}/subscriptions/confirm?subscription_token={}",
+        base_url.0, subscription_token
+    );
+
+    event!(Level::INFO, confirmation_link, "computed confirmation link");
+
+    let html_content = HtmlContentTemplate {
+        confirmation_link: &confirmation_link,
+    };
+
+    let text_content = TextContentTemplate {
+        confirmation_link: &confirmation_link,
+    };
+
+    email_client
+        .send_email(
+            &new_subscriber.email,
+            "Welcome!",
+            &html_content.render().unwrap(),
+            &text_content.render().unwrap(),
+            None,
+        )
+        .await?;
+
+    Ok(())
+}
@@ -8,6 +8,38 @@ pub struct NewSubscriber {
     pub name: SubscriberName,
 }
 
+/// The state of a subscriber, stored in the `status` column of `subscriptions`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriberStatus {
+    PendingConfirmation,
+    Confirmed,
+    Unsubscribed,
+}
+
+impl SubscriberStatus {
+    pub const ALL: [SubscriberStatus; 3] = [
+        SubscriberStatus::PendingConfirmation,
+        SubscriberStatus::Confirmed,
+        SubscriberStatus::Unsubscribed,
+    ];
+
+    pub fn parse(s: &str) -> Result<SubscriberStatus, String> {
+        Self::ALL
+            .into_iter()
+            .find(|status| status.as_str() == s)
+            .ok_or_else(|| format!("{} is not a valid subscriber status", s))
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriberStatus::PendingConfirmation => "pending_confirmation",
+            SubscriberStatus::Confirmed => "confirmed",
+            SubscriberStatus::Unsubscribed => "unsubscribed",
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct SubscriberName(String);
 
@@ -104,6 +136,20 @@ mod tests {
         assert_ok!(SubscriberName::parse(name));
     }
 
+    // Status tests
+
+    #[test]
+    fn every_status_is_parsed_back() {
+        for status in SubscriberStatus::ALL {
+            assert_eq!(SubscriberStatus::parse(status.as_str()), Ok(status));
+        }
+    }
+
+    #[test]
+    fn an_unknown_status_is_rejected() {
+        assert_err!(SubscriberStatus::parse("deleted"));
+    }
+
     // Email tests
 
     #[test]
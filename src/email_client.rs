@@ -0,0 +1,93 @@
+use crate::domain::SubscriberEmail;
+use lettre::message::header::{Header, HeaderName, HeaderValue};
+use lettre::message::{Mailbox, MultiPart};
+use lettre::Message;
+
+#[async_trait::async_trait]
+pub trait EmailSender: Send + Sync {
+    /// Sends an email to `recipient`.
+    ///
+    /// If `unsubscribe_link` is set the email carries the RFC 8058 one-click unsubscribe headers.
+    ///
+    /// Returns the response of the email provider, which is kept in the delivery log.
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        unsubscribe_link: Option<&str>,
+    ) -> Result<String, anyhow::Error>;
+}
+
+pub(crate) const SENDER_NAME: &str = "Vincent";
+
+pub(crate) const LIST_UNSUBSCRIBE: &str = "List-Unsubscribe";
+pub(crate) const LIST_UNSUBSCRIBE_POST: &str = "List-Unsubscribe-Post";
+pub(crate) const LIST_UNSUBSCRIBE_POST_VALUE: &str = "List-Unsubscribe=One-Click";
+
+/// Returns the value of the `List-Unsubscribe` header for this link.
+pub(crate) fn list_unsubscribe_value(unsubscribe_link: &str) -> String {
+    format!("<{}>", unsubscribe_link)
+}
+
+#[derive(Clone)]
+struct ListUnsubscribe(String);
+
+impl Header for ListUnsubscribe {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str(LIST_UNSUBSCRIBE)
+    }
+
+    fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(Self(s.to_string()))
+    }
+
+    fn display(&self) -> HeaderValue {
+        HeaderValue::new(Self::name(), self.0.clone())
+    }
+}
+
+#[derive(Clone)]
+struct ListUnsubscribePost;
+
+impl Header for ListUnsubscribePost {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str(LIST_UNSUBSCRIBE_POST)
+    }
+
+    fn parse(_s: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(Self)
+    }
+
+    fn display(&self) -> HeaderValue {
+        HeaderValue::new(Self::name(), LIST_UNSUBSCRIBE_POST_VALUE.to_string())
+    }
+}
+
+/// Builds a multipart/alternative message suitable for the lettre based senders.
+pub(crate) fn build_message(
+    sender: &SubscriberEmail,
+    recipient: &SubscriberEmail,
+    subject: &str,
+    html_content: &str,
+    text_content: &str,
+    unsubscribe_link: Option<&str>,
+) -> Result<Message, anyhow::Error> {
+    let from = Mailbox::new(Some(SENDER_NAME.to_string()), sender.as_ref().parse()?);
+    let to = Mailbox::new(None, recipient.as_ref().parse()?);
+
+    let mut builder = Message::builder().from(from).to(to).subject(subject);
+    if let Some(unsubscribe_link) = unsubscribe_link {
+        builder = builder
+            .header(ListUnsubscribe(list_unsubscribe_value(unsubscribe_link)))
+            .header(ListUnsubscribePost);
+    }
+
+    let message = builder.multipart(MultiPart::alternative_plain_html(
+        text_content.to_string(),
+        html_content.to_string(),
+    ))?;
+
+    Ok(message)
+}
@@ -137,6 +137,7 @@ pub async fn save_response(
     Ok(new_http_response)
 }
 
+#[allow(clippy::large_enum_variant)]
 pub enum NextAction {
     StartProcessing(sqlx::Transaction<'static, sqlx::Postgres>),
     ReturnSavedResponse(HttpResponse),
@@ -0,0 +1,221 @@
+use crate::authentication::{hash_token, Role};
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailSender;
+use crate::issue_delivery_worker::{ExecutionOutcome, RetryPolicy};
+use crate::startup::ApplicationBaseUrl;
+use crate::token_bucket::RateLimiter;
+use anyhow::Context;
+use askama::Template;
+use rand::distributions::{Alphanumeric, DistString};
+use time::OffsetDateTime;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+/// How long an invitation can be accepted after it has been sent.
+const INVITATION_VALIDITY: time::Duration = time::Duration::days(7);
+
+#[derive(askama::Template)]
+#[template(path = "invitation_email.html")]
+struct InvitationHtmlTemplate<'a> {
+    invitation_link: &'a str,
+    role: Role,
+}
+
+#[derive(askama::Template)]
+#[template(path = "invitation_email.txt")]
+struct InvitationTextTemplate<'a> {
+    invitation_link: &'a str,
+    role: Role,
+}
+
+/// Enqueues an invitation, to be emailed by the worker.
+#[tracing::instrument(skip(pool, email))]
+pub(crate) async fn enqueue_invitation(
+    pool: &sqlx::PgPool,
+    email: &SubscriberEmail,
+    role: Role,
+    invited_by: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO user_invitation_queue(id, email, role, invited_by, n_retries, execute_after)
+        VALUES ($1, $2, $3, $4, 0, now())
+        "#,
+        Uuid::new_v4(),
+        email.as_ref(),
+        role.as_str(),
+        invited_by,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to enqueue the invitation")?;
+
+    Ok(())
+}
+
+/// Sends one of the enqueued invitations, if any.
+///
+/// The invitation is only stored once its email has been sent, failed emails are retried like
+/// the deliveries of the issues.
+#[tracing::instrument(skip_all, level = "debug")]
+pub async fn try_send_invitation(
+    pool: &sqlx::PgPool,
+    email_client: &dyn EmailSender,
+    retry_policy: &RetryPolicy,
+    rate_limiter: &RateLimiter,
+    shutdown: &CancellationToken,
+    base_url: &ApplicationBaseUrl,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let task = sqlx::query!(
+        r#"
+        SELECT id, email, role, invited_by, n_retries
+        FROM user_invitation_queue
+        WHERE execute_after <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+
+    let task = match task {
+        Some(task) => task,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+
+    let role = Role::parse(&task.role)?;
+    let invitation_token = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+
+    if !rate_limiter.acquire_unless_cancelled(shutdown).await {
+        return Ok(ExecutionOutcome::Cancelled);
+    }
+
+    let send_result =
+        send_invitation_link(email_client, base_url, &task.email, role, &invitation_token).await;
+
+    match send_result {
+        Ok(()) => {
+            store_invitation(
+                &mut transaction,
+                &invitation_token,
+                &task.email,
+                role,
+                task.invited_by,
+            )
+            .await?;
+        }
+        Err(err) if task.n_retries >= retry_policy.max_retries() => {
+            error!(
+                error.cause_chain = ?err,
+                error.message = %err,
+                n_retries = task.n_retries,
+                "Failed to send an invitation email, giving up",
+            );
+        }
+        Err(err) => {
+            let delay = retry_policy.backoff(task.n_retries);
+
+            warn!(
+                error.cause_chain = ?err,
+                error.message = %err,
+                n_retries = task.n_retries,
+                retry_in = ?delay,
+                "Failed to send an invitation email, retrying later",
+            );
+
+            sqlx::query!(
+                r#"
+                UPDATE user_invitation_queue
+                SET n_retries = n_retries + 1, execute_after = $2
+                WHERE id = $1
+                "#,
+                task.id,
+                OffsetDateTime::now_utc() + delay,
+            )
+            .execute(&mut transaction)
+            .await?;
+            transaction.commit().await?;
+
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    }
+
+    sqlx::query!(
+        r#"DELETE FROM user_invitation_queue WHERE id = $1"#,
+        task.id
+    )
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+async fn send_invitation_link(
+    email_client: &dyn EmailSender,
+    base_url: &ApplicationBaseUrl,
+    email: &str,
+    role: Role,
+    invitation_token: &str,
+) -> Result<(), anyhow::Error> {
+    let email = SubscriberEmail::parse(email.to_string()).map_err(anyhow::Error::msg)?;
+
+    let invitation_link = format!(
+        "{}/invitations/accept?invitation_token={}",
+        base_url.0, invitation_token
+    );
+    let html_content = InvitationHtmlTemplate {
+        invitation_link: &invitation_link,
+        role,
+    };
+    let text_content = InvitationTextTemplate {
+        invitation_link: &invitation_link,
+        role,
+    };
+
+    email_client
+        .send_email(
+            &email,
+            "You have been invited to administer the newsletter",
+            &html_content.render().unwrap(),
+            &text_content.render().unwrap(),
+            None,
+        )
+        .await
+        .context("Failed to send the invitation email")?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip(transaction, invitation_token, email))]
+async fn store_invitation(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    invitation_token: &str,
+    email: &str,
+    role: Role,
+    invited_by: Uuid,
+) -> Result<(), anyhow::Error> {
+    let now = OffsetDateTime::now_utc();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO user_invitations(token_hash, email, role, invited_by, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        hash_token(invitation_token),
+        email,
+        role.as_str(),
+        invited_by,
+        now,
+        now + INVITATION_VALIDITY,
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to store the invitation")?;
+
+    Ok(())
+}
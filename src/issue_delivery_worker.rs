@@ -1,12 +1,66 @@
+use crate::configuration::WorkerSettings;
+use crate::confirmation_email_queue::try_send_confirmation_email;
 use crate::domain::SubscriberEmail;
-use crate::tem;
+use crate::email_client::EmailSender;
+use crate::invitation_queue::try_send_invitation;
+use crate::issue_template::{self, SubscriberVariables};
+use crate::password_reset_queue::try_send_password_reset;
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use crate::token_bucket::RateLimiter;
+use crate::unsubscribe::UnsubscribeToken;
+use anyhow::Context;
+use askama::Template;
+use rand::Rng;
+use std::sync::Arc;
 use std::time::Duration;
-use tracing::error;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
 pub enum ExecutionOutcome {
     TaskCompleted,
     EmptyQueue,
+    /// The worker was stopped while waiting for the rate limiter, the task is left in the queue.
+    Cancelled,
+}
+
+/// Controls how failed deliveries are retried.
+///
+/// The delay before the n-th retry is `base_delay * 2^n`, capped to `max_delay`, with
+/// a random jitter of up to half of that delay.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    max_retries: i32,
+    base_delay: Duration,
+    max_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: i32, base_delay: Duration, max_delay: Duration) -> Self {
+        Self {
+            max_retries,
+            base_delay,
+            max_delay,
+        }
+    }
+
+    /// Failed tasks which have been retried this many times are given up.
+    pub(crate) fn max_retries(&self) -> i32 {
+        self.max_retries
+    }
+
+    pub(crate) fn backoff(&self, n_retries: i32) -> Duration {
+        let exponent = n_retries.clamp(0, 31) as u32;
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(exponent))
+            .min(self.max_delay);
+
+        let half = delay / 2;
+        let jitter = rand::thread_rng().gen_range(0..=half.as_millis() as u64);
+
+        half + Duration::from_millis(jitter)
+    }
 }
 
 #[tracing::instrument(
@@ -19,34 +73,135 @@ pub enum ExecutionOutcome {
 )]
 pub async fn try_execute_task(
     pool: &sqlx::PgPool,
-    email_client: &tem::Client,
+    email_client: &dyn EmailSender,
+    retry_policy: &RetryPolicy,
+    rate_limiter: &RateLimiter,
+    shutdown: &CancellationToken,
+    base_url: &ApplicationBaseUrl,
+    hmac_secret: &HmacSecret,
 ) -> Result<ExecutionOutcome, anyhow::Error> {
     let result = dequeue_task(pool).await?;
     if result.is_none() {
         return Ok(ExecutionOutcome::EmptyQueue);
     }
 
-    let (transaction, issue_id, email) = result.unwrap();
+    let (mut transaction, task) = result.unwrap();
+
+    tracing::Span::current()
+        .record(
+            "newsletter_issue_id",
+            tracing::field::display(&task.issue_id),
+        )
+        .record("subscriber_email", tracing::field::display(&task.email));
+
+    let subscriber = match get_confirmed_subscriber(pool, &task.email).await? {
+        Some(subscriber) => subscriber,
+        None => {
+            info!("Skipping a subscriber who is not confirmed anymore");
 
-    match SubscriberEmail::parse(email.clone()) {
+            record_delivery(&mut transaction, &task, DeliveryOutcome::Skipped, None).await?;
+            delete_task(transaction, task.issue_id, &task.email).await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+
+    match SubscriberEmail::parse(task.email.clone()) {
         Ok(email) => {
-            let issue = get_issue(pool, issue_id).await?;
+            let issue = get_issue(pool, task.issue_id).await?;
+
+            let unsubscribe_link =
+                UnsubscribeToken::generate(subscriber.id, hmac_secret).link(base_url);
+            let variables = SubscriberVariables::new(
+                &subscriber.name,
+                email.as_ref(),
+                subscriber.subscribed_at,
+                &unsubscribe_link,
+            );
+
+            // Contents are validated when they're published so this should not happen,
+            // retrying wouldn't help anyway.
+            let (html_content, text_content) = match render_issue(&issue, &variables) {
+                Ok(contents) => contents,
+                Err(err) => {
+                    error!(
+                        error.cause_chain = ?err,
+                        error.message = %err,
+                        "Failed to render the issue for a confirmed subscriber, giving up",
+                    );
+
+                    record_delivery(
+                        &mut transaction,
+                        &task,
+                        DeliveryOutcome::Failed,
+                        Some(&format!("{:#}", err)),
+                    )
+                    .await?;
+                    dead_letter_task(transaction, &task, &err).await?;
+
+                    return Ok(ExecutionOutcome::TaskCompleted);
+                }
+            };
+
+            // Only the emails actually sent count towards the rate limit; the row stays locked
+            // while waiting but the other consumers skip it, and stopping the worker releases it.
+            if !rate_limiter.acquire_unless_cancelled(shutdown).await {
+                return Ok(ExecutionOutcome::Cancelled);
+            }
 
             let send_result = email_client
                 .send_email(
                     &email,
                     &issue.title,
-                    &issue.html_content,
-                    &issue.text_content,
+                    &html_content,
+                    &text_content,
+                    Some(&unsubscribe_link),
                 )
                 .await;
 
-            if let Err(err) = send_result {
-                error!(
-                    error.cause_chain = ?err,
-                    error.message = %err,
-                    "Failed to deliver issue to a confirmed subscriber, skipping",
-                )
+            match send_result {
+                Ok(response_body) => {
+                    record_delivery(
+                        &mut transaction,
+                        &task,
+                        DeliveryOutcome::Sent,
+                        Some(&response_body),
+                    )
+                    .await?;
+                }
+                Err(err) if task.n_retries >= retry_policy.max_retries => {
+                    error!(
+                        error.cause_chain = ?err,
+                        error.message = %err,
+                        n_retries = task.n_retries,
+                        "Failed to deliver issue to a confirmed subscriber, giving up",
+                    );
+
+                    record_delivery(
+                        &mut transaction,
+                        &task,
+                        DeliveryOutcome::Failed,
+                        Some(&format!("{:#}", err)),
+                    )
+                    .await?;
+                    dead_letter_task(transaction, &task, &err).await?;
+
+                    return Ok(ExecutionOutcome::TaskCompleted);
+                }
+                Err(err) => {
+                    let delay = retry_policy.backoff(task.n_retries);
+
+                    warn!(
+                        error.cause_chain = ?err,
+                        error.message = %err,
+                        n_retries = task.n_retries,
+                        retry_in = ?delay,
+                        "Failed to deliver issue to a confirmed subscriber, retrying later",
+                    );
+
+                    reschedule_task(transaction, &task, delay).await?;
+
+                    return Ok(ExecutionOutcome::TaskCompleted);
+                }
             }
         }
         Err(err) => {
@@ -54,27 +209,55 @@ pub async fn try_execute_task(
                 error.cause_chain = ?err,
                 error.message = %err,
                 "Skipping a confirmed subscriber, their stored contact details are invalid",
-            )
+            );
+
+            record_delivery(&mut transaction, &task, DeliveryOutcome::Skipped, None).await?;
         }
     }
 
-    delete_task(transaction, issue_id, &email).await?;
+    delete_task(transaction, task.issue_id, &task.email).await?;
 
     Ok(ExecutionOutcome::TaskCompleted)
 }
 
 type PgTransaction = sqlx::Transaction<'static, sqlx::Postgres>;
 
+/// Enqueues one delivery task per confirmed subscriber for this issue.
+#[tracing::instrument(skip_all)]
+pub async fn enqueue_delivery_tasks(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue(newsletter_issue_id, subscriber_email)
+        SELECT $1, email
+        FROM subscriptions
+        WHERE status = 'confirmed'
+        "#,
+        newsletter_issue_id,
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(())
+}
+
+struct Task {
+    issue_id: Uuid,
+    email: String,
+    n_retries: i32,
+}
+
 #[tracing::instrument(skip_all, level = "debug")]
-async fn dequeue_task(
-    pool: &sqlx::PgPool,
-) -> Result<Option<(PgTransaction, Uuid, String)>, anyhow::Error> {
+async fn dequeue_task(pool: &sqlx::PgPool) -> Result<Option<(PgTransaction, Task)>, anyhow::Error> {
     let mut transaction = pool.begin().await?;
 
     let record = sqlx::query!(
         r#"
-        SELECT newsletter_issue_id, subscriber_email
+        SELECT newsletter_issue_id, subscriber_email, n_retries
         FROM issue_delivery_queue
+        WHERE execute_after <= now()
         FOR UPDATE
         SKIP LOCKED
         LIMIT 1
@@ -85,17 +268,121 @@ async fn dequeue_task(
 
     match record {
         Some(record) => {
-            let result = (
-                transaction,
-                record.newsletter_issue_id,
-                record.subscriber_email,
-            );
-            Ok(Some(result))
+            let task = Task {
+                issue_id: record.newsletter_issue_id,
+                email: record.subscriber_email,
+                n_retries: record.n_retries,
+            };
+            Ok(Some((transaction, task)))
         }
         None => Ok(None),
     }
 }
 
+#[tracing::instrument(skip_all)]
+async fn reschedule_task(
+    mut transaction: PgTransaction,
+    task: &Task,
+    delay: Duration,
+) -> Result<(), anyhow::Error> {
+    let execute_after = time::OffsetDateTime::now_utc() + delay;
+
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET n_retries = n_retries + 1, execute_after = $3
+        WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+        "#,
+        task.issue_id,
+        task.email,
+        execute_after,
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn dead_letter_task(
+    mut transaction: PgTransaction,
+    task: &Task,
+    err: &anyhow::Error,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_dead_letters(newsletter_issue_id, subscriber_email, n_retries, last_error, failed_at)
+        VALUES ($1, $2, $3, $4, now())
+        ON CONFLICT (newsletter_issue_id, subscriber_email) DO UPDATE
+        SET n_retries = EXCLUDED.n_retries, last_error = EXCLUDED.last_error, failed_at = EXCLUDED.failed_at
+        "#,
+        task.issue_id,
+        task.email,
+        task.n_retries,
+        format!("{:#}", err),
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    delete_task(transaction, task.issue_id, &task.email).await
+}
+
+/// The final outcome of a delivery task, recorded in `newsletter_deliveries`.
+#[derive(Clone, Copy, Debug, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryOutcome {
+    Sent,
+    Failed,
+    Skipped,
+}
+
+impl DeliveryOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryOutcome::Sent => "sent",
+            DeliveryOutcome::Failed => "failed",
+            DeliveryOutcome::Skipped => "skipped",
+        }
+    }
+}
+
+#[tracing::instrument(skip(transaction, task, response_body))]
+async fn record_delivery(
+    transaction: &mut PgTransaction,
+    task: &Task,
+    outcome: DeliveryOutcome,
+    response_body: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    // Skipped tasks were never attempted
+    let n_attempts = match outcome {
+        DeliveryOutcome::Skipped => task.n_retries,
+        _ => task.n_retries + 1,
+    };
+
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_deliveries(newsletter_issue_id, subscriber_email, outcome, n_attempts, response_body, recorded_at)
+        VALUES ($1, $2, $3, $4, $5, now())
+        ON CONFLICT (newsletter_issue_id, subscriber_email) DO UPDATE
+        SET outcome = EXCLUDED.outcome,
+            n_attempts = newsletter_deliveries.n_attempts + EXCLUDED.n_attempts,
+            response_body = EXCLUDED.response_body,
+            recorded_at = EXCLUDED.recorded_at
+        "#,
+        task.issue_id,
+        task.email,
+        outcome.as_str(),
+        n_attempts,
+        response_body,
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(())
+}
+
 #[tracing::instrument(skip_all)]
 async fn delete_task(
     mut transaction: PgTransaction,
@@ -118,10 +405,82 @@ async fn delete_task(
     Ok(())
 }
 
+#[derive(askama::Template)]
+#[template(path = "issue_unsubscribe.html")]
+struct HtmlUnsubscribeTemplate<'a> {
+    unsubscribe_link: &'a str,
+}
+
+#[derive(askama::Template)]
+#[template(path = "issue_unsubscribe.txt")]
+struct TextUnsubscribeTemplate<'a> {
+    unsubscribe_link: &'a str,
+}
+
+/// Renders the contents of an issue for a subscriber, followed by the unsubscribe footer.
+fn render_issue(
+    issue: &NewsletterIssue,
+    variables: &SubscriberVariables<'_>,
+) -> Result<(String, String), anyhow::Error> {
+    let (html_content, text_content) = issue_template::render(
+        &issue.html_content,
+        &issue.text_content,
+        issue.content_is_template,
+        variables,
+    )
+    .context("Failed to render the contents")?;
+
+    let html_content = format!(
+        "{}{}",
+        html_content,
+        HtmlUnsubscribeTemplate {
+            unsubscribe_link: variables.unsubscribe_link
+        }
+        .render()?
+    );
+    let text_content = format!(
+        "{}{}",
+        text_content,
+        TextUnsubscribeTemplate {
+            unsubscribe_link: variables.unsubscribe_link
+        }
+        .render()?
+    );
+
+    Ok((html_content, text_content))
+}
+
+struct ConfirmedSubscriber {
+    id: Uuid,
+    name: String,
+    subscribed_at: time::OffsetDateTime,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_confirmed_subscriber(
+    pool: &sqlx::PgPool,
+    email: &str,
+) -> Result<Option<ConfirmedSubscriber>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        ConfirmedSubscriber,
+        r#"
+        SELECT id, name, subscribed_at
+        FROM subscriptions
+        WHERE email = $1 AND status = 'confirmed'
+        "#,
+        email,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(subscriber)
+}
+
 struct NewsletterIssue {
     title: String,
     text_content: String,
     html_content: String,
+    content_is_template: bool,
 }
 
 #[tracing::instrument(skip_all)]
@@ -129,7 +488,7 @@ async fn get_issue(pool: &sqlx::PgPool, issue_id: Uuid) -> Result<NewsletterIssu
     let issue = sqlx::query_as!(
         NewsletterIssue,
         r#"
-        SELECT title, text_content, html_content
+        SELECT title, text_content, html_content, content_is_template
         FROM newsletter_issues
         WHERE id = $1
         "#,
@@ -141,23 +500,224 @@ async fn get_issue(pool: &sqlx::PgPool, issue_id: Uuid) -> Result<NewsletterIssu
     Ok(issue)
 }
 
-async fn worker_loop(pool: sqlx::PgPool, email_client: tem::Client) -> Result<(), anyhow::Error> {
-    loop {
-        match try_execute_task(&pool, &email_client).await {
-            Ok(ExecutionOutcome::TaskCompleted) => {}
-            Ok(ExecutionOutcome::EmptyQueue) => {
-                tokio::time::sleep(Duration::from_secs(1)).await;
-            }
-            Err(_) => {
-                tokio::time::sleep(Duration::from_secs(1)).await;
+/// How long to wait before polling the queue again, doubling after each poll which found
+/// the queue empty.
+#[derive(Clone, Debug)]
+pub struct PollInterval {
+    min: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl PollInterval {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Self {
+            min,
+            max: max.max(min),
+            current: min,
+        }
+    }
+
+    /// Returns the delay before the next poll of an empty queue.
+    fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = self.current.saturating_mul(2).min(self.max);
+        delay
+    }
+
+    fn reset(&mut self) {
+        self.current = self.min;
+    }
+}
+
+/// The state shared by all the consumers of the delivery queue.
+#[derive(Clone)]
+struct Consumer {
+    pool: sqlx::PgPool,
+    email_client: Arc<dyn EmailSender>,
+    retry_policy: RetryPolicy,
+    rate_limiter: Arc<RateLimiter>,
+    base_url: ApplicationBaseUrl,
+    hmac_secret: HmacSecret,
+}
+
+/// Sends a pending password reset link first, since its user is waiting for it, then the
+/// invitations and the confirmation links, or else executes a delivery task.
+async fn try_execute_next_task(
+    consumer: &Consumer,
+    shutdown: &CancellationToken,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let outcome = try_send_password_reset(
+        &consumer.pool,
+        consumer.email_client.as_ref(),
+        &consumer.rate_limiter,
+        shutdown,
+        &consumer.base_url,
+    )
+    .await?;
+    if !matches!(outcome, ExecutionOutcome::EmptyQueue) {
+        return Ok(outcome);
+    }
+
+    let outcome = try_send_invitation(
+        &consumer.pool,
+        consumer.email_client.as_ref(),
+        &consumer.retry_policy,
+        &consumer.rate_limiter,
+        shutdown,
+        &consumer.base_url,
+    )
+    .await?;
+    if !matches!(outcome, ExecutionOutcome::EmptyQueue) {
+        return Ok(outcome);
+    }
+
+    let outcome = try_send_confirmation_email(
+        &consumer.pool,
+        consumer.email_client.as_ref(),
+        &consumer.retry_policy,
+        &consumer.rate_limiter,
+        shutdown,
+        &consumer.base_url,
+    )
+    .await?;
+    if !matches!(outcome, ExecutionOutcome::EmptyQueue) {
+        return Ok(outcome);
+    }
+
+    try_execute_task(
+        &consumer.pool,
+        consumer.email_client.as_ref(),
+        &consumer.retry_policy,
+        &consumer.rate_limiter,
+        shutdown,
+        &consumer.base_url,
+        &consumer.hmac_secret,
+    )
+    .await
+}
+
+/// Processes delivery tasks, confirmation emails, invitations and password reset requests until `shutdown` is cancelled.
+///
+/// The token is only checked between tasks and while waiting for the rate limiter: the email
+/// being sent when it's cancelled is always finished and its transaction committed or rolled
+/// back.
+async fn worker_loop(
+    consumer: Consumer,
+    mut poll_interval: PollInterval,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    while !shutdown.is_cancelled() {
+        let outcome = try_execute_next_task(&consumer, &shutdown).await;
+        if let Err(e) = &outcome {
+            error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to execute the next task of the queue",
+            );
+        }
+
+        match outcome {
+            Ok(ExecutionOutcome::TaskCompleted) => poll_interval.reset(),
+            Ok(ExecutionOutcome::Cancelled) => {}
+            Ok(ExecutionOutcome::EmptyQueue) | Err(_) => {
+                tokio::select! {
+                    _ = tokio::time::sleep(poll_interval.next_delay()) => {}
+                    _ = shutdown.cancelled() => {}
+                }
             }
         }
     }
+
+    Ok(())
 }
 
+/// Runs `settings.concurrency` consumers of the delivery queue until `shutdown` is cancelled.
+/// They also send the confirmation links, the invitations and the password reset links.
+///
+/// Dequeuing uses `SKIP LOCKED` so the consumers never process the same task.
 pub async fn run_worker_until_stopped(
     pool: sqlx::PgPool,
-    email_client: tem::Client,
+    email_client: Arc<dyn EmailSender>,
+    settings: WorkerSettings,
+    base_url: ApplicationBaseUrl,
+    hmac_secret: HmacSecret,
+    shutdown: CancellationToken,
 ) -> Result<(), anyhow::Error> {
-    worker_loop(pool, email_client).await
+    let consumer = Consumer {
+        pool,
+        email_client,
+        retry_policy: settings.retry_policy(),
+        rate_limiter: Arc::new(settings.rate_limiter()?),
+        base_url,
+        hmac_secret,
+    };
+
+    let consumers: Vec<_> = (0..settings.concurrency.max(1))
+        .map(|_| {
+            tokio::spawn(worker_loop(
+                consumer.clone(),
+                settings.poll_interval(),
+                shutdown.clone(),
+            ))
+        })
+        .collect();
+
+    for consumer in consumers {
+        consumer.await??;
+    }
+
+    info!("Issue delivery worker stopped");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PollInterval, RetryPolicy};
+    use std::time::Duration;
+
+    fn retry_policy() -> RetryPolicy {
+        RetryPolicy::new(5, Duration::from_secs(10), Duration::from_secs(3600))
+    }
+
+    #[test]
+    fn backoff_grows_exponentially() {
+        let policy = retry_policy();
+
+        for n_retries in 0..5 {
+            let delay = policy.backoff(n_retries);
+            let expected = Duration::from_secs(10 * 2u64.pow(n_retries as u32));
+
+            assert!(delay >= expected / 2, "delay {:?} is too short", delay);
+            assert!(delay <= expected, "delay {:?} is too long", delay);
+        }
+    }
+
+    #[test]
+    fn backoff_is_capped_to_the_max_delay() {
+        let policy = retry_policy();
+
+        for n_retries in [10, 31, 1000] {
+            let delay = policy.backoff(n_retries);
+            assert!(delay <= Duration::from_secs(3600));
+        }
+    }
+
+    #[test]
+    fn poll_interval_doubles_up_to_the_max_and_resets() {
+        let mut poll_interval =
+            PollInterval::new(Duration::from_millis(100), Duration::from_millis(500));
+
+        let delays: Vec<_> = (0..5).map(|_| poll_interval.next_delay()).collect();
+        assert_eq!(
+            delays,
+            [100, 200, 400, 500, 500]
+                .map(Duration::from_millis)
+                .to_vec()
+        );
+
+        poll_interval.reset();
+        assert_eq!(poll_interval.next_delay(), Duration::from_millis(100));
+    }
 }
@@ -0,0 +1,94 @@
+use crate::issue_delivery_worker::enqueue_delivery_tasks;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+use uuid::Uuid;
+
+pub enum SchedulingOutcome {
+    IssuePublished(Uuid),
+    NothingDue,
+}
+
+/// Publishes one scheduled issue whose send time has come, if any.
+///
+/// Publishing an issue enqueues its delivery tasks and marks it as published in the same
+/// transaction, so an issue is never delivered twice even with several schedulers running.
+#[tracing::instrument(skip_all, level = "debug")]
+pub async fn try_publish_scheduled_issue(
+    pool: &sqlx::PgPool,
+) -> Result<SchedulingOutcome, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let record = sqlx::query!(
+        r#"
+        SELECT id
+        FROM newsletter_issues
+        WHERE status = 'scheduled' AND scheduled_for <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+
+    let issue_id = match record {
+        Some(record) => record.id,
+        None => return Ok(SchedulingOutcome::NothingDue),
+    };
+
+    enqueue_delivery_tasks(&mut transaction, issue_id).await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'published', published_at = now()
+        WHERE id = $1
+        "#,
+        issue_id,
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    info!(newsletter_issue_id = %issue_id, "Published a scheduled newsletter issue");
+
+    Ok(SchedulingOutcome::IssuePublished(issue_id))
+}
+
+/// Publishes scheduled issues until `shutdown` is cancelled.
+async fn scheduler_loop(
+    pool: sqlx::PgPool,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    while !shutdown.is_cancelled() {
+        match try_publish_scheduled_issue(&pool).await {
+            Ok(SchedulingOutcome::IssuePublished(_)) => continue,
+            Ok(SchedulingOutcome::NothingDue) => {}
+            Err(err) => {
+                error!(
+                    error.cause_chain = ?err,
+                    error.message = %err,
+                    "Failed to publish scheduled newsletter issues",
+                );
+            }
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(10)) => {}
+            _ = shutdown.cancelled() => {}
+        }
+    }
+
+    info!("Issue scheduler stopped");
+
+    Ok(())
+}
+
+pub async fn run_scheduler_until_stopped(
+    pool: sqlx::PgPool,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    scheduler_loop(pool, shutdown).await
+}
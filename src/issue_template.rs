@@ -0,0 +1,182 @@
+//! Newsletter issue contents are templates rendered for each subscriber at delivery time.
+//!
+//! The syntax is the same as the Jinja templates we use for the pages, for example `Hi {{ name }}!`.
+//! The available variables are those of [`SubscriberVariables`].
+
+use minijinja::{AutoEscape, Environment, UndefinedBehavior};
+use time::macros::format_description;
+use time::OffsetDateTime;
+
+const HTML_TEMPLATE_NAME: &str = "html_content.html";
+const TEXT_TEMPLATE_NAME: &str = "text_content.txt";
+
+/// The variables available in the content of a newsletter issue.
+#[derive(serde::Serialize)]
+pub struct SubscriberVariables<'a> {
+    pub name: &'a str,
+    pub email: &'a str,
+    pub subscribed_at: String,
+    pub unsubscribe_link: &'a str,
+}
+
+impl<'a> SubscriberVariables<'a> {
+    pub fn new(
+        name: &'a str,
+        email: &'a str,
+        subscribed_at: OffsetDateTime,
+        unsubscribe_link: &'a str,
+    ) -> Self {
+        let subscribed_at = subscribed_at
+            .format(format_description!("[year]-[month]-[day]"))
+            .expect("Failed to format a subscription date");
+
+        Self {
+            name,
+            email,
+            subscribed_at,
+            unsubscribe_link,
+        }
+    }
+}
+
+fn environment() -> Environment<'static> {
+    let mut env = Environment::new();
+
+    // A typo in a variable name must be an error, not an empty string sent to every subscriber
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+    env.set_auto_escape_callback(|name| {
+        if name == HTML_TEMPLATE_NAME {
+            AutoEscape::Html
+        } else {
+            AutoEscape::None
+        }
+    });
+
+    env
+}
+
+/// Renders the HTML content of an issue, variables are HTML escaped.
+pub fn render_html(
+    html_content: &str,
+    variables: &SubscriberVariables<'_>,
+) -> Result<String, minijinja::Error> {
+    environment().render_named_str(HTML_TEMPLATE_NAME, html_content, variables)
+}
+
+/// Renders the text content of an issue.
+pub fn render_text(
+    text_content: &str,
+    variables: &SubscriberVariables<'_>,
+) -> Result<String, minijinja::Error> {
+    environment().render_named_str(TEXT_TEMPLATE_NAME, text_content, variables)
+}
+
+/// Renders the HTML and text contents of an issue.
+///
+/// The contents of the issues written before they were templates are returned as they are, they
+/// could contain `{{` or `{%` which aren't meant as template syntax.
+pub fn render(
+    html_content: &str,
+    text_content: &str,
+    content_is_template: bool,
+    variables: &SubscriberVariables<'_>,
+) -> Result<(String, String), minijinja::Error> {
+    if !content_is_template {
+        return Ok((html_content.to_string(), text_content.to_string()));
+    }
+
+    Ok((
+        render_html(html_content, variables)?,
+        render_text(text_content, variables)?,
+    ))
+}
+
+/// Checks that the contents of an issue can be rendered for any subscriber.
+///
+/// This catches both syntax errors and unknown variables, the contents are rendered with
+/// placeholder values.
+pub fn validate(html_content: &str, text_content: &str) -> Result<(), minijinja::Error> {
+    let variables = SubscriberVariables::new(
+        "Jane Doe",
+        "jane@example.com",
+        OffsetDateTime::now_utc(),
+        "https://example.com/subscriptions/unsubscribe",
+    );
+
+    render_html(html_content, &variables)?;
+    render_text(text_content, &variables)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render, render_html, render_text, validate, SubscriberVariables};
+    use claim::{assert_err, assert_ok};
+    use time::macros::datetime;
+
+    fn variables() -> SubscriberVariables<'static> {
+        SubscriberVariables::new(
+            "Tom & Jerry",
+            "tom@example.com",
+            datetime!(2022-10-16 15:16 UTC),
+            "https://example.com/unsubscribe?token=abc",
+        )
+    }
+
+    #[test]
+    fn subscriber_variables_are_rendered() {
+        let text = render_text(
+            "Hi {{ name }} <{{ email }}>, subscribed on {{ subscribed_at }}. {{ unsubscribe_link }}",
+            &variables(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            text,
+            "Hi Tom & Jerry <tom@example.com>, subscribed on 2022-10-16. https://example.com/unsubscribe?token=abc"
+        );
+    }
+
+    #[test]
+    fn variables_are_escaped_in_the_html_content() {
+        let html = render_html("<p>Hi {{ name }}</p>", &variables()).unwrap();
+
+        assert_eq!(html, "<p>Hi Tom &amp; Jerry</p>");
+    }
+
+    #[test]
+    fn contents_without_variables_are_left_untouched() {
+        let html = render_html("<p>Hello & welcome</p>", &variables()).unwrap();
+
+        assert_eq!(html, "<p>Hello & welcome</p>");
+    }
+
+    #[test]
+    fn contents_written_before_templates_are_left_untouched() {
+        let (html, text) = render("<p>{{ name }}</p>", "{% raw", false, &variables()).unwrap();
+
+        assert_eq!(html, "<p>{{ name }}</p>");
+        assert_eq!(text, "{% raw");
+    }
+
+    #[test]
+    fn valid_contents_are_accepted() {
+        assert_ok!(validate(
+            "<p>Hi {{ name }}</p>{% if email %}<p>{{ email }}</p>{% endif %}",
+            "Hi {{ name }}"
+        ));
+    }
+
+    #[test]
+    fn syntax_errors_are_rejected() {
+        assert_err!(validate("<p>Hi {{ name </p>", "Hi"));
+        assert_err!(validate("<p>Hi</p>", "{% if name %}Hi"));
+    }
+
+    #[test]
+    fn unknown_variables_are_rejected() {
+        assert_err!(validate("<p>Hi {{ nmae }}</p>", "Hi"));
+        assert_err!(validate("<p>Hi</p>", "Hi {{ first_name }}"));
+    }
+}
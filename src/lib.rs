@@ -1,10 +1,23 @@
+pub mod audit_log;
 pub mod authentication;
 pub mod configuration;
+pub mod confirmation_email_queue;
 pub mod domain;
+pub mod email_client;
 pub mod idempotency;
+pub mod invitation_queue;
 pub mod issue_delivery_worker;
+pub mod issue_scheduler;
+pub mod issue_template;
+pub mod password_reset_queue;
 mod routes;
 pub mod sessions;
+pub mod shutdown;
+pub mod smtp;
+pub mod spool;
 pub mod startup;
+pub mod subscription_purge;
 pub mod telemetry;
 pub mod tem;
+pub mod token_bucket;
+pub mod unsubscribe;
@@ -1,19 +1,53 @@
+use anyhow::Context;
+use clap::Parser;
+use secrecy::ExposeSecret;
+use sqlx::migrate::MigrateDatabase;
+use sqlx::Postgres;
 use std::fmt::{Debug, Display};
+use std::future::Future;
+use tokio_util::sync::CancellationToken;
 use tracing::{error, info};
-use zero2prod::configuration::get_configuration;
+use zero2prod::configuration::{get_configuration, DatabaseSettings, Settings};
 use zero2prod::issue_delivery_worker;
-use zero2prod::startup::Application;
-use zero2prod::startup::{get_connection_pool, get_tem_client};
+use zero2prod::issue_scheduler;
+use zero2prod::shutdown;
+use zero2prod::startup::{get_connection_pool, get_email_client, get_worker_connection_pool};
+use zero2prod::startup::{Application, ApplicationBaseUrl, HmacSecret};
+use zero2prod::subscription_purge;
 use zero2prod::telemetry;
 
+#[derive(clap::Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Run the API server only
+    Serve,
+    /// Run the issue delivery worker, the issue scheduler and the expired data purge only
+    Worker,
+    /// Run the API server and every worker (the default)
+    All,
+    /// Create the database if necessary and apply the migrations embedded in the binary
+    Migrate,
+    /// Validate the configuration and exit
+    CheckConfig,
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
     let subscriber = telemetry::get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
     telemetry::init_subscriber(subscriber);
 
     //
 
-    let configuration = get_configuration().expect("Failed to read configuration");
+    let configuration = get_configuration().context("Failed to read configuration")?;
+    configuration.validate().context("Invalid configuration")?;
 
     tracing::info!(
         application_host = %configuration.application.host,
@@ -21,35 +55,149 @@ async fn main() -> anyhow::Result<()> {
         "got configuration",
     );
 
-    let app_pool = get_connection_pool(&configuration.database).await;
-    let app_email_client = get_tem_client(&configuration.tem);
+    match cli.command.unwrap_or(Command::All) {
+        Command::Serve => run(configuration, true, false).await,
+        Command::Worker => run(configuration, false, true).await,
+        Command::All => run(configuration, true, true).await,
+        Command::Migrate => migrate(&configuration.database).await,
+        Command::CheckConfig => {
+            info!("The configuration is valid");
+            Ok(())
+        }
+    }
+}
+
+#[tracing::instrument(skip_all)]
+async fn migrate(configuration: &DatabaseSettings) -> anyhow::Result<()> {
+    let url = configuration.connection_string();
+
+    if !Postgres::database_exists(url.expose_secret()).await? {
+        info!(database = %configuration.name, "creating the database");
+        Postgres::create_database(url.expose_secret())
+            .await
+            .context("Failed to create the database")?;
+    }
+
+    let pool = get_connection_pool(configuration).await;
+    sqlx::migrate!("./migrations")
+        .run(&pool)
+        .await
+        .context("Failed to migrate the database")?;
+
+    info!("The database is up to date");
+
+    Ok(())
+}
+
+/// Runs the API and/or the workers until one of them exits or the process is asked to stop.
+///
+/// Fails if any of them failed, so that the process exits with an error status.
+async fn run(configuration: Settings, serve_api: bool, run_workers: bool) -> anyhow::Result<()> {
+    // Cancelled on SIGINT/SIGTERM, or as soon as one of the tasks exits
+    let shutdown = CancellationToken::new();
+    tokio::spawn(shutdown::cancel_on_signal(shutdown.clone()));
+
+    let mut tasks = Vec::new();
+
+    if serve_api {
+        let app_pool = get_connection_pool(&configuration.database).await;
+        let app_email_client = get_email_client(&configuration.email);
+
+        let app =
+            Application::build_with_pool(configuration.clone(), app_pool, app_email_client).await?;
+        tasks.push(spawn_task(
+            "API",
+            shutdown.clone(),
+            app.run_until_stopped(shutdown.clone()),
+        ));
+    }
+
+    if run_workers {
+        let issue_delivery_worker_pool =
+            get_worker_connection_pool(&configuration.database, &configuration.worker).await;
+        let issue_delivery_email_client = get_email_client(&configuration.email);
+        tasks.push(spawn_task(
+            "Issue delivery worker",
+            shutdown.clone(),
+            issue_delivery_worker::run_worker_until_stopped(
+                issue_delivery_worker_pool,
+                issue_delivery_email_client,
+                configuration.worker.clone(),
+                ApplicationBaseUrl(configuration.application.base_url.clone()),
+                HmacSecret(configuration.application.hmac_secret.clone()),
+                shutdown.clone(),
+            ),
+        ));
 
-    let app =
-        Application::build_with_pool(configuration.clone(), app_pool, app_email_client).await?;
-    let app_task = tokio::spawn(app.run_until_stopped());
+        let issue_scheduler_pool = get_connection_pool(&configuration.database).await;
+        tasks.push(spawn_task(
+            "Issue scheduler",
+            shutdown.clone(),
+            issue_scheduler::run_scheduler_until_stopped(issue_scheduler_pool, shutdown.clone()),
+        ));
+
+        let purge_pool = get_connection_pool(&configuration.database).await;
+        tasks.push(spawn_task(
+            "Expired data purge",
+            shutdown.clone(),
+            subscription_purge::run_purge_until_stopped(
+                purge_pool,
+                configuration.subscriptions.clone(),
+                configuration.login_protection.policy(),
+                shutdown.clone(),
+            ),
+        ));
+    }
 
-    let issue_delivery_worker_pool = get_connection_pool(&configuration.database).await;
-    let issue_delivery_email_client = get_tem_client(&configuration.tem);
-    let issue_delivery_worker_task = tokio::spawn(issue_delivery_worker::run_worker_until_stopped(
-        issue_delivery_worker_pool,
-        issue_delivery_email_client,
-    ));
+    let mut failed_tasks = Vec::new();
+    for task in tasks {
+        if let Err(task_name) = task.await.context("Failed to wait for a task to exit")? {
+            failed_tasks.push(task_name);
+        }
+    }
 
-    tokio::select! {
-        outcome = app_task => report_exit("API", outcome),
-        outcome = issue_delivery_worker_task => report_exit("Issue delivery worker", outcome),
-    };
+    if !failed_tasks.is_empty() {
+        anyhow::bail!("Failed tasks: {}", failed_tasks.join(", "));
+    }
 
     Ok(())
 }
 
+/// Spawns a task and reports its exit, which also shuts down every other task.
+///
+/// The returned handle resolves to the name of the task if it failed.
+fn spawn_task<F, E>(
+    task_name: &'static str,
+    shutdown: CancellationToken,
+    task: F,
+) -> tokio::task::JoinHandle<Result<(), &'static str>>
+where
+    F: Future<Output = Result<(), E>> + Send + 'static,
+    E: Debug + Display + Send + 'static,
+{
+    let handle = tokio::spawn(task);
+
+    tokio::spawn(async move {
+        let succeeded = report_exit(task_name, handle.await);
+        shutdown.cancel();
+
+        if succeeded {
+            Ok(())
+        } else {
+            Err(task_name)
+        }
+    })
+}
+
+/// Logs how a task exited, returning whether it succeeded.
 fn report_exit(
     task_name: &str,
     outcome: Result<Result<(), impl Debug + Display>, tokio::task::JoinError>,
-) {
+) -> bool {
     match outcome {
         Ok(Ok(())) => {
             info!("{} has exited", task_name);
+            true
         }
         Ok(Err(err)) => {
             error!(
@@ -57,7 +205,8 @@ fn report_exit(
                 error.message = %err,
                 "{} failed",
                 task_name,
-            )
+            );
+            false
         }
         Err(err) => {
             error!(
@@ -65,7 +214,8 @@ fn report_exit(
                 error.message = %err,
                 "'{}' task failed to complete",
                 task_name,
-            )
+            );
+            false
         }
     }
 }
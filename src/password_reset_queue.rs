@@ -0,0 +1,291 @@
+use crate::authentication::hash_token;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailSender;
+use crate::issue_delivery_worker::ExecutionOutcome;
+use crate::startup::ApplicationBaseUrl;
+use crate::token_bucket::RateLimiter;
+use anyhow::Context;
+use askama::Template;
+use rand::distributions::{Alphanumeric, DistString};
+use time::OffsetDateTime;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+use uuid::Uuid;
+
+/// How long a password reset link can be used after it has been sent.
+const PASSWORD_RESET_VALIDITY: time::Duration = time::Duration::hours(1);
+
+/// Requests are throttled over this window, per email address and per client IP address.
+const REQUEST_WINDOW: time::Duration = time::Duration::hours(1);
+const MAX_REQUESTS_PER_EMAIL: i64 = 3;
+const MAX_REQUESTS_PER_CLIENT_IP: i64 = 20;
+
+#[derive(askama::Template)]
+#[template(path = "password_reset_email.html")]
+struct PasswordResetHtmlTemplate<'a> {
+    reset_link: &'a str,
+    username: &'a str,
+}
+
+#[derive(askama::Template)]
+#[template(path = "password_reset_email.txt")]
+struct PasswordResetTextTemplate<'a> {
+    reset_link: &'a str,
+    username: &'a str,
+}
+
+/// Records a password reset request, to be emailed by the worker, and returns whether it was
+/// accepted.
+///
+/// The same work is done whether an account exists for the email address or not: the account
+/// is only looked up by the worker. Requests over the limits are refused.
+#[tracing::instrument(skip(pool, email))]
+pub(crate) async fn enqueue_password_reset(
+    pool: &sqlx::PgPool,
+    email: &SubscriberEmail,
+    client_ip: &str,
+) -> Result<bool, anyhow::Error> {
+    let now = OffsetDateTime::now_utc();
+
+    let counts = sqlx::query!(
+        r#"
+        SELECT
+            COUNT(*) FILTER (WHERE email = $1) AS "by_email!",
+            COUNT(*) FILTER (WHERE client_ip = $2) AS "by_client_ip!"
+        FROM password_reset_requests
+        WHERE (email = $1 OR client_ip = $2) AND requested_at > $3
+        "#,
+        email.as_ref(),
+        client_ip,
+        now - REQUEST_WINDOW,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to count the recent password reset requests")?;
+
+    if counts.by_email >= MAX_REQUESTS_PER_EMAIL
+        || counts.by_client_ip >= MAX_REQUESTS_PER_CLIENT_IP
+    {
+        return Ok(false);
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO password_reset_requests(id, email, client_ip, requested_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        Uuid::new_v4(),
+        email.as_ref(),
+        client_ip,
+        now,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the password reset request")?;
+
+    Ok(true)
+}
+
+/// Sends the reset link of one pending request, if any, when an active user has its email
+/// address.
+///
+/// A link that fails to be sent isn't retried: the user can ask for a new one.
+#[tracing::instrument(skip_all, level = "debug")]
+pub async fn try_send_password_reset(
+    pool: &sqlx::PgPool,
+    email_client: &dyn EmailSender,
+    rate_limiter: &RateLimiter,
+    shutdown: &CancellationToken,
+    base_url: &ApplicationBaseUrl,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let request = sqlx::query!(
+        r#"
+        SELECT id, email
+        FROM password_reset_requests
+        WHERE processed_at IS NULL
+        ORDER BY requested_at
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+
+    let request = match request {
+        Some(request) => request,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+
+    let user = get_active_user_by_email(&mut transaction, &request.email).await?;
+    if let Some((user_id, username)) = user {
+        let reset_token = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+        store_reset_token(&mut transaction, &reset_token, user_id).await?;
+
+        if !rate_limiter.acquire_unless_cancelled(shutdown).await {
+            return Ok(ExecutionOutcome::Cancelled);
+        }
+
+        if let Err(err) = send_reset_link(
+            email_client,
+            base_url,
+            &request.email,
+            &username,
+            &reset_token,
+        )
+        .await
+        {
+            error!(
+                error.cause_chain = ?err,
+                error.message = %err,
+                "Failed to send a password reset email, giving up",
+            );
+        }
+    }
+
+    let now = OffsetDateTime::now_utc();
+    sqlx::query!(
+        r#"
+        UPDATE password_reset_requests SET processed_at = $2
+        WHERE id = $1
+        "#,
+        request.id,
+        now,
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    // Older requests don't count towards the limits anymore
+    sqlx::query!(
+        r#"
+        DELETE FROM password_reset_requests
+        WHERE processed_at IS NOT NULL AND requested_at <= $1
+        "#,
+        now - REQUEST_WINDOW,
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+async fn send_reset_link(
+    email_client: &dyn EmailSender,
+    base_url: &ApplicationBaseUrl,
+    email: &str,
+    username: &str,
+    reset_token: &str,
+) -> Result<(), anyhow::Error> {
+    let email = SubscriberEmail::parse(email.to_string()).map_err(anyhow::Error::msg)?;
+
+    let reset_link = format!(
+        "{}/password_reset/confirm?reset_token={}",
+        base_url.0, reset_token
+    );
+    let html_content = PasswordResetHtmlTemplate {
+        reset_link: &reset_link,
+        username,
+    };
+    let text_content = PasswordResetTextTemplate {
+        reset_link: &reset_link,
+        username,
+    };
+
+    email_client
+        .send_email(
+            &email,
+            "Reset your password",
+            &html_content.render().unwrap(),
+            &text_content.render().unwrap(),
+            None,
+        )
+        .await
+        .context("Failed to send the password reset email")?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip(transaction, email))]
+async fn get_active_user_by_email(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    email: &str,
+) -> Result<Option<(Uuid, String)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id, username FROM users
+        WHERE email = $1 AND disabled_at IS NULL
+        "#,
+        email,
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("Failed to fetch the user by email")?;
+
+    Ok(row.map(|row| (row.user_id, row.username)))
+}
+
+/// Stores a new reset token for the user, invalidating the ones they haven't used yet so that
+/// only the latest link works.
+#[tracing::instrument(skip(transaction, reset_token))]
+async fn store_reset_token(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    reset_token: &str,
+    user_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    let now = OffsetDateTime::now_utc();
+
+    sqlx::query!(
+        r#"
+        DELETE FROM password_reset_tokens
+        WHERE user_id = $1 AND used_at IS NULL
+        "#,
+        user_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the previous password reset tokens")?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO password_reset_tokens(token_hash, user_id, created_at, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        hash_token(reset_token),
+        user_id,
+        now,
+        now + PASSWORD_RESET_VALIDITY,
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to store the password reset token")?;
+
+    Ok(())
+}
+
+/// Deletes the password reset tokens which have expired, used or not. Returns how many have
+/// been deleted.
+#[tracing::instrument(skip(pool), level = "debug")]
+pub async fn purge_expired_password_reset_tokens(
+    pool: &sqlx::PgPool,
+) -> Result<u64, anyhow::Error> {
+    let purged = sqlx::query!(
+        r#"
+        DELETE FROM password_reset_tokens
+        WHERE expires_at <= $1
+        "#,
+        OffsetDateTime::now_utc(),
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    if purged > 0 {
+        info!(purged, "Purged the expired password reset tokens");
+    }
+
+    Ok(purged)
+}
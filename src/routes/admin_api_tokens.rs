@@ -0,0 +1,153 @@
+use crate::authentication::{create_api_token, UserId};
+use crate::routes::{e500, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use askama::Template;
+use secrecy::ExposeSecret;
+use time::OffsetDateTime;
+use unicode_segmentation::UnicodeSegmentation;
+use uuid::Uuid;
+
+const MAX_NAME_LENGTH: usize = 100;
+
+pub struct ApiToken {
+    id: Uuid,
+    name: String,
+    created_at: OffsetDateTime,
+    last_used_at: Option<OffsetDateTime>,
+}
+
+#[derive(askama::Template)]
+#[template(path = "admin_api_tokens.html.j2")]
+pub struct ApiTokensTemplate {
+    user_id: Option<Uuid>,
+    flash_messages: Option<IncomingFlashMessages>,
+    api_tokens: Vec<ApiToken>,
+}
+
+pub async fn admin_api_tokens(
+    pool: web::Data<sqlx::PgPool>,
+    user_id: web::ReqData<UserId>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+
+    let api_tokens = get_api_tokens(&pool, *user_id).await.map_err(e500)?;
+
+    let tpl = ApiTokensTemplate {
+        user_id: Some(*user_id),
+        flash_messages: Some(flash_messages),
+        api_tokens,
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(tpl.render().unwrap()))
+}
+
+#[derive(askama::Template)]
+#[template(path = "admin_api_token_created.html.j2")]
+pub struct ApiTokenCreatedTemplate {
+    user_id: Option<Uuid>,
+    flash_messages: Option<IncomingFlashMessages>,
+    name: String,
+    token: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct CreateApiTokenFormData {
+    name: String,
+}
+
+#[tracing::instrument(name = "Create an API token", skip(pool, form))]
+pub async fn admin_create_api_token(
+    pool: web::Data<sqlx::PgPool>,
+    user_id: web::ReqData<UserId>,
+    form: web::Form<CreateApiTokenFormData>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+
+    let name = form.0.name.trim().to_string();
+    if name.is_empty() {
+        FlashMessage::error("The name of the token is missing").send();
+        return Ok(see_other("/admin/api_tokens"));
+    }
+    if name.graphemes(true).count() > MAX_NAME_LENGTH {
+        FlashMessage::error(format!(
+            "The name of the token must be at most {} characters long",
+            MAX_NAME_LENGTH
+        ))
+        .send();
+        return Ok(see_other("/admin/api_tokens"));
+    }
+
+    let token = create_api_token(&pool, *user_id, &name)
+        .await
+        .map_err(e500)?;
+
+    // The token is rendered right away since this is the only time it is known
+    let tpl = ApiTokenCreatedTemplate {
+        user_id: Some(*user_id),
+        flash_messages: None,
+        name,
+        token: token.expose_secret().clone(),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(tpl.render().unwrap()))
+}
+
+#[tracing::instrument(name = "Revoke an API token", skip(pool))]
+pub async fn admin_revoke_api_token(
+    pool: web::Data<sqlx::PgPool>,
+    user_id: web::ReqData<UserId>,
+    token_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+
+    // Only the tokens of the user can be found, whatever the id
+    let deleted = sqlx::query!(
+        r#"DELETE FROM api_tokens WHERE id = $1 AND user_id = $2"#,
+        *token_id,
+        *user_id,
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to delete the API token")
+    .map_err(e500)?
+    .rows_affected();
+
+    if deleted > 0 {
+        FlashMessage::info("The API token has been revoked").send();
+    } else {
+        FlashMessage::error("This API token doesn't exist anymore").send();
+    }
+
+    Ok(see_other("/admin/api_tokens"))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_api_tokens(
+    pool: &sqlx::PgPool,
+    user_id: Uuid,
+) -> Result<Vec<ApiToken>, anyhow::Error> {
+    let api_tokens = sqlx::query_as!(
+        ApiToken,
+        r#"
+        SELECT id, name, created_at, last_used_at
+        FROM api_tokens
+        WHERE user_id = $1
+        ORDER BY created_at DESC
+        "#,
+        user_id,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the API tokens")?;
+
+    Ok(api_tokens)
+}
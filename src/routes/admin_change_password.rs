@@ -1,14 +1,15 @@
-use crate::authentication::{change_password, validate_credentials};
-use crate::authentication::{AuthError, Credentials, UserId};
+use crate::authentication::{change_password, validate_credentials, validate_new_password};
+use crate::authentication::{AuthError, Credentials, PasswordHashing, UserId};
 use crate::routes::admin_dashboard::get_username;
-use crate::routes::{e500, error_chain_fmt, see_other};
+use crate::routes::{e500, see_other};
+use crate::sessions::{current_session_id, UserSessionStore};
+use crate::startup::HmacSecret;
 use actix_web::http::header::ContentType;
 use actix_web::web;
-use actix_web::HttpResponse;
+use actix_web::{HttpRequest, HttpResponse};
 use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
 use askama::Template;
-use secrecy::{ExposeSecret, Secret};
-use std::fmt;
+use secrecy::Secret;
 use uuid::Uuid;
 
 #[derive(askama::Template)]
@@ -32,62 +33,46 @@ pub async fn admin_change_password_form(
         .body(tpl.render().unwrap()))
 }
 
-#[derive(thiserror::Error)]
-pub enum ChangePasswordError {
-    #[error("Something went wrong")]
-    Unexpected(#[from] anyhow::Error),
-}
-
-impl fmt::Debug for ChangePasswordError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        error_chain_fmt(self, f)
-    }
-}
-
 #[derive(serde::Deserialize)]
 pub struct ChangePasswordFormData {
     current_password: Secret<String>,
     new_password: Secret<String>,
     new_password_check: Secret<String>,
+    /// Unchecked checkboxes aren't sent at all.
+    #[serde(default)]
+    log_out_other_sessions: bool,
 }
 
 pub async fn admin_change_password(
     pool: web::Data<sqlx::PgPool>,
+    hashing: web::Data<PasswordHashing>,
+    session_store: web::Data<dyn UserSessionStore>,
+    hmac_secret: web::Data<HmacSecret>,
     user_id: web::ReqData<UserId>,
+    req: HttpRequest,
     form: web::Form<ChangePasswordFormData>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = user_id.into_inner();
 
     let form = form.0;
 
+    // Obtain the username
+    let username = get_username(&pool, *user_id).await.map_err(e500)?;
+
     // Validate new password
-    if form.new_password.expose_secret() != form.new_password_check.expose_secret() {
-        FlashMessage::error(
-            "You entered two different new passwords - the field values must match.",
-        )
-        .send();
-        return Ok(see_other("/admin/password"));
-    }
-    let new_password_len = form.new_password.expose_secret().len();
-    if new_password_len < 4 {
-        FlashMessage::error("New password is too short").send();
-        return Ok(see_other("/admin/password"));
-    }
-    if new_password_len > 128 {
-        FlashMessage::error("New password is too long").send();
+    if let Err(err) = validate_new_password(&form.new_password, &form.new_password_check, &username)
+    {
+        FlashMessage::error(err.to_string()).send();
         return Ok(see_other("/admin/password"));
     }
 
-    // Obtain the username
-    let username = get_username(&pool, *user_id).await.map_err(e500)?;
-
     // Validate the credentials
     let credentials = Credentials {
         username,
         password: form.current_password,
     };
 
-    if let Err(err) = validate_credentials(&pool, credentials).await {
+    if let Err(err) = validate_credentials(&pool, &hashing, credentials).await {
         match err {
             AuthError::InvalidCredentials(_) => {
                 FlashMessage::error("The current password is incorrect").send();
@@ -98,11 +83,21 @@ pub async fn admin_change_password(
     }
 
     // All good; change the password
-    change_password(&pool, *user_id, form.new_password)
+    change_password(pool.get_ref(), &hashing, *user_id, form.new_password)
         .await
         .map_err(e500)?;
 
     FlashMessage::warning("Your password has been changed").send();
 
+    if form.log_out_other_sessions {
+        let current_session_id = current_session_id(&req, &hmac_secret.cookie_key());
+        session_store
+            .delete_user_sessions(*user_id, current_session_id)
+            .await
+            .map_err(e500)?;
+
+        FlashMessage::info("You have been logged out everywhere else").send();
+    }
+
     Ok(see_other("/admin/password"))
 }
@@ -1,4 +1,4 @@
-use crate::authentication::UserId;
+use crate::authentication::{Role, UserId};
 use crate::routes::e500;
 use actix_web::http::header::ContentType;
 use actix_web::web;
@@ -13,6 +13,7 @@ use uuid::Uuid;
 pub struct DashboardTemplate {
     user_id: Option<Uuid>,
     username: String,
+    can_manage_users: bool,
     flash_messages: Option<IncomingFlashMessages>,
 }
 
@@ -20,6 +21,7 @@ pub async fn admin_dashboard(
     pool: web::Data<sqlx::PgPool>,
     flash_messages: IncomingFlashMessages,
     user_id: web::ReqData<UserId>,
+    role: web::ReqData<Role>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = user_id.into_inner();
 
@@ -28,6 +30,7 @@ pub async fn admin_dashboard(
     let tpl = DashboardTemplate {
         user_id: Some(*user_id),
         username,
+        can_manage_users: role.into_inner() == Role::Owner,
         flash_messages: Some(flash_messages),
     };
 
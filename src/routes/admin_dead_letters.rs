@@ -0,0 +1,133 @@
+use crate::authentication::UserId;
+use crate::routes::{e500, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use askama::Template;
+use uuid::Uuid;
+
+pub struct DeadLetter {
+    newsletter_issue_id: Uuid,
+    title: String,
+    subscriber_email: String,
+    n_retries: i32,
+    last_error: String,
+    failed_at: time::OffsetDateTime,
+}
+
+#[derive(askama::Template)]
+#[template(path = "admin_dead_letters.html.j2")]
+pub struct DeadLettersTemplate {
+    user_id: Option<Uuid>,
+    flash_messages: Option<IncomingFlashMessages>,
+    dead_letters: Vec<DeadLetter>,
+}
+
+pub async fn admin_dead_letters(
+    pool: web::Data<sqlx::PgPool>,
+    user_id: web::ReqData<UserId>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let dead_letters = get_dead_letters(&pool).await.map_err(e500)?;
+
+    let tpl = DeadLettersTemplate {
+        user_id: Some(*user_id.into_inner()),
+        flash_messages: Some(flash_messages),
+        dead_letters,
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(tpl.render().unwrap()))
+}
+
+#[derive(serde::Deserialize)]
+pub struct RequeueFormData {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+}
+
+#[tracing::instrument(name = "Requeue a dead letter", skip(pool, form))]
+pub async fn admin_requeue_dead_letter(
+    pool: web::Data<sqlx::PgPool>,
+    form: web::Form<RequeueFormData>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let requeued = requeue_dead_letter(&pool, form.newsletter_issue_id, &form.subscriber_email)
+        .await
+        .map_err(e500)?;
+
+    if requeued {
+        FlashMessage::info(format!(
+            "The delivery to {} has been requeued",
+            form.subscriber_email
+        ))
+        .send();
+    } else {
+        FlashMessage::error("This delivery is not in the dead letters anymore").send();
+    }
+
+    Ok(see_other("/admin/dead_letters"))
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_dead_letters(pool: &sqlx::PgPool) -> Result<Vec<DeadLetter>, anyhow::Error> {
+    let dead_letters = sqlx::query_as!(
+        DeadLetter,
+        r#"
+        SELECT d.newsletter_issue_id, n.title, d.subscriber_email, d.n_retries, d.last_error, d.failed_at
+        FROM issue_delivery_dead_letters d
+        INNER JOIN newsletter_issues n ON n.id = d.newsletter_issue_id
+        ORDER BY d.failed_at DESC
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the dead letters")?;
+
+    Ok(dead_letters)
+}
+
+#[tracing::instrument(skip(pool))]
+async fn requeue_dead_letter(
+    pool: &sqlx::PgPool,
+    newsletter_issue_id: Uuid,
+    subscriber_email: &str,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let deleted = sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_dead_letters
+        WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+        "#,
+        newsletter_issue_id,
+        subscriber_email,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete the dead letter")?
+    .rows_affected();
+
+    if deleted == 0 {
+        return Ok(false);
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue(newsletter_issue_id, subscriber_email)
+        VALUES ($1, $2)
+        ON CONFLICT DO NOTHING
+        "#,
+        newsletter_issue_id,
+        subscriber_email,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to enqueue the delivery task")?;
+
+    transaction.commit().await?;
+
+    Ok(true)
+}
@@ -1,7 +1,10 @@
 use crate::authentication::UserId;
 use crate::idempotency::{save_response, try_processing};
 use crate::idempotency::{IdempotencyKey, NextAction};
+use crate::issue_delivery_worker::enqueue_delivery_tasks;
+use crate::issue_template;
 use crate::routes::{e400, e500, error_chain_fmt, see_other};
+use actix_web::body::{self, BoxBody};
 use actix_web::error::InternalError;
 use actix_web::http::header::ContentType;
 use actix_web::http::StatusCode;
@@ -11,7 +14,9 @@ use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
 use anyhow::Context;
 use askama::Template;
 use std::fmt;
-use tracing::error;
+use time::format_description::FormatItem;
+use time::macros::format_description;
+use time::{OffsetDateTime, PrimitiveDateTime};
 use uuid::Uuid;
 
 #[derive(askama::Template)]
@@ -20,16 +25,35 @@ pub struct NewsletterTemplate {
     user_id: Option<Uuid>,
     flash_messages: Option<IncomingFlashMessages>,
     idempotency_key: String,
+    drafts: Vec<Draft>,
+    issues: Vec<IssueSummary>,
+}
+
+pub struct Draft {
+    id: Uuid,
+    title: String,
+}
+
+pub struct IssueSummary {
+    id: Uuid,
+    title: String,
+    status: String,
 }
 
 pub async fn newsletter_form(
+    pool: web::Data<sqlx::PgPool>,
     user_id: web::ReqData<UserId>,
     flash_messages: IncomingFlashMessages,
 ) -> Result<HttpResponse, actix_web::Error> {
+    let drafts = get_drafts(&pool).await.map_err(e500)?;
+    let issues = get_issues(&pool).await.map_err(e500)?;
+
     let tpl = NewsletterTemplate {
         user_id: Some(*user_id.into_inner()),
         flash_messages: Some(flash_messages),
         idempotency_key: Uuid::new_v4().to_string(),
+        drafts,
+        issues,
     };
 
     Ok(HttpResponse::Ok()
@@ -43,6 +67,12 @@ pub enum PublishError {
     MissingTitle,
     #[error("missing content")]
     MissingContent,
+    #[error("invalid template: {0}")]
+    InvalidTemplate(#[source] minijinja::Error),
+    #[error("invalid send time")]
+    InvalidSendTime(#[source] time::error::Parse),
+    #[error("the send time is in the past")]
+    SendTimeInThePast,
     #[error(transparent)]
     Unexpected(#[from] anyhow::Error),
 }
@@ -53,7 +83,55 @@ impl fmt::Debug for PublishError {
     }
 }
 
-const SUCCESS_MESSAGE: &str = "The newsletter issue has been published";
+pub(crate) const SUCCESS_MESSAGE: &str = "The newsletter issue has been published";
+pub(crate) const SCHEDULED_MESSAGE: &str = "The newsletter issue has been scheduled";
+const DRAFT_SAVED_MESSAGE: &str = "The newsletter draft has been saved";
+
+/// Formats of the value of a `datetime-local` input, browsers only send the seconds if they're set.
+/// Times are always in UTC.
+const SEND_AT_FORMAT: &[FormatItem<'static>] =
+    format_description!("[year]-[month]-[day]T[hour]:[minute]");
+const SEND_AT_WITH_SECONDS_FORMAT: &[FormatItem<'static>] =
+    format_description!("[year]-[month]-[day]T[hour]:[minute]:[second]");
+
+/// Parses the value of a `datetime-local` input as a UTC time.
+pub(crate) fn parse_send_at(value: &str) -> Result<OffsetDateTime, time::error::Parse> {
+    PrimitiveDateTime::parse(value, SEND_AT_FORMAT)
+        .or_else(|_| PrimitiveDateTime::parse(value, SEND_AT_WITH_SECONDS_FORMAT))
+        .map(PrimitiveDateTime::assume_utc)
+}
+
+/// Formats a time so that it can be used as the value of a `datetime-local` input.
+pub(crate) fn format_send_at(value: OffsetDateTime) -> String {
+    value
+        .to_offset(time::UtcOffset::UTC)
+        .format(SEND_AT_FORMAT)
+        .expect("Failed to format a send time")
+}
+
+/// Parses the optional send time of an issue, an empty send time means "send now".
+pub(crate) fn parse_scheduled_for(
+    send_at: Option<&str>,
+) -> Result<Option<OffsetDateTime>, PublishError> {
+    match send_at.filter(|v| !v.is_empty()) {
+        Some(send_at) => {
+            let scheduled_for = parse_send_at(send_at).map_err(PublishError::InvalidSendTime)?;
+            if scheduled_for <= OffsetDateTime::now_utc() {
+                return Err(PublishError::SendTimeInThePast);
+            }
+            Ok(Some(scheduled_for))
+        }
+        None => Ok(None),
+    }
+}
+
+#[derive(serde::Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NewsletterAction {
+    #[default]
+    Publish,
+    Draft,
+}
 
 #[derive(serde::Deserialize)]
 pub struct NewsletterData {
@@ -61,6 +139,9 @@ pub struct NewsletterData {
     text_content: String,
     html_content: String,
     idempotency_key: String,
+    send_at: Option<String>,
+    #[serde(default)]
+    action: NewsletterAction,
 }
 
 #[tracing::instrument(name = "Publish newsletter", skip(pool, form))]
@@ -77,6 +158,8 @@ pub async fn publish_newsletter(
         text_content,
         html_content,
         idempotency_key,
+        send_at,
+        action,
     } = form.0;
 
     // 1) Handle idempotency key if necessary
@@ -93,8 +176,10 @@ pub async fn publish_newsletter(
     let mut transaction = match next_action {
         NextAction::StartProcessing(transaction) => transaction,
         NextAction::ReturnSavedResponse(response) => {
-            FlashMessage::info(SUCCESS_MESSAGE).send();
-            return Ok(response);
+            return replay_saved_response(response)
+                .await
+                .map_err(Into::<PublishError>::into)
+                .map_err(e500);
         }
     };
 
@@ -105,56 +190,132 @@ pub async fn publish_newsletter(
         return Err(err);
     }
 
-    if text_content.is_empty() || html_content.is_empty() {
-        let err = InternalError::new(PublishError::MissingContent, StatusCode::BAD_REQUEST);
-        return Err(err);
-    }
+    // Drafts can be saved with an incomplete content, it's validated when they're published
+    let status = if action == NewsletterAction::Draft {
+        IssueStatus::Draft
+    } else {
+        if text_content.is_empty() || html_content.is_empty() {
+            let err = InternalError::new(PublishError::MissingContent, StatusCode::BAD_REQUEST);
+            return Err(err);
+        }
 
-    // 3) Insert newsletter issue and enqueue delivery tasks
+        issue_template::validate(&html_content, &text_content)
+            .map_err(PublishError::InvalidTemplate)
+            .map_err(e400)?;
 
-    let issue_id = insert_newsletter_issue(&mut transaction, &title, &text_content, &html_content)
-        .await
-        .context("Failed to store newsletter issue details")
-        .map_err(Into::<PublishError>::into)
-        .map_err(e500)?;
+        match parse_scheduled_for(send_at.as_deref()).map_err(e400)? {
+            Some(scheduled_for) => IssueStatus::Scheduled(scheduled_for),
+            None => IssueStatus::Published,
+        }
+    };
 
-    enqueue_delivery_tasks(&mut transaction, issue_id)
-        .await
-        .context("Failed to enqueue delivery tasks")
-        .map_err(Into::<PublishError>::into)
-        .map_err(e500)?;
+    // 3) Insert newsletter issue and enqueue delivery tasks if it's published now
+
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        &title,
+        &text_content,
+        &html_content,
+        &status,
+    )
+    .await
+    .context("Failed to store newsletter issue details")
+    .map_err(Into::<PublishError>::into)
+    .map_err(e500)?;
+
+    if let IssueStatus::Published = status {
+        enqueue_delivery_tasks(&mut transaction, issue_id)
+            .await
+            .context("Failed to enqueue delivery tasks")
+            .map_err(Into::<PublishError>::into)
+            .map_err(e500)?;
+    }
 
     // 4) Finally produce the response and save it to the database
 
-    let response = see_other("/admin/newsletters");
+    let (response, message) = match status {
+        IssueStatus::Draft => (
+            see_other(&format!("/admin/newsletters/{}", issue_id)),
+            DRAFT_SAVED_MESSAGE,
+        ),
+        IssueStatus::Scheduled(_) => (see_other("/admin/newsletters"), SCHEDULED_MESSAGE),
+        IssueStatus::Published => (see_other("/admin/newsletters"), SUCCESS_MESSAGE),
+    };
+    // The message is saved as the body of the redirect, to be shown again on a replay
+    let response = response.set_body(BoxBody::new(message));
     let response = save_response(transaction, *user_id, &idempotency_key, response)
         .await
         .map_err(Into::<PublishError>::into)
         .map_err(e500)?;
 
-    FlashMessage::info(SUCCESS_MESSAGE).send();
+    FlashMessage::info(message).send();
 
     Ok(response)
 }
 
+/// Returns the saved response of a submission again, with the message it was shown with.
+async fn replay_saved_response(response: HttpResponse) -> Result<HttpResponse, anyhow::Error> {
+    let (response_head, body) = response.into_parts();
+    let body = body::to_bytes(body)
+        .await
+        .map_err(|e| anyhow::anyhow!("{}", e))?;
+
+    let message = std::str::from_utf8(&body).context("The saved message isn't UTF-8")?;
+    if !message.is_empty() {
+        FlashMessage::info(message).send();
+    }
+
+    Ok(response_head.set_body(body).map_into_boxed_body())
+}
+
+/// The state of a newsletter issue, stored in the `status` column of `newsletter_issues`.
+pub(crate) enum IssueStatus {
+    Draft,
+    Scheduled(OffsetDateTime),
+    Published,
+}
+
+impl IssueStatus {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            IssueStatus::Draft => "draft",
+            IssueStatus::Scheduled(_) => "scheduled",
+            IssueStatus::Published => "published",
+        }
+    }
+
+    pub(crate) fn scheduled_for(&self) -> Option<OffsetDateTime> {
+        match self {
+            IssueStatus::Scheduled(scheduled_for) => Some(*scheduled_for),
+            _ => None,
+        }
+    }
+}
+
 #[tracing::instrument(skip_all)]
-async fn insert_newsletter_issue(
+pub(crate) async fn insert_newsletter_issue(
     transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
     title: &str,
     text_content: &str,
     html_content: &str,
+    status: &IssueStatus,
 ) -> Result<Uuid, sqlx::Error> {
     let id = Uuid::new_v4();
 
     sqlx::query!(
         r#"
-        INSERT INTO newsletter_issues(id, title, text_content, html_content, published_at)
-        VALUES ($1, $2, $3, $4, now())
+        INSERT INTO newsletter_issues(id, title, text_content, html_content, status, scheduled_for, published_at)
+        VALUES (
+            $1, $2, $3, $4, $5, $6,
+            CASE WHEN $5 = 'published' THEN now() END
+        )
         "#,
         id,
         title,
         text_content,
         html_content,
+        status.as_str(),
+        status.scheduled_for(),
     )
     .execute(transaction)
     .await?;
@@ -163,21 +324,72 @@ async fn insert_newsletter_issue(
 }
 
 #[tracing::instrument(skip_all)]
-async fn enqueue_delivery_tasks(
-    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
-    newsletter_issue_id: Uuid,
-) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+async fn get_drafts(pool: &sqlx::PgPool) -> Result<Vec<Draft>, anyhow::Error> {
+    let drafts = sqlx::query_as!(
+        Draft,
         r#"
-        INSERT INTO issue_delivery_queue(newsletter_issue_id, subscriber_email)
-        SELECT $1, email
-        FROM subscriptions
-        WHERE status = 'confirmed'
+        SELECT id, title
+        FROM newsletter_issues
+        WHERE status = 'draft'
+        ORDER BY title
         "#,
-        newsletter_issue_id,
     )
-    .execute(transaction)
-    .await?;
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the newsletter drafts")?;
+
+    Ok(drafts)
+}
 
-    Ok(())
+#[tracing::instrument(skip_all)]
+async fn get_issues(pool: &sqlx::PgPool) -> Result<Vec<IssueSummary>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        IssueSummary,
+        r#"
+        SELECT id, title, status
+        FROM newsletter_issues
+        WHERE status <> 'draft'
+        ORDER BY COALESCE(published_at, scheduled_for) DESC
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the newsletter issues")?;
+
+    Ok(issues)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_send_at, parse_send_at};
+    use claim::assert_err;
+    use time::macros::datetime;
+
+    #[test]
+    fn send_at_is_parsed_as_utc() {
+        assert_eq!(
+            parse_send_at("2022-12-18T10:30").unwrap(),
+            datetime!(2022-12-18 10:30 UTC)
+        );
+        assert_eq!(
+            parse_send_at("2022-12-18T10:30:15").unwrap(),
+            datetime!(2022-12-18 10:30:15 UTC)
+        );
+    }
+
+    #[test]
+    fn invalid_send_at_is_rejected() {
+        assert_err!(parse_send_at(""));
+        assert_err!(parse_send_at("tomorrow"));
+        assert_err!(parse_send_at("2022-12-18 10:30"));
+    }
+
+    #[test]
+    fn formatted_send_at_can_be_parsed_back() {
+        let send_at = datetime!(2022-12-18 10:30 +02:00);
+        let formatted = format_send_at(send_at);
+
+        assert_eq!(formatted, "2022-12-18T08:30");
+        assert_eq!(parse_send_at(&formatted).unwrap(), send_at);
+    }
 }
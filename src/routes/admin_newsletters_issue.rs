@@ -0,0 +1,459 @@
+use super::admin_newsletters::{format_send_at, parse_scheduled_for, IssueStatus, PublishError};
+use super::admin_newsletters::{SCHEDULED_MESSAGE, SUCCESS_MESSAGE};
+use crate::authentication::UserId;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailSender;
+use crate::issue_delivery_worker::{enqueue_delivery_tasks, DeliveryOutcome};
+use crate::issue_template::{self, SubscriberVariables};
+use crate::routes::{e500, see_other};
+use crate::startup::ApplicationBaseUrl;
+use actix_web::http::header::ContentType;
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use askama::Template;
+use uuid::Uuid;
+
+pub struct Issue {
+    id: Uuid,
+    title: String,
+    text_content: String,
+    html_content: String,
+    content_is_template: bool,
+    status: String,
+    send_at: Option<String>,
+}
+
+impl Issue {
+    fn is_draft(&self) -> bool {
+        self.status == "draft"
+    }
+}
+
+pub struct DeliveryStats {
+    queued: i64,
+    sent: i64,
+    failed: i64,
+    skipped: i64,
+}
+
+#[derive(askama::Template)]
+#[template(path = "admin_newsletter_issue.html.j2")]
+pub struct NewsletterIssueTemplate {
+    user_id: Option<Uuid>,
+    flash_messages: Option<IncomingFlashMessages>,
+    issue: Issue,
+    stats: DeliveryStats,
+}
+
+pub async fn admin_newsletter_issue(
+    pool: web::Data<sqlx::PgPool>,
+    user_id: web::ReqData<UserId>,
+    issue_id: web::Path<Uuid>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue = match get_issue(&pool, *issue_id).await.map_err(e500)? {
+        Some(issue) => issue,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let stats = get_delivery_stats(&pool, issue.id).await.map_err(e500)?;
+
+    let tpl = NewsletterIssueTemplate {
+        user_id: Some(*user_id.into_inner()),
+        flash_messages: Some(flash_messages),
+        issue,
+        stats,
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(tpl.render().unwrap()))
+}
+
+#[derive(askama::Template)]
+#[template(path = "admin_newsletter_preview.html.j2")]
+pub struct NewsletterPreviewTemplate {
+    user_id: Option<Uuid>,
+    flash_messages: Option<IncomingFlashMessages>,
+    issue: Issue,
+}
+
+pub async fn admin_newsletter_preview(
+    pool: web::Data<sqlx::PgPool>,
+    user_id: web::ReqData<UserId>,
+    issue_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue = match get_issue(&pool, *issue_id).await.map_err(e500)? {
+        Some(issue) => issue,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let tpl = NewsletterPreviewTemplate {
+        user_id: Some(*user_id.into_inner()),
+        flash_messages: None,
+        issue,
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(tpl.render().unwrap()))
+}
+
+pub struct Delivery {
+    subscriber_email: String,
+    outcome: String,
+    n_attempts: i32,
+    response_body: Option<String>,
+    recorded_at: time::OffsetDateTime,
+}
+
+#[derive(askama::Template)]
+#[template(path = "admin_newsletter_deliveries.html.j2")]
+pub struct NewsletterDeliveriesTemplate {
+    user_id: Option<Uuid>,
+    flash_messages: Option<IncomingFlashMessages>,
+    issue: Issue,
+    deliveries: Vec<Delivery>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct DeliveriesQuery {
+    outcome: Option<DeliveryOutcome>,
+}
+
+pub async fn admin_newsletter_deliveries(
+    pool: web::Data<sqlx::PgPool>,
+    user_id: web::ReqData<UserId>,
+    issue_id: web::Path<Uuid>,
+    query: web::Query<DeliveriesQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue = match get_issue(&pool, *issue_id).await.map_err(e500)? {
+        Some(issue) => issue,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let deliveries = get_deliveries(&pool, issue.id, query.outcome)
+        .await
+        .map_err(e500)?;
+
+    let tpl = NewsletterDeliveriesTemplate {
+        user_id: Some(*user_id.into_inner()),
+        flash_messages: None,
+        issue,
+        deliveries,
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(tpl.render().unwrap()))
+}
+
+const NOT_A_DRAFT_MESSAGE: &str = "This newsletter issue is not a draft anymore";
+
+#[derive(serde::Deserialize)]
+pub struct DraftFormData {
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+#[tracing::instrument(name = "Save a newsletter draft", skip(pool, form))]
+pub async fn admin_save_newsletter_draft(
+    pool: web::Data<sqlx::PgPool>,
+    issue_id: web::Path<Uuid>,
+    form: web::Form<DraftFormData>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let location = format!("/admin/newsletters/{}", issue_id);
+
+    if form.title.is_empty() {
+        FlashMessage::error("The title is missing").send();
+        return Ok(see_other(&location));
+    }
+
+    let updated = update_draft(&pool, *issue_id, &form).await.map_err(e500)?;
+
+    if updated {
+        FlashMessage::info("The newsletter draft has been saved").send();
+    } else {
+        FlashMessage::error(NOT_A_DRAFT_MESSAGE).send();
+    }
+
+    Ok(see_other(&location))
+}
+
+#[derive(serde::Deserialize)]
+pub struct TestSendFormData {
+    email: String,
+}
+
+#[tracing::instrument(
+    name = "Send a test newsletter",
+    skip(pool, email_client, base_url, form)
+)]
+pub async fn admin_test_send_newsletter(
+    pool: web::Data<sqlx::PgPool>,
+    email_client: web::Data<dyn EmailSender>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    issue_id: web::Path<Uuid>,
+    form: web::Form<TestSendFormData>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let location = format!("/admin/newsletters/{}", issue_id);
+
+    let issue = match get_issue(&pool, *issue_id).await.map_err(e500)? {
+        Some(issue) => issue,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let recipient = match SubscriberEmail::parse(form.0.email) {
+        Ok(recipient) => recipient,
+        Err(_) => {
+            FlashMessage::error("The test email address is invalid").send();
+            return Ok(see_other(&location));
+        }
+    };
+
+    // The test recipient is not a subscriber, the remaining variables are placeholders
+    let unsubscribe_link = format!("{}/subscriptions/unsubscribe", base_url.0);
+    let variables = SubscriberVariables::new(
+        "Test subscriber",
+        recipient.as_ref(),
+        time::OffsetDateTime::now_utc(),
+        &unsubscribe_link,
+    );
+
+    let contents = issue_template::render(
+        &issue.html_content,
+        &issue.text_content,
+        issue.content_is_template,
+        &variables,
+    );
+    let (html_content, text_content) = match contents {
+        Ok(contents) => contents,
+        Err(err) => {
+            FlashMessage::error(format!("The newsletter content is invalid: {}", err)).send();
+            return Ok(see_other(&location));
+        }
+    };
+
+    let send_result = email_client
+        .send_email(
+            &recipient,
+            &format!("[Test] {}", issue.title),
+            &html_content,
+            &text_content,
+            None,
+        )
+        .await;
+
+    match send_result {
+        Ok(_) => {
+            FlashMessage::info(format!("A test email has been sent to {}", recipient)).send();
+        }
+        Err(err) => {
+            tracing::error!(
+                error.cause_chain = ?err,
+                error.message = %err,
+                "Failed to send a test newsletter",
+            );
+            FlashMessage::error("Failed to send the test email").send();
+        }
+    }
+
+    Ok(see_other(&location))
+}
+
+#[derive(serde::Deserialize)]
+pub struct PublishDraftFormData {
+    send_at: Option<String>,
+}
+
+#[tracing::instrument(name = "Publish a newsletter draft", skip(pool, form))]
+pub async fn admin_publish_newsletter_draft(
+    pool: web::Data<sqlx::PgPool>,
+    issue_id: web::Path<Uuid>,
+    form: web::Form<PublishDraftFormData>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let location = format!("/admin/newsletters/{}", issue_id);
+
+    let issue = match get_issue(&pool, *issue_id).await.map_err(e500)? {
+        Some(issue) => issue,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    if issue.text_content.is_empty() || issue.html_content.is_empty() {
+        FlashMessage::error("The newsletter content is missing").send();
+        return Ok(see_other(&location));
+    }
+
+    if issue.content_is_template {
+        if let Err(err) = issue_template::validate(&issue.html_content, &issue.text_content) {
+            FlashMessage::error(format!("The newsletter content is invalid: {}", err)).send();
+            return Ok(see_other(&location));
+        }
+    }
+
+    let status = match parse_scheduled_for(form.send_at.as_deref()) {
+        Ok(Some(scheduled_for)) => IssueStatus::Scheduled(scheduled_for),
+        Ok(None) => IssueStatus::Published,
+        Err(PublishError::SendTimeInThePast) => {
+            FlashMessage::error("The send time must be in the future").send();
+            return Ok(see_other(&location));
+        }
+        Err(_) => {
+            FlashMessage::error("The send time is invalid").send();
+            return Ok(see_other(&location));
+        }
+    };
+
+    let mut transaction = pool.begin().await.map_err(e500)?;
+    let published = publish_draft(&mut transaction, *issue_id, &status)
+        .await
+        .map_err(e500)?;
+    transaction.commit().await.map_err(e500)?;
+
+    match (published, status) {
+        (false, _) => FlashMessage::error(NOT_A_DRAFT_MESSAGE).send(),
+        (true, IssueStatus::Scheduled(_)) => FlashMessage::info(SCHEDULED_MESSAGE).send(),
+        (true, _) => FlashMessage::info(SUCCESS_MESSAGE).send(),
+    }
+
+    Ok(see_other(&location))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_issue(pool: &sqlx::PgPool, issue_id: Uuid) -> Result<Option<Issue>, anyhow::Error> {
+    let record = sqlx::query!(
+        r#"
+        SELECT id, title, text_content, html_content, content_is_template, status, scheduled_for
+        FROM newsletter_issues
+        WHERE id = $1
+        "#,
+        issue_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the newsletter issue")?;
+
+    Ok(record.map(|record| Issue {
+        id: record.id,
+        title: record.title,
+        text_content: record.text_content,
+        html_content: record.html_content,
+        content_is_template: record.content_is_template,
+        status: record.status,
+        send_at: record.scheduled_for.map(format_send_at),
+    }))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_delivery_stats(
+    pool: &sqlx::PgPool,
+    issue_id: Uuid,
+) -> Result<DeliveryStats, anyhow::Error> {
+    let stats = sqlx::query_as!(
+        DeliveryStats,
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM issue_delivery_queue WHERE newsletter_issue_id = $1) as "queued!",
+            COUNT(*) FILTER (WHERE outcome = 'sent') as "sent!",
+            COUNT(*) FILTER (WHERE outcome = 'failed') as "failed!",
+            COUNT(*) FILTER (WHERE outcome = 'skipped') as "skipped!"
+        FROM newsletter_deliveries
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to fetch the delivery statistics")?;
+
+    Ok(stats)
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_deliveries(
+    pool: &sqlx::PgPool,
+    issue_id: Uuid,
+    outcome: Option<DeliveryOutcome>,
+) -> Result<Vec<Delivery>, anyhow::Error> {
+    let deliveries = sqlx::query_as!(
+        Delivery,
+        r#"
+        SELECT subscriber_email, outcome, n_attempts, response_body, recorded_at
+        FROM newsletter_deliveries
+        WHERE newsletter_issue_id = $1 AND ($2::text IS NULL OR outcome = $2)
+        ORDER BY subscriber_email
+        "#,
+        issue_id,
+        outcome.map(|outcome| outcome.as_str()),
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the deliveries")?;
+
+    Ok(deliveries)
+}
+
+/// Saves the contents of a draft, written as templates from now on even if the draft is older
+/// than them.
+#[tracing::instrument(skip(pool, form))]
+async fn update_draft(
+    pool: &sqlx::PgPool,
+    issue_id: Uuid,
+    form: &DraftFormData,
+) -> Result<bool, anyhow::Error> {
+    let updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET title = $2, text_content = $3, html_content = $4, content_is_template = true
+        WHERE id = $1 AND status = 'draft'
+        "#,
+        issue_id,
+        form.title,
+        form.text_content,
+        form.html_content,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to update the newsletter draft")?
+    .rows_affected();
+
+    Ok(updated > 0)
+}
+
+/// Publishes or schedules a draft, returning `false` if the issue is not a draft anymore.
+#[tracing::instrument(skip(transaction, status))]
+pub(crate) async fn publish_draft(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    issue_id: Uuid,
+    status: &IssueStatus,
+) -> Result<bool, anyhow::Error> {
+    let updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = $2, scheduled_for = $3, published_at = CASE WHEN $2 = 'published' THEN now() END
+        WHERE id = $1 AND status = 'draft'
+        "#,
+        issue_id,
+        status.as_str(),
+        status.scheduled_for(),
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to publish the newsletter draft")?
+    .rows_affected();
+
+    if updated == 0 {
+        return Ok(false);
+    }
+
+    if let IssueStatus::Published = status {
+        enqueue_delivery_tasks(transaction, issue_id)
+            .await
+            .context("Failed to enqueue delivery tasks")?;
+    }
+
+    Ok(true)
+}
@@ -0,0 +1,164 @@
+use super::admin_newsletters::{format_send_at, parse_send_at};
+use crate::authentication::UserId;
+use crate::routes::{e500, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use askama::Template;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+pub struct ScheduledIssue {
+    id: Uuid,
+    title: String,
+    send_at: String,
+}
+
+#[derive(askama::Template)]
+#[template(path = "admin_scheduled_newsletters.html.j2")]
+pub struct ScheduledNewslettersTemplate {
+    user_id: Option<Uuid>,
+    flash_messages: Option<IncomingFlashMessages>,
+    issues: Vec<ScheduledIssue>,
+}
+
+pub async fn admin_scheduled_newsletters(
+    pool: web::Data<sqlx::PgPool>,
+    user_id: web::ReqData<UserId>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issues = get_scheduled_issues(&pool).await.map_err(e500)?;
+
+    let tpl = ScheduledNewslettersTemplate {
+        user_id: Some(*user_id.into_inner()),
+        flash_messages: Some(flash_messages),
+        issues,
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(tpl.render().unwrap()))
+}
+
+const NOT_SCHEDULED_MESSAGE: &str = "This newsletter issue is not scheduled anymore";
+
+#[derive(serde::Deserialize)]
+pub struct RescheduleFormData {
+    send_at: String,
+}
+
+#[tracing::instrument(name = "Reschedule a newsletter issue", skip(pool, form))]
+pub async fn admin_reschedule_newsletter(
+    pool: web::Data<sqlx::PgPool>,
+    issue_id: web::Path<Uuid>,
+    form: web::Form<RescheduleFormData>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let scheduled_for = match parse_send_at(&form.send_at) {
+        Ok(scheduled_for) if scheduled_for > OffsetDateTime::now_utc() => scheduled_for,
+        Ok(_) => {
+            FlashMessage::error("The send time must be in the future").send();
+            return Ok(see_other("/admin/newsletters/scheduled"));
+        }
+        Err(_) => {
+            FlashMessage::error("The send time is invalid").send();
+            return Ok(see_other("/admin/newsletters/scheduled"));
+        }
+    };
+
+    let rescheduled = reschedule_issue(&pool, *issue_id, scheduled_for)
+        .await
+        .map_err(e500)?;
+
+    if rescheduled {
+        FlashMessage::info("The newsletter issue has been rescheduled").send();
+    } else {
+        FlashMessage::error(NOT_SCHEDULED_MESSAGE).send();
+    }
+
+    Ok(see_other("/admin/newsletters/scheduled"))
+}
+
+#[tracing::instrument(name = "Cancel a scheduled newsletter issue", skip(pool))]
+pub async fn admin_cancel_newsletter(
+    pool: web::Data<sqlx::PgPool>,
+    issue_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let cancelled = cancel_issue(&pool, *issue_id).await.map_err(e500)?;
+
+    if cancelled {
+        FlashMessage::info("The newsletter issue has been cancelled").send();
+    } else {
+        FlashMessage::error(NOT_SCHEDULED_MESSAGE).send();
+    }
+
+    Ok(see_other("/admin/newsletters/scheduled"))
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_scheduled_issues(pool: &sqlx::PgPool) -> Result<Vec<ScheduledIssue>, anyhow::Error> {
+    let records = sqlx::query!(
+        r#"
+        SELECT id, title, scheduled_for as "scheduled_for!"
+        FROM newsletter_issues
+        WHERE status = 'scheduled'
+        ORDER BY scheduled_for
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the scheduled newsletter issues")?;
+
+    let issues = records
+        .into_iter()
+        .map(|record| ScheduledIssue {
+            id: record.id,
+            title: record.title,
+            send_at: format_send_at(record.scheduled_for),
+        })
+        .collect();
+
+    Ok(issues)
+}
+
+#[tracing::instrument(skip(pool))]
+async fn reschedule_issue(
+    pool: &sqlx::PgPool,
+    issue_id: Uuid,
+    scheduled_for: OffsetDateTime,
+) -> Result<bool, anyhow::Error> {
+    let updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET scheduled_for = $2
+        WHERE id = $1 AND status = 'scheduled'
+        "#,
+        issue_id,
+        scheduled_for,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to reschedule the newsletter issue")?
+    .rows_affected();
+
+    Ok(updated > 0)
+}
+
+#[tracing::instrument(skip(pool))]
+async fn cancel_issue(pool: &sqlx::PgPool, issue_id: Uuid) -> Result<bool, anyhow::Error> {
+    let updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'cancelled'
+        WHERE id = $1 AND status = 'scheduled'
+        "#,
+        issue_id,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to cancel the newsletter issue")?
+    .rows_affected();
+
+    Ok(updated > 0)
+}
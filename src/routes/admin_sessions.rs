@@ -0,0 +1,99 @@
+use crate::authentication::UserId;
+use crate::routes::{e500, see_other};
+use crate::sessions::{current_session_id, UserSession, UserSessionStore};
+use crate::startup::HmacSecret;
+use actix_web::http::header::ContentType;
+use actix_web::web;
+use actix_web::{HttpRequest, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use askama::Template;
+use uuid::Uuid;
+
+#[derive(askama::Template)]
+#[template(path = "admin_sessions.html.j2")]
+pub struct SessionsTemplate {
+    user_id: Option<Uuid>,
+    flash_messages: Option<IncomingFlashMessages>,
+    current_session_id: Option<Uuid>,
+    sessions: Vec<UserSession>,
+}
+
+pub async fn admin_sessions(
+    session_store: web::Data<dyn UserSessionStore>,
+    hmac_secret: web::Data<HmacSecret>,
+    user_id: web::ReqData<UserId>,
+    req: HttpRequest,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+
+    let sessions = session_store
+        .get_user_sessions(*user_id)
+        .await
+        .map_err(e500)?;
+
+    let tpl = SessionsTemplate {
+        user_id: Some(*user_id),
+        flash_messages: Some(flash_messages),
+        current_session_id: current_session_id(&req, &hmac_secret.cookie_key()),
+        sessions,
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(tpl.render().unwrap()))
+}
+
+#[tracing::instrument(name = "Revoke a session", skip(session_store, hmac_secret, req))]
+pub async fn admin_revoke_session(
+    session_store: web::Data<dyn UserSessionStore>,
+    hmac_secret: web::Data<HmacSecret>,
+    user_id: web::ReqData<UserId>,
+    req: HttpRequest,
+    session_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let session_id = session_id.into_inner();
+
+    if current_session_id(&req, &hmac_secret.cookie_key()) == Some(session_id) {
+        FlashMessage::error("Log out to end the session you are using").send();
+        return Ok(see_other("/admin/sessions"));
+    }
+
+    // Only the sessions of the user can be found, whatever the id
+    let deleted = session_store
+        .delete_user_session(*user_id, session_id)
+        .await
+        .map_err(e500)?;
+
+    if deleted {
+        FlashMessage::info("The session has been revoked").send();
+    } else {
+        FlashMessage::error("This session doesn't exist anymore").send();
+    }
+
+    Ok(see_other("/admin/sessions"))
+}
+
+#[tracing::instrument(
+    name = "Revoke the other sessions",
+    skip(session_store, hmac_secret, req)
+)]
+pub async fn admin_revoke_other_sessions(
+    session_store: web::Data<dyn UserSessionStore>,
+    hmac_secret: web::Data<HmacSecret>,
+    user_id: web::ReqData<UserId>,
+    req: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+
+    let current_session_id = current_session_id(&req, &hmac_secret.cookie_key());
+    session_store
+        .delete_user_sessions(*user_id, current_session_id)
+        .await
+        .map_err(e500)?;
+
+    FlashMessage::info("You have been logged out everywhere else").send();
+
+    Ok(see_other("/admin/sessions"))
+}
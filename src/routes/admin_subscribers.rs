@@ -0,0 +1,426 @@
+use super::subscriptions::{consume_tokens, resend_confirmation_email};
+use crate::authentication::{Role, UserId};
+use crate::domain::SubscriberStatus;
+use crate::routes::{e500, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::web;
+use actix_web::{HttpRequest, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use askama::Template;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+const PAGE_SIZE: i64 = 50;
+
+const UNKNOWN_SUBSCRIBER_MESSAGE: &str = "This subscriber doesn't exist anymore";
+
+pub struct Subscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: OffsetDateTime,
+}
+
+impl Subscriber {
+    fn is_pending(&self) -> bool {
+        self.status == SubscriberStatus::PendingConfirmation.as_str()
+    }
+
+    fn is_unsubscribed(&self) -> bool {
+        self.status == SubscriberStatus::Unsubscribed.as_str()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriberSort {
+    #[default]
+    Newest,
+    Oldest,
+}
+
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct SubscribersQuery {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    status: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    search: String,
+    #[serde(default)]
+    sort: SubscriberSort,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    page: Option<i64>,
+}
+
+impl SubscribersQuery {
+    /// The link to another page of the same list.
+    fn page_link(&self, page: i64) -> String {
+        let query = Self {
+            page: Some(page),
+            ..self.clone()
+        };
+        format!(
+            "/admin/subscribers?{}",
+            serde_urlencoded::to_string(query).unwrap()
+        )
+    }
+}
+
+#[derive(askama::Template)]
+#[template(path = "admin_subscribers.html.j2")]
+pub struct SubscribersTemplate {
+    user_id: Option<Uuid>,
+    flash_messages: Option<IncomingFlashMessages>,
+    can_edit: bool,
+    statuses: [SubscriberStatus; 3],
+    query: SubscribersQuery,
+    /// Appended to the actions so that they come back to the same page.
+    return_query: String,
+    subscribers: Vec<Subscriber>,
+    total: i64,
+    page: i64,
+    page_count: i64,
+    previous_page_link: Option<String>,
+    next_page_link: Option<String>,
+}
+
+pub async fn admin_subscribers(
+    pool: web::Data<sqlx::PgPool>,
+    user_id: web::ReqData<UserId>,
+    role: web::ReqData<Role>,
+    req: HttpRequest,
+    query: web::Query<SubscribersQuery>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let query = query.into_inner();
+
+    // An unknown status shows every subscriber, like the empty one of the form
+    let status = SubscriberStatus::parse(&query.status).ok();
+    let search = query.search.trim();
+
+    let total = count_subscribers(&pool, status, search)
+        .await
+        .map_err(e500)?;
+    let page_count = ((total + PAGE_SIZE - 1) / PAGE_SIZE).max(1);
+    // Past the last page, show the last page rather than an empty one
+    let page = query.page.unwrap_or(1).clamp(1, page_count);
+
+    let subscribers = get_subscribers(&pool, status, search, query.sort, page)
+        .await
+        .map_err(e500)?;
+
+    let previous_page_link = (page > 1).then(|| query.page_link(page - 1));
+    let next_page_link = (page < page_count).then(|| query.page_link(page + 1));
+
+    let tpl = SubscribersTemplate {
+        user_id: Some(*user_id.into_inner()),
+        flash_messages: Some(flash_messages),
+        can_edit: role.into_inner() >= Role::Editor,
+        statuses: SubscriberStatus::ALL,
+        return_query: return_query(&req),
+        query,
+        subscribers,
+        total,
+        page,
+        page_count,
+        previous_page_link,
+        next_page_link,
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(tpl.render().unwrap()))
+}
+
+#[tracing::instrument(name = "Confirm a subscriber by hand", skip(pool, req))]
+pub async fn admin_confirm_subscriber(
+    pool: web::Data<sqlx::PgPool>,
+    req: HttpRequest,
+    subscriber_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let updated = update_status(
+        &pool,
+        *subscriber_id,
+        &[SubscriberStatus::PendingConfirmation],
+        SubscriberStatus::Confirmed,
+    )
+    .await
+    .map_err(e500)?;
+
+    match updated {
+        None => FlashMessage::error(UNKNOWN_SUBSCRIBER_MESSAGE).send(),
+        Some(false) => FlashMessage::error("Only pending subscribers can be confirmed").send(),
+        Some(true) => FlashMessage::info("The subscriber has been confirmed").send(),
+    }
+
+    Ok(see_other(&list_location(&req)))
+}
+
+#[tracing::instrument(name = "Unsubscribe a subscriber by hand", skip(pool, req))]
+pub async fn admin_unsubscribe_subscriber(
+    pool: web::Data<sqlx::PgPool>,
+    req: HttpRequest,
+    subscriber_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let updated = update_status(
+        &pool,
+        *subscriber_id,
+        &[
+            SubscriberStatus::PendingConfirmation,
+            SubscriberStatus::Confirmed,
+        ],
+        SubscriberStatus::Unsubscribed,
+    )
+    .await
+    .map_err(e500)?;
+
+    match updated {
+        None => FlashMessage::error(UNKNOWN_SUBSCRIBER_MESSAGE).send(),
+        Some(false) => FlashMessage::error("This subscriber has already unsubscribed").send(),
+        Some(true) => FlashMessage::info("The subscriber has been unsubscribed").send(),
+    }
+
+    Ok(see_other(&list_location(&req)))
+}
+
+#[tracing::instrument(name = "Delete a subscriber", skip(pool, req))]
+pub async fn admin_delete_subscriber(
+    pool: web::Data<sqlx::PgPool>,
+    req: HttpRequest,
+    subscriber_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let deleted = sqlx::query!(r#"DELETE FROM subscriptions WHERE id = $1"#, *subscriber_id)
+        .execute(pool.as_ref())
+        .await
+        .context("Failed to delete the subscriber")
+        .map_err(e500)?
+        .rows_affected();
+
+    if deleted > 0 {
+        FlashMessage::info("The subscriber has been deleted").send();
+    } else {
+        FlashMessage::error(UNKNOWN_SUBSCRIBER_MESSAGE).send();
+    }
+
+    Ok(see_other(&list_location(&req)))
+}
+
+#[tracing::instrument(
+    name = "Resend the confirmation email of a subscriber",
+    skip(pool, req)
+)]
+pub async fn admin_resend_confirmation(
+    pool: web::Data<sqlx::PgPool>,
+    req: HttpRequest,
+    subscriber_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let location = list_location(&req);
+
+    let subscriber = match get_subscriber(&pool, *subscriber_id).await.map_err(e500)? {
+        Some(subscriber) => subscriber,
+        None => {
+            FlashMessage::error(UNKNOWN_SUBSCRIBER_MESSAGE).send();
+            return Ok(see_other(&location));
+        }
+    };
+    if !subscriber.is_pending() {
+        FlashMessage::error("Only pending subscribers can be sent a confirmation email").send();
+        return Ok(see_other(&location));
+    }
+
+    resend_confirmation_email(&pool, subscriber.id)
+        .await
+        .map_err(e500)?;
+
+    FlashMessage::info("The confirmation email will be sent again").send();
+    Ok(see_other(&location))
+}
+
+/// The query string of the list the request comes from, to display the same page afterwards.
+fn return_query(req: &HttpRequest) -> String {
+    match req.query_string() {
+        "" => String::new(),
+        query => format!("?{}", query),
+    }
+}
+
+fn list_location(req: &HttpRequest) -> String {
+    format!("/admin/subscribers{}", return_query(req))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_subscribers(
+    pool: &sqlx::PgPool,
+    status: Option<SubscriberStatus>,
+    search: &str,
+    sort: SubscriberSort,
+    page: i64,
+) -> Result<Vec<Subscriber>, anyhow::Error> {
+    let status = status.map(|status| status.as_str());
+    let pattern = format!("%{}%", escape_like_pattern(search));
+
+    let subscribers = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE ($1::text IS NULL OR status = $1)
+            AND (email ILIKE $2 OR name ILIKE $2)
+        ORDER BY
+            CASE WHEN $3 THEN subscribed_at END ASC,
+            CASE WHEN NOT $3 THEN subscribed_at END DESC,
+            id
+        LIMIT $4 OFFSET $5
+        "#,
+        status,
+        pattern,
+        sort == SubscriberSort::Oldest,
+        PAGE_SIZE,
+        (page - 1) * PAGE_SIZE,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the subscribers")?;
+
+    Ok(subscribers)
+}
+
+#[tracing::instrument(skip(pool))]
+async fn count_subscribers(
+    pool: &sqlx::PgPool,
+    status: Option<SubscriberStatus>,
+    search: &str,
+) -> Result<i64, anyhow::Error> {
+    let status = status.map(|status| status.as_str());
+    let pattern = format!("%{}%", escape_like_pattern(search));
+
+    let total = sqlx::query!(
+        r#"
+        SELECT COUNT(*) as "total!"
+        FROM subscriptions
+        WHERE ($1::text IS NULL OR status = $1)
+            AND (email ILIKE $2 OR name ILIKE $2)
+        "#,
+        status,
+        pattern,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to count the subscribers")?
+    .total;
+
+    Ok(total)
+}
+
+/// Escapes the wildcards of a `LIKE` pattern, so that a search matches them literally.
+fn escape_like_pattern(value: &str) -> String {
+    let mut pattern = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_subscriber(
+    pool: &sqlx::PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<Subscriber>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the subscriber")?;
+
+    Ok(subscriber)
+}
+
+/// Changes the status of a subscriber if it is currently one of `from`, consuming their
+/// confirmation tokens so that a pending link can't change it back.
+///
+/// Returns `None` if the subscriber doesn't exist, otherwise whether the status was changed.
+#[tracing::instrument(skip(pool))]
+async fn update_status(
+    pool: &sqlx::PgPool,
+    subscriber_id: Uuid,
+    from: &[SubscriberStatus],
+    to: SubscriberStatus,
+) -> Result<Option<bool>, anyhow::Error> {
+    if get_subscriber(pool, subscriber_id).await?.is_none() {
+        return Ok(None);
+    }
+
+    let from: Vec<String> = from
+        .iter()
+        .map(|status| status.as_str().to_string())
+        .collect();
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let updated = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = $2
+        WHERE id = $1 AND status = ANY($3)
+        "#,
+        subscriber_id,
+        to.as_str(),
+        &from,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to update the status of the subscriber")?
+    .rows_affected();
+
+    if updated > 0 {
+        consume_tokens(&mut transaction, subscriber_id, OffsetDateTime::now_utc())
+            .await
+            .context("Failed to consume the confirmation tokens of the subscriber")?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update the status of a subscriber")?;
+
+    Ok(Some(updated > 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape_like_pattern, SubscriberSort, SubscribersQuery};
+
+    #[test]
+    fn like_wildcards_are_escaped() {
+        assert_eq!(escape_like_pattern("ursula"), "ursula");
+        assert_eq!(escape_like_pattern("100%_\\"), "100\\%\\_\\\\");
+    }
+
+    #[test]
+    fn page_links_keep_the_filters() {
+        let query = SubscribersQuery {
+            status: "confirmed".to_string(),
+            search: "le guin".to_string(),
+            sort: SubscriberSort::Oldest,
+            page: Some(1),
+        };
+
+        assert_eq!(
+            query.page_link(2),
+            "/admin/subscribers?status=confirmed&search=le+guin&sort=oldest&page=2"
+        );
+    }
+}
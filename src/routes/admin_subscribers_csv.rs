@@ -0,0 +1,587 @@
+use super::subscriptions::{generate_subscription_token, store_token};
+use crate::authentication::UserId;
+use crate::confirmation_email_queue::enqueue_confirmation_email;
+use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName, SubscriberStatus};
+use crate::routes::{e500, format_api_time, see_other};
+use actix_multipart::{Field, Multipart, MultipartError};
+use actix_web::http::header::{ContentDisposition, ContentType, DispositionParam, DispositionType};
+use actix_web::web::{self, Bytes};
+use actix_web::HttpResponse;
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use askama::Template;
+use futures_util::{stream, Stream, StreamExt, TryStreamExt};
+use std::collections::HashSet;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// The largest CSV file which can be imported.
+const MAX_IMPORT_SIZE: usize = 10 * 1024 * 1024;
+
+/// How many subscribers are fetched at once while exporting.
+const EXPORT_BATCH_SIZE: i64 = 500;
+
+const IMPORT_LOCATION: &str = "/admin/subscribers/import";
+
+/// The largest value of the other fields of the import form.
+const MAX_TEXT_FIELD_SIZE: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportMode {
+    /// The subscribers already agreed somewhere else, like with the previous provider.
+    Confirmed,
+    /// The subscribers get a confirmation email, like when they subscribe themselves.
+    SendConfirmation,
+}
+
+impl ImportMode {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "confirmed" => Some(Self::Confirmed),
+            "send_confirmation" => Some(Self::SendConfirmation),
+            _ => None,
+        }
+    }
+
+    fn status(&self) -> SubscriberStatus {
+        match self {
+            Self::Confirmed => SubscriberStatus::Confirmed,
+            Self::SendConfirmation => SubscriberStatus::PendingConfirmation,
+        }
+    }
+}
+
+/// A row which can't be imported, with the line it comes from.
+#[derive(Debug, PartialEq, Eq)]
+pub struct RowError {
+    line: u64,
+    message: String,
+}
+
+pub struct ImportReport {
+    dry_run: bool,
+    mode: ImportMode,
+    imported: usize,
+    errors: Vec<RowError>,
+}
+
+#[derive(askama::Template)]
+#[template(path = "admin_subscribers_import.html.j2")]
+pub struct ImportTemplate {
+    user_id: Option<Uuid>,
+    flash_messages: Option<IncomingFlashMessages>,
+    max_import_size_mb: usize,
+    report: Option<ImportReport>,
+}
+
+impl ImportTemplate {
+    fn render_response(
+        user_id: UserId,
+        report: Option<ImportReport>,
+        flash_messages: IncomingFlashMessages,
+    ) -> HttpResponse {
+        let tpl = ImportTemplate {
+            user_id: Some(*user_id),
+            flash_messages: Some(flash_messages),
+            max_import_size_mb: MAX_IMPORT_SIZE / (1024 * 1024),
+            report,
+        };
+
+        HttpResponse::Ok()
+            .content_type(ContentType::html())
+            .body(tpl.render().unwrap())
+    }
+}
+
+pub async fn admin_import_subscribers_form(
+    user_id: web::ReqData<UserId>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    Ok(ImportTemplate::render_response(
+        user_id.into_inner(),
+        None,
+        flash_messages,
+    ))
+}
+
+/// The fields of the import form.
+#[derive(Default)]
+struct ImportForm {
+    mode: Option<String>,
+    dry_run: bool,
+    file: Option<Vec<u8>>,
+}
+
+/// Why the import form can't be used.
+enum ImportFormError {
+    Invalid(MultipartError),
+    TooLarge,
+}
+
+impl From<MultipartError> for ImportFormError {
+    fn from(err: MultipartError) -> Self {
+        Self::Invalid(err)
+    }
+}
+
+/// Reads the fields of the import form, ignoring the unknown ones.
+async fn read_import_form(mut payload: Multipart) -> Result<ImportForm, ImportFormError> {
+    let mut form = ImportForm::default();
+
+    while let Some(field) = payload.try_next().await? {
+        match field.name() {
+            Some("mode") => {
+                let value = read_field(field, MAX_TEXT_FIELD_SIZE).await?;
+                form.mode = String::from_utf8(value).ok();
+            }
+            Some("dry_run") => form.dry_run = true,
+            Some("file") => form.file = Some(read_field(field, MAX_IMPORT_SIZE).await?),
+            _ => {}
+        }
+    }
+
+    Ok(form)
+}
+
+/// Reads the content of a field, up to `limit` bytes.
+async fn read_field(mut field: Field, limit: usize) -> Result<Vec<u8>, ImportFormError> {
+    let mut content = Vec::new();
+
+    while let Some(chunk) = field.try_next().await? {
+        if content.len() + chunk.len() > limit {
+            return Err(ImportFormError::TooLarge);
+        }
+        content.extend_from_slice(&chunk);
+    }
+
+    Ok(content)
+}
+
+#[tracing::instrument(name = "Import subscribers", skip(pool, payload, flash_messages))]
+pub async fn admin_import_subscribers(
+    pool: web::Data<sqlx::PgPool>,
+    user_id: web::ReqData<UserId>,
+    payload: Multipart,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let form = match read_import_form(payload).await {
+        Ok(form) => form,
+        Err(ImportFormError::TooLarge) => {
+            FlashMessage::error("The CSV file is too large").send();
+            return Ok(see_other(IMPORT_LOCATION));
+        }
+        Err(ImportFormError::Invalid(err)) => {
+            tracing::warn!(error = %err, "invalid import form");
+            FlashMessage::error("The import form could not be read").send();
+            return Ok(see_other(IMPORT_LOCATION));
+        }
+    };
+
+    let mode = match form.mode.as_deref().and_then(ImportMode::parse) {
+        Some(mode) => mode,
+        None => {
+            FlashMessage::error("Choose whether the imported subscribers are already confirmed")
+                .send();
+            return Ok(see_other(IMPORT_LOCATION));
+        }
+    };
+    let dry_run = form.dry_run;
+
+    let file = match form.file {
+        Some(file) if !file.is_empty() => file,
+        _ => {
+            FlashMessage::error("The CSV file is missing").send();
+            return Ok(see_other(IMPORT_LOCATION));
+        }
+    };
+
+    let rows = match parse_csv(&file) {
+        Ok(rows) => rows,
+        Err(err) => {
+            FlashMessage::error(err).send();
+            return Ok(see_other(IMPORT_LOCATION));
+        }
+    };
+
+    let (subscribers, mut errors) = validate_rows(&pool, rows).await.map_err(e500)?;
+
+    let imported = if dry_run {
+        subscribers.len()
+    } else {
+        import(&pool, mode, subscribers, &mut errors)
+            .await
+            .map_err(e500)?
+    };
+
+    errors.sort_by_key(|error| error.line);
+
+    let report = ImportReport {
+        dry_run,
+        mode,
+        imported,
+        errors,
+    };
+
+    Ok(ImportTemplate::render_response(
+        user_id.into_inner(),
+        Some(report),
+        flash_messages,
+    ))
+}
+
+/// A parsed row of the CSV file, valid or not.
+#[derive(Debug)]
+struct ImportRow {
+    line: u64,
+    subscriber: Result<NewSubscriber, String>,
+}
+
+/// Parses the rows of a CSV file whose header has an `email` and a `name` column, in any order.
+fn parse_csv(data: &[u8]) -> Result<Vec<ImportRow>, String> {
+    // Spreadsheets tend to start their CSV files with a byte order mark
+    let data = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data);
+
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(data);
+
+    let headers = reader
+        .headers()
+        .map_err(|_| "The CSV file could not be read".to_string())?;
+    let column = |name: &str| headers.iter().position(|h| h.eq_ignore_ascii_case(name));
+    let (email_column, name_column) = match (column("email"), column("name")) {
+        (Some(email_column), Some(name_column)) => (email_column, name_column),
+        _ => return Err("The CSV file must have an email and a name column".to_string()),
+    };
+
+    let mut rows = Vec::new();
+    for result in reader.records() {
+        let row = match result {
+            Ok(record) => {
+                let line = record.position().map_or(0, |position| position.line());
+                let field = |column: usize, name: &str| {
+                    record
+                        .get(column)
+                        .map(str::to_string)
+                        .ok_or_else(|| format!("The {} is missing", name))
+                };
+                let subscriber = field(email_column, "email")
+                    .and_then(SubscriberEmail::parse)
+                    .and_then(|email| {
+                        let name = field(name_column, "name").and_then(SubscriberName::parse)?;
+                        Ok(NewSubscriber { email, name })
+                    });
+                ImportRow { line, subscriber }
+            }
+            Err(err) => ImportRow {
+                line: err.position().map_or(0, |position| position.line()),
+                subscriber: Err("This row could not be read".to_string()),
+            },
+        };
+        rows.push(row);
+    }
+
+    Ok(rows)
+}
+
+/// Splits the rows which can be imported from the others, which are invalid, repeated in the
+/// file or already on the list.
+async fn validate_rows(
+    pool: &sqlx::PgPool,
+    rows: Vec<ImportRow>,
+) -> Result<(Vec<(u64, NewSubscriber)>, Vec<RowError>), anyhow::Error> {
+    let emails: Vec<String> = rows
+        .iter()
+        .filter_map(|row| row.subscriber.as_ref().ok())
+        .map(|subscriber| subscriber.email.as_ref().to_string())
+        .collect();
+    let existing_emails: HashSet<String> = sqlx::query_scalar!(
+        r#"SELECT email FROM subscriptions WHERE email = ANY($1)"#,
+        &emails,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the existing subscribers")?
+    .into_iter()
+    .collect();
+
+    let mut seen_emails = HashSet::new();
+    let mut subscribers = Vec::new();
+    let mut errors = Vec::new();
+
+    for row in rows {
+        let subscriber = match row.subscriber {
+            Ok(subscriber) => subscriber,
+            Err(message) => {
+                errors.push(RowError {
+                    line: row.line,
+                    message,
+                });
+                continue;
+            }
+        };
+
+        let email = subscriber.email.as_ref();
+        let message = if existing_emails.contains(email) {
+            "This email address is already on the list"
+        } else if !seen_emails.insert(email.to_string()) {
+            "This email address is repeated in the file"
+        } else {
+            subscribers.push((row.line, subscriber));
+            continue;
+        };
+        errors.push(RowError {
+            line: row.line,
+            message: message.to_string(),
+        });
+    }
+
+    Ok((subscribers, errors))
+}
+
+/// Imports the subscribers, returning how many were, and enqueues the confirmation emails if
+/// needed: they are sent by the worker, so that large files don't keep the request waiting.
+#[tracing::instrument(skip(pool, subscribers, errors))]
+async fn import(
+    pool: &sqlx::PgPool,
+    mode: ImportMode,
+    subscribers: Vec<(u64, NewSubscriber)>,
+    errors: &mut Vec<RowError>,
+) -> Result<usize, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let now = OffsetDateTime::now_utc();
+    let mut imported = 0;
+
+    for (line, subscriber) in subscribers {
+        // Someone may have subscribed since the rows were validated
+        let subscriber_id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO subscriptions(id, email, name, subscribed_at, status)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (email) DO NOTHING
+            RETURNING id
+            "#,
+            Uuid::new_v4(),
+            subscriber.email.as_ref(),
+            subscriber.name.as_ref(),
+            now,
+            mode.status().as_str(),
+        )
+        .fetch_optional(&mut transaction)
+        .await
+        .context("Failed to insert an imported subscriber")?;
+
+        let subscriber_id = match subscriber_id {
+            Some(subscriber_id) => subscriber_id,
+            None => {
+                errors.push(RowError {
+                    line,
+                    message: "This email address is already on the list".to_string(),
+                });
+                continue;
+            }
+        };
+
+        if mode == ImportMode::SendConfirmation {
+            let subscription_token = generate_subscription_token();
+            store_token(&mut transaction, subscriber_id, &subscription_token)
+                .await
+                .context("Failed to store the confirmation token of an imported subscriber")?;
+            enqueue_confirmation_email(&mut transaction, subscriber_id, &subscription_token)
+                .await
+                .context("Failed to enqueue the confirmation email of an imported subscriber")?;
+        }
+
+        imported += 1;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to import subscribers")?;
+
+    Ok(imported)
+}
+
+#[derive(serde::Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    status: String,
+}
+
+/// Streams the subscribers as a CSV file, optionally only those with a given status.
+#[tracing::instrument(name = "Export subscribers", skip(pool, query))]
+pub async fn admin_export_subscribers(
+    pool: web::Data<sqlx::PgPool>,
+    query: web::Query<ExportQuery>,
+) -> HttpResponse {
+    let status = SubscriberStatus::parse(&query.status).ok();
+
+    let filename = match status {
+        Some(status) => format!("subscribers-{}.csv", status.as_str()),
+        None => "subscribers.csv".to_string(),
+    };
+
+    HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(filename)],
+        })
+        .streaming(export_stream(pool, status))
+}
+
+struct ExportCursor {
+    pool: web::Data<sqlx::PgPool>,
+    status: Option<SubscriberStatus>,
+    /// The sort key of the last exported subscriber.
+    after: Option<(OffsetDateTime, Uuid)>,
+    done: bool,
+}
+
+fn export_stream(
+    pool: web::Data<sqlx::PgPool>,
+    status: Option<SubscriberStatus>,
+) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+    let header = write_csv(&[["email", "name", "status", "subscribed_at"]]);
+
+    let cursor = ExportCursor {
+        pool,
+        status,
+        after: None,
+        done: false,
+    };
+
+    // Subscribers are fetched by batches so that neither the list nor a transaction are kept
+    // around while the client downloads the file.
+    let rows = stream::try_unfold(cursor, |mut cursor| async move {
+        if cursor.done {
+            return Ok(None);
+        }
+
+        let batch = get_subscribers_batch(&cursor.pool, cursor.status, cursor.after)
+            .await
+            .map_err(e500)?;
+        cursor.done = batch.len() < EXPORT_BATCH_SIZE as usize;
+        let last = match batch.last() {
+            Some(last) => last,
+            None => return Ok(None),
+        };
+        cursor.after = Some((last.subscribed_at, last.id));
+
+        let records: Vec<[String; 4]> = batch
+            .into_iter()
+            .map(|subscriber| {
+                [
+                    subscriber.email,
+                    subscriber.name,
+                    subscriber.status,
+                    format_api_time(subscriber.subscribed_at),
+                ]
+            })
+            .collect();
+
+        Ok(Some((write_csv(&records), cursor)))
+    });
+
+    stream::once(async move { Ok(header) }).chain(rows)
+}
+
+fn write_csv<R, F>(records: &[R]) -> Bytes
+where
+    R: AsRef<[F]>,
+    F: AsRef<[u8]>,
+{
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for record in records {
+        writer
+            .write_record(record.as_ref())
+            .expect("Writing CSV to memory can't fail");
+    }
+    Bytes::from(
+        writer
+            .into_inner()
+            .expect("Writing CSV to memory can't fail"),
+    )
+}
+
+struct ExportedSubscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: OffsetDateTime,
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_subscribers_batch(
+    pool: &sqlx::PgPool,
+    status: Option<SubscriberStatus>,
+    after: Option<(OffsetDateTime, Uuid)>,
+) -> Result<Vec<ExportedSubscriber>, anyhow::Error> {
+    let (after_subscribed_at, after_id) = after.unzip();
+
+    let subscribers = sqlx::query_as!(
+        ExportedSubscriber,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE ($1::text IS NULL OR status = $1)
+            AND ($2::timestamptz IS NULL OR (subscribed_at, id) > ($2, $3::uuid))
+        ORDER BY subscribed_at, id
+        LIMIT $4
+        "#,
+        status.map(|status| status.as_str()),
+        after_subscribed_at,
+        after_id,
+        EXPORT_BATCH_SIZE,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch a batch of subscribers to export")?;
+
+    Ok(subscribers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_csv;
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn valid_rows_are_parsed_whatever_the_order_of_the_columns() {
+        let data = "\u{FEFF}Name,Email,Source\nUrsula Le Guin, ursula@example.com ,old\n";
+
+        let rows = assert_ok!(parse_csv(data.as_bytes()));
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].line, 2);
+        let subscriber = rows[0].subscriber.as_ref().unwrap();
+        assert_eq!(subscriber.email.as_ref(), "ursula@example.com");
+        assert_eq!(subscriber.name.as_ref(), "Ursula Le Guin");
+    }
+
+    #[test]
+    fn invalid_rows_are_reported_with_their_line() {
+        let data = "email,name\nnot-an-email,Ursula\noctavia@example.com,\nfrank@example.com\n";
+
+        let rows = assert_ok!(parse_csv(data.as_bytes()));
+        let lines: Vec<u64> = rows.iter().map(|row| row.line).collect();
+        assert_eq!(lines, vec![2, 3, 4]);
+        assert!(rows.iter().all(|row| row.subscriber.is_err()));
+        assert_eq!(
+            rows[2].subscriber.as_ref().unwrap_err(),
+            "The name is missing"
+        );
+    }
+
+    #[test]
+    fn a_file_without_the_required_columns_is_rejected() {
+        assert_err!(parse_csv(b"email,first_name\nursula@example.com,Ursula\n"));
+        assert_err!(parse_csv(b""));
+    }
+}
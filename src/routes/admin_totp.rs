@@ -0,0 +1,194 @@
+use crate::authentication::{count_unused_recovery_codes, disable_totp, enable_totp};
+use crate::authentication::{get_totp_secret, validate_credentials, TotpSecret};
+use crate::authentication::{AuthError, Credentials, PasswordHashing, UserId};
+use crate::routes::admin_dashboard::get_username;
+use crate::routes::{e500, see_other};
+use crate::sessions::TypedSession;
+use actix_web::http::header::ContentType;
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use askama::Template;
+use secrecy::{ExposeSecret, Secret};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// The issuer shown in authenticator apps.
+const TOTP_ISSUER: &str = "zero2prod";
+
+#[derive(askama::Template)]
+#[template(path = "admin_totp.html.j2")]
+pub struct TotpTemplate {
+    user_id: Option<Uuid>,
+    flash_messages: Option<IncomingFlashMessages>,
+    enabled: bool,
+    unused_recovery_codes: i64,
+}
+
+pub async fn admin_totp(
+    pool: web::Data<sqlx::PgPool>,
+    user_id: web::ReqData<UserId>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+
+    let enabled = get_totp_secret(&pool, *user_id)
+        .await
+        .map_err(e500)?
+        .is_some();
+    let unused_recovery_codes = count_unused_recovery_codes(&pool, *user_id)
+        .await
+        .map_err(e500)?;
+
+    let tpl = TotpTemplate {
+        user_id: Some(*user_id),
+        flash_messages: Some(flash_messages),
+        enabled,
+        unused_recovery_codes,
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(tpl.render().unwrap()))
+}
+
+#[tracing::instrument(name = "Start TOTP enrollment", skip(session))]
+pub async fn admin_totp_enroll(session: TypedSession) -> Result<HttpResponse, actix_web::Error> {
+    // The secret is only stored for the user once confirmed with a first valid code
+    let secret = TotpSecret::generate();
+    session
+        .insert_pending_totp_secret(&secret.to_base32())
+        .map_err(e500)?;
+
+    Ok(see_other("/admin/totp/enroll"))
+}
+
+#[derive(askama::Template)]
+#[template(path = "admin_totp_enroll.html.j2")]
+pub struct TotpEnrollTemplate {
+    user_id: Option<Uuid>,
+    flash_messages: Option<IncomingFlashMessages>,
+    otpauth_uri: String,
+    secret: String,
+}
+
+pub async fn admin_totp_enroll_form(
+    pool: web::Data<sqlx::PgPool>,
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+
+    let secret = match session.get_pending_totp_secret().map_err(e500)? {
+        Some(secret) => TotpSecret::from_base32(&secret).map_err(e500)?,
+        None => return Ok(see_other("/admin/totp")),
+    };
+
+    let username = get_username(&pool, *user_id).await.map_err(e500)?;
+
+    let tpl = TotpEnrollTemplate {
+        user_id: Some(*user_id),
+        flash_messages: Some(flash_messages),
+        otpauth_uri: secret.otpauth_uri(TOTP_ISSUER, &username),
+        secret: secret.to_base32(),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(tpl.render().unwrap()))
+}
+
+#[derive(askama::Template)]
+#[template(path = "admin_totp_recovery_codes.html.j2")]
+pub struct TotpRecoveryCodesTemplate {
+    user_id: Option<Uuid>,
+    flash_messages: Option<IncomingFlashMessages>,
+    recovery_codes: Vec<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ConfirmTotpFormData {
+    code: String,
+}
+
+#[tracing::instrument(name = "Confirm TOTP enrollment", skip(pool, hashing, session, form))]
+pub async fn admin_totp_confirm(
+    pool: web::Data<sqlx::PgPool>,
+    hashing: web::Data<PasswordHashing>,
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+    form: web::Form<ConfirmTotpFormData>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+
+    let secret = match session.get_pending_totp_secret().map_err(e500)? {
+        Some(secret) => TotpSecret::from_base32(&secret).map_err(e500)?,
+        None => return Ok(see_other("/admin/totp")),
+    };
+
+    if secret
+        .verify(form.code.trim(), OffsetDateTime::now_utc(), None)
+        .is_none()
+    {
+        FlashMessage::error("The code is invalid, check the clock of your device and try again")
+            .send();
+        return Ok(see_other("/admin/totp/enroll"));
+    }
+
+    let recovery_codes = enable_totp(&pool, &hashing, *user_id, &secret)
+        .await
+        .map_err(e500)?;
+    session.remove_pending_totp_secret();
+
+    // The recovery codes are rendered right away since this is the only time they are known
+    let tpl = TotpRecoveryCodesTemplate {
+        user_id: Some(*user_id),
+        flash_messages: None,
+        recovery_codes: recovery_codes
+            .iter()
+            .map(|code| code.expose_secret().clone())
+            .collect(),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(tpl.render().unwrap()))
+}
+
+#[derive(serde::Deserialize)]
+pub struct DisableTotpFormData {
+    current_password: Secret<String>,
+}
+
+#[tracing::instrument(name = "Disable TOTP", skip(pool, hashing, form))]
+pub async fn admin_totp_disable(
+    pool: web::Data<sqlx::PgPool>,
+    hashing: web::Data<PasswordHashing>,
+    user_id: web::ReqData<UserId>,
+    form: web::Form<DisableTotpFormData>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+
+    let username = get_username(&pool, *user_id).await.map_err(e500)?;
+    let credentials = Credentials {
+        username,
+        password: form.0.current_password,
+    };
+
+    if let Err(err) = validate_credentials(&pool, &hashing, credentials).await {
+        match err {
+            AuthError::InvalidCredentials(_) => {
+                FlashMessage::error("The current password is incorrect").send();
+                return Ok(see_other("/admin/totp"));
+            }
+            AuthError::Unexpected(_) => return Err(e500(err).into()),
+        }
+    }
+
+    disable_totp(&pool, *user_id).await.map_err(e500)?;
+
+    FlashMessage::warning("Two-factor authentication has been disabled").send();
+
+    Ok(see_other("/admin/totp"))
+}
@@ -0,0 +1,444 @@
+use crate::authentication::{compute_password_hash, validate_new_password, PasswordHashing};
+use crate::authentication::{Role, UserId};
+use crate::domain::SubscriberEmail;
+use crate::invitation_queue::enqueue_invitation;
+use crate::routes::{e500, see_other};
+use crate::telemetry::spawn_blocking_with_tracing;
+use actix_web::http::header::ContentType;
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use askama::Template;
+use secrecy::{ExposeSecret, Secret};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+const UNKNOWN_USER_MESSAGE: &str = "This user doesn't exist anymore";
+const SELF_MANAGEMENT_MESSAGE: &str = "You can't change your own account from this page";
+
+pub struct User {
+    user_id: Uuid,
+    username: String,
+    email: Option<String>,
+    role: String,
+    disabled_at: Option<OffsetDateTime>,
+}
+
+pub struct PendingInvitation {
+    email: String,
+    role: String,
+    expires_at: OffsetDateTime,
+}
+
+#[derive(askama::Template)]
+#[template(path = "admin_users.html.j2")]
+pub struct UsersTemplate {
+    user_id: Option<Uuid>,
+    flash_messages: Option<IncomingFlashMessages>,
+    current_user_id: Uuid,
+    users: Vec<User>,
+    invitations: Vec<PendingInvitation>,
+    roles: [Role; 3],
+}
+
+pub async fn admin_users(
+    pool: web::Data<sqlx::PgPool>,
+    user_id: web::ReqData<UserId>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+
+    let users = get_users(&pool).await.map_err(e500)?;
+    let invitations = get_pending_invitations(&pool).await.map_err(e500)?;
+
+    let tpl = UsersTemplate {
+        user_id: Some(*user_id),
+        flash_messages: Some(flash_messages),
+        current_user_id: *user_id,
+        users,
+        invitations,
+        roles: Role::ALL,
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(tpl.render().unwrap()))
+}
+
+#[derive(serde::Deserialize)]
+pub struct CreateUserFormData {
+    username: String,
+    /// Optional, but required to reset a forgotten password.
+    #[serde(default)]
+    email: String,
+    password: Secret<String>,
+    password_check: Secret<String>,
+    role: Role,
+}
+
+#[tracing::instrument(name = "Create an admin user", skip(pool, hashing, form), fields(username = %form.username))]
+pub async fn admin_create_user(
+    pool: web::Data<sqlx::PgPool>,
+    hashing: web::Data<PasswordHashing>,
+    form: web::Form<CreateUserFormData>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let form = form.0;
+
+    let username = match parse_username(&form.username) {
+        Ok(username) => username,
+        Err(err) => {
+            FlashMessage::error(err).send();
+            return Ok(see_other("/admin/users"));
+        }
+    };
+    let email = match form.email.trim() {
+        "" => None,
+        email => match SubscriberEmail::parse(email.to_string()) {
+            Ok(email) => Some(email),
+            Err(_) => {
+                FlashMessage::error("The email address is invalid").send();
+                return Ok(see_other("/admin/users"));
+            }
+        },
+    };
+    if let Err(err) = validate_new_password(&form.password, &form.password_check, &username) {
+        FlashMessage::error(err.to_string()).send();
+        return Ok(see_other("/admin/users"));
+    }
+
+    let mut transaction = pool.begin().await.map_err(e500)?;
+    if let Some(email) = &email {
+        if email_is_taken(&mut transaction, email.as_ref())
+            .await
+            .map_err(e500)?
+        {
+            FlashMessage::error(EMAIL_TAKEN_MESSAGE).send();
+            return Ok(see_other("/admin/users"));
+        }
+    }
+    let created = insert_user(
+        &mut transaction,
+        &hashing,
+        &username,
+        email.as_ref().map(AsRef::as_ref),
+        form.password,
+        form.role,
+    )
+    .await
+    .map_err(e500)?;
+    transaction.commit().await.map_err(e500)?;
+
+    match created {
+        Some(_) => FlashMessage::info(format!("The user {} has been created", username)).send(),
+        None => FlashMessage::error(USERNAME_TAKEN_MESSAGE).send(),
+    }
+
+    Ok(see_other("/admin/users"))
+}
+
+#[derive(serde::Deserialize)]
+pub struct InviteUserFormData {
+    email: String,
+    role: Role,
+}
+
+#[tracing::instrument(
+    name = "Invite an admin user",
+    skip(pool, form),
+    fields(email = %form.email, role = %form.role)
+)]
+pub async fn admin_invite_user(
+    pool: web::Data<sqlx::PgPool>,
+    user_id: web::ReqData<UserId>,
+    form: web::Form<InviteUserFormData>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let form = form.0;
+
+    let email = match SubscriberEmail::parse(form.email) {
+        Ok(email) => email,
+        Err(_) => {
+            FlashMessage::error("The email address is invalid").send();
+            return Ok(see_other("/admin/users"));
+        }
+    };
+
+    enqueue_invitation(&pool, &email, form.role, *user_id.into_inner())
+        .await
+        .map_err(e500)?;
+
+    FlashMessage::info(format!("An invitation will be sent to {}", email.as_ref())).send();
+
+    Ok(see_other("/admin/users"))
+}
+
+#[derive(serde::Deserialize)]
+pub struct UpdateRoleFormData {
+    role: Role,
+}
+
+#[tracing::instrument(name = "Update the role of an admin user", skip(pool, form))]
+pub async fn admin_update_user_role(
+    pool: web::Data<sqlx::PgPool>,
+    user_id: web::ReqData<UserId>,
+    target_user_id: web::Path<Uuid>,
+    form: web::Form<UpdateRoleFormData>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if *target_user_id == *user_id.into_inner() {
+        FlashMessage::error(SELF_MANAGEMENT_MESSAGE).send();
+        return Ok(see_other("/admin/users"));
+    }
+
+    let updated = sqlx::query!(
+        r#"
+        UPDATE users SET role = $1
+        WHERE user_id = $2
+        "#,
+        form.role.as_str(),
+        *target_user_id,
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to update the user role")
+    .map_err(e500)?
+    .rows_affected();
+
+    if updated > 0 {
+        FlashMessage::info(format!("The user is now {}", form.role)).send();
+    } else {
+        FlashMessage::error(UNKNOWN_USER_MESSAGE).send();
+    }
+
+    Ok(see_other("/admin/users"))
+}
+
+#[tracing::instrument(name = "Disable an admin user", skip(pool))]
+pub async fn admin_disable_user(
+    pool: web::Data<sqlx::PgPool>,
+    user_id: web::ReqData<UserId>,
+    target_user_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if *target_user_id == *user_id.into_inner() {
+        FlashMessage::error(SELF_MANAGEMENT_MESSAGE).send();
+        return Ok(see_other("/admin/users"));
+    }
+
+    let updated = sqlx::query!(
+        r#"
+        UPDATE users SET disabled_at = COALESCE(disabled_at, now())
+        WHERE user_id = $1
+        "#,
+        *target_user_id,
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to disable the user")
+    .map_err(e500)?
+    .rows_affected();
+
+    if updated > 0 {
+        FlashMessage::info("The user has been disabled").send();
+    } else {
+        FlashMessage::error(UNKNOWN_USER_MESSAGE).send();
+    }
+
+    Ok(see_other("/admin/users"))
+}
+
+#[tracing::instrument(name = "Enable an admin user", skip(pool))]
+pub async fn admin_enable_user(
+    pool: web::Data<sqlx::PgPool>,
+    target_user_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let updated = sqlx::query!(
+        r#"
+        UPDATE users SET disabled_at = NULL
+        WHERE user_id = $1
+        "#,
+        *target_user_id,
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to enable the user")
+    .map_err(e500)?
+    .rows_affected();
+
+    if updated > 0 {
+        FlashMessage::info("The user has been enabled").send();
+    } else {
+        FlashMessage::error(UNKNOWN_USER_MESSAGE).send();
+    }
+
+    Ok(see_other("/admin/users"))
+}
+
+#[tracing::instrument(name = "Delete an admin user", skip(pool))]
+pub async fn admin_delete_user(
+    pool: web::Data<sqlx::PgPool>,
+    user_id: web::ReqData<UserId>,
+    target_user_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if *target_user_id == *user_id.into_inner() {
+        FlashMessage::error(SELF_MANAGEMENT_MESSAGE).send();
+        return Ok(see_other("/admin/users"));
+    }
+
+    let deleted = sqlx::query!(
+        r#"
+        DELETE FROM users
+        WHERE user_id = $1
+        "#,
+        *target_user_id,
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to delete the user")
+    .map_err(e500)?
+    .rows_affected();
+
+    if deleted > 0 {
+        FlashMessage::info("The user has been deleted").send();
+    } else {
+        FlashMessage::error(UNKNOWN_USER_MESSAGE).send();
+    }
+
+    Ok(see_other("/admin/users"))
+}
+
+pub(crate) const USERNAME_TAKEN_MESSAGE: &str = "This username is already taken";
+pub(crate) const EMAIL_TAKEN_MESSAGE: &str = "This email address is already used by another user";
+
+/// Trims the username and checks it is neither empty nor too long.
+pub(crate) fn parse_username(s: &str) -> Result<String, &'static str> {
+    let username = s.trim();
+
+    if username.is_empty() {
+        return Err("The username can't be empty");
+    }
+    if username.chars().count() > 64 {
+        return Err("The username is too long");
+    }
+    if username.chars().any(char::is_control) {
+        return Err("The username contains invalid characters");
+    }
+
+    Ok(username.to_string())
+}
+
+/// Creates a new admin user.
+///
+/// Returns `None` if the username is already taken.
+#[tracing::instrument(name = "Insert user", skip(transaction, hashing, password))]
+pub(crate) async fn insert_user(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    hashing: &PasswordHashing,
+    username: &str,
+    email: Option<&str>,
+    password: Secret<String>,
+    role: Role,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let hashing = hashing.clone();
+    let password_hash =
+        spawn_blocking_with_tracing(move || compute_password_hash(&hashing, password))
+            .await
+            .context("Failed to spawn blocking task")??;
+
+    let user_id = Uuid::new_v4();
+
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO users(user_id, username, email, password_hash, role)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (username) DO NOTHING
+        "#,
+        user_id,
+        username,
+        email,
+        password_hash.expose_secret(),
+        role.as_str(),
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to insert the user")?
+    .rows_affected();
+
+    Ok((inserted > 0).then_some(user_id))
+}
+
+#[tracing::instrument(name = "Check if an email is taken", skip(transaction))]
+pub(crate) async fn email_is_taken(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    email: &str,
+) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!("SELECT 1 AS n FROM users WHERE email = $1", email)
+        .fetch_optional(transaction)
+        .await
+        .context("Failed to look up the email address")?;
+
+    Ok(row.is_some())
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_users(pool: &sqlx::PgPool) -> Result<Vec<User>, anyhow::Error> {
+    let users = sqlx::query_as!(
+        User,
+        r#"
+        SELECT user_id, username, email, role, disabled_at
+        FROM users
+        ORDER BY username
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the users")?;
+
+    Ok(users)
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_pending_invitations(
+    pool: &sqlx::PgPool,
+) -> Result<Vec<PendingInvitation>, anyhow::Error> {
+    let invitations = sqlx::query_as!(
+        PendingInvitation,
+        r#"
+        SELECT email, role, expires_at
+        FROM user_invitations
+        WHERE accepted_at IS NULL AND expires_at > now()
+        ORDER BY created_at DESC
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the pending invitations")?;
+
+    Ok(invitations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_username;
+    use claim::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn a_username_is_trimmed() {
+        assert_ok_eq!(parse_username("  vincent "), "vincent".to_string());
+    }
+
+    #[test]
+    fn an_empty_username_is_rejected() {
+        assert_err!(parse_username(""));
+        assert_err!(parse_username("   "));
+    }
+
+    #[test]
+    fn a_username_longer_than_64_characters_is_rejected() {
+        assert_ok_eq!(parse_username(&"ё".repeat(64)), "ё".repeat(64));
+        assert_err!(parse_username(&"a".repeat(65)));
+    }
+
+    #[test]
+    fn a_username_with_control_characters_is_rejected() {
+        assert_err!(parse_username("vin\ncent"));
+    }
+}
@@ -0,0 +1,162 @@
+use crate::idempotency::{save_response, try_processing, IdempotencyKey, NextAction};
+use crate::routes::error_chain_fmt;
+use actix_web::http::header::WWW_AUTHENTICATE;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use std::fmt;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// An error of the JSON API, rendered as `{"error": "<message>"}`.
+#[derive(thiserror::Error)]
+pub enum ApiError {
+    #[error("{0}")]
+    Unauthorized(&'static str),
+    #[error("{0}")]
+    Validation(String),
+    #[error("Not found")]
+    NotFound,
+    #[error("{0}")]
+    Conflict(&'static str),
+    #[error(transparent)]
+    Unexpected(#[from] anyhow::Error),
+}
+
+impl fmt::Debug for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+#[derive(serde::Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+impl actix_web::ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Validation(_) => StatusCode::BAD_REQUEST,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::Unexpected(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        // The details of unexpected errors are only logged
+        let error = match self {
+            Self::Unexpected(_) => "Internal server error".to_string(),
+            _ => self.to_string(),
+        };
+
+        let mut response = HttpResponse::build(self.status_code());
+        if let Self::Unauthorized(_) = self {
+            response.insert_header((WWW_AUTHENTICATE, "Bearer"));
+        }
+        response.json(ErrorBody { error })
+    }
+}
+
+/// Rejects invalid JSON bodies with a JSON error like every other API error.
+pub fn api_json_config() -> web::JsonConfig {
+    web::JsonConfig::default()
+        .error_handler(|err, _| ApiError::Validation(format!("Invalid body: {}", err)).into())
+}
+
+/// Rejects invalid query strings with a JSON error like every other API error.
+pub fn api_query_config() -> web::QueryConfig {
+    web::QueryConfig::default()
+        .error_handler(|err, _| ApiError::Validation(format!("Invalid query: {}", err)).into())
+}
+
+/// Rejects invalid path parameters, such as malformed ids, with a JSON error like every other
+/// API error.
+pub fn api_path_config() -> web::PathConfig {
+    web::PathConfig::default()
+        .error_handler(|err, _| ApiError::Validation(format!("Invalid path: {}", err)).into())
+}
+
+/// Formats a time of an API response.
+pub(crate) fn format_api_time(value: OffsetDateTime) -> String {
+    value
+        .format(&Rfc3339)
+        .expect("Failed to format an API time")
+}
+
+/// Parses a time of an API request.
+pub(crate) fn parse_api_time(value: &str) -> Result<OffsetDateTime, ApiError> {
+    OffsetDateTime::parse(value, &Rfc3339)
+        .map_err(|_| ApiError::Validation(format!("{} is not a RFC 3339 time", value)))
+}
+
+/// Reads the optional `Idempotency-Key` header of a request.
+fn get_idempotency_key(req: &HttpRequest) -> Result<Option<IdempotencyKey>, ApiError> {
+    let value = match req.headers().get("Idempotency-Key") {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+
+    let value = value
+        .to_str()
+        .map_err(|_| ApiError::Validation("Invalid Idempotency-Key header".to_string()))?;
+    let key = IdempotencyKey::try_from(value.to_string())
+        .map_err(|err| ApiError::Validation(err.to_string()))?;
+
+    Ok(Some(key))
+}
+
+/// An API call which changes something, and is only done once per idempotency key if the
+/// request has one.
+pub(crate) struct IdempotentCall {
+    user_id: Uuid,
+    idempotency_key: Option<IdempotencyKey>,
+}
+
+impl IdempotentCall {
+    pub(crate) fn new(req: &HttpRequest, user_id: Uuid) -> Result<Self, ApiError> {
+        Ok(Self {
+            user_id,
+            idempotency_key: get_idempotency_key(req)?,
+        })
+    }
+
+    /// Starts the transaction of the call, or returns the saved response of a previous call
+    /// with the same idempotency key.
+    pub(crate) async fn start(&self, pool: &sqlx::PgPool) -> Result<NextAction, ApiError> {
+        match &self.idempotency_key {
+            Some(idempotency_key) => Ok(try_processing(pool, self.user_id, idempotency_key).await?),
+            None => {
+                let transaction = pool
+                    .begin()
+                    .await
+                    .context("Failed to acquire a Postgres connection from the pool")?;
+                Ok(NextAction::StartProcessing(transaction))
+            }
+        }
+    }
+
+    /// Commits the transaction of the call, saving its response if the request has an
+    /// idempotency key.
+    pub(crate) async fn finish(
+        &self,
+        transaction: sqlx::Transaction<'static, sqlx::Postgres>,
+        response: HttpResponse,
+    ) -> Result<HttpResponse, ApiError> {
+        match &self.idempotency_key {
+            Some(idempotency_key) => {
+                Ok(save_response(transaction, self.user_id, idempotency_key, response).await?)
+            }
+            None => {
+                transaction
+                    .commit()
+                    .await
+                    .context("Failed to commit the transaction")?;
+                Ok(response)
+            }
+        }
+    }
+}
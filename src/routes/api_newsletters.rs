@@ -0,0 +1,289 @@
+use super::admin_newsletters::{insert_newsletter_issue, IssueStatus, NewsletterAction};
+use super::admin_newsletters_issue::publish_draft;
+use crate::authentication::UserId;
+use crate::idempotency::NextAction;
+use crate::issue_delivery_worker::enqueue_delivery_tasks;
+use crate::issue_template;
+use crate::routes::{format_api_time, parse_api_time, ApiError, IdempotentCall};
+use actix_web::http::header::LOCATION;
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+#[derive(serde::Serialize)]
+pub struct ApiIssue {
+    id: Uuid,
+    title: String,
+    status: String,
+    scheduled_for: Option<String>,
+    published_at: Option<String>,
+    deliveries: ApiDeliveryStats,
+}
+
+#[derive(serde::Serialize)]
+pub struct ApiDeliveryStats {
+    queued: i64,
+    sent: i64,
+    failed: i64,
+    skipped: i64,
+}
+
+pub async fn api_get_issue(
+    pool: web::Data<sqlx::PgPool>,
+    issue_id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let issue = get_issue(pool.as_ref(), *issue_id)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+
+    Ok(HttpResponse::Ok().json(issue))
+}
+
+#[derive(serde::Deserialize)]
+pub struct CreateIssueBody {
+    title: String,
+    text_content: String,
+    html_content: String,
+    /// When to send the issue, right away if missing.
+    send_at: Option<String>,
+    #[serde(default)]
+    action: NewsletterAction,
+}
+
+#[tracing::instrument(name = "Create a newsletter issue", skip(pool, req, body))]
+pub async fn api_create_issue(
+    pool: web::Data<sqlx::PgPool>,
+    user_id: web::ReqData<UserId>,
+    req: HttpRequest,
+    body: web::Json<CreateIssueBody>,
+) -> Result<HttpResponse, ApiError> {
+    let body = body.into_inner();
+    let call = IdempotentCall::new(&req, *user_id.into_inner())?;
+
+    if body.title.is_empty() {
+        return Err(ApiError::Validation("The title is missing".to_string()));
+    }
+
+    // Drafts can be saved with an incomplete content, it's validated when they're published
+    let status = match body.action {
+        NewsletterAction::Draft => IssueStatus::Draft,
+        NewsletterAction::Publish => {
+            validate_content(&body.text_content, &body.html_content)?;
+            parse_status(body.send_at.as_deref())?
+        }
+    };
+
+    let mut transaction = match call.start(&pool).await? {
+        NextAction::StartProcessing(transaction) => transaction,
+        NextAction::ReturnSavedResponse(response) => return Ok(response),
+    };
+
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        &body.title,
+        &body.text_content,
+        &body.html_content,
+        &status,
+    )
+    .await
+    .context("Failed to store newsletter issue details")?;
+
+    if let IssueStatus::Published = status {
+        enqueue_delivery_tasks(&mut transaction, issue_id)
+            .await
+            .context("Failed to enqueue delivery tasks")?;
+    }
+
+    let issue = get_issue(&mut transaction, issue_id)
+        .await?
+        .context("The newsletter issue has just been created")?;
+
+    let response = HttpResponse::Created()
+        .insert_header((LOCATION, format!("/api/v1/newsletters/{}", issue_id)))
+        .json(issue);
+
+    call.finish(transaction, response).await
+}
+
+#[derive(serde::Deserialize)]
+pub struct PublishIssueBody {
+    /// When to send the issue, right away if missing.
+    send_at: Option<String>,
+}
+
+#[tracing::instrument(name = "Publish a newsletter issue", skip(pool, req, body))]
+pub async fn api_publish_issue(
+    pool: web::Data<sqlx::PgPool>,
+    user_id: web::ReqData<UserId>,
+    req: HttpRequest,
+    issue_id: web::Path<Uuid>,
+    // The body is optional since it only holds optional fields
+    body: Option<web::Json<PublishIssueBody>>,
+) -> Result<HttpResponse, ApiError> {
+    let issue_id = issue_id.into_inner();
+    let call = IdempotentCall::new(&req, *user_id.into_inner())?;
+
+    let send_at = body.and_then(|body| body.into_inner().send_at);
+    let status = parse_status(send_at.as_deref())?;
+
+    let mut transaction = match call.start(&pool).await? {
+        NextAction::StartProcessing(transaction) => transaction,
+        NextAction::ReturnSavedResponse(response) => return Ok(response),
+    };
+
+    let draft = sqlx::query!(
+        r#"
+        SELECT text_content, html_content, status
+        FROM newsletter_issues
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        issue_id,
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to fetch the newsletter issue")?
+    .ok_or(ApiError::NotFound)?;
+
+    if draft.status != IssueStatus::Draft.as_str() {
+        return Err(ApiError::Conflict(
+            "This newsletter issue is not a draft anymore",
+        ));
+    }
+    validate_content(&draft.text_content, &draft.html_content)?;
+
+    publish_draft(&mut transaction, issue_id, &status).await?;
+
+    let issue = get_issue(&mut transaction, issue_id)
+        .await?
+        .context("The newsletter issue has just been published")?;
+
+    call.finish(transaction, HttpResponse::Ok().json(issue))
+        .await
+}
+
+#[derive(serde::Serialize)]
+struct ApiStats {
+    subscribers: ApiSubscriberStats,
+    issues: ApiIssueStats,
+    deliveries: ApiDeliveryStats,
+}
+
+#[derive(serde::Serialize)]
+struct ApiSubscriberStats {
+    pending_confirmation: i64,
+    confirmed: i64,
+    unsubscribed: i64,
+}
+
+#[derive(serde::Serialize)]
+struct ApiIssueStats {
+    draft: i64,
+    scheduled: i64,
+    published: i64,
+}
+
+pub async fn api_stats(pool: web::Data<sqlx::PgPool>) -> Result<HttpResponse, ApiError> {
+    let row = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM subscriptions WHERE status = 'pending_confirmation') as "pending_confirmation!",
+            (SELECT COUNT(*) FROM subscriptions WHERE status = 'confirmed') as "confirmed!",
+            (SELECT COUNT(*) FROM subscriptions WHERE status = 'unsubscribed') as "unsubscribed!",
+            (SELECT COUNT(*) FROM newsletter_issues WHERE status = 'draft') as "draft!",
+            (SELECT COUNT(*) FROM newsletter_issues WHERE status = 'scheduled') as "scheduled!",
+            (SELECT COUNT(*) FROM newsletter_issues WHERE status = 'published') as "published!",
+            (SELECT COUNT(*) FROM issue_delivery_queue) as "queued!",
+            (SELECT COUNT(*) FROM newsletter_deliveries WHERE outcome = 'sent') as "sent!",
+            (SELECT COUNT(*) FROM newsletter_deliveries WHERE outcome = 'failed') as "failed!",
+            (SELECT COUNT(*) FROM newsletter_deliveries WHERE outcome = 'skipped') as "skipped!"
+        "#,
+    )
+    .fetch_one(pool.as_ref())
+    .await
+    .context("Failed to fetch the statistics")?;
+
+    Ok(HttpResponse::Ok().json(ApiStats {
+        subscribers: ApiSubscriberStats {
+            pending_confirmation: row.pending_confirmation,
+            confirmed: row.confirmed,
+            unsubscribed: row.unsubscribed,
+        },
+        issues: ApiIssueStats {
+            draft: row.draft,
+            scheduled: row.scheduled,
+            published: row.published,
+        },
+        deliveries: ApiDeliveryStats {
+            queued: row.queued,
+            sent: row.sent,
+            failed: row.failed,
+            skipped: row.skipped,
+        },
+    }))
+}
+
+fn validate_content(text_content: &str, html_content: &str) -> Result<(), ApiError> {
+    if text_content.is_empty() || html_content.is_empty() {
+        return Err(ApiError::Validation(
+            "The newsletter content is missing".to_string(),
+        ));
+    }
+
+    issue_template::validate(html_content, text_content)
+        .map_err(|err| ApiError::Validation(format!("The newsletter content is invalid: {}", err)))
+}
+
+/// Parses the optional send time of an issue to publish, a missing send time means "send now".
+fn parse_status(send_at: Option<&str>) -> Result<IssueStatus, ApiError> {
+    let send_at = match send_at {
+        Some(send_at) => parse_api_time(send_at)?,
+        None => return Ok(IssueStatus::Published),
+    };
+
+    if send_at <= OffsetDateTime::now_utc() {
+        return Err(ApiError::Validation(
+            "The send time must be in the future".to_string(),
+        ));
+    }
+
+    Ok(IssueStatus::Scheduled(send_at))
+}
+
+#[tracing::instrument(skip(executor))]
+async fn get_issue<'a, E>(executor: E, issue_id: Uuid) -> Result<Option<ApiIssue>, anyhow::Error>
+where
+    E: sqlx::PgExecutor<'a>,
+{
+    let row = sqlx::query!(
+        r#"
+        SELECT
+            id, title, status, scheduled_for, published_at,
+            (SELECT COUNT(*) FROM issue_delivery_queue WHERE newsletter_issue_id = $1) as "queued!",
+            (SELECT COUNT(*) FROM newsletter_deliveries WHERE newsletter_issue_id = $1 AND outcome = 'sent') as "sent!",
+            (SELECT COUNT(*) FROM newsletter_deliveries WHERE newsletter_issue_id = $1 AND outcome = 'failed') as "failed!",
+            (SELECT COUNT(*) FROM newsletter_deliveries WHERE newsletter_issue_id = $1 AND outcome = 'skipped') as "skipped!"
+        FROM newsletter_issues
+        WHERE id = $1
+        "#,
+        issue_id,
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to fetch the newsletter issue")?;
+
+    Ok(row.map(|row| ApiIssue {
+        id: row.id,
+        title: row.title,
+        status: row.status,
+        scheduled_for: row.scheduled_for.map(format_api_time),
+        published_at: row.published_at.map(format_api_time),
+        deliveries: ApiDeliveryStats {
+            queued: row.queued,
+            sent: row.sent,
+            failed: row.failed,
+            skipped: row.skipped,
+        },
+    }))
+}
@@ -0,0 +1,262 @@
+use super::subscriptions::{generate_subscription_token, store_token};
+use crate::confirmation_email_queue::enqueue_confirmation_email;
+use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName, SubscriberStatus};
+use crate::routes::{format_api_time, ApiError};
+use actix_web::http::header::LOCATION;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+const DEFAULT_PAGE_SIZE: i64 = 100;
+const MAX_PAGE_SIZE: i64 = 1000;
+
+#[derive(serde::Serialize)]
+pub struct ApiSubscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: String,
+}
+
+struct SubscriberRow {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: OffsetDateTime,
+}
+
+impl From<SubscriberRow> for ApiSubscriber {
+    fn from(row: SubscriberRow) -> Self {
+        Self {
+            id: row.id,
+            email: row.email,
+            name: row.name,
+            status: row.status,
+            subscribed_at: format_api_time(row.subscribed_at),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct ListSubscribersQuery {
+    status: Option<SubscriberStatus>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+struct SubscriberList {
+    subscribers: Vec<ApiSubscriber>,
+    total: i64,
+}
+
+pub async fn api_list_subscribers(
+    pool: web::Data<sqlx::PgPool>,
+    query: web::Query<ListSubscribersQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(ApiError::Validation(format!(
+            "The limit must be between 1 and {}",
+            MAX_PAGE_SIZE
+        )));
+    }
+    let offset = query.offset.unwrap_or(0);
+    if offset < 0 {
+        return Err(ApiError::Validation(
+            "The offset can't be negative".to_string(),
+        ));
+    }
+
+    let (subscribers, total) = get_subscribers(&pool, query.status, limit, offset).await?;
+
+    Ok(HttpResponse::Ok().json(SubscriberList {
+        subscribers: subscribers.into_iter().map(Into::into).collect(),
+        total,
+    }))
+}
+
+pub async fn api_get_subscriber(
+    pool: web::Data<sqlx::PgPool>,
+    subscriber_id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let subscriber = get_subscriber(&pool, *subscriber_id)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+
+    Ok(HttpResponse::Ok().json(ApiSubscriber::from(subscriber)))
+}
+
+#[derive(serde::Deserialize)]
+pub struct CreateSubscriberBody {
+    email: String,
+    name: String,
+    /// Confirmed subscribers don't get a confirmation email, this is meant for subscribers who
+    /// already agreed somewhere else.
+    #[serde(default)]
+    confirmed: bool,
+}
+
+#[tracing::instrument(
+    name = "Create a subscriber",
+    skip(pool, body),
+    fields(subscriber_email = %body.email)
+)]
+pub async fn api_create_subscriber(
+    pool: web::Data<sqlx::PgPool>,
+    body: web::Json<CreateSubscriberBody>,
+) -> Result<HttpResponse, ApiError> {
+    let body = body.into_inner();
+
+    let new_subscriber = NewSubscriber {
+        email: SubscriberEmail::parse(body.email).map_err(ApiError::Validation)?,
+        name: SubscriberName::parse(body.name).map_err(ApiError::Validation)?,
+    };
+    let status = if body.confirmed {
+        SubscriberStatus::Confirmed
+    } else {
+        SubscriberStatus::PendingConfirmation
+    };
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let subscriber = insert_subscriber(&mut transaction, &new_subscriber, status)
+        .await?
+        .ok_or(ApiError::Conflict(
+            "A subscriber with this email address already exists",
+        ))?;
+
+    if status == SubscriberStatus::PendingConfirmation {
+        let subscription_token = generate_subscription_token();
+        store_token(&mut transaction, subscriber.id, &subscription_token)
+            .await
+            .context("Failed to store the confirmation token for a new subscriber")?;
+        enqueue_confirmation_email(&mut transaction, subscriber.id, &subscription_token)
+            .await
+            .context("Failed to enqueue the confirmation email of a new subscriber")?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a new subscriber")?;
+
+    Ok(HttpResponse::Created()
+        .insert_header((LOCATION, format!("/api/v1/subscribers/{}", subscriber.id)))
+        .json(ApiSubscriber::from(subscriber)))
+}
+
+#[tracing::instrument(name = "Delete a subscriber", skip(pool))]
+pub async fn api_delete_subscriber(
+    pool: web::Data<sqlx::PgPool>,
+    subscriber_id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let deleted = sqlx::query!(r#"DELETE FROM subscriptions WHERE id = $1"#, *subscriber_id)
+        .execute(pool.as_ref())
+        .await
+        .context("Failed to delete the subscriber")?
+        .rows_affected();
+
+    if deleted == 0 {
+        return Err(ApiError::NotFound);
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_subscribers(
+    pool: &sqlx::PgPool,
+    status: Option<SubscriberStatus>,
+    limit: i64,
+    offset: i64,
+) -> Result<(Vec<SubscriberRow>, i64), anyhow::Error> {
+    let status = status.map(|status| status.as_str());
+
+    let subscribers = sqlx::query_as!(
+        SubscriberRow,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE $1::text IS NULL OR status = $1
+        ORDER BY subscribed_at, id
+        LIMIT $2 OFFSET $3
+        "#,
+        status,
+        limit,
+        offset,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the subscribers")?;
+
+    let total = sqlx::query!(
+        r#"
+        SELECT COUNT(*) as "total!"
+        FROM subscriptions
+        WHERE $1::text IS NULL OR status = $1
+        "#,
+        status,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to count the subscribers")?
+    .total;
+
+    Ok((subscribers, total))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_subscriber(
+    pool: &sqlx::PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberRow>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        SubscriberRow,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the subscriber")?;
+
+    Ok(subscriber)
+}
+
+/// Inserts a subscriber, returning `None` if the email address is already taken.
+#[tracing::instrument(skip(transaction, new_subscriber))]
+async fn insert_subscriber(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    new_subscriber: &NewSubscriber,
+    status: SubscriberStatus,
+) -> Result<Option<SubscriberRow>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        SubscriberRow,
+        r#"
+        INSERT INTO subscriptions(id, email, name, subscribed_at, status)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (email) DO NOTHING
+        RETURNING id, email, name, status, subscribed_at
+        "#,
+        Uuid::new_v4(),
+        new_subscriber.email.as_ref(),
+        new_subscriber.name.as_ref(),
+        OffsetDateTime::now_utc(),
+        status.as_str(),
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("Failed to insert the subscriber")?;
+
+    Ok(subscriber)
+}
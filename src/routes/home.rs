@@ -1,23 +1,40 @@
 use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
 use actix_web::HttpResponse;
 use actix_web_flash_messages::IncomingFlashMessages;
 use askama::Template;
 use uuid::Uuid;
 
+/// What the signup form shows: empty at first, then what was submitted with a message for
+/// each invalid field.
+#[derive(Default)]
+pub(crate) struct SignupForm<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) email: &'a str,
+    pub(crate) name_error: Option<&'static str>,
+    pub(crate) email_error: Option<&'static str>,
+}
+
 #[derive(askama::Template)]
 #[template(path = "home.html.j2")]
-pub struct HomeTemplate {
+pub struct HomeTemplate<'a> {
     user_id: Option<Uuid>,
     flash_messages: Option<IncomingFlashMessages>,
+    form: SignupForm<'a>,
 }
 
 pub async fn home() -> HttpResponse {
+    render_home(StatusCode::OK, SignupForm::default())
+}
+
+pub(crate) fn render_home(status: StatusCode, form: SignupForm<'_>) -> HttpResponse {
     let tpl = HomeTemplate {
         user_id: None,
         flash_messages: None,
+        form,
     };
 
-    HttpResponse::Ok()
+    HttpResponse::build(status)
         .content_type(ContentType::html())
         .body(tpl.render().unwrap())
 }
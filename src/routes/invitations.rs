@@ -0,0 +1,197 @@
+use super::admin_users::{email_is_taken, insert_user, parse_username};
+use super::admin_users::{EMAIL_TAKEN_MESSAGE, USERNAME_TAKEN_MESSAGE};
+use crate::authentication::{hash_token, validate_new_password, PasswordHashing, Role};
+use crate::routes::{e500, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use askama::Template;
+use secrecy::Secret;
+use uuid::Uuid;
+
+const INVALID_INVITATION_MESSAGE: &str = "This invitation is invalid or has expired";
+
+#[derive(serde::Deserialize)]
+pub struct InvitationParameters {
+    invitation_token: String,
+}
+
+#[derive(askama::Template)]
+#[template(path = "accept_invitation.html.j2")]
+pub struct AcceptInvitationTemplate {
+    user_id: Option<Uuid>,
+    flash_messages: Option<IncomingFlashMessages>,
+    invitation_token: String,
+    email: String,
+    role: Role,
+}
+
+pub async fn accept_invitation_form(
+    pool: web::Data<sqlx::PgPool>,
+    parameters: web::Query<InvitationParameters>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let parameters = parameters.into_inner();
+
+    let invitation = get_pending_invitation(&pool, &parameters.invitation_token)
+        .await
+        .map_err(e500)?;
+    let (email, role) = match invitation {
+        Some(invitation) => invitation,
+        None => return Ok(HttpResponse::Unauthorized().body(INVALID_INVITATION_MESSAGE)),
+    };
+
+    let tpl = AcceptInvitationTemplate {
+        user_id: None,
+        flash_messages: Some(flash_messages),
+        invitation_token: parameters.invitation_token,
+        email,
+        role,
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(tpl.render().unwrap()))
+}
+
+#[derive(serde::Deserialize)]
+pub struct AcceptInvitationFormData {
+    invitation_token: String,
+    username: String,
+    password: Secret<String>,
+    password_check: Secret<String>,
+}
+
+#[tracing::instrument(name = "Accept an invitation", skip(pool, hashing, form), fields(username = %form.username))]
+pub async fn accept_invitation(
+    pool: web::Data<sqlx::PgPool>,
+    hashing: web::Data<PasswordHashing>,
+    form: web::Form<AcceptInvitationFormData>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let form = form.0;
+
+    // Invitation tokens are alphanumeric, which also makes them safe to put back in the form URL
+    if !form
+        .invitation_token
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric())
+    {
+        return Ok(HttpResponse::Unauthorized().body(INVALID_INVITATION_MESSAGE));
+    }
+    let form_location = format!(
+        "/invitations/accept?invitation_token={}",
+        form.invitation_token
+    );
+
+    let username = match parse_username(&form.username) {
+        Ok(username) => username,
+        Err(err) => {
+            FlashMessage::error(err).send();
+            return Ok(see_other(&form_location));
+        }
+    };
+    if let Err(err) = validate_new_password(&form.password, &form.password_check, &username) {
+        FlashMessage::error(err.to_string()).send();
+        return Ok(see_other(&form_location));
+    }
+
+    let mut transaction = pool.begin().await.map_err(e500)?;
+
+    // Lock the invitation so that it can only be accepted once
+    let (email, role) = match lock_pending_invitation(&mut transaction, &form.invitation_token)
+        .await
+        .map_err(e500)?
+    {
+        Some(invitation) => invitation,
+        None => return Ok(HttpResponse::Unauthorized().body(INVALID_INVITATION_MESSAGE)),
+    };
+
+    if email_is_taken(&mut transaction, &email)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::error(EMAIL_TAKEN_MESSAGE).send();
+        return Ok(see_other(&form_location));
+    }
+
+    if insert_user(
+        &mut transaction,
+        &hashing,
+        &username,
+        Some(&email),
+        form.password,
+        role,
+    )
+    .await
+    .map_err(e500)?
+    .is_none()
+    {
+        FlashMessage::error(USERNAME_TAKEN_MESSAGE).send();
+        return Ok(see_other(&form_location));
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE user_invitations SET accepted_at = now()
+        WHERE token_hash = $1
+        "#,
+        hash_token(&form.invitation_token),
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to mark the invitation as accepted")
+    .map_err(e500)?;
+
+    transaction.commit().await.map_err(e500)?;
+
+    FlashMessage::info("Your account has been created, you can now log in").send();
+
+    Ok(see_other("/login"))
+}
+
+#[tracing::instrument(skip(pool, invitation_token))]
+async fn get_pending_invitation(
+    pool: &sqlx::PgPool,
+    invitation_token: &str,
+) -> Result<Option<(String, Role)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT email, role FROM user_invitations
+        WHERE token_hash = $1 AND accepted_at IS NULL AND expires_at > now()
+        "#,
+        hash_token(invitation_token),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the invitation")?;
+
+    match row {
+        Some(row) => Ok(Some((row.email, Role::parse(&row.role)?))),
+        None => Ok(None),
+    }
+}
+
+#[tracing::instrument(skip(transaction, invitation_token))]
+async fn lock_pending_invitation(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    invitation_token: &str,
+) -> Result<Option<(String, Role)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT email, role FROM user_invitations
+        WHERE token_hash = $1 AND accepted_at IS NULL AND expires_at > now()
+        FOR UPDATE
+        "#,
+        hash_token(invitation_token),
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("Failed to lock the invitation")?;
+
+    match row {
+        Some(row) => Ok(Some((row.email, Role::parse(&row.role)?))),
+        None => Ok(None),
+    }
+}
@@ -1,10 +1,14 @@
+use crate::authentication::{check_login_attempt, record_login_failure, reset_login_failures};
+use crate::authentication::{get_totp_secret, verify_second_factor};
 use crate::authentication::{validate_credentials, AuthError, Credentials};
-use crate::routes::{e500, error_chain_fmt};
+use crate::authentication::{LoginProtectionPolicy, LoginThrottle, PasswordHashing};
+use crate::routes::admin_dashboard::get_username;
+use crate::routes::{e500, error_chain_fmt, see_other};
 use crate::sessions::TypedSession;
 use actix_web::error::InternalError;
-use actix_web::http::header::{ContentType, LOCATION};
+use actix_web::http::header::{ContentType, LOCATION, USER_AGENT};
 use actix_web::web;
-use actix_web::HttpResponse;
+use actix_web::{HttpRequest, HttpResponse};
 use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
 use askama::Template;
 use secrecy::Secret;
@@ -39,6 +43,8 @@ pub async fn login_form(
 pub enum LoginError {
     #[error("Authentication failed")]
     Auth(#[source] anyhow::Error),
+    #[error("Too many failed login attempts, try again later")]
+    Throttled,
     #[error("Something went wrong")]
     Unexpected(#[from] anyhow::Error),
 }
@@ -57,7 +63,7 @@ pub struct LoginFormData {
 
 #[tracing::instrument(
     name = "Do login",
-    skip(pool, session, form),
+    skip(pool, hashing, login_protection, req, session, form),
     fields(
         username = tracing::field::Empty,
         user_id = tracing::field::Empty,
@@ -65,6 +71,9 @@ pub struct LoginFormData {
 )]
 pub async fn login(
     pool: web::Data<sqlx::PgPool>,
+    hashing: web::Data<PasswordHashing>,
+    login_protection: web::Data<LoginProtectionPolicy>,
+    req: HttpRequest,
     session: TypedSession,
     form: web::Form<LoginFormData>,
 ) -> Result<HttpResponse, InternalError<LoginError>> {
@@ -72,14 +81,52 @@ pub async fn login(
         username: form.0.username,
         password: form.0.password,
     };
+    let username = credentials.username.clone();
+    let client_ip = login_protection.client_ip(&req);
+
+    tracing::Span::current().record("username", tracing::field::display(&username));
+
+    // Throttled attempts are refused without checking the password, whether the username
+    // exists or not. They don't count as failures so that they can't extend a lockout.
+    let throttle = check_login_attempt(&pool, &login_protection, &username, &client_ip)
+        .await
+        .map_err(|err| login_redirect(LoginError::Unexpected(err)))?;
+    if throttle != LoginThrottle::Allowed {
+        return Err(login_redirect(LoginError::Throttled));
+    }
 
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
-
-    match validate_credentials(&pool, credentials).await {
+    match validate_credentials(&pool, &hashing, credentials).await {
         Ok(user_id) => {
-            tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+            tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+            let has_second_factor = get_totp_secret(&pool, user_id)
+                .await
+                .map_err(|err| login_redirect(LoginError::Unexpected(err)))?
+                .is_some();
 
             session.renew();
+            let user_agent = req
+                .headers()
+                .get(USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default();
+            session
+                .insert_client(user_agent, &client_ip)
+                .map_err(|err| login_redirect(LoginError::Unexpected(err.into())))?;
+
+            if has_second_factor {
+                session
+                    .insert_second_factor_user_id(user_id)
+                    .map_err(|err| login_redirect(LoginError::Unexpected(err.into())))?;
+
+                // The failures are only forgotten once the second factor is verified too
+                return Ok(see_other("/login/totp"));
+            }
+
+            reset_login_failures(&pool, &username)
+                .await
+                .map_err(|err| login_redirect(LoginError::Unexpected(err)))?;
+
             session
                 .insert_user_id(user_id)
                 .map_err(|err| login_redirect(LoginError::Unexpected(err.into())))?;
@@ -90,7 +137,13 @@ pub async fn login(
         }
         Err(err) => {
             let err = match err {
-                AuthError::InvalidCredentials(_) => LoginError::Auth(err.into()),
+                AuthError::InvalidCredentials(_) => {
+                    record_login_failure(&pool, &login_protection, &username, &client_ip)
+                        .await
+                        .map_err(|err| login_redirect(LoginError::Unexpected(err)))?;
+
+                    LoginError::Auth(err.into())
+                }
                 AuthError::Unexpected(_) => LoginError::Unexpected(err.into()),
             };
 
@@ -108,3 +161,83 @@ fn login_redirect(err: LoginError) -> InternalError<LoginError> {
 
     InternalError::from_response(err, response)
 }
+
+#[derive(askama::Template)]
+#[template(path = "login_totp.html.j2")]
+pub struct LoginSecondFactorTemplate {
+    user_id: Option<Uuid>,
+    flash_messages: Option<IncomingFlashMessages>,
+}
+
+pub async fn login_second_factor_form(
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_second_factor_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let tpl = LoginSecondFactorTemplate {
+        user_id: None,
+        flash_messages: Some(flash_messages),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(tpl.render().unwrap()))
+}
+
+#[derive(serde::Deserialize)]
+pub struct LoginSecondFactorFormData {
+    code: Secret<String>,
+}
+
+#[tracing::instrument(
+    name = "Do login second factor",
+    skip(pool, hashing, login_protection, req, session, form),
+    fields(user_id = tracing::field::Empty)
+)]
+pub async fn login_second_factor(
+    pool: web::Data<sqlx::PgPool>,
+    hashing: web::Data<PasswordHashing>,
+    login_protection: web::Data<LoginProtectionPolicy>,
+    req: HttpRequest,
+    session: TypedSession,
+    form: web::Form<LoginSecondFactorFormData>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = match session.get_second_factor_user_id().map_err(e500)? {
+        Some(user_id) => user_id,
+        None => return Ok(see_other("/login")),
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let username = get_username(&pool, user_id).await.map_err(e500)?;
+    let client_ip = login_protection.client_ip(&req);
+
+    let throttle = check_login_attempt(&pool, &login_protection, &username, &client_ip)
+        .await
+        .map_err(e500)?;
+    if throttle != LoginThrottle::Allowed {
+        FlashMessage::error(LoginError::Throttled.to_string()).send();
+        return Ok(see_other("/login/totp"));
+    }
+
+    let valid = verify_second_factor(&pool, &hashing, user_id, form.0.code)
+        .await
+        .map_err(e500)?;
+    if !valid {
+        record_login_failure(&pool, &login_protection, &username, &client_ip)
+            .await
+            .map_err(e500)?;
+
+        FlashMessage::error("The code is invalid").send();
+        return Ok(see_other("/login/totp"));
+    }
+
+    reset_login_failures(&pool, &username).await.map_err(e500)?;
+
+    session.renew();
+    session.insert_user_id(user_id).map_err(e500)?;
+
+    Ok(see_other("/admin/dashboard"))
+}
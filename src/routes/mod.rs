@@ -3,23 +3,53 @@ use actix_web::http::StatusCode;
 use actix_web::HttpResponse;
 use std::fmt;
 
+pub use admin_api_tokens::*;
 pub use admin_change_password::*;
 pub use admin_dashboard::*;
+pub use admin_dead_letters::*;
 pub use admin_logout::*;
 pub use admin_newsletters::*;
+pub use admin_newsletters_issue::*;
+pub use admin_newsletters_scheduled::*;
+pub use admin_sessions::*;
+pub use admin_subscribers::*;
+pub use admin_subscribers_csv::*;
+pub use admin_totp::*;
+pub use admin_users::*;
+pub use api::*;
+pub use api_newsletters::*;
+pub use api_subscribers::*;
 pub use home::*;
+pub use invitations::*;
 pub use login::*;
+pub use password_reset::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_unsubscribe::*;
 
+mod admin_api_tokens;
 mod admin_change_password;
 mod admin_dashboard;
+mod admin_dead_letters;
 mod admin_logout;
 mod admin_newsletters;
+mod admin_newsletters_issue;
+mod admin_newsletters_scheduled;
+mod admin_sessions;
+mod admin_subscribers;
+mod admin_subscribers_csv;
+mod admin_totp;
+mod admin_users;
+mod api;
+mod api_newsletters;
+mod api_subscribers;
 mod home;
+mod invitations;
 mod login;
+mod password_reset;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
 
 pub fn error_chain_fmt(err: &impl std::error::Error, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     writeln!(f, "{}\n", err)?;
@@ -0,0 +1,241 @@
+use crate::authentication::{change_password, reset_login_failures, validate_new_password};
+use crate::authentication::{hash_token, LoginProtectionPolicy, PasswordHashing};
+use crate::domain::SubscriberEmail;
+use crate::password_reset_queue::enqueue_password_reset;
+use crate::routes::{e500, see_other};
+use crate::sessions::{TypedSession, UserSessionStore};
+use actix_web::http::header::ContentType;
+use actix_web::web;
+use actix_web::{HttpRequest, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use askama::Template;
+use secrecy::Secret;
+use uuid::Uuid;
+
+const INVALID_RESET_TOKEN_MESSAGE: &str = "This password reset link is invalid or has expired";
+
+/// Shown whether an account exists for the email address or not.
+const RESET_LINK_SENT_MESSAGE: &str =
+    "If an account exists for this email address, a link to reset its password has been sent";
+
+/// Shown whether an account exists for the email address or not, too.
+const TOO_MANY_REQUESTS_MESSAGE: &str =
+    "Too many password resets have been requested, try again later";
+
+#[derive(askama::Template)]
+#[template(path = "password_reset_request.html.j2")]
+pub struct PasswordResetRequestTemplate {
+    user_id: Option<Uuid>,
+    flash_messages: Option<IncomingFlashMessages>,
+}
+
+pub async fn password_reset_request_form(
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let tpl = PasswordResetRequestTemplate {
+        user_id: None,
+        flash_messages: Some(flash_messages),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(tpl.render().unwrap()))
+}
+
+#[derive(serde::Deserialize)]
+pub struct PasswordResetRequestFormData {
+    email: String,
+}
+
+#[tracing::instrument(
+    name = "Request a password reset",
+    skip(pool, login_protection, req, form)
+)]
+pub async fn request_password_reset(
+    pool: web::Data<sqlx::PgPool>,
+    login_protection: web::Data<LoginProtectionPolicy>,
+    req: HttpRequest,
+    form: web::Form<PasswordResetRequestFormData>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = match SubscriberEmail::parse(form.0.email) {
+        Ok(email) => email,
+        Err(_) => {
+            FlashMessage::error("The email address is invalid").send();
+            return Ok(see_other("/password_reset"));
+        }
+    };
+
+    // The link is sent by the worker, so that responding doesn't take longer for an address
+    // which has an account.
+    let client_ip = login_protection.client_ip(&req);
+    let accepted = enqueue_password_reset(&pool, &email, &client_ip)
+        .await
+        .map_err(e500)?;
+    if !accepted {
+        FlashMessage::error(TOO_MANY_REQUESTS_MESSAGE).send();
+        return Ok(see_other("/password_reset"));
+    }
+
+    FlashMessage::info(RESET_LINK_SENT_MESSAGE).send();
+
+    Ok(see_other("/login"))
+}
+
+#[derive(serde::Deserialize)]
+pub struct PasswordResetParameters {
+    reset_token: String,
+}
+
+#[derive(askama::Template)]
+#[template(path = "password_reset.html.j2")]
+pub struct PasswordResetTemplate {
+    user_id: Option<Uuid>,
+    flash_messages: Option<IncomingFlashMessages>,
+    reset_token: String,
+}
+
+pub async fn password_reset_form(
+    pool: web::Data<sqlx::PgPool>,
+    parameters: web::Query<PasswordResetParameters>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let parameters = parameters.into_inner();
+
+    let valid = get_reset_token_user_id(&pool, &parameters.reset_token)
+        .await
+        .map_err(e500)?
+        .is_some();
+    if !valid {
+        return Ok(HttpResponse::Unauthorized().body(INVALID_RESET_TOKEN_MESSAGE));
+    }
+
+    let tpl = PasswordResetTemplate {
+        user_id: None,
+        flash_messages: Some(flash_messages),
+        reset_token: parameters.reset_token,
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(tpl.render().unwrap()))
+}
+
+#[derive(serde::Deserialize)]
+pub struct PasswordResetFormData {
+    reset_token: String,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+#[tracing::instrument(
+    name = "Reset a password",
+    skip(pool, hashing, session_store, session, form)
+)]
+pub async fn reset_password(
+    pool: web::Data<sqlx::PgPool>,
+    hashing: web::Data<PasswordHashing>,
+    session_store: web::Data<dyn UserSessionStore>,
+    session: TypedSession,
+    form: web::Form<PasswordResetFormData>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let form = form.0;
+
+    // Reset tokens are alphanumeric, which also makes them safe to put back in the form URL
+    if !form.reset_token.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Ok(HttpResponse::Unauthorized().body(INVALID_RESET_TOKEN_MESSAGE));
+    }
+    let form_location = format!("/password_reset/confirm?reset_token={}", form.reset_token);
+
+    let mut transaction = pool.begin().await.map_err(e500)?;
+
+    // Lock the token so that it can only be used once
+    let (user_id, username) = match lock_reset_token(&mut transaction, &form.reset_token)
+        .await
+        .map_err(e500)?
+    {
+        Some(user) => user,
+        None => return Ok(HttpResponse::Unauthorized().body(INVALID_RESET_TOKEN_MESSAGE)),
+    };
+
+    if let Err(err) = validate_new_password(&form.new_password, &form.new_password_check, &username)
+    {
+        FlashMessage::error(err.to_string()).send();
+        return Ok(see_other(&form_location));
+    }
+
+    // In the same transaction as marking the token as used, so that both happen or neither does
+    change_password(&mut transaction, &hashing, user_id, form.new_password)
+        .await
+        .map_err(e500)?;
+
+    sqlx::query!(
+        r#"
+        UPDATE password_reset_tokens SET used_at = now()
+        WHERE token_hash = $1
+        "#,
+        hash_token(&form.reset_token),
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to mark the password reset token as used")
+    .map_err(e500)?;
+
+    transaction.commit().await.map_err(e500)?;
+
+    // Whoever knew the old password must not stay logged in, and the owner of the account
+    // shouldn't stay locked out either.
+    session_store
+        .delete_user_sessions(user_id, None)
+        .await
+        .map_err(e500)?;
+    reset_login_failures(&pool, &username).await.map_err(e500)?;
+    session.logout();
+
+    FlashMessage::info("Your password has been reset, you can now log in").send();
+
+    Ok(see_other("/login"))
+}
+
+#[tracing::instrument(skip(pool, reset_token))]
+async fn get_reset_token_user_id(
+    pool: &sqlx::PgPool,
+    reset_token: &str,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT users.user_id FROM password_reset_tokens
+        INNER JOIN users ON users.user_id = password_reset_tokens.user_id
+        WHERE token_hash = $1 AND used_at IS NULL AND expires_at > now()
+          AND users.disabled_at IS NULL
+        "#,
+        hash_token(reset_token),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the password reset token")?;
+
+    Ok(row.map(|row| row.user_id))
+}
+
+#[tracing::instrument(skip(transaction, reset_token))]
+async fn lock_reset_token(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    reset_token: &str,
+) -> Result<Option<(Uuid, String)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT users.user_id, users.username FROM password_reset_tokens
+        INNER JOIN users ON users.user_id = password_reset_tokens.user_id
+        WHERE token_hash = $1 AND used_at IS NULL AND expires_at > now()
+          AND users.disabled_at IS NULL
+        FOR UPDATE OF password_reset_tokens
+        "#,
+        hash_token(reset_token),
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("Failed to lock the password reset token")?;
+
+    Ok(row.map(|row| (row.user_id, row.username)))
+}
@@ -1,26 +1,60 @@
-use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use super::home::{render_home, SignupForm};
+use crate::confirmation_email_queue::enqueue_confirmation_email;
+use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName, SubscriberStatus};
 use crate::routes::error_chain_fmt;
-use crate::startup::ApplicationBaseUrl;
-use crate::tem;
+use actix_web::http::header::ContentType;
 use actix_web::http::StatusCode;
 use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
 use anyhow::Context;
 use askama::Template;
 use rand::Rng;
 use std::fmt;
-use tracing::{event, Level};
+use time::OffsetDateTime;
 use uuid::Uuid;
 
+/// Missing fields are empty, so that they get the same messages as the empty ones.
 #[derive(serde::Deserialize)]
 pub struct FormData {
+    #[serde(default)]
     name: String,
+    #[serde(default)]
     email: String,
 }
 
+impl FormData {
+    /// Parses the subscriber, or returns the form to show again with what's wrong with it.
+    fn validate(&self) -> Result<NewSubscriber, SignupForm<'_>> {
+        let name = SubscriberName::parse(self.name.clone());
+        let email = SubscriberEmail::parse(self.email.clone());
+
+        match (name, email) {
+            (Ok(name), Ok(email)) => Ok(NewSubscriber { email, name }),
+            (name, email) => Err(SignupForm {
+                name: &self.name,
+                email: &self.email,
+                name_error: name.err().map(|_| {
+                    if self.name.trim().is_empty() {
+                        "Please enter your name."
+                    } else {
+                        "Your name can't be longer than 256 characters, nor contain any of / ( ) \" < > \\ { }"
+                    }
+                }),
+                email_error: email.err().map(|_| {
+                    if self.email.trim().is_empty() {
+                        "Please enter your email address."
+                    } else {
+                        "This doesn't look like a valid email address, it should be like ursula@example.com."
+                    }
+                }),
+            }),
+        }
+    }
+}
+
+/// Unexpected errors of the pages shown to subscribers, which only get a generic error page.
 #[derive(thiserror::Error)]
 pub enum SubscribeError {
-    #[error("{0}")]
-    Validation(String),
     #[error(transparent)]
     Unexpected(#[from] anyhow::Error),
 }
@@ -31,151 +65,240 @@ impl fmt::Debug for SubscribeError {
     }
 }
 
+#[derive(askama::Template)]
+#[template(path = "subscription_error.html.j2")]
+pub struct ErrorTemplate {
+    user_id: Option<Uuid>,
+    flash_messages: Option<IncomingFlashMessages>,
+}
+
 impl actix_web::ResponseError for SubscribeError {
     fn status_code(&self) -> StatusCode {
-        match self {
-            Self::Validation(_) => StatusCode::BAD_REQUEST,
-            Self::Unexpected(_) => StatusCode::INTERNAL_SERVER_ERROR,
-        }
+        StatusCode::INTERNAL_SERVER_ERROR
     }
-}
 
-impl From<String> for SubscribeError {
-    fn from(e: String) -> Self {
-        Self::Validation(e)
+    fn error_response(&self) -> HttpResponse {
+        // The details are only logged
+        let tpl = ErrorTemplate {
+            user_id: None,
+            flash_messages: None,
+        };
+
+        HttpResponse::build(self.status_code())
+            .content_type(ContentType::html())
+            .body(tpl.render().unwrap())
     }
 }
 
+#[derive(askama::Template)]
+#[template(path = "subscription_signup_success.html.j2")]
+pub struct SignupSuccessTemplate {
+    user_id: Option<Uuid>,
+    flash_messages: Option<IncomingFlashMessages>,
+}
+
 //
 
+/// How pending subscribers are confirmed.
+#[derive(Clone, Debug)]
+pub struct SubscriptionPolicy {
+    /// A new confirmation email is sent at most this often to the same address.
+    pub confirmation_resend_interval: time::Duration,
+    /// How long a confirmation link is valid.
+    pub confirmation_window: time::Duration,
+}
+
+/// Signing up always gets the same response, whether the address is new, pending or
+/// already confirmed, so that the form can't be used to find out who is on the list.
 #[tracing::instrument(
     name = "Subscribe",
-    skip(base_url, pool, email_client, form),
+    skip(pool, policy, form),
     fields(
         subscriber_email = %form.email,
         subscriber_name = %form.name
     )
 )]
 pub async fn subscribe(
-    base_url: web::Data<ApplicationBaseUrl>,
     pool: web::Data<sqlx::PgPool>,
-    email_client: web::Data<tem::Client>,
+    policy: web::Data<SubscriptionPolicy>,
     form: web::Form<FormData>,
 ) -> Result<HttpResponse, SubscribeError> {
+    let new_subscriber = match form.validate() {
+        Ok(new_subscriber) => new_subscriber,
+        Err(signup_form) => return Ok(render_home(StatusCode::BAD_REQUEST, signup_form)),
+    };
+
     let mut tx = pool
         .begin()
         .await
         .context("Failed to acquire a Postgre connection from the pool")?;
-
-    let new_subscriber = form.0.try_into().map_err(SubscribeError::Validation)?;
-    let subscriber_id = insert_subscriber(&mut tx, &new_subscriber)
+    let subscriber_id = match insert_subscriber(&mut tx, &new_subscriber)
         .await
-        .context("Failed to insert new subscriber in the database")?;
+        .context("Failed to insert new subscriber in the database")?
+    {
+        Some(subscriber_id) => subscriber_id,
+        None => {
+            let existing = get_existing_subscriber(&mut tx, &new_subscriber)
+                .await
+                .context("Failed to fetch the existing subscriber")?;
+
+            let now = OffsetDateTime::now_utc();
+            if !existing.can_resend_confirmation(&policy, now) {
+                tracing::info!(
+                    subscriber_status = %existing.status,
+                    "Not resending a confirmation email"
+                );
+                return Ok(signup_success());
+            }
+
+            record_confirmation_sent(&mut tx, existing.id, now)
+                .await
+                .context("Failed to record the confirmation email of a subscriber")?;
+
+            existing.id
+        }
+    };
 
     let subscription_token = generate_subscription_token();
     store_token(&mut tx, subscriber_id, &subscription_token)
         .await
         .context("Failed to store the confirmation token for a new subscriber.")?;
 
+    // The email is sent by the worker, so that this takes as long as for a confirmed address
+    enqueue_confirmation_email(&mut tx, subscriber_id, &subscription_token)
+        .await
+        .context("Failed to enqueue the confirmation email for a new subscriber.")?;
+
     tx.commit()
         .await
         .context("Failed to commit SQL transaction to store a new subscriber")?;
 
-    //
-
-    send_confirmation_email(
-        &base_url,
-        &email_client,
-        new_subscriber,
-        &subscription_token,
-    )
-    .await
-    .context("Failed to send a confirmation email.")?;
-
-    //
-
-    Ok(HttpResponse::Ok().finish())
+    Ok(signup_success())
 }
 
-#[derive(askama::Template)]
-#[template(path = "html_content.html")]
-struct HtmlContentTemplate<'a> {
-    confirmation_link: &'a str,
-}
+fn signup_success() -> HttpResponse {
+    let tpl = SignupSuccessTemplate {
+        user_id: None,
+        flash_messages: None,
+    };
 
-#[derive(askama::Template)]
-#[template(path = "text_content.txt")]
-struct TextContentTemplate<'a> {
-    confirmation_link: &'a str,
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(tpl.render().unwrap())
 }
 
-#[tracing::instrument(name = "Send confirmation email", skip(base_url, email_client))]
-async fn send_confirmation_email(
-    base_url: &ApplicationBaseUrl,
-    email_client: &tem::Client,
-    new_subscriber: NewSubscriber,
-    subscription_token: &str,
-) -> Result<(), reqwest::Error> {
-    let confirmation_link = format!(
-        "{}/subscriptions/confirm?subscription_token={}",
-        base_url.0, subscription_token
-    );
-
-    event!(Level::INFO, confirmation_link, "computed confirmation link");
-
-    let html_content = HtmlContentTemplate {
-        confirmation_link: &confirmation_link,
-    };
+/// Stores a new confirmation token for a pending subscriber and queues the email sending it.
+#[tracing::instrument(name = "Resend confirmation email", skip(pool))]
+pub(crate) async fn resend_confirmation_email(
+    pool: &sqlx::PgPool,
+    subscriber_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    let mut tx = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgre connection from the pool")?;
 
-    let text_content = TextContentTemplate {
-        confirmation_link: &confirmation_link,
-    };
+    let subscription_token = generate_subscription_token();
+    store_token(&mut tx, subscriber_id, &subscription_token)
+        .await
+        .context("Failed to store a new confirmation token")?;
+    record_confirmation_sent(&mut tx, subscriber_id, OffsetDateTime::now_utc())
+        .await
+        .context("Failed to record the confirmation email of a subscriber")?;
+    enqueue_confirmation_email(&mut tx, subscriber_id, &subscription_token)
+        .await
+        .context("Failed to enqueue the confirmation email of a subscriber")?;
 
-    email_client
-        .send_email(
-            &new_subscriber.email,
-            "Welcome!",
-            &html_content.render().unwrap(),
-            &text_content.render().unwrap(),
-        )
+    tx.commit()
         .await
+        .context("Failed to commit SQL transaction to store a new confirmation token")
 }
 
-impl TryFrom<FormData> for NewSubscriber {
-    type Error = String;
+/// Inserts a pending subscriber, returning `None` if the email address is already taken.
+#[tracing::instrument(name = "Insert subscriber", skip(tx, new_subscriber))]
+async fn insert_subscriber(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    new_subscriber: &crate::domain::NewSubscriber,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let subscriber_id = sqlx::query!(
+        r#"
+        INSERT INTO subscriptions(id, email, name, subscribed_at, status)
+        VALUES($1, $2, $3, $4, $5)
+        ON CONFLICT (email) DO NOTHING
+        RETURNING id"#,
+        Uuid::new_v4(),
+        new_subscriber.email.as_ref(),
+        new_subscriber.name.as_ref(),
+        OffsetDateTime::now_utc(),
+        SubscriberStatus::PendingConfirmation.as_str(),
+    )
+    .fetch_optional(tx)
+    .await?
+    .map(|row| row.id);
+
+    Ok(subscriber_id)
+}
 
-    fn try_from(value: FormData) -> Result<Self, Self::Error> {
-        let name = SubscriberName::parse(value.name)?;
-        let email = SubscriberEmail::parse(value.email)?;
+pub(crate) struct ExistingSubscriber {
+    pub(crate) id: Uuid,
+    pub(crate) status: String,
+    pub(crate) subscribed_at: OffsetDateTime,
+    pub(crate) last_confirmation_sent_at: Option<OffsetDateTime>,
+}
 
-        Ok(NewSubscriber { email, name })
+impl ExistingSubscriber {
+    /// Confirmed subscribers don't need a new confirmation email, the others get one unless
+    /// one has been sent to them too recently.
+    pub(crate) fn can_resend_confirmation(
+        &self,
+        policy: &SubscriptionPolicy,
+        now: OffsetDateTime,
+    ) -> bool {
+        let last_sent_at = self.last_confirmation_sent_at.unwrap_or(self.subscribed_at);
+
+        self.status != SubscriberStatus::Confirmed.as_str()
+            && now - last_sent_at >= policy.confirmation_resend_interval
     }
 }
 
-#[tracing::instrument(name = "Insert subscriber", skip(tx, new_subscriber))]
-async fn insert_subscriber(
+/// Fetches the subscriber with the same email address, locking it until the end of the
+/// transaction so that concurrent signups can't both resend a confirmation.
+#[tracing::instrument(name = "Get existing subscriber", skip(tx, new_subscriber))]
+async fn get_existing_subscriber(
     tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
     new_subscriber: &crate::domain::NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
-    let subscriber_id = Uuid::new_v4();
+) -> Result<ExistingSubscriber, sqlx::Error> {
+    sqlx::query_as!(
+        ExistingSubscriber,
+        r#"
+        SELECT id, status, subscribed_at, last_confirmation_sent_at
+        FROM subscriptions
+        WHERE email = $1
+        FOR UPDATE"#,
+        new_subscriber.email.as_ref(),
+    )
+    .fetch_one(tx)
+    .await
+}
 
+#[tracing::instrument(name = "Record a confirmation email", skip(tx))]
+pub(crate) async fn record_confirmation_sent(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    subscriber_id: Uuid,
+    sent_at: OffsetDateTime,
+) -> Result<(), sqlx::Error> {
     sqlx::query!(
-        r#"
-        INSERT INTO subscriptions(id, email, name, subscribed_at, status)
-        VALUES($1, $2, $3, $4, $5)"#,
+        r#"UPDATE subscriptions SET last_confirmation_sent_at = $2 WHERE id = $1"#,
         subscriber_id,
-        new_subscriber.email.as_ref(),
-        new_subscriber.name.as_ref(),
-        time::OffsetDateTime::now_utc(),
-        "pending_confirmation",
+        sent_at,
     )
     .execute(tx)
     .await?;
 
-    Ok(subscriber_id)
+    Ok(())
 }
 
-fn generate_subscription_token() -> String {
+pub(crate) fn generate_subscription_token() -> String {
     let mut rng = rand::thread_rng();
 
     let mut token = String::new();
@@ -214,17 +337,18 @@ impl std::error::Error for StoreTokenError {
     name = "Store the subscription token in the database",
     skip(tx, subscription_token)
 )]
-async fn store_token(
+pub(crate) async fn store_token(
     tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
     subscriber_id: Uuid,
     subscription_token: &str,
 ) -> Result<(), StoreTokenError> {
     sqlx::query!(
         r#"
-        INSERT INTO subscription_tokens(subscriber_id, subscription_token)
-        VALUES($1, $2)"#,
+        INSERT INTO subscription_tokens(subscriber_id, subscription_token, created_at)
+        VALUES($1, $2, $3)"#,
         subscriber_id,
         subscription_token,
+        OffsetDateTime::now_utc(),
     )
     .execute(tx)
     .await
@@ -233,6 +357,28 @@ async fn store_token(
     Ok(())
 }
 
+/// Marks every token of a subscriber as consumed, so that none of their links can confirm them
+/// anymore once their status changed.
+#[tracing::instrument(name = "Consume the subscription tokens of a subscriber", skip(tx))]
+pub(crate) async fn consume_tokens(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    subscriber_id: Uuid,
+    now: OffsetDateTime,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscription_tokens
+        SET consumed_at = $2
+        WHERE subscriber_id = $1 AND consumed_at IS NULL"#,
+        subscriber_id,
+        now,
+    )
+    .execute(tx)
+    .await?;
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -242,4 +388,49 @@ mod tests {
         let token = generate_subscription_token();
         assert_eq!(token.len(), 25);
     }
+
+    fn subscriber(status: SubscriberStatus, minutes_since_last_sent: i64) -> ExistingSubscriber {
+        let now = OffsetDateTime::now_utc();
+
+        ExistingSubscriber {
+            id: Uuid::new_v4(),
+            status: status.as_str().to_string(),
+            subscribed_at: now - time::Duration::days(1),
+            last_confirmation_sent_at: Some(now - time::Duration::minutes(minutes_since_last_sent)),
+        }
+    }
+
+    #[test]
+    fn confirmations_are_resent_at_most_once_per_interval() {
+        let policy = SubscriptionPolicy {
+            confirmation_resend_interval: time::Duration::minutes(10),
+            confirmation_window: time::Duration::days(7),
+        };
+        let now = OffsetDateTime::now_utc();
+
+        let pending = SubscriberStatus::PendingConfirmation;
+        assert!(subscriber(pending, 11).can_resend_confirmation(&policy, now));
+        assert!(!subscriber(pending, 9).can_resend_confirmation(&policy, now));
+        assert!(
+            subscriber(SubscriberStatus::Unsubscribed, 11).can_resend_confirmation(&policy, now)
+        );
+        assert!(!subscriber(SubscriberStatus::Confirmed, 11).can_resend_confirmation(&policy, now));
+    }
+
+    #[test]
+    fn the_signup_counts_as_the_first_confirmation() {
+        let policy = SubscriptionPolicy {
+            confirmation_resend_interval: time::Duration::minutes(10),
+            confirmation_window: time::Duration::days(7),
+        };
+        let now = OffsetDateTime::now_utc();
+
+        let mut subscriber = subscriber(SubscriberStatus::PendingConfirmation, 0);
+        subscriber.last_confirmation_sent_at = None;
+        subscriber.subscribed_at = now - time::Duration::minutes(5);
+        assert!(!subscriber.can_resend_confirmation(&policy, now));
+
+        subscriber.subscribed_at = now - time::Duration::minutes(15);
+        assert!(subscriber.can_resend_confirmation(&policy, now));
+    }
 }
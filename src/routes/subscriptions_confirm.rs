@@ -1,6 +1,16 @@
+use super::subscriptions::SubscriptionPolicy;
+use super::subscriptions::{consume_tokens, generate_subscription_token, store_token};
+use super::subscriptions::{record_confirmation_sent, ExistingSubscriber, SubscribeError};
+use crate::confirmation_email_queue::enqueue_confirmation_email;
+use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
 use actix_web::web;
 use actix_web::HttpResponse;
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use askama::Template;
 use sqlx::PgPool;
+use time::OffsetDateTime;
 use uuid::Uuid;
 
 #[derive(serde::Deserialize)]
@@ -8,58 +18,223 @@ pub struct Parameters {
     subscription_token: String,
 }
 
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(pool, parameters))]
-pub async fn confirm(pool: web::Data<PgPool>, parameters: web::Query<Parameters>) -> HttpResponse {
-    let id = match get_subscriber_id_from_token(&pool, &parameters.subscription_token).await {
-        Ok(id) => id,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
+/// Why a confirmation link can't be used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InvalidToken {
+    /// The token doesn't exist, or its subscriber has been purged.
+    Unknown,
+    Expired,
+    Consumed,
+}
+
+#[derive(askama::Template)]
+#[template(path = "subscription_confirm_invalid.html.j2")]
+pub struct InvalidTokenTemplate<'a> {
+    user_id: Option<Uuid>,
+    flash_messages: Option<IncomingFlashMessages>,
+    reason: InvalidToken,
+    subscription_token: &'a str,
+}
+
+#[derive(askama::Template)]
+#[template(path = "subscription_confirmed.html.j2")]
+pub struct ConfirmedTemplate {
+    user_id: Option<Uuid>,
+    flash_messages: Option<IncomingFlashMessages>,
+}
+
+#[derive(askama::Template)]
+#[template(path = "subscription_confirm_resent.html.j2")]
+pub struct ResentTemplate {
+    user_id: Option<Uuid>,
+    flash_messages: Option<IncomingFlashMessages>,
+}
+
+#[tracing::instrument(name = "Confirm a pending subscriber", skip(pool, policy, parameters))]
+pub async fn confirm(
+    pool: web::Data<PgPool>,
+    policy: web::Data<SubscriptionPolicy>,
+    parameters: web::Query<Parameters>,
+) -> Result<HttpResponse, SubscribeError> {
+    let subscription_token = &parameters.subscription_token;
+
+    let reason = match consume_token(&pool, &policy, subscription_token).await? {
+        Ok(()) => {
+            let tpl = ConfirmedTemplate {
+                user_id: None,
+                flash_messages: None,
+            };
+
+            return Ok(HttpResponse::Ok()
+                .content_type(ContentType::html())
+                .body(tpl.render().unwrap()));
+        }
+        Err(reason) => reason,
+    };
+
+    tracing::info!(?reason, "Rejected a confirmation link");
+
+    let status = match reason {
+        InvalidToken::Unknown => StatusCode::UNAUTHORIZED,
+        InvalidToken::Expired | InvalidToken::Consumed => StatusCode::GONE,
+    };
+    let tpl = InvalidTokenTemplate {
+        user_id: None,
+        flash_messages: None,
+        reason,
+        subscription_token,
+    };
+
+    Ok(HttpResponse::build(status)
+        .content_type(ContentType::html())
+        .body(tpl.render().unwrap()))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ResendFormData {
+    subscription_token: String,
+}
+
+/// Sends a new confirmation link to the subscriber of an expired or already used one.
+///
+/// The page is the same whether a link has been sent or not: confirmed subscribers, and those
+/// who got a link too recently, don't get a new one.
+#[tracing::instrument(name = "Resend a confirmation link", skip(pool, policy, form))]
+pub async fn resend_confirmation(
+    pool: web::Data<PgPool>,
+    policy: web::Data<SubscriptionPolicy>,
+    form: web::Form<ResendFormData>,
+) -> Result<HttpResponse, SubscribeError> {
+    renew_token(&pool, &policy, &form.subscription_token).await?;
+
+    let tpl = ResentTemplate {
+        user_id: None,
+        flash_messages: None,
     };
 
-    match id {
-        Some(id) => {
-            if confirm_subscriber(&pool, id).await.is_err() {
-                return HttpResponse::InternalServerError().finish();
-            }
-            HttpResponse::Ok().finish()
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(tpl.render().unwrap()))
+}
+
+/// Marks every token of the subscriber as consumed and confirms them, if the token is valid.
+#[tracing::instrument(
+    name = "Consume a confirmation token",
+    skip(pool, policy, subscription_token)
+)]
+async fn consume_token(
+    pool: &PgPool,
+    policy: &SubscriptionPolicy,
+    subscription_token: &str,
+) -> Result<Result<(), InvalidToken>, anyhow::Error> {
+    let mut tx = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgre connection from the pool")?;
+
+    let token = sqlx::query!(
+        r#"
+        SELECT subscriber_id, created_at, consumed_at
+        FROM subscription_tokens
+        WHERE subscription_token = $1
+        FOR UPDATE"#,
+        subscription_token,
+    )
+    .fetch_optional(&mut tx)
+    .await
+    .context("Failed to fetch the confirmation token")?;
+
+    let now = OffsetDateTime::now_utc();
+    let subscriber_id = match token {
+        None => return Ok(Err(InvalidToken::Unknown)),
+        Some(token) if token.consumed_at.is_some() => return Ok(Err(InvalidToken::Consumed)),
+        Some(token) if now - token.created_at >= policy.confirmation_window => {
+            return Ok(Err(InvalidToken::Expired))
         }
-        None => HttpResponse::Unauthorized().finish(),
-    }
+        Some(token) => token.subscriber_id,
+    };
+
+    consume_tokens(&mut tx, subscriber_id, now)
+        .await
+        .context("Failed to consume the confirmation tokens")?;
+
+    confirm_subscriber(&mut tx, subscriber_id)
+        .await
+        .context("Failed to confirm the subscriber")?;
+
+    tx.commit()
+        .await
+        .context("Failed to commit SQL transaction to confirm a subscriber")?;
+
+    Ok(Ok(()))
 }
 
-#[tracing::instrument(name = "Mark subscriber as confirmed", skip(pool))]
-async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+#[tracing::instrument(name = "Mark subscriber as confirmed", skip(tx))]
+async fn confirm_subscriber(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
     sqlx::query!(
         r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
         subscriber_id,
     )
-    .execute(pool)
-    .await
-    .map_err(|err| {
-        tracing::error!("Failed to execute query: {:?}", err);
-        err
-    })?;
+    .execute(tx)
+    .await?;
 
     Ok(())
 }
 
+/// Stores a new confirmation token for the subscriber of a token and enqueues its email,
+/// unless they don't need one or can't get one yet.
 #[tracing::instrument(
-    name = "Get subscriber id from a token",
-    skip(pool, subscription_token)
+    name = "Renew a confirmation token",
+    skip(pool, policy, subscription_token)
 )]
-async fn get_subscriber_id_from_token(
+async fn renew_token(
     pool: &PgPool,
+    policy: &SubscriptionPolicy,
     subscription_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
-        r#"SELECT subscriber_id FROM subscription_tokens WHERE subscription_token = $1"#,
+) -> Result<(), anyhow::Error> {
+    let mut tx = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgre connection from the pool")?;
+
+    let subscriber = sqlx::query_as!(
+        ExistingSubscriber,
+        r#"
+        SELECT s.id, s.status, s.subscribed_at, s.last_confirmation_sent_at
+        FROM subscriptions s
+        INNER JOIN subscription_tokens t ON t.subscriber_id = s.id
+        WHERE t.subscription_token = $1
+        FOR UPDATE OF s"#,
         subscription_token,
     )
-    .fetch_optional(pool)
+    .fetch_optional(&mut tx)
     .await
-    .map_err(|err| {
-        tracing::error!("Failed to execute query: {:?}", err);
-        err
-    })?;
+    .context("Failed to fetch the subscriber of a confirmation token")?;
 
-    Ok(result.map(|r| r.subscriber_id))
+    let now = OffsetDateTime::now_utc();
+    let subscriber = match subscriber {
+        Some(subscriber) if subscriber.can_resend_confirmation(policy, now) => subscriber,
+        _ => return Ok(()),
+    };
+
+    record_confirmation_sent(&mut tx, subscriber.id, now)
+        .await
+        .context("Failed to record the confirmation email of a subscriber")?;
+
+    let subscription_token = generate_subscription_token();
+    store_token(&mut tx, subscriber.id, &subscription_token)
+        .await
+        .context("Failed to store a new confirmation token")?;
+    enqueue_confirmation_email(&mut tx, subscriber.id, &subscription_token)
+        .await
+        .context("Failed to enqueue a confirmation email")?;
+
+    tx.commit()
+        .await
+        .context("Failed to commit SQL transaction to store a new confirmation token")?;
+
+    Ok(())
 }
@@ -0,0 +1,88 @@
+use super::subscriptions::consume_tokens;
+use crate::startup::HmacSecret;
+use crate::unsubscribe::UnsubscribeToken;
+use actix_web::http::header::ContentType;
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+use sqlx::PgPool;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeParameters {
+    token: String,
+}
+
+#[derive(askama::Template)]
+#[template(path = "unsubscribe.html.j2")]
+pub struct UnsubscribeTemplate<'a> {
+    user_id: Option<Uuid>,
+    flash_messages: Option<IncomingFlashMessages>,
+    token: &'a str,
+    unsubscribed: bool,
+}
+
+/// Shows a confirmation form: link scanners following the URL must not unsubscribe anyone.
+#[tracing::instrument(name = "Show the unsubscribe form", skip(hmac_secret, parameters))]
+pub async fn unsubscribe_form(
+    hmac_secret: web::Data<HmacSecret>,
+    parameters: web::Query<UnsubscribeParameters>,
+) -> HttpResponse {
+    if UnsubscribeToken::verify(&parameters.token, &hmac_secret).is_none() {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    render(&parameters.token, false)
+}
+
+/// Unsubscribes a subscriber, either from the form or from a RFC 8058 one-click request.
+#[tracing::instrument(name = "Unsubscribe a subscriber", skip(pool, hmac_secret, parameters))]
+pub async fn unsubscribe(
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    parameters: web::Query<UnsubscribeParameters>,
+) -> HttpResponse {
+    let subscriber_id = match UnsubscribeToken::verify(&parameters.token, &hmac_secret) {
+        Some(subscriber_id) => subscriber_id,
+        None => return HttpResponse::Unauthorized().finish(),
+    };
+
+    if let Err(err) = unsubscribe_subscriber(&pool, subscriber_id).await {
+        tracing::error!("Failed to unsubscribe a subscriber: {:?}", err);
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    render(&parameters.token, true)
+}
+
+fn render(token: &str, unsubscribed: bool) -> HttpResponse {
+    let tpl = UnsubscribeTemplate {
+        user_id: None,
+        flash_messages: None,
+        token,
+        unsubscribed,
+    };
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(tpl.render().unwrap())
+}
+
+/// Consumes the confirmation tokens of the subscriber too, so that a pending link can't subscribe
+/// them again.
+#[tracing::instrument(name = "Mark subscriber as unsubscribed", skip(pool))]
+async fn unsubscribe_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        r#"UPDATE subscriptions SET status = 'unsubscribed' WHERE id = $1"#,
+        subscriber_id,
+    )
+    .execute(&mut tx)
+    .await?;
+    consume_tokens(&mut tx, subscriber_id, OffsetDateTime::now_utc()).await?;
+
+    tx.commit().await
+}
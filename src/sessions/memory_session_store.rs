@@ -0,0 +1,228 @@
+use super::session_store::{session_key_to_uuid, uuid_to_session_key, SessionRecord, SessionState};
+use super::{CleanupConfig, SessionLimits, TypedSession, UserSession, UserSessionStore};
+use actix_session::storage::{LoadError, SaveError, SessionKey, SessionStore, UpdateError};
+use actix_web::cookie::time::Duration;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+type Sessions = Mutex<HashMap<Uuid, SessionRecord>>;
+
+/// Keeps the sessions in the memory of the process.
+///
+/// Sessions are lost on restart and aren't shared between instances, so this is only meant for
+/// tests and deployments with a single instance.
+#[derive(Debug, Clone)]
+pub struct MemorySessionStore {
+    sessions: Arc<Sessions>,
+    limits: SessionLimits,
+}
+
+impl MemorySessionStore {
+    pub fn new(cleanup_config: CleanupConfig, limits: SessionLimits) -> Self {
+        let sessions = Arc::new(Sessions::default());
+
+        // Evict the expired sessions in the background, until the store is dropped
+        if cleanup_config.enabled {
+            let sessions = Arc::downgrade(&sessions);
+            tokio::spawn(async move {
+                evict_sessions(sessions, limits, cleanup_config.interval).await;
+            });
+        }
+
+        Self { sessions, limits }
+    }
+
+    fn sessions(&self) -> std::sync::MutexGuard<'_, HashMap<Uuid, SessionRecord>> {
+        // The map is never left inconsistent, even by a panicking thread
+        self.sessions
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+async fn evict_sessions(
+    sessions: Weak<Sessions>,
+    limits: SessionLimits,
+    evict_interval: time::Duration,
+) {
+    let mut interval = tokio::time::interval(evict_interval.unsigned_abs());
+    loop {
+        let _ = interval.tick().await;
+
+        let sessions = match sessions.upgrade() {
+            Some(sessions) => sessions,
+            None => {
+                tracing::debug!("session store is dropped");
+                return;
+            }
+        };
+
+        let now = OffsetDateTime::now_utc();
+        let mut sessions = sessions
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let n_sessions = sessions.len();
+        sessions.retain(|_, record| !record.is_expired(&limits, now));
+
+        tracing::debug!(cleaned = %(n_sessions - sessions.len()), "sessions cleanup done");
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for MemorySessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let session_id = session_key_to_uuid(session_key).map_err(LoadError::Other)?;
+        let now = OffsetDateTime::now_utc();
+
+        let mut sessions = self.sessions();
+        let record = match sessions.get_mut(&session_id) {
+            None => return Ok(None),
+            Some(record) => record,
+        };
+
+        if record.is_expired(&self.limits, now) {
+            sessions.remove(&session_id);
+            return Ok(None);
+        }
+
+        record.touch(&self.limits, now);
+
+        Ok(Some(record.state.clone()))
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let session_id = Uuid::new_v4();
+        let record =
+            SessionRecord::new(session_state, ttl, &self.limits, OffsetDateTime::now_utc());
+
+        self.sessions().insert(session_id, record);
+
+        uuid_to_session_key(session_id).map_err(SaveError::Other)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let session_id = session_key_to_uuid(&session_key).map_err(UpdateError::Other)?;
+        let now = OffsetDateTime::now_utc();
+
+        {
+            let mut sessions = self.sessions();
+            if let Some(record) = sessions.remove(&session_id) {
+                let record = record.update(session_state, ttl, &self.limits, now);
+                sessions.insert(session_id, record);
+
+                return Ok(session_key);
+            }
+        }
+
+        // If the session doesn't exist anymore it has been deleted, e.g. by logging out
+        // everywhere: only save its state as a new anonymous session
+        let mut session_state = session_state;
+        TypedSession::remove_user_from_state(&mut session_state);
+        self.save(session_state, ttl)
+            .await
+            .map_err(|err| match err {
+                SaveError::Serialization(err) => UpdateError::Serialization(err),
+                SaveError::Other(err) => UpdateError::Other(err),
+            })
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        let session_id = session_key_to_uuid(session_key)?;
+
+        self.sessions().remove(&session_id);
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl UserSessionStore for MemorySessionStore {
+    async fn get_user_sessions(&self, user_id: Uuid) -> Result<Vec<UserSession>, anyhow::Error> {
+        let now = OffsetDateTime::now_utc();
+
+        let mut sessions: Vec<UserSession> = self
+            .sessions()
+            .iter()
+            .filter(|(_, record)| {
+                record.user_id == Some(user_id) && !record.is_expired(&self.limits, now)
+            })
+            .map(|(id, record)| record.to_user_session(*id))
+            .collect();
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.last_seen_at));
+
+        Ok(sessions)
+    }
+
+    async fn delete_user_session(
+        &self,
+        user_id: Uuid,
+        session_id: Uuid,
+    ) -> Result<bool, anyhow::Error> {
+        let mut sessions = self.sessions();
+
+        match sessions.get(&session_id) {
+            Some(record) if record.user_id == Some(user_id) => {
+                sessions.remove(&session_id);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn delete_user_sessions(
+        &self,
+        user_id: Uuid,
+        except: Option<Uuid>,
+    ) -> Result<u64, anyhow::Error> {
+        let mut sessions = self.sessions();
+
+        let n_sessions = sessions.len();
+        sessions.retain(|id, record| record.user_id != Some(user_id) || Some(*id) == except);
+
+        Ok((n_sessions - sessions.len()) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemorySessionStore;
+    use crate::sessions::session_store::tests::session_store_conformance_tests;
+    use crate::sessions::{CleanupConfig, SessionLimits};
+    use actix_session::storage::SessionStore;
+    use actix_web::cookie::time::Duration;
+    use std::collections::HashMap;
+
+    session_store_conformance_tests!(
+        #[tokio::test]
+        () | limits
+            | MemorySessionStore::new(CleanupConfig::default(), limits)
+    );
+
+    #[tokio::test]
+    async fn the_cleanup_task_evicts_the_expired_sessions() {
+        let store = MemorySessionStore::new(
+            CleanupConfig::new(true, Duration::milliseconds(100)),
+            SessionLimits::default(),
+        );
+
+        store
+            .save(HashMap::new(), &Duration::milliseconds(50))
+            .await
+            .expect("Unable to save the session");
+
+        tokio::time::sleep(Duration::milliseconds(300).unsigned_abs()).await;
+
+        assert!(store.sessions().is_empty());
+    }
+}
@@ -1,5 +1,11 @@
+pub use memory_session_store::*;
+pub use pg_session_store::*;
+pub use redis_session_store::*;
 pub use session_state::*;
 pub use session_store::*;
 
+mod memory_session_store;
+mod pg_session_store;
+mod redis_session_store;
 mod session_state;
 mod session_store;
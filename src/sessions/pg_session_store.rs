@@ -0,0 +1,333 @@
+use super::session_store::{session_key_to_uuid, uuid_to_session_key, SessionState};
+use super::{CleanupConfig, SessionLimits, TypedSession, UserSession, UserSessionStore};
+use actix_session::storage::{LoadError, SaveError, UpdateError};
+use actix_session::storage::{SessionKey, SessionStore};
+use actix_web::cookie::time::Duration;
+use anyhow::{anyhow, Context};
+use uuid::Uuid;
+
+/// Keeps the sessions in the `sessions` table.
+#[derive(Debug, Clone)]
+pub struct PgSessionStore {
+    pool: sqlx::PgPool,
+    limits: SessionLimits,
+}
+
+impl PgSessionStore {
+    pub fn new(pool: sqlx::PgPool, cleanup_config: CleanupConfig, limits: SessionLimits) -> Self {
+        // Launch a background cleanup task if necessary
+        if cleanup_config.enabled {
+            let cleanup_pool = pool.clone();
+            tokio::spawn(async move {
+                clean_sessions(cleanup_pool, cleanup_config.interval).await;
+            });
+        }
+
+        Self { pool, limits }
+    }
+}
+
+async fn clean_sessions(pool: sqlx::PgPool, clean_interval: time::Duration) {
+    let mut interval = tokio::time::interval(clean_interval.unsigned_abs());
+    loop {
+        let _ = interval.tick().await;
+        let now = time::OffsetDateTime::now_utc();
+
+        let result = sqlx::query!("DELETE FROM sessions WHERE expires_at <= $1", now)
+            .execute(&pool)
+            .await;
+        match result {
+            Ok(result) => {
+                tracing::debug!(cleaned = %result.rows_affected(), "sessions cleanup done");
+            }
+            Err(err) => match err {
+                sqlx::Error::PoolClosed => {
+                    tracing::debug!("pool is closed");
+                    return;
+                }
+                _ => tracing::error!(?err, "unable to cleanup sessions"),
+            },
+        }
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for PgSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let session_id = session_key_to_uuid(session_key).map_err(LoadError::Other)?;
+
+        // Fetch the state
+        let row = sqlx::query!(
+            "SELECT state, created_at, last_seen_at, expires_at FROM sessions WHERE id = $1",
+            session_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(Into::<anyhow::Error>::into)
+        .map_err(LoadError::Other)?;
+
+        let row = match row {
+            None => return Ok(None),
+            Some(row) => row,
+        };
+
+        // Check the expiry date, and the limits in case they have been lowered since the
+        // expiry date has been computed.
+        let now = time::OffsetDateTime::now_utc();
+        if row.expires_at < now
+            || self
+                .limits
+                .is_expired(row.created_at, row.last_seen_at, now)
+        {
+            return Ok(None);
+        }
+
+        tracing::trace!(now = %now, expires_at = %row.expires_at, session_id = %session_id, "loaded state");
+
+        // Push back the idle timeout, but not on every request
+        if now - row.last_seen_at >= self.limits.touch_interval {
+            sqlx::query!(
+                "UPDATE sessions SET last_seen_at = $1, expires_at = $2 WHERE id = $3",
+                now,
+                self.limits.expires_at(row.created_at, now),
+                session_id,
+            )
+            .execute(&self.pool)
+            .await
+            .map_err(Into::<anyhow::Error>::into)
+            .map_err(LoadError::Other)?;
+        }
+
+        let session_state_data = row.state;
+
+        let state = serde_json::from_slice(&session_state_data)
+            .map_err(Into::<anyhow::Error>::into)
+            .map_err(LoadError::Deserialization)?;
+
+        Ok(state)
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        // Setup
+
+        let session_id = Uuid::new_v4();
+        let user_id = TypedSession::user_id_from_state(&session_state);
+        let (user_agent, ip_address) = TypedSession::client_from_state(&session_state);
+        let state = serde_json::to_string(&session_state)
+            .map_err(Into::into)
+            .map_err(SaveError::Serialization)?;
+
+        let created_at = time::OffsetDateTime::now_utc();
+        let expires_at = created_at
+            .checked_add(*ttl)
+            .ok_or_else(|| SaveError::Other(anyhow!("unable to compute expiry timestamp")))?
+            .min(created_at + self.limits.max_lifetime);
+
+        // Save data
+
+        sqlx::query!(
+            r#"
+            INSERT INTO sessions(id, state, created_at, expires_at, user_id, user_agent, ip_address, last_seen_at)
+            VALUES($1, $2, $3, $4, $5, $6, $7, $3)
+            "#,
+            session_id,
+            state.as_bytes(),
+            created_at,
+            expires_at,
+            user_id,
+            user_agent,
+            ip_address,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(Into::<anyhow::Error>::into)
+        .map_err(SaveError::Other)?;
+
+        // Return the session key
+
+        let session_key = uuid_to_session_key(session_id).map_err(SaveError::Other)?;
+
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        // Setup
+
+        let session_id = session_key_to_uuid(&session_key).map_err(UpdateError::Other)?;
+        let user_id = TypedSession::user_id_from_state(&session_state);
+        let (user_agent, ip_address) = TypedSession::client_from_state(&session_state);
+        let state = serde_json::to_string(&session_state)
+            .map_err(Into::into)
+            .map_err(UpdateError::Serialization)?;
+        let now = time::OffsetDateTime::now_utc();
+        let expires_at = now
+            .checked_add(*ttl)
+            .ok_or_else(|| UpdateError::Other(anyhow!("unable to compute expiry timestamp")))?;
+
+        // Check if the session exists
+        let row = sqlx::query!("SELECT created_at FROM sessions WHERE id = $1", session_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(Into::into)
+            .map_err(UpdateError::Other)?;
+
+        match row {
+            Some(row) => {
+                // The session exists, update it without going past its max lifetime
+                let expires_at = expires_at.min(row.created_at + self.limits.max_lifetime);
+
+                sqlx::query!(
+                    r#"
+                    UPDATE sessions
+                    SET state = $1, expires_at = $2, user_id = $3, user_agent = $4, ip_address = $5,
+                        last_seen_at = $6
+                    WHERE id = $7
+                    "#,
+                    state.as_bytes(),
+                    expires_at,
+                    user_id,
+                    user_agent,
+                    ip_address,
+                    now,
+                    session_id,
+                )
+                .execute(&self.pool)
+                .await
+                .map_err(Into::into)
+                .map_err(UpdateError::Other)?;
+
+                Ok(session_key)
+            }
+            None => {
+                // If the session doesn't exist anymore it has been deleted, e.g. by logging out
+                // everywhere: only save its state as a new anonymous session
+                let mut session_state = session_state;
+                TypedSession::remove_user_from_state(&mut session_state);
+
+                self.save(session_state, ttl)
+                    .await
+                    .map_err(|err| match err {
+                        SaveError::Serialization(err) => UpdateError::Serialization(err),
+                        SaveError::Other(err) => UpdateError::Other(err),
+                    })
+            }
+        }
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        let session_id = session_key_to_uuid(session_key)?;
+
+        sqlx::query!("DELETE FROM sessions WHERE id = $1", session_id)
+            .execute(&self.pool)
+            .await
+            .map_err(Into::into)
+            .map_err(UpdateError::Other)?;
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl UserSessionStore for PgSessionStore {
+    #[tracing::instrument(skip(self))]
+    async fn get_user_sessions(&self, user_id: Uuid) -> Result<Vec<UserSession>, anyhow::Error> {
+        let sessions = sqlx::query_as!(
+            UserSession,
+            r#"
+            SELECT id, user_agent, ip_address, created_at, last_seen_at FROM sessions
+            WHERE user_id = $1 AND expires_at > now()
+            ORDER BY last_seen_at DESC
+            "#,
+            user_id,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to fetch the sessions of the user")?;
+
+        Ok(sessions)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn delete_user_session(
+        &self,
+        user_id: Uuid,
+        session_id: Uuid,
+    ) -> Result<bool, anyhow::Error> {
+        let deleted = sqlx::query!(
+            "DELETE FROM sessions WHERE id = $1 AND user_id = $2",
+            session_id,
+            user_id,
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to delete the session")?
+        .rows_affected();
+
+        Ok(deleted > 0)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn delete_user_sessions(
+        &self,
+        user_id: Uuid,
+        except: Option<Uuid>,
+    ) -> Result<u64, anyhow::Error> {
+        let deleted = sqlx::query!(
+            "DELETE FROM sessions WHERE user_id = $1 AND id IS DISTINCT FROM $2",
+            user_id,
+            except,
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to delete the sessions of the user")?
+        .rows_affected();
+
+        Ok(deleted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PgSessionStore;
+    use crate::sessions::session_store::tests::session_store_conformance_tests;
+    use crate::sessions::CleanupConfig;
+    use actix_session::storage::SessionStore;
+    use actix_web::cookie::time::Duration;
+    use std::collections::HashMap;
+
+    session_store_conformance_tests!(
+        #[sqlx::test] (pool: sqlx::PgPool)
+        |limits| PgSessionStore::new(pool, CleanupConfig::default(), limits)
+    );
+
+    #[sqlx::test]
+    async fn the_cleanup_task_deletes_the_expired_sessions(pool: sqlx::PgPool) {
+        let store = PgSessionStore::new(
+            pool.clone(),
+            CleanupConfig::new(true, Duration::milliseconds(100)),
+            Default::default(),
+        );
+
+        store
+            .save(HashMap::new(), &Duration::milliseconds(50))
+            .await
+            .expect("Unable to save the session");
+
+        tokio::time::sleep(Duration::milliseconds(300).unsigned_abs()).await;
+
+        let n_sessions = sqlx::query_scalar!("SELECT COUNT(*) FROM sessions")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(n_sessions, Some(0));
+    }
+}
@@ -0,0 +1,338 @@
+use super::session_store::{session_key_to_uuid, uuid_to_session_key, SessionRecord, SessionState};
+use super::{SessionLimits, TypedSession, UserSession, UserSessionStore};
+use actix_session::storage::{LoadError, SaveError, SessionKey, SessionStore, UpdateError};
+use actix_web::cookie::time::Duration;
+use anyhow::Context;
+use redis::aio::ConnectionManager;
+use redis::FromRedisValue;
+use std::sync::Arc;
+use time::OffsetDateTime;
+use tokio::sync::OnceCell;
+use uuid::Uuid;
+
+/// Keeps the sessions in Redis, which evicts them by itself once expired.
+///
+/// Each session is a JSON record at `session:{id}`, and the ids of the sessions of a user are in
+/// the set at `user_sessions:{user_id}`. Ids of sessions evicted by Redis are only removed from
+/// that set when listing the sessions of the user.
+///
+/// Commands are multiplexed over a connection shared by every worker, opened on first use and
+/// opened again if it breaks.
+#[derive(Clone)]
+pub struct RedisSessionStore {
+    client: redis::Client,
+    connection: Arc<OnceCell<ConnectionManager>>,
+    timeout: std::time::Duration,
+    limits: SessionLimits,
+}
+
+impl RedisSessionStore {
+    pub fn new(client: redis::Client, timeout: std::time::Duration, limits: SessionLimits) -> Self {
+        Self {
+            client,
+            connection: Arc::new(OnceCell::new()),
+            timeout,
+            limits,
+        }
+    }
+
+    /// Runs a command, failing if the server doesn't reply in time.
+    async fn query<T: FromRedisValue>(&self, command: &redis::Cmd) -> Result<T, anyhow::Error> {
+        tokio::time::timeout(self.timeout, async {
+            let mut connection = self
+                .connection
+                .get_or_try_init(|| self.client.get_connection_manager())
+                .await
+                .context("Failed to connect to Redis")?
+                .clone();
+
+            Ok(command.query_async(&mut connection).await?)
+        })
+        .await
+        .context("The Redis server didn't reply in time")?
+    }
+
+    async fn get_record(&self, session_id: Uuid) -> Result<Option<SessionRecord>, anyhow::Error> {
+        let data: Option<Vec<u8>> = self
+            .query(redis::cmd("GET").arg(session_key(session_id)))
+            .await?;
+
+        match data {
+            Some(data) => {
+                let record = serde_json::from_slice(&data).context("invalid session record")?;
+                Ok(Some(record))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Writes a record which expires at the same time as the session; `only_if_exists` avoids
+    /// bringing back a session deleted in the meantime.
+    ///
+    /// Returns `false` if the session hasn't been written.
+    async fn set_record(
+        &self,
+        session_id: Uuid,
+        record: &SessionRecord,
+        only_if_exists: bool,
+        now: OffsetDateTime,
+    ) -> Result<bool, anyhow::Error> {
+        let ttl_milliseconds = (record.expires_at - now).whole_milliseconds();
+        if ttl_milliseconds <= 0 {
+            return Ok(false);
+        }
+
+        let mut command = redis::cmd("SET");
+        command
+            .arg(session_key(session_id))
+            .arg(serde_json::to_vec(record)?)
+            .arg("PX")
+            .arg(ttl_milliseconds as u64);
+        if only_if_exists {
+            command.arg("XX");
+        }
+
+        let written: Option<String> = self.query(&command).await?;
+        if written.is_none() {
+            return Ok(false);
+        }
+
+        if let Some(user_id) = record.user_id {
+            let key = user_sessions_key(user_id);
+
+            self.query::<()>(redis::cmd("SADD").arg(&key).arg(session_id.to_string()))
+                .await?;
+            self.query::<()>(
+                redis::cmd("PEXPIRE")
+                    .arg(&key)
+                    .arg(self.limits.max_lifetime.whole_milliseconds() as u64),
+            )
+            .await?;
+        }
+
+        Ok(true)
+    }
+
+    /// Deletes a session, returning `false` if it didn't exist.
+    async fn delete_record(
+        &self,
+        session_id: Uuid,
+        user_id: Option<Uuid>,
+    ) -> Result<bool, anyhow::Error> {
+        let n_deleted: i64 = self
+            .query(redis::cmd("DEL").arg(session_key(session_id)))
+            .await?;
+
+        if let Some(user_id) = user_id {
+            self.query::<()>(
+                redis::cmd("SREM")
+                    .arg(user_sessions_key(user_id))
+                    .arg(session_id.to_string()),
+            )
+            .await?;
+        }
+
+        Ok(n_deleted > 0)
+    }
+
+    /// Returns the sessions listed in the set of a user, forgetting the ones which are gone.
+    async fn get_user_records(
+        &self,
+        user_id: Uuid,
+        now: OffsetDateTime,
+    ) -> Result<Vec<(Uuid, SessionRecord)>, anyhow::Error> {
+        let key = user_sessions_key(user_id);
+
+        let members: Vec<String> = self.query(redis::cmd("SMEMBERS").arg(&key)).await?;
+
+        let mut records = Vec::new();
+        for member in members {
+            let session_id = Uuid::parse_str(&member).context("invalid session id")?;
+
+            match self.get_record(session_id).await? {
+                Some(record)
+                    if record.user_id == Some(user_id) && !record.is_expired(&self.limits, now) =>
+                {
+                    records.push((session_id, record));
+                }
+                _ => {
+                    self.query::<()>(redis::cmd("SREM").arg(&key).arg(member))
+                        .await?;
+                }
+            }
+        }
+
+        Ok(records)
+    }
+}
+
+fn session_key(session_id: Uuid) -> String {
+    format!("session:{}", session_id)
+}
+
+fn user_sessions_key(user_id: Uuid) -> String {
+    format!("user_sessions:{}", user_id)
+}
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for RedisSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let session_id = session_key_to_uuid(session_key).map_err(LoadError::Other)?;
+        let now = OffsetDateTime::now_utc();
+
+        let mut record = match self
+            .get_record(session_id)
+            .await
+            .map_err(LoadError::Other)?
+        {
+            None => return Ok(None),
+            Some(record) => record,
+        };
+
+        // Redis evicts the session once expired, but the limits may have been lowered since
+        if record.is_expired(&self.limits, now) {
+            self.delete_record(session_id, record.user_id)
+                .await
+                .map_err(LoadError::Other)?;
+            return Ok(None);
+        }
+
+        if record.touch(&self.limits, now) {
+            self.set_record(session_id, &record, true, now)
+                .await
+                .map_err(LoadError::Other)?;
+        }
+
+        Ok(Some(record.state))
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let session_id = Uuid::new_v4();
+        let now = OffsetDateTime::now_utc();
+        let record = SessionRecord::new(session_state, ttl, &self.limits, now);
+
+        self.set_record(session_id, &record, false, now)
+            .await
+            .map_err(SaveError::Other)?;
+
+        uuid_to_session_key(session_id).map_err(SaveError::Other)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let session_id = session_key_to_uuid(&session_key).map_err(UpdateError::Other)?;
+        let now = OffsetDateTime::now_utc();
+
+        if let Some(record) = self
+            .get_record(session_id)
+            .await
+            .map_err(UpdateError::Other)?
+        {
+            let record = record.update(session_state.clone(), ttl, &self.limits, now);
+
+            let updated = self
+                .set_record(session_id, &record, true, now)
+                .await
+                .map_err(UpdateError::Other)?;
+            if updated {
+                return Ok(session_key);
+            }
+        }
+
+        // If the session doesn't exist anymore it has been deleted, e.g. by logging out
+        // everywhere: only save its state as a new anonymous session
+        let mut session_state = session_state;
+        TypedSession::remove_user_from_state(&mut session_state);
+        self.save(session_state, ttl)
+            .await
+            .map_err(|err| match err {
+                SaveError::Serialization(err) => UpdateError::Serialization(err),
+                SaveError::Other(err) => UpdateError::Other(err),
+            })
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        let session_id = session_key_to_uuid(session_key)?;
+
+        let user_id = self
+            .get_record(session_id)
+            .await?
+            .and_then(|record| record.user_id);
+        self.delete_record(session_id, user_id).await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl UserSessionStore for RedisSessionStore {
+    async fn get_user_sessions(&self, user_id: Uuid) -> Result<Vec<UserSession>, anyhow::Error> {
+        let now = OffsetDateTime::now_utc();
+
+        let mut sessions: Vec<UserSession> = self
+            .get_user_records(user_id, now)
+            .await?
+            .iter()
+            .map(|(id, record)| record.to_user_session(*id))
+            .collect();
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.last_seen_at));
+
+        Ok(sessions)
+    }
+
+    async fn delete_user_session(
+        &self,
+        user_id: Uuid,
+        session_id: Uuid,
+    ) -> Result<bool, anyhow::Error> {
+        match self.get_record(session_id).await? {
+            Some(record) if record.user_id == Some(user_id) => {
+                self.delete_record(session_id, Some(user_id)).await
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn delete_user_sessions(
+        &self,
+        user_id: Uuid,
+        except: Option<Uuid>,
+    ) -> Result<u64, anyhow::Error> {
+        let now = OffsetDateTime::now_utc();
+
+        let mut n_deleted = 0;
+        for (session_id, _) in self.get_user_records(user_id, now).await? {
+            if Some(session_id) != except && self.delete_record(session_id, Some(user_id)).await? {
+                n_deleted += 1;
+            }
+        }
+
+        Ok(n_deleted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RedisSessionStore;
+    use crate::sessions::session_store::tests::session_store_conformance_tests;
+    use std::time::Duration;
+
+    // These tests need a Redis server listening on localhost, CI runs one
+    session_store_conformance_tests!(
+        #[tokio::test]
+        () | limits
+            | RedisSessionStore::new(
+                redis::Client::open("redis://127.0.0.1:6379/0").unwrap(),
+                Duration::from_secs(1),
+                limits,
+            )
+    );
+}
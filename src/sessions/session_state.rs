@@ -1,29 +1,111 @@
 use actix_session::{Session, SessionExt};
 use actix_web::dev::Payload;
 use actix_web::{FromRequest, HttpRequest};
+use std::collections::HashMap;
 use std::future;
 use uuid::Uuid;
 
 pub struct TypedSession(Session);
 
 impl TypedSession {
+    /// Set once the user is fully authenticated.
     const USER_ID_KEY: &'static str = "user_id";
+    /// Set when the password has been verified but the second factor hasn't been yet.
+    const SECOND_FACTOR_USER_ID_KEY: &'static str = "second_factor_user_id";
+    /// The TOTP secret being enrolled, until the user confirms it with a first code.
+    const PENDING_TOTP_SECRET_KEY: &'static str = "pending_totp_secret";
+    /// The device the session was opened from, shown to the user in the list of their sessions.
+    const USER_AGENT_KEY: &'static str = "user_agent";
+    const IP_ADDRESS_KEY: &'static str = "ip_address";
+
+    /// User agents are only displayed, there is no point in storing huge ones.
+    const MAX_USER_AGENT_LENGTH: usize = 256;
 
     pub fn renew(&self) {
         self.0.renew();
     }
 
+    /// Marks the session as fully authenticated.
     pub fn insert_user_id(&self, user_id: Uuid) -> Result<(), serde_json::Error> {
+        self.0.remove(Self::SECOND_FACTOR_USER_ID_KEY);
         self.0.insert(Self::USER_ID_KEY, user_id)
     }
 
+    /// Returns the user id only if the session is fully authenticated.
     pub fn get_user_id(&self) -> Result<Option<Uuid>, serde_json::Error> {
         self.0.get(Self::USER_ID_KEY)
     }
 
+    /// Marks the session as half authenticated: the password is valid and the user must now
+    /// provide their second factor.
+    pub fn insert_second_factor_user_id(&self, user_id: Uuid) -> Result<(), serde_json::Error> {
+        self.0.remove(Self::USER_ID_KEY);
+        self.0.insert(Self::SECOND_FACTOR_USER_ID_KEY, user_id)
+    }
+
+    pub fn get_second_factor_user_id(&self) -> Result<Option<Uuid>, serde_json::Error> {
+        self.0.get(Self::SECOND_FACTOR_USER_ID_KEY)
+    }
+
+    pub fn insert_pending_totp_secret(&self, secret: &str) -> Result<(), serde_json::Error> {
+        self.0.insert(Self::PENDING_TOTP_SECRET_KEY, secret)
+    }
+
+    pub fn get_pending_totp_secret(&self) -> Result<Option<String>, serde_json::Error> {
+        self.0.get(Self::PENDING_TOTP_SECRET_KEY)
+    }
+
+    pub fn remove_pending_totp_secret(&self) {
+        self.0.remove(Self::PENDING_TOTP_SECRET_KEY);
+    }
+
+    pub fn insert_client(
+        &self,
+        user_agent: &str,
+        ip_address: &str,
+    ) -> Result<(), serde_json::Error> {
+        let user_agent: String = user_agent
+            .chars()
+            .take(Self::MAX_USER_AGENT_LENGTH)
+            .collect();
+        self.0.insert(Self::USER_AGENT_KEY, user_agent)?;
+        self.0.insert(Self::IP_ADDRESS_KEY, ip_address)
+    }
+
     pub fn logout(self) {
         self.0.purge()
     }
+
+    /// Returns the user a session state belongs to, whether fully authenticated or not.
+    pub(crate) fn user_id_from_state(state: &HashMap<String, String>) -> Option<Uuid> {
+        [Self::USER_ID_KEY, Self::SECOND_FACTOR_USER_ID_KEY]
+            .iter()
+            .find_map(|key| state.get(*key))
+            .and_then(|value| serde_json::from_str(value).ok())
+    }
+
+    /// Removes everything authenticating a session state, leaving an anonymous session.
+    pub(crate) fn remove_user_from_state(state: &mut HashMap<String, String>) {
+        for key in [
+            Self::USER_ID_KEY,
+            Self::SECOND_FACTOR_USER_ID_KEY,
+            Self::PENDING_TOTP_SECRET_KEY,
+        ] {
+            state.remove(key);
+        }
+    }
+
+    /// Returns the user agent and the IP address the session was opened from, if known.
+    pub(crate) fn client_from_state(
+        state: &HashMap<String, String>,
+    ) -> (Option<String>, Option<String>) {
+        let get = |key: &str| {
+            state
+                .get(key)
+                .and_then(|value| serde_json::from_str(value).ok())
+        };
+        (get(Self::USER_AGENT_KEY), get(Self::IP_ADDRESS_KEY))
+    }
 }
 
 impl FromRequest for TypedSession {
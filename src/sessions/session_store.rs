@@ -1,19 +1,67 @@
+use super::TypedSession;
 use actix_session::storage::{LoadError, SaveError, UpdateError};
 use actix_session::storage::{SessionKey, SessionStore};
 use actix_web::cookie::time::Duration;
-use anyhow::anyhow;
+use actix_web::cookie::{CookieJar, Key};
+use actix_web::HttpRequest;
 use std::collections::HashMap;
+use std::sync::Arc;
+use time::OffsetDateTime;
 use uuid::Uuid;
 
-#[derive(Debug, Clone)]
-pub struct PgSessionStore {
-    pool: sqlx::PgPool,
+/// The name of the cookie holding the session key, encrypted.
+pub const SESSION_COOKIE_NAME: &str = "id";
+
+pub(crate) type SessionState = HashMap<String, String>;
+
+/// How long sessions can live.
+#[derive(Debug, Clone, Copy)]
+pub struct SessionLimits {
+    /// Sessions unused for this long are rejected.
+    pub idle_timeout: time::Duration,
+    /// Sessions are rejected this long after being created, even if they are in use.
+    pub max_lifetime: time::Duration,
+    /// Loading a session only updates the last time it has been seen once per interval.
+    pub touch_interval: time::Duration,
+}
+
+impl SessionLimits {
+    pub(crate) fn is_expired(
+        &self,
+        created_at: OffsetDateTime,
+        last_seen_at: OffsetDateTime,
+        now: OffsetDateTime,
+    ) -> bool {
+        now >= created_at + self.max_lifetime || now >= last_seen_at + self.idle_timeout
+    }
+
+    /// Returns when a session seen at `last_seen_at` expires, unless it's used again.
+    pub(crate) fn expires_at(
+        &self,
+        created_at: OffsetDateTime,
+        last_seen_at: OffsetDateTime,
+    ) -> OffsetDateTime {
+        std::cmp::min(
+            last_seen_at + self.idle_timeout,
+            created_at + self.max_lifetime,
+        )
+    }
+}
+
+impl Default for SessionLimits {
+    fn default() -> Self {
+        Self {
+            idle_timeout: time::Duration::days(1),
+            max_lifetime: time::Duration::days(30),
+            touch_interval: time::Duration::minutes(1),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct CleanupConfig {
-    enabled: bool,
-    interval: time::Duration,
+    pub(crate) enabled: bool,
+    pub(crate) interval: time::Duration,
 }
 
 impl CleanupConfig {
@@ -28,79 +76,47 @@ impl Default for CleanupConfig {
     }
 }
 
-impl PgSessionStore {
-    pub fn new(pool: sqlx::PgPool, cleanup_config: CleanupConfig) -> Self {
-        // Launch a background cleanup task if necessary
-        if cleanup_config.enabled {
-            let cleanup_pool = pool.clone();
-            tokio::spawn(async move {
-                clean_sessions(cleanup_pool, cleanup_config.interval).await;
-            });
-        }
-
-        Self { pool }
-    }
+/// A session of a user, as listed to let them revoke it.
+#[derive(Debug, Clone)]
+pub struct UserSession {
+    pub id: Uuid,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: OffsetDateTime,
+    pub last_seen_at: OffsetDateTime,
 }
 
-async fn clean_sessions(pool: sqlx::PgPool, clean_interval: time::Duration) {
-    let mut interval = tokio::time::interval(clean_interval.unsigned_abs());
-    loop {
-        let _ = interval.tick().await;
-        let now = time::OffsetDateTime::now_utc();
-
-        let result = sqlx::query!("DELETE FROM sessions WHERE expires_at <= $1", now)
-            .execute(&pool)
-            .await;
-        match result {
-            Ok(result) => {
-                tracing::debug!(cleaned = %result.rows_affected(), "sessions cleanup done");
-            }
-            Err(err) => match err {
-                sqlx::Error::PoolClosed => {
-                    tracing::debug!("pool is closed");
-                    return;
-                }
-                _ => tracing::error!(?err, "unable to cleanup sessions"),
-            },
-        }
-    }
+/// A session store which also knows the sessions of each user, to list and revoke them.
+#[async_trait::async_trait(?Send)]
+pub trait UserSessionStore: SessionStore + Send + Sync {
+    /// Returns the unexpired sessions of a user, the most recently used first.
+    async fn get_user_sessions(&self, user_id: Uuid) -> Result<Vec<UserSession>, anyhow::Error>;
+
+    /// Deletes a single session of a user; returns `false` if the user has no such session.
+    async fn delete_user_session(
+        &self,
+        user_id: Uuid,
+        session_id: Uuid,
+    ) -> Result<bool, anyhow::Error>;
+
+    /// Deletes every session of a user but `except`, logging them out everywhere else.
+    ///
+    /// Returns the number of deleted sessions.
+    async fn delete_user_sessions(
+        &self,
+        user_id: Uuid,
+        except: Option<Uuid>,
+    ) -> Result<u64, anyhow::Error>;
 }
 
-type SessionState = HashMap<String, String>;
+/// The session store selected in the settings, shared by the session middleware of every worker.
+#[derive(Clone)]
+pub struct SharedSessionStore(pub Arc<dyn UserSessionStore>);
 
 #[async_trait::async_trait(?Send)]
-impl SessionStore for PgSessionStore {
+impl SessionStore for SharedSessionStore {
     async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
-        let session_id = session_key_to_uuid(session_key).map_err(LoadError::Other)?;
-
-        // Fetch the state
-        let row = sqlx::query!(
-            "SELECT state, expires_at FROM sessions WHERE id = $1",
-            session_id
-        )
-        .fetch_optional(&self.pool)
-        .await
-        .map_err(Into::<anyhow::Error>::into)
-        .map_err(LoadError::Other)?;
-
-        let (session_state_data, expires_at) = match row {
-            None => return Ok(None),
-            Some(row) => (row.state, row.expires_at),
-        };
-
-        // Check the expiry date
-        let now = time::OffsetDateTime::now_utc();
-        if expires_at < now {
-            return Ok(None);
-        }
-
-        tracing::trace!(now = %now, expires_at = %expires_at, session_id = %session_id, "loaded state");
-
-        let state = serde_json::from_slice(&session_state_data)
-            .map_err(Into::<anyhow::Error>::into)
-            .map_err(LoadError::Deserialization)?;
-
-        Ok(state)
+        self.0.load(session_key).await
     }
 
     async fn save(
@@ -108,106 +124,135 @@ impl SessionStore for PgSessionStore {
         session_state: SessionState,
         ttl: &Duration,
     ) -> Result<SessionKey, SaveError> {
-        // Setup
+        self.0.save(session_state, ttl).await
+    }
 
-        let session_id = Uuid::new_v4();
-        let state = serde_json::to_string(&session_state)
-            .map_err(Into::into)
-            .map_err(SaveError::Serialization)?;
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        self.0.update(session_key, session_state, ttl).await
+    }
 
-        let created_at = time::OffsetDateTime::now_utc();
-        let expires_at = created_at
-            .checked_add(*ttl)
-            .ok_or_else(|| SaveError::Other(anyhow!("unable to compute expiry timestamp")))?;
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        self.0.delete(session_key).await
+    }
+}
 
-        // Save data
+/// A session as kept by the stores which don't have a table with a column per field.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub(crate) struct SessionRecord {
+    pub(crate) state: SessionState,
+    pub(crate) user_id: Option<Uuid>,
+    pub(crate) user_agent: Option<String>,
+    pub(crate) ip_address: Option<String>,
+    #[serde(with = "unix_timestamp_micros")]
+    pub(crate) created_at: OffsetDateTime,
+    #[serde(with = "unix_timestamp_micros")]
+    pub(crate) last_seen_at: OffsetDateTime,
+    #[serde(with = "unix_timestamp_micros")]
+    pub(crate) expires_at: OffsetDateTime,
+}
 
-        sqlx::query!(
-            "INSERT INTO sessions(id, state, created_at, expires_at) VALUES($1, $2, $3, $4)",
-            session_id,
-            state.as_bytes(),
+impl SessionRecord {
+    pub(crate) fn new(
+        state: SessionState,
+        ttl: &Duration,
+        limits: &SessionLimits,
+        now: OffsetDateTime,
+    ) -> Self {
+        let (user_agent, ip_address) = TypedSession::client_from_state(&state);
+
+        Self {
+            user_id: TypedSession::user_id_from_state(&state),
+            user_agent,
+            ip_address,
+            state,
+            created_at: now,
+            last_seen_at: now,
+            expires_at: std::cmp::min(now + *ttl, now + limits.max_lifetime),
+        }
+    }
+
+    /// Replaces the state of the session, keeping its creation time.
+    pub(crate) fn update(
+        self,
+        state: SessionState,
+        ttl: &Duration,
+        limits: &SessionLimits,
+        now: OffsetDateTime,
+    ) -> Self {
+        let created_at = self.created_at;
+
+        Self {
             created_at,
-            expires_at,
-        )
-        .execute(&self.pool)
-        .await
-        .map_err(Into::<anyhow::Error>::into)
-        .map_err(SaveError::Other)?;
+            expires_at: std::cmp::min(now + *ttl, created_at + limits.max_lifetime),
+            ..Self::new(state, ttl, limits, now)
+        }
+    }
 
-        // Return the session key
+    pub(crate) fn is_expired(&self, limits: &SessionLimits, now: OffsetDateTime) -> bool {
+        self.expires_at <= now || limits.is_expired(self.created_at, self.last_seen_at, now)
+    }
 
-        let session_key = uuid_to_session_key(session_id).map_err(SaveError::Other)?;
+    /// Pushes back the idle timeout, returning `false` if the session has been touched too
+    /// recently to bother.
+    pub(crate) fn touch(&mut self, limits: &SessionLimits, now: OffsetDateTime) -> bool {
+        if now - self.last_seen_at < limits.touch_interval {
+            return false;
+        }
 
-        Ok(session_key)
+        self.last_seen_at = now;
+        self.expires_at = limits.expires_at(self.created_at, now);
+        true
     }
 
-    async fn update(
-        &self,
-        session_key: SessionKey,
-        session_state: SessionState,
-        ttl: &Duration,
-    ) -> Result<SessionKey, UpdateError> {
-        // Setup
-
-        let session_id = session_key_to_uuid(&session_key).map_err(UpdateError::Other)?;
-        let state = serde_json::to_string(&session_state)
-            .map_err(Into::into)
-            .map_err(UpdateError::Serialization)?;
-        let expires_at = time::OffsetDateTime::now_utc()
-            .checked_add(*ttl)
-            .ok_or_else(|| UpdateError::Other(anyhow!("unable to compute expiry timestamp")))?;
-
-        // Check if the session exists
-        let row = sqlx::query!("SELECT 1 AS n FROM sessions WHERE id = $1", session_id)
-            .fetch_optional(&self.pool)
-            .await
-            .map_err(Into::into)
-            .map_err(UpdateError::Other)?;
-
-        match row {
-            Some(_) => {
-                // The session exists, update it
-
-                sqlx::query!(
-                    "UPDATE sessions SET state = $1, expires_at = $2 WHERE id = $3",
-                    state.as_bytes(),
-                    expires_at,
-                    session_id,
-                )
-                .execute(&self.pool)
-                .await
-                .map_err(Into::into)
-                .map_err(UpdateError::Other)?;
-
-                Ok(session_key)
-            }
-            None => {
-                // If the session doesn't exist fall back to calling save
-
-                self.save(session_state, ttl)
-                    .await
-                    .map_err(|err| match err {
-                        SaveError::Serialization(err) => UpdateError::Serialization(err),
-                        SaveError::Other(err) => UpdateError::Other(err),
-                    })
-            }
+    pub(crate) fn to_user_session(&self, id: Uuid) -> UserSession {
+        UserSession {
+            id,
+            user_agent: self.user_agent.clone(),
+            ip_address: self.ip_address.clone(),
+            created_at: self.created_at,
+            last_seen_at: self.last_seen_at,
         }
     }
+}
 
-    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
-        let session_id = session_key_to_uuid(session_key)?;
+/// Timestamps are kept with the precision of Postgres, whatever the store.
+mod unix_timestamp_micros {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use time::OffsetDateTime;
 
-        sqlx::query!("DELETE FROM sessions WHERE id = $1", session_id)
-            .execute(&self.pool)
-            .await
-            .map_err(Into::into)
-            .map_err(UpdateError::Other)?;
+    pub fn serialize<S: Serializer>(dt: &OffsetDateTime, serializer: S) -> Result<S::Ok, S::Error> {
+        ((dt.unix_timestamp_nanos() / 1000) as i64).serialize(serializer)
+    }
 
-        Ok(())
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<OffsetDateTime, D::Error> {
+        let micros = i64::deserialize(deserializer)?;
+        OffsetDateTime::from_unix_timestamp_nanos(micros as i128 * 1000)
+            .map_err(serde::de::Error::custom)
     }
 }
 
-fn uuid_to_session_key(id: Uuid) -> Result<SessionKey, anyhow::Error> {
+/// Returns the id of the session of a request, read back from its session cookie.
+///
+/// The session middleware doesn't expose the session key to the handlers, so the cookie is
+/// decrypted the same way it does.
+pub fn current_session_id(req: &HttpRequest, cookie_key: &Key) -> Option<Uuid> {
+    let cookie = req.cookie(SESSION_COOKIE_NAME)?;
+
+    let mut jar = CookieJar::new();
+    jar.add_original(cookie);
+    let cookie = jar.private(cookie_key).get(SESSION_COOKIE_NAME)?;
+
+    Uuid::try_parse(cookie.value()).ok()
+}
+
+pub(crate) fn uuid_to_session_key(id: Uuid) -> Result<SessionKey, anyhow::Error> {
     let session_key_string = id.to_string();
 
     let res: Result<SessionKey, _> = session_key_string.try_into();
@@ -216,26 +261,84 @@ fn uuid_to_session_key(id: Uuid) -> Result<SessionKey, anyhow::Error> {
     Ok(session_key)
 }
 
-fn session_key_to_uuid(session_key: &SessionKey) -> Result<Uuid, anyhow::Error> {
+pub(crate) fn session_key_to_uuid(session_key: &SessionKey) -> Result<Uuid, anyhow::Error> {
     Uuid::try_parse(session_key.as_ref()).map_err(Into::<anyhow::Error>::into)
 }
 
+/// The behaviour every session store must have, whatever its backend.
+///
+/// Each test takes a function building the store to test from its limits; the
+/// `session_store_conformance_tests!` macro generates the tests calling them for a store.
 #[cfg(test)]
-mod tests {
-    use super::{uuid_to_session_key, CleanupConfig, PgSessionStore};
-    use actix_session::storage::SessionStore;
+pub(crate) mod tests {
+    use super::{session_key_to_uuid, uuid_to_session_key, SessionLimits, UserSessionStore};
     use actix_web::cookie::time::Duration;
-    use claim::assert_none;
+    use claim::{assert_none, assert_some};
     use std::collections::HashMap;
     use uuid::Uuid;
 
+    /// Generates a test for each conformance test, building the store with `$new_store`.
+    ///
+    /// `$attr` are the test attributes and `$args` the arguments of the test functions, to let
+    /// `#[sqlx::test]` inject a database.
+    macro_rules! session_store_conformance_tests {
+        ($(#[$attr:meta])* ($($args:tt)*) $new_store:expr) => {
+            $crate::sessions::session_store::tests::session_store_conformance_tests!(
+                @tests [$(#[$attr])*] ($($args)*) $new_store;
+                loading_a_missing_session_returns_none,
+                loading_an_existing_session_returns_its_state,
+                updating_then_loading_an_existing_session_returns_its_updated_state,
+                updating_a_missing_session_saves_it,
+                updating_a_deleted_session_does_not_bring_its_user_back,
+                loading_a_session_saved_with_a_negative_ttl_returns_none,
+                loading_a_deleted_session_returns_none,
+                loading_an_expired_session_returns_none,
+                loading_a_session_idle_for_too_long_returns_none,
+                loading_a_session_past_its_max_lifetime_returns_none,
+                loading_a_session_only_touches_it_once_per_interval,
+                the_sessions_of_a_user_are_listed_with_their_client,
+                a_user_can_only_delete_their_own_sessions,
+                deleting_the_sessions_of_a_user_only_deletes_theirs,
+                deleting_the_sessions_of_a_user_can_keep_one
+            );
+        };
+        (@tests [$(#[$attr:meta])*] ($($args:tt)*) $new_store:expr; $name:ident $(, $rest:ident)*) => {
+            $(#[$attr])*
+            async fn $name($($args)*) {
+                $crate::sessions::session_store::tests::$name($new_store).await;
+            }
+
+            $crate::sessions::session_store::tests::session_store_conformance_tests!(
+                @tests [$(#[$attr])*] ($($args)*) $new_store; $($rest),*
+            );
+        };
+        (@tests [$(#[$attr:meta])*] ($($args:tt)*) $new_store:expr;) => {};
+    }
+    pub(crate) use session_store_conformance_tests;
+
     fn make_state() -> HashMap<String, String> {
         HashMap::from([("foo".into(), "bar".into()), ("bar".into(), "baz".into())])
     }
 
-    #[sqlx::test]
-    async fn loading_a_missing_session_returns_none(pool: sqlx::PgPool) {
-        let store = PgSessionStore::new(pool, CleanupConfig::default());
+    fn make_user_state(user_id: Uuid, user_agent: &str) -> HashMap<String, String> {
+        let mut state = make_state();
+        state.insert("user_id".into(), serde_json::to_string(&user_id).unwrap());
+        state.insert(
+            "user_agent".into(),
+            serde_json::to_string(user_agent).unwrap(),
+        );
+        state.insert("ip_address".into(), r#""192.0.2.1""#.into());
+        state
+    }
+
+    async fn sleep(duration: Duration) {
+        tokio::time::sleep(duration.unsigned_abs()).await;
+    }
+
+    pub(crate) async fn loading_a_missing_session_returns_none<S: UserSessionStore>(
+        new_store: impl FnOnce(SessionLimits) -> S,
+    ) {
+        let store = new_store(SessionLimits::default());
 
         let session_key = uuid_to_session_key(Uuid::new_v4()).unwrap();
 
@@ -246,9 +349,10 @@ mod tests {
         assert_none!(result);
     }
 
-    #[sqlx::test]
-    async fn loading_an_existing_session_returns_its_state(pool: sqlx::PgPool) {
-        let store = PgSessionStore::new(pool, CleanupConfig::default());
+    pub(crate) async fn loading_an_existing_session_returns_its_state<S: UserSessionStore>(
+        new_store: impl FnOnce(SessionLimits) -> S,
+    ) {
+        let store = new_store(SessionLimits::default());
         let state = make_state();
 
         let session_key = store
@@ -266,11 +370,12 @@ mod tests {
         assert_eq!(state, loaded_state);
     }
 
-    #[sqlx::test]
-    async fn updating_then_loading_an_existing_session_returns_its_updated_state(
-        pool: sqlx::PgPool,
+    pub(crate) async fn updating_then_loading_an_existing_session_returns_its_updated_state<
+        S: UserSessionStore,
+    >(
+        new_store: impl FnOnce(SessionLimits) -> S,
     ) {
-        let store = PgSessionStore::new(pool, CleanupConfig::default());
+        let store = new_store(SessionLimits::default());
         let mut state = make_state();
 
         let session_key = store
@@ -293,9 +398,77 @@ mod tests {
         assert_eq!(state, loaded_state);
     }
 
-    #[sqlx::test]
-    async fn loading_a_session_saved_with_a_negative_ttl_returns_none(pool: sqlx::PgPool) {
-        let store = PgSessionStore::new(pool, CleanupConfig::default());
+    pub(crate) async fn updating_a_missing_session_saves_it<S: UserSessionStore>(
+        new_store: impl FnOnce(SessionLimits) -> S,
+    ) {
+        let store = new_store(SessionLimits::default());
+        let state = make_state();
+
+        let missing_session_id = Uuid::new_v4();
+        let session_key = store
+            .update(
+                uuid_to_session_key(missing_session_id).unwrap(),
+                state.clone(),
+                &Duration::seconds(10),
+            )
+            .await
+            .expect("Unable to update the session");
+        assert_ne!(
+            session_key_to_uuid(&session_key).unwrap(),
+            missing_session_id
+        );
+
+        let loaded_state = store
+            .load(&session_key)
+            .await
+            .expect("Unable to load the session")
+            .unwrap();
+
+        assert_eq!(state, loaded_state);
+    }
+
+    pub(crate) async fn updating_a_deleted_session_does_not_bring_its_user_back<
+        S: UserSessionStore,
+    >(
+        new_store: impl FnOnce(SessionLimits) -> S,
+    ) {
+        let store = new_store(SessionLimits::default());
+        let user_id = Uuid::new_v4();
+        let state = make_user_state(user_id, "curl");
+
+        let session_key = store
+            .save(state.clone(), &Duration::days(1))
+            .await
+            .expect("Unable to save the session");
+        store
+            .delete_user_sessions(user_id, None)
+            .await
+            .expect("Unable to delete the sessions");
+
+        // A request which was still running when the session got deleted
+        let session_key = store
+            .update(session_key, state, &Duration::days(1))
+            .await
+            .expect("Unable to update the session");
+
+        let loaded_state = store
+            .load(&session_key)
+            .await
+            .expect("Unable to load the session")
+            .unwrap();
+        assert!(!loaded_state.contains_key("user_id"));
+        assert_eq!(loaded_state.get("foo").map(String::as_str), Some("bar"));
+
+        let sessions = store.get_user_sessions(user_id).await.unwrap();
+        assert!(sessions.is_empty());
+    }
+
+    pub(crate) async fn loading_a_session_saved_with_a_negative_ttl_returns_none<
+        S: UserSessionStore,
+    >(
+        new_store: impl FnOnce(SessionLimits) -> S,
+    ) {
+        let store = new_store(SessionLimits::default());
         let state = make_state();
 
         let session_key = store
@@ -311,9 +484,10 @@ mod tests {
         assert_none!(loaded_state);
     }
 
-    #[sqlx::test]
-    async fn loading_a_deleted_session_returns_none(pool: sqlx::PgPool) {
-        let store = PgSessionStore::new(pool, CleanupConfig::default());
+    pub(crate) async fn loading_a_deleted_session_returns_none<S: UserSessionStore>(
+        new_store: impl FnOnce(SessionLimits) -> S,
+    ) {
+        let store = new_store(SessionLimits::default());
         let state = make_state();
 
         let session_key = store
@@ -334,10 +508,10 @@ mod tests {
         assert_none!(loaded_state);
     }
 
-    #[sqlx::test]
-    async fn loading_an_expired_session_returns_none(pool: sqlx::PgPool) {
-        let store =
-            PgSessionStore::new(pool, CleanupConfig::new(true, Duration::milliseconds(100)));
+    pub(crate) async fn loading_an_expired_session_returns_none<S: UserSessionStore>(
+        new_store: impl FnOnce(SessionLimits) -> S,
+    ) {
+        let store = new_store(SessionLimits::default());
         let state = make_state();
 
         let session_key = store
@@ -345,7 +519,7 @@ mod tests {
             .await
             .expect("Unable to save the session");
 
-        tokio::time::sleep(Duration::milliseconds(200).unsigned_abs()).await;
+        sleep(Duration::milliseconds(200)).await;
 
         let loaded_state = store
             .load(&session_key)
@@ -354,4 +528,227 @@ mod tests {
 
         assert_none!(loaded_state, "found state for {:?}", session_key);
     }
+
+    pub(crate) async fn loading_a_session_idle_for_too_long_returns_none<S: UserSessionStore>(
+        new_store: impl FnOnce(SessionLimits) -> S,
+    ) {
+        let store = new_store(SessionLimits {
+            idle_timeout: Duration::milliseconds(100),
+            ..SessionLimits::default()
+        });
+
+        let session_key = store
+            .save(make_state(), &Duration::days(1))
+            .await
+            .expect("Unable to save the session");
+
+        sleep(Duration::milliseconds(200)).await;
+
+        let loaded_state = store
+            .load(&session_key)
+            .await
+            .expect("Unable to load the session");
+        assert_none!(loaded_state);
+    }
+
+    pub(crate) async fn loading_a_session_past_its_max_lifetime_returns_none<
+        S: UserSessionStore,
+    >(
+        new_store: impl FnOnce(SessionLimits) -> S,
+    ) {
+        let store = new_store(SessionLimits {
+            max_lifetime: Duration::milliseconds(300),
+            touch_interval: Duration::ZERO,
+            ..SessionLimits::default()
+        });
+
+        let session_key = store
+            .save(make_state(), &Duration::days(1))
+            .await
+            .expect("Unable to save the session");
+
+        // Used along the way, but created too long ago
+        sleep(Duration::milliseconds(150)).await;
+        assert_some!(store.load(&session_key).await.unwrap());
+        sleep(Duration::milliseconds(200)).await;
+
+        let loaded_state = store
+            .load(&session_key)
+            .await
+            .expect("Unable to load the session");
+        assert_none!(loaded_state);
+    }
+
+    pub(crate) async fn loading_a_session_only_touches_it_once_per_interval<S: UserSessionStore>(
+        new_store: impl FnOnce(SessionLimits) -> S,
+    ) {
+        let store = new_store(SessionLimits {
+            touch_interval: Duration::milliseconds(200),
+            ..SessionLimits::default()
+        });
+        let user_id = Uuid::new_v4();
+
+        let session_key = store
+            .save(make_user_state(user_id, "curl"), &Duration::days(1))
+            .await
+            .expect("Unable to save the session");
+        let created_at = store.get_user_sessions(user_id).await.unwrap()[0].created_at;
+
+        // Seen just now, the session isn't touched
+        assert_some!(store.load(&session_key).await.unwrap());
+        let sessions = store.get_user_sessions(user_id).await.unwrap();
+        assert_eq!(sessions[0].last_seen_at, created_at);
+
+        // Seen a while ago, it is
+        sleep(Duration::milliseconds(250)).await;
+        assert_some!(store.load(&session_key).await.unwrap());
+        let sessions = store.get_user_sessions(user_id).await.unwrap();
+        assert!(sessions[0].last_seen_at >= created_at + Duration::milliseconds(250));
+    }
+
+    pub(crate) async fn the_sessions_of_a_user_are_listed_with_their_client<S: UserSessionStore>(
+        new_store: impl FnOnce(SessionLimits) -> S,
+    ) {
+        let store = new_store(SessionLimits {
+            touch_interval: Duration::ZERO,
+            ..SessionLimits::default()
+        });
+        let user_id = Uuid::new_v4();
+
+        let first_session_key = store
+            .save(make_user_state(user_id, "curl/7.86.0"), &Duration::days(1))
+            .await
+            .expect("Unable to save the session");
+        let second_session_key = store
+            .save(
+                make_user_state(user_id, "Firefox/108.0"),
+                &Duration::days(1),
+            )
+            .await
+            .expect("Unable to save the session");
+        store
+            .save(
+                make_user_state(Uuid::new_v4(), "Safari"),
+                &Duration::days(1),
+            )
+            .await
+            .expect("Unable to save the session");
+        store
+            .save(make_state(), &Duration::days(1))
+            .await
+            .expect("Unable to save the session");
+
+        // The most recently used session comes first
+        sleep(Duration::milliseconds(10)).await;
+        store.load(&first_session_key).await.unwrap();
+
+        let sessions = store
+            .get_user_sessions(user_id)
+            .await
+            .expect("Unable to list the sessions");
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(
+            sessions[0].id,
+            session_key_to_uuid(&first_session_key).unwrap()
+        );
+        assert_eq!(sessions[0].user_agent.as_deref(), Some("curl/7.86.0"));
+        assert_eq!(sessions[0].ip_address.as_deref(), Some("192.0.2.1"));
+        assert_eq!(
+            sessions[1].id,
+            session_key_to_uuid(&second_session_key).unwrap()
+        );
+        assert_eq!(sessions[1].user_agent.as_deref(), Some("Firefox/108.0"));
+    }
+
+    pub(crate) async fn a_user_can_only_delete_their_own_sessions<S: UserSessionStore>(
+        new_store: impl FnOnce(SessionLimits) -> S,
+    ) {
+        let store = new_store(SessionLimits::default());
+        let user_id = Uuid::new_v4();
+        let other_user_id = Uuid::new_v4();
+
+        let session_key = store
+            .save(make_user_state(other_user_id, "curl"), &Duration::days(1))
+            .await
+            .expect("Unable to save the session");
+        let session_id = session_key_to_uuid(&session_key).unwrap();
+
+        let deleted = store
+            .delete_user_session(user_id, session_id)
+            .await
+            .expect("Unable to delete the session");
+        assert!(!deleted);
+        assert_some!(store.load(&session_key).await.unwrap());
+
+        let deleted = store
+            .delete_user_session(other_user_id, session_id)
+            .await
+            .expect("Unable to delete the session");
+        assert!(deleted);
+        assert_none!(store.load(&session_key).await.unwrap());
+    }
+
+    pub(crate) async fn deleting_the_sessions_of_a_user_only_deletes_theirs<S: UserSessionStore>(
+        new_store: impl FnOnce(SessionLimits) -> S,
+    ) {
+        let store = new_store(SessionLimits::default());
+        let user_id = Uuid::new_v4();
+
+        let user_session_key = store
+            .save(make_user_state(user_id, "curl"), &Duration::seconds(10))
+            .await
+            .expect("Unable to save the session");
+        let other_session_key = store
+            .save(make_state(), &Duration::seconds(10))
+            .await
+            .expect("Unable to save the session");
+
+        let deleted = store
+            .delete_user_sessions(user_id, None)
+            .await
+            .expect("Unable to delete the sessions");
+        assert_eq!(deleted, 1);
+
+        let loaded_state = store
+            .load(&user_session_key)
+            .await
+            .expect("Unable to load the session");
+        assert_none!(loaded_state);
+
+        let loaded_state = store
+            .load(&other_session_key)
+            .await
+            .expect("Unable to load the session");
+        assert!(loaded_state.is_some());
+    }
+
+    pub(crate) async fn deleting_the_sessions_of_a_user_can_keep_one<S: UserSessionStore>(
+        new_store: impl FnOnce(SessionLimits) -> S,
+    ) {
+        let store = new_store(SessionLimits::default());
+        let user_id = Uuid::new_v4();
+
+        let current_session_key = store
+            .save(make_user_state(user_id, "curl"), &Duration::days(1))
+            .await
+            .expect("Unable to save the session");
+        store
+            .save(make_user_state(user_id, "curl"), &Duration::days(1))
+            .await
+            .expect("Unable to save the session");
+
+        let current_session_id = session_key_to_uuid(&current_session_key).unwrap();
+        let deleted = store
+            .delete_user_sessions(user_id, Some(current_session_id))
+            .await
+            .expect("Unable to delete the sessions");
+        assert_eq!(deleted, 1);
+
+        let sessions = store
+            .get_user_sessions(user_id)
+            .await
+            .expect("Unable to list the sessions");
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].id, current_session_id);
+    }
 }
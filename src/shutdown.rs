@@ -0,0 +1,19 @@
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+/// Cancels `shutdown` when the process receives SIGINT or SIGTERM.
+pub async fn cancel_on_signal(shutdown: CancellationToken) -> Result<(), anyhow::Error> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+
+    tokio::select! {
+        _ = sigterm.recv() => info!("Received SIGTERM, shutting down"),
+        _ = sigint.recv() => info!("Received SIGINT, shutting down"),
+        _ = shutdown.cancelled() => return Ok(()),
+    }
+
+    shutdown.cancel();
+
+    Ok(())
+}
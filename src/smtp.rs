@@ -0,0 +1,92 @@
+use crate::domain::SubscriberEmail;
+use crate::email_client::{build_message, EmailSender};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use secrecy::{ExposeSecret, Secret};
+use std::time::Duration;
+use tracing::{event, Level};
+
+#[derive(Clone, Copy, Debug, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Security {
+    /// No encryption at all, only useful for a local relay
+    None,
+    /// Upgrade a plain connection with STARTTLS
+    StartTls,
+    /// Connect with TLS from the start
+    Tls,
+}
+
+pub struct Client {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    sender: SubscriberEmail,
+}
+
+impl Client {
+    pub fn new(
+        host: &str,
+        port: u16,
+        security: Security,
+        credentials: Option<(String, Secret<String>)>,
+        sender: SubscriberEmail,
+        timeout: Duration,
+    ) -> Result<Self, lettre::transport::smtp::Error> {
+        let builder = match security {
+            Security::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+            Security::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+            Security::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+        };
+
+        let mut builder = builder.port(port).timeout(Some(timeout));
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(
+                username,
+                password.expose_secret().to_string(),
+            ));
+        }
+
+        Ok(Self {
+            transport: builder.build(),
+            sender,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for Client {
+    #[tracing::instrument(
+        name = "Send an email through SMTP",
+        skip(self, html_content, text_content)
+    )]
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        unsubscribe_link: Option<&str>,
+    ) -> Result<String, anyhow::Error> {
+        let message = build_message(
+            &self.sender,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            unsubscribe_link,
+        )?;
+
+        let response = self.transport.send(message).await?;
+
+        event!(
+            Level::INFO,
+            response_code = %response.code(),
+            "sent email"
+        );
+
+        Ok(format!(
+            "{} {}",
+            response.code(),
+            response.message().collect::<Vec<_>>().join(" ")
+        ))
+    }
+}
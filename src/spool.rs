@@ -0,0 +1,118 @@
+use crate::domain::SubscriberEmail;
+use crate::email_client::{build_message, EmailSender};
+use lettre::{AsyncFileTransport, AsyncTransport, Tokio1Executor};
+use std::path::PathBuf;
+use tracing::{event, Level};
+
+/// Writes every email as a `.eml` file in a local directory instead of sending it.
+///
+/// This is meant for local development where no real email provider is available.
+pub struct Client {
+    transport: AsyncFileTransport<Tokio1Executor>,
+    sender: SubscriberEmail,
+}
+
+impl Client {
+    pub fn new(path: PathBuf, sender: SubscriberEmail) -> Self {
+        Self {
+            transport: AsyncFileTransport::new(path),
+            sender,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for Client {
+    #[tracing::instrument(
+        name = "Write an email to the spool directory",
+        skip(self, html_content, text_content)
+    )]
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        unsubscribe_link: Option<&str>,
+    ) -> Result<String, anyhow::Error> {
+        let message = build_message(
+            &self.sender,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            unsubscribe_link,
+        )?;
+
+        let id = self.transport.send(message).await?;
+
+        event!(Level::INFO, id = id, "spooled email");
+
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Client;
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::EmailSender;
+    use claim::assert_ok;
+    use fake::faker::internet::en::SafeEmail;
+    use fake::Fake;
+    use uuid::Uuid;
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_email_writes_a_file_in_the_spool_directory() {
+        let path = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&path).unwrap();
+
+        let client = Client::new(path.clone(), email());
+
+        let result = client
+            .send_email(&email(), "Subject", "<p>HTML</p>", "Text", None)
+            .await;
+        assert_ok!(result);
+
+        let entries: Vec<_> = std::fs::read_dir(&path)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(entries.len(), 1);
+
+        let content = std::fs::read_to_string(&entries[0]).unwrap();
+        assert!(content.contains("Subject: Subject"));
+
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn send_email_with_an_unsubscribe_link_writes_the_list_unsubscribe_headers() {
+        let path = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&path).unwrap();
+
+        let client = Client::new(path.clone(), email());
+
+        let result = client
+            .send_email(
+                &email(),
+                "Subject",
+                "<p>HTML</p>",
+                "Text",
+                Some("https://example.com/unsubscribe"),
+            )
+            .await;
+        assert_ok!(result);
+
+        let entry = std::fs::read_dir(&path).unwrap().next().unwrap().unwrap();
+        let content = std::fs::read_to_string(entry.path()).unwrap();
+        assert!(content.contains("List-Unsubscribe: <https://example.com/unsubscribe>"));
+        assert!(content.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"));
+
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+}
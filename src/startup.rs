@@ -1,10 +1,16 @@
-use crate::authentication::reject_anonymous_users;
-use crate::configuration::{DatabaseSettings, Settings, TEMSettings};
+use crate::authentication::{reject_anonymous_users, require_api_token};
+use crate::authentication::{require_editor_role, require_owner_role};
+use crate::authentication::{LoginProtectionPolicy, PasswordHashing};
+use crate::configuration::{DatabaseSettings, EmailBackend, EmailSettings};
+use crate::configuration::{SessionBackend, SessionSettings, Settings, WorkerSettings};
+use crate::email_client::EmailSender;
 use crate::routes;
-use crate::sessions::{CleanupConfig, PgSessionStore};
-use crate::tem;
+use crate::sessions::{CleanupConfig, MemorySessionStore, PgSessionStore, RedisSessionStore};
+use crate::sessions::{SharedSessionStore, UserSessionStore, SESSION_COOKIE_NAME};
+use crate::{smtp, spool, tem};
 use actix_files::Files;
-use actix_session::SessionMiddleware;
+use actix_session::{CookieContentSecurity, SessionMiddleware};
+use actix_web::cookie::Key;
 use actix_web::dev::Server;
 use actix_web::{web, App, HttpServer};
 use actix_web_flash_messages::storage::CookieMessageStore;
@@ -15,14 +21,24 @@ use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
 use std::io;
 use std::net::TcpListener;
+use std::sync::Arc;
 use std::time::Duration;
+use tokio_util::sync::CancellationToken;
 use tracing_actix_web::TracingLogger;
 
+#[derive(Clone)]
 pub struct ApplicationBaseUrl(pub String);
 
 #[derive(Clone)]
 pub struct HmacSecret(pub Secret<String>);
 
+impl HmacSecret {
+    /// The key signing and encrypting the cookies.
+    pub fn cookie_key(&self) -> Key {
+        Key::from(self.0.expose_secret().as_bytes())
+    }
+}
+
 pub struct Application {
     pub port: u16,
     pub pool: PgPool,
@@ -30,41 +46,118 @@ pub struct Application {
 }
 
 pub async fn get_connection_pool(configuration: &DatabaseSettings) -> sqlx::PgPool {
+    connect_pool(configuration, 1024).await
+}
+
+/// The pool of the issue delivery worker: each of its consumers keeps a transaction open while
+/// sending an email, and reads with a second connection meanwhile.
+pub async fn get_worker_connection_pool(
+    configuration: &DatabaseSettings,
+    worker: &WorkerSettings,
+) -> sqlx::PgPool {
+    connect_pool(configuration, 2 * worker.concurrency.max(1) as u32).await
+}
+
+async fn connect_pool(configuration: &DatabaseSettings, max_connections: u32) -> sqlx::PgPool {
     PgPoolOptions::new()
-        .max_connections(1024)
+        .max_connections(max_connections)
         .acquire_timeout(Duration::from_secs(1))
         .connect(configuration.connection_string().expose_secret())
         .await
         .expect("Failed to connect to PostgreSQL")
 }
 
-pub fn get_tem_client(configuration: &TEMSettings) -> tem::Client {
+pub fn get_email_client(configuration: &EmailSettings) -> Arc<dyn EmailSender> {
     let sender_email = configuration
         .sender()
         .expect("Invalid sender email address");
 
-    tem::Client::new(
-        configuration.base_url.clone(),
-        configuration.project_id(),
-        configuration.auth_key.clone(),
-        sender_email,
-        configuration.timeout(),
-    )
+    match configuration.backend {
+        EmailBackend::Tem => {
+            let tem = configuration
+                .tem
+                .as_ref()
+                .expect("Missing TEM configuration");
+
+            Arc::new(tem::Client::new(
+                tem.base_url.clone(),
+                tem.project_id(),
+                tem.auth_key.clone(),
+                sender_email,
+                configuration.timeout(),
+            ))
+        }
+        EmailBackend::Smtp => {
+            let smtp = configuration
+                .smtp
+                .as_ref()
+                .expect("Missing SMTP configuration");
+
+            let client = smtp::Client::new(
+                &smtp.host,
+                smtp.port,
+                smtp.security,
+                smtp.credentials(),
+                sender_email,
+                configuration.timeout(),
+            )
+            .expect("Failed to build the SMTP client");
+
+            Arc::new(client)
+        }
+        EmailBackend::Spool => {
+            let spool = configuration
+                .spool
+                .as_ref()
+                .expect("Missing spool configuration");
+
+            std::fs::create_dir_all(&spool.path).expect("Failed to create the spool directory");
+
+            Arc::new(spool::Client::new(spool.path.clone(), sender_email))
+        }
+    }
+}
+
+pub fn get_session_store(
+    configuration: &SessionSettings,
+    pool: PgPool,
+) -> Arc<dyn UserSessionStore> {
+    let cleanup_config = CleanupConfig::new(
+        configuration.cleanup_enabled,
+        configuration.cleanup_interval(),
+    );
+    let limits = configuration.limits();
+
+    match configuration.backend {
+        SessionBackend::Postgres => Arc::new(PgSessionStore::new(pool, cleanup_config, limits)),
+        SessionBackend::Memory => Arc::new(MemorySessionStore::new(cleanup_config, limits)),
+        SessionBackend::Redis => {
+            let redis = configuration
+                .redis
+                .as_ref()
+                .expect("Missing Redis configuration");
+
+            // Redis evicts the expired sessions by itself, there's nothing to clean up
+            let client =
+                redis::Client::open(redis.connection_info()).expect("Invalid Redis configuration");
+
+            Arc::new(RedisSessionStore::new(client, redis.timeout(), limits))
+        }
+    }
 }
 
 impl Application {
     pub async fn build_with_pool(
         configuration: Settings,
         pool: PgPool,
-        email_client: tem::Client,
+        email_client: Arc<dyn EmailSender>,
     ) -> Result<Self, io::Error> {
-        let session_store = PgSessionStore::new(
-            pool.clone(),
-            CleanupConfig::new(
-                configuration.session.cleanup_enabled,
-                configuration.session.cleanup_interval(),
-            ),
-        );
+        let session_store = get_session_store(&configuration.session, pool.clone());
+
+        let password_hashing = configuration
+            .password_hashing
+            .hashing()
+            .expect("Invalid password hashing settings");
 
         //
 
@@ -73,6 +166,7 @@ impl Application {
             configuration.application.host, configuration.application.port
         ))?;
         let port = listener.local_addr().unwrap().port();
+        let shutdown_timeout = configuration.application.shutdown_timeout();
 
         let server = run(
             listener,
@@ -81,46 +175,76 @@ impl Application {
             session_store,
             ApplicationBaseUrl(configuration.application.base_url),
             HmacSecret(configuration.application.hmac_secret),
-            configuration.session.ttl(),
+            configuration.session.limits().idle_timeout,
+            configuration.login_protection.policy(),
+            password_hashing,
+            configuration.subscriptions.policy(),
+            shutdown_timeout,
         )?;
 
         Ok(Self { port, pool, server })
     }
 
-    pub async fn run_until_stopped(self) -> Result<(), anyhow::Error> {
+    /// Runs the server until `shutdown` is cancelled.
+    ///
+    /// The server then stops accepting connections and gives in-flight requests until the
+    /// shutdown timeout to complete.
+    pub async fn run_until_stopped(self, shutdown: CancellationToken) -> Result<(), anyhow::Error> {
+        let handle = self.server.handle();
+        tokio::spawn(async move {
+            shutdown.cancelled().await;
+            handle.stop(true).await;
+        });
+
         self.server.await?;
         Ok(())
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn run(
     listener: TcpListener,
     pool: PgPool,
-    email_client: tem::Client,
-    session_store: PgSessionStore,
+    email_client: Arc<dyn EmailSender>,
+    session_store: Arc<dyn UserSessionStore>,
     base_url: ApplicationBaseUrl,
     hmac_secret: HmacSecret,
-    session_ttl: time::Duration,
+    session_idle_timeout: time::Duration,
+    login_protection: LoginProtectionPolicy,
+    password_hashing: PasswordHashing,
+    subscription_policy: routes::SubscriptionPolicy,
+    shutdown_timeout: Duration,
 ) -> Result<Server, io::Error> {
-    let cookie_signing_key = actix_web::cookie::Key::from(hmac_secret.0.expose_secret().as_bytes());
+    let cookie_signing_key = hmac_secret.cookie_key();
 
     // Flash messages
     let flash_messages_store = CookieMessageStore::builder(cookie_signing_key.clone()).build();
     let flash_messages_framework = FlashMessagesFramework::builder(flash_messages_store).build();
 
-    // Session store
+    // Session store; the middleware needs a sized store, the handlers listing and revoking the
+    // sessions of a user share the same one.
+    let session_middleware_store = SharedSessionStore(session_store.clone());
+    let session_store: web::Data<dyn UserSessionStore> = web::Data::from(session_store);
 
     let pool = web::Data::new(pool);
-    let email_client = web::Data::new(email_client);
+    let email_client: web::Data<dyn EmailSender> = web::Data::from(email_client);
     let base_url = web::Data::new(base_url);
+    let hmac_secret = web::Data::new(hmac_secret);
+    let login_protection = web::Data::new(login_protection);
+    let password_hashing = web::Data::new(password_hashing);
+    let subscription_policy = web::Data::new(subscription_policy);
 
     let server = HttpServer::new(move || {
-        let session_middleware =
-            SessionMiddleware::builder(session_store.clone(), cookie_signing_key.clone())
-                .session_length(actix_session::SessionLength::BrowserSession {
-                    state_ttl: Some(session_ttl),
-                })
-                .build();
+        let session_middleware = SessionMiddleware::builder(
+            session_middleware_store.clone(),
+            cookie_signing_key.clone(),
+        )
+        .cookie_name(SESSION_COOKIE_NAME.to_string())
+        .cookie_content_security(CookieContentSecurity::Private)
+        .session_length(actix_session::SessionLength::BrowserSession {
+            state_ttl: Some(session_idle_timeout),
+        })
+        .build();
 
         App::new()
             .wrap(flash_messages_framework.clone())
@@ -131,11 +255,89 @@ fn run(
             .route("/health_check", web::get().to(routes::health_check))
             .route("/login", web::get().to(routes::login_form))
             .route("/login", web::post().to(routes::login))
+            .route(
+                "/login/totp",
+                web::get().to(routes::login_second_factor_form),
+            )
+            .route("/login/totp", web::post().to(routes::login_second_factor))
             .route("/subscriptions", web::post().to(routes::subscribe))
             .route("/subscriptions/confirm", web::get().to(routes::confirm))
+            .route(
+                "/subscriptions/confirm/resend",
+                web::post().to(routes::resend_confirmation),
+            )
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(routes::unsubscribe_form),
+            )
+            .route(
+                "/subscriptions/unsubscribe",
+                web::post().to(routes::unsubscribe),
+            )
+            .route(
+                "/invitations/accept",
+                web::get().to(routes::accept_invitation_form),
+            )
+            .route(
+                "/invitations/accept",
+                web::post().to(routes::accept_invitation),
+            )
+            .route(
+                "/password_reset",
+                web::get().to(routes::password_reset_request_form),
+            )
+            .route(
+                "/password_reset",
+                web::post().to(routes::request_password_reset),
+            )
+            .route(
+                "/password_reset/confirm",
+                web::get().to(routes::password_reset_form),
+            )
+            .route(
+                "/password_reset/confirm",
+                web::post().to(routes::reset_password),
+            )
+            .service(
+                web::scope("/api/v1")
+                    .wrap(from_fn(require_api_token))
+                    .app_data(routes::api_json_config())
+                    .app_data(routes::api_query_config())
+                    .app_data(routes::api_path_config())
+                    // Every token, whatever the role of its user
+                    .route("/subscribers", web::get().to(routes::api_list_subscribers))
+                    .route(
+                        "/subscribers/{subscriber_id}",
+                        web::get().to(routes::api_get_subscriber),
+                    )
+                    .route(
+                        "/newsletters/{issue_id}",
+                        web::get().to(routes::api_get_issue),
+                    )
+                    .route("/stats", web::get().to(routes::api_stats))
+                    // Editors and owners; this scope must come last since it matches every path
+                    .service(
+                        web::scope("")
+                            .wrap(from_fn(require_editor_role))
+                            .route(
+                                "/subscribers",
+                                web::post().to(routes::api_create_subscriber),
+                            )
+                            .route(
+                                "/subscribers/{subscriber_id}",
+                                web::delete().to(routes::api_delete_subscriber),
+                            )
+                            .route("/newsletters", web::post().to(routes::api_create_issue))
+                            .route(
+                                "/newsletters/{issue_id}/publish",
+                                web::post().to(routes::api_publish_issue),
+                            ),
+                    ),
+            )
             .service(
                 web::scope("/admin")
                     .wrap(from_fn(reject_anonymous_users))
+                    // Every admin, whatever their role
                     .route("/logout", web::post().to(routes::logout))
                     .route("/dashboard", web::get().to(routes::admin_dashboard))
                     .route(
@@ -143,14 +345,143 @@ fn run(
                         web::get().to(routes::admin_change_password_form),
                     )
                     .route("/password", web::post().to(routes::admin_change_password))
+                    .route("/sessions", web::get().to(routes::admin_sessions))
+                    .route(
+                        "/sessions/revoke_others",
+                        web::post().to(routes::admin_revoke_other_sessions),
+                    )
+                    .route(
+                        "/sessions/{session_id}/revoke",
+                        web::post().to(routes::admin_revoke_session),
+                    )
+                    .route("/api_tokens", web::get().to(routes::admin_api_tokens))
+                    .route(
+                        "/api_tokens",
+                        web::post().to(routes::admin_create_api_token),
+                    )
+                    .route(
+                        "/api_tokens/{token_id}/revoke",
+                        web::post().to(routes::admin_revoke_api_token),
+                    )
+                    .route("/totp", web::get().to(routes::admin_totp))
+                    .route(
+                        "/totp/enroll",
+                        web::get().to(routes::admin_totp_enroll_form),
+                    )
+                    .route("/totp/enroll", web::post().to(routes::admin_totp_enroll))
+                    .route("/totp/confirm", web::post().to(routes::admin_totp_confirm))
+                    .route("/totp/disable", web::post().to(routes::admin_totp_disable))
                     .route("/newsletters", web::get().to(routes::newsletter_form))
-                    .route("/newsletters", web::post().to(routes::publish_newsletter)),
+                    .route(
+                        "/newsletters/scheduled",
+                        web::get().to(routes::admin_scheduled_newsletters),
+                    )
+                    .route(
+                        "/newsletters/{issue_id}",
+                        web::get().to(routes::admin_newsletter_issue),
+                    )
+                    .route(
+                        "/newsletters/{issue_id}/preview",
+                        web::get().to(routes::admin_newsletter_preview),
+                    )
+                    .route(
+                        "/newsletters/{issue_id}/deliveries",
+                        web::get().to(routes::admin_newsletter_deliveries),
+                    )
+                    .route("/dead_letters", web::get().to(routes::admin_dead_letters))
+                    .route("/subscribers", web::get().to(routes::admin_subscribers))
+                    .route(
+                        "/subscribers/export",
+                        web::get().to(routes::admin_export_subscribers),
+                    )
+                    // Owners only
+                    .service(
+                        web::scope("/users")
+                            .wrap(from_fn(require_owner_role))
+                            .route("", web::get().to(routes::admin_users))
+                            .route("", web::post().to(routes::admin_create_user))
+                            .route("/invite", web::post().to(routes::admin_invite_user))
+                            .route(
+                                "/{user_id}/role",
+                                web::post().to(routes::admin_update_user_role),
+                            )
+                            .route(
+                                "/{user_id}/disable",
+                                web::post().to(routes::admin_disable_user),
+                            )
+                            .route(
+                                "/{user_id}/enable",
+                                web::post().to(routes::admin_enable_user),
+                            )
+                            .route(
+                                "/{user_id}/delete",
+                                web::post().to(routes::admin_delete_user),
+                            ),
+                    )
+                    // Editors and owners; this scope must come last since it matches every path
+                    .service(
+                        web::scope("")
+                            .wrap(from_fn(require_editor_role))
+                            .route("/newsletters", web::post().to(routes::publish_newsletter))
+                            .route(
+                                "/newsletters/{issue_id}",
+                                web::post().to(routes::admin_save_newsletter_draft),
+                            )
+                            .route(
+                                "/newsletters/{issue_id}/test",
+                                web::post().to(routes::admin_test_send_newsletter),
+                            )
+                            .route(
+                                "/newsletters/{issue_id}/publish",
+                                web::post().to(routes::admin_publish_newsletter_draft),
+                            )
+                            .route(
+                                "/newsletters/{issue_id}/reschedule",
+                                web::post().to(routes::admin_reschedule_newsletter),
+                            )
+                            .route(
+                                "/newsletters/{issue_id}/cancel",
+                                web::post().to(routes::admin_cancel_newsletter),
+                            )
+                            .route(
+                                "/dead_letters/requeue",
+                                web::post().to(routes::admin_requeue_dead_letter),
+                            )
+                            .service(
+                                web::resource("/subscribers/import")
+                                    .route(web::get().to(routes::admin_import_subscribers_form))
+                                    .route(web::post().to(routes::admin_import_subscribers)),
+                            )
+                            .route(
+                                "/subscribers/{subscriber_id}/confirm",
+                                web::post().to(routes::admin_confirm_subscriber),
+                            )
+                            .route(
+                                "/subscribers/{subscriber_id}/unsubscribe",
+                                web::post().to(routes::admin_unsubscribe_subscriber),
+                            )
+                            .route(
+                                "/subscribers/{subscriber_id}/delete",
+                                web::post().to(routes::admin_delete_subscriber),
+                            )
+                            .route(
+                                "/subscribers/{subscriber_id}/resend_confirmation",
+                                web::post().to(routes::admin_resend_confirmation),
+                            ),
+                    ),
             )
             .app_data(pool.clone())
             .app_data(email_client.clone())
+            .app_data(session_store.clone())
             .app_data(base_url.clone())
             .app_data(hmac_secret.clone())
+            .app_data(login_protection.clone())
+            .app_data(password_hashing.clone())
+            .app_data(subscription_policy.clone())
     })
+    // Signals are handled by the caller, which stops every task with the same shutdown token
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout.as_secs())
     .listen(listener)?
     .run();
 
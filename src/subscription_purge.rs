@@ -0,0 +1,87 @@
+use crate::authentication::{purge_forgotten_login_failures, LoginProtectionPolicy};
+use crate::configuration::SubscriptionSettings;
+use crate::password_reset_queue::purge_expired_password_reset_tokens;
+use time::OffsetDateTime;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+/// Deletes the pending subscribers who haven't been sent a confirmation link during the
+/// confirmation window, and their tokens with them. Returns how many have been deleted.
+///
+/// Their links have all expired: they can only subscribe again.
+#[tracing::instrument(skip(pool), level = "debug")]
+pub async fn purge_expired_subscribers(
+    pool: &sqlx::PgPool,
+    confirmation_window: time::Duration,
+) -> Result<u64, anyhow::Error> {
+    let purged = sqlx::query!(
+        r#"
+        DELETE FROM subscriptions
+        WHERE status = 'pending_confirmation'
+        AND COALESCE(last_confirmation_sent_at, subscribed_at) < $1
+        "#,
+        OffsetDateTime::now_utc() - confirmation_window,
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    if purged > 0 {
+        info!(purged, "Purged the expired pending subscribers");
+    }
+
+    Ok(purged)
+}
+
+/// Purges the expired pending subscribers and password reset tokens, and the forgotten login
+/// failure counters, periodically until `shutdown` is cancelled.
+async fn purge_loop(
+    pool: sqlx::PgPool,
+    settings: SubscriptionSettings,
+    login_protection: LoginProtectionPolicy,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    let confirmation_window = settings.policy().confirmation_window;
+
+    while !shutdown.is_cancelled() {
+        if let Err(err) = purge_expired_subscribers(&pool, confirmation_window).await {
+            error!(
+                error.cause_chain = ?err,
+                error.message = %err,
+                "Failed to purge the expired pending subscribers",
+            );
+        }
+        if let Err(err) = purge_expired_password_reset_tokens(&pool).await {
+            error!(
+                error.cause_chain = ?err,
+                error.message = %err,
+                "Failed to purge the expired password reset tokens",
+            );
+        }
+        if let Err(err) = purge_forgotten_login_failures(&pool, &login_protection).await {
+            error!(
+                error.cause_chain = ?err,
+                error.message = %err,
+                "Failed to purge the forgotten login failure counters",
+            );
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(settings.purge_interval()) => {}
+            _ = shutdown.cancelled() => {}
+        }
+    }
+
+    info!("Expired data purge stopped");
+
+    Ok(())
+}
+
+pub async fn run_purge_until_stopped(
+    pool: sqlx::PgPool,
+    settings: SubscriptionSettings,
+    login_protection: LoginProtectionPolicy,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    purge_loop(pool, settings, login_protection, shutdown).await
+}
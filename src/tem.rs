@@ -1,9 +1,23 @@
 use crate::domain::SubscriberEmail;
+use crate::email_client::{list_unsubscribe_value, LIST_UNSUBSCRIBE};
+use crate::email_client::{EmailSender, SENDER_NAME};
+use crate::email_client::{LIST_UNSUBSCRIBE_POST, LIST_UNSUBSCRIBE_POST_VALUE};
 use secrecy::{ExposeSecret, Secret};
 use serde_json::json;
 use std::time::Duration;
 use tracing::{event, Level};
 
+#[derive(thiserror::Error, Debug)]
+pub enum SendEmailError {
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+    #[error("the email API rejected the email with status {status}: {body}")]
+    Rejected {
+        status: reqwest::StatusCode,
+        body: String,
+    },
+}
+
 #[derive(Clone, serde::Serialize)]
 pub struct ProjectId(String);
 
@@ -19,6 +33,12 @@ struct SendEmailRequestRecipient<'a> {
     name: Option<&'a str>,
 }
 
+#[derive(serde::Serialize)]
+struct SendEmailRequestHeader<'a> {
+    key: &'a str,
+    value: String,
+}
+
 #[derive(serde::Serialize)]
 struct SendEmailRequest<'a> {
     from: SendEmailRequestRecipient<'a>,
@@ -27,6 +47,8 @@ struct SendEmailRequest<'a> {
     text: String,
     html: String,
     project_id: ProjectId,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    additional_headers: Vec<SendEmailRequestHeader<'a>>,
 }
 
 pub struct Client {
@@ -64,13 +86,28 @@ impl Client {
         subject: &str,
         html_content: &str,
         text_content: &str,
-    ) -> Result<(), reqwest::Error> {
+        unsubscribe_link: Option<&str>,
+    ) -> Result<String, SendEmailError> {
         let url = format!("{}/emails", &self.base_url);
 
+        let additional_headers = match unsubscribe_link {
+            Some(unsubscribe_link) => vec![
+                SendEmailRequestHeader {
+                    key: LIST_UNSUBSCRIBE,
+                    value: list_unsubscribe_value(unsubscribe_link),
+                },
+                SendEmailRequestHeader {
+                    key: LIST_UNSUBSCRIBE_POST,
+                    value: LIST_UNSUBSCRIBE_POST_VALUE.to_string(),
+                },
+            ],
+            None => Vec::new(),
+        };
+
         let body = SendEmailRequest {
             from: SendEmailRequestRecipient {
                 email: self.sender.as_ref(),
-                name: Some("Vincent"),
+                name: Some(SENDER_NAME),
             },
             to: vec![SendEmailRequestRecipient {
                 email: recipient.as_ref(),
@@ -80,6 +117,7 @@ impl Client {
             subject: subject.to_string(),
             text: text_content.to_string(),
             html: html_content.to_string(),
+            additional_headers,
         };
 
         event!(
@@ -94,14 +132,44 @@ impl Client {
             .header("X-Auth-Token", self.auth_key.expose_secret())
             .json(&body)
             .send()
-            .await?
-            .error_for_status()?;
+            .await?;
 
+        let status = response.status();
         let response_body = response.text().await?;
 
+        if status.is_client_error() || status.is_server_error() {
+            return Err(SendEmailError::Rejected {
+                status,
+                body: response_body,
+            });
+        }
+
         event!(Level::INFO, response_body = response_body, "sent email");
 
-        Ok(())
+        Ok(response_body)
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for Client {
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        unsubscribe_link: Option<&str>,
+    ) -> Result<String, anyhow::Error> {
+        let response_body = Client::send_email(
+            self,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            unsubscribe_link,
+        )
+        .await?;
+        Ok(response_body)
     }
 }
 
@@ -174,7 +242,7 @@ mod tests {
             .await;
 
         let result = client
-            .send_email(&email(), &subject(), &content(), &content())
+            .send_email(&email(), &subject(), &content(), &content(), None)
             .await;
 
         assert_ok!(result);
@@ -186,16 +254,17 @@ mod tests {
         let client = email_client(mock_server.uri());
 
         Mock::given(any())
-            .respond_with(ResponseTemplate::new(500))
+            .respond_with(ResponseTemplate::new(500).set_body_string("internal error"))
             .expect(1)
             .mount(&mock_server)
             .await;
 
         let result = client
-            .send_email(&email(), &subject(), &content(), &content())
+            .send_email(&email(), &subject(), &content(), &content(), None)
             .await;
 
-        assert_err!(result);
+        let err = assert_err!(result);
+        assert!(err.to_string().contains("internal error"));
     }
 
     #[tokio::test]
@@ -212,7 +281,7 @@ mod tests {
             .await;
 
         let result = client
-            .send_email(&email(), &subject(), &content(), &content())
+            .send_email(&email(), &subject(), &content(), &content(), None)
             .await;
 
         assert_err!(result);
@@ -234,7 +303,50 @@ mod tests {
             .await;
 
         let _ = client
-            .send_email(&email(), &subject(), &content(), &content())
+            .send_email(&email(), &subject(), &content(), &content(), None)
             .await;
     }
+
+    struct UnsubscribeHeadersMatcher;
+
+    impl wiremock::Match for UnsubscribeHeadersMatcher {
+        fn matches(&self, request: &wiremock::Request) -> bool {
+            let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+            match result {
+                Ok(body) => {
+                    body["additional_headers"]
+                        == serde_json::json!([
+                            {"key": "List-Unsubscribe", "value": "<https://example.com/unsubscribe>"},
+                            {"key": "List-Unsubscribe-Post", "value": "List-Unsubscribe=One-Click"},
+                        ])
+                }
+                Err(_) => false,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn send_email_with_an_unsubscribe_link_sends_the_list_unsubscribe_headers() {
+        let mock_server = MockServer::start().await;
+        let client = email_client(mock_server.uri());
+
+        Mock::given(path("/emails"))
+            .and(UnsubscribeHeadersMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let result = client
+            .send_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                Some("https://example.com/unsubscribe"),
+            )
+            .await;
+
+        assert_ok!(result);
+    }
 }
@@ -0,0 +1,149 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+/// A token bucket holding up to `capacity` tokens, refilled at `rate` tokens per second.
+#[derive(Debug)]
+pub struct TokenBucket {
+    capacity: f64,
+    rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Whether tokens can be refilled at `rate` tokens per second.
+pub fn is_valid_rate(rate: f64) -> bool {
+    rate.is_finite() && rate > 0.0
+}
+
+impl TokenBucket {
+    /// Creates a full bucket, or fails if `rate` isn't a finite positive number.
+    pub fn new(rate: f64, capacity: u32, now: Instant) -> Result<Self, anyhow::Error> {
+        if !is_valid_rate(rate) {
+            anyhow::bail!("invalid token bucket rate: {}", rate);
+        }
+        let capacity = f64::from(capacity.max(1));
+
+        Ok(Self {
+            capacity,
+            rate,
+            tokens: capacity,
+            last_refill: now,
+        })
+    }
+
+    /// Takes a token, or returns how long to wait until one is available.
+    pub fn try_take(&mut self, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.capacity);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+        }
+    }
+}
+
+/// Limits the rate of an operation shared by several tasks.
+pub struct RateLimiter {
+    bucket: Option<Mutex<TokenBucket>>,
+}
+
+impl RateLimiter {
+    /// Allows `rate` operations per second on average with bursts of up to `burst` operations.
+    pub fn new(rate: f64, burst: u32) -> Result<Self, anyhow::Error> {
+        let bucket = TokenBucket::new(rate, burst, Instant::now())?;
+
+        Ok(Self {
+            bucket: Some(Mutex::new(bucket)),
+        })
+    }
+
+    pub fn unlimited() -> Self {
+        Self { bucket: None }
+    }
+
+    /// Waits until the operation is allowed.
+    pub async fn acquire(&self) {
+        let bucket = match &self.bucket {
+            Some(bucket) => bucket,
+            None => return,
+        };
+
+        loop {
+            let result = bucket.lock().unwrap().try_take(Instant::now());
+            match result {
+                Ok(()) => return,
+                Err(wait) => tokio::time::sleep(wait).await,
+            }
+        }
+    }
+
+    /// Waits until the operation is allowed, returning `false` if `shutdown` is cancelled first.
+    pub async fn acquire_unless_cancelled(&self, shutdown: &CancellationToken) -> bool {
+        tokio::select! {
+            _ = self.acquire() => true,
+            _ = shutdown.cancelled() => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TokenBucket;
+    use claim::{assert_err, assert_ok};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn a_full_bucket_allows_a_burst() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(1.0, 3, now).unwrap();
+
+        for _ in 0..3 {
+            assert_ok!(bucket.try_take(now));
+        }
+        assert_err!(bucket.try_take(now));
+    }
+
+    #[test]
+    fn an_empty_bucket_tells_how_long_to_wait() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(4.0, 1, now).unwrap();
+
+        assert_ok!(bucket.try_take(now));
+
+        let wait = bucket.try_take(now).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(250));
+    }
+
+    #[test]
+    fn tokens_are_refilled_over_time_up_to_the_capacity() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(10.0, 2, now).unwrap();
+
+        assert_ok!(bucket.try_take(now));
+        assert_ok!(bucket.try_take(now));
+        assert_err!(bucket.try_take(now));
+
+        let later = now + Duration::from_millis(100);
+        assert_ok!(bucket.try_take(later));
+        assert_err!(bucket.try_take(later));
+
+        let much_later = later + Duration::from_secs(60);
+        assert_ok!(bucket.try_take(much_later));
+        assert_ok!(bucket.try_take(much_later));
+        assert_err!(bucket.try_take(much_later));
+    }
+
+    #[test]
+    fn a_rate_that_is_not_finite_and_positive_is_rejected() {
+        let now = Instant::now();
+
+        for rate in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert_err!(TokenBucket::new(rate, 1, now));
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use hmac::{Hmac, Mac};
+use secrecy::ExposeSecret;
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// A token identifying a subscriber in an unsubscribe link.
+///
+/// The token is the subscriber id followed by a HMAC of that id, which means it can't be forged
+/// without knowing the secret and doesn't need to be stored.
+#[derive(Debug)]
+pub struct UnsubscribeToken(String);
+
+impl UnsubscribeToken {
+    pub fn generate(subscriber_id: Uuid, hmac_secret: &HmacSecret) -> Self {
+        let tag = compute_tag(subscriber_id, hmac_secret)
+            .finalize()
+            .into_bytes();
+
+        Self(format!("{}.{}", subscriber_id.simple(), hex::encode(tag)))
+    }
+
+    /// Returns the subscriber id if the token has been signed with this secret.
+    pub fn verify(token: &str, hmac_secret: &HmacSecret) -> Option<Uuid> {
+        let (subscriber_id, tag) = token.split_once('.')?;
+
+        let subscriber_id = Uuid::try_parse(subscriber_id).ok()?;
+        let tag = hex::decode(tag).ok()?;
+
+        compute_tag(subscriber_id, hmac_secret)
+            .verify_slice(&tag)
+            .ok()
+            .map(|_| subscriber_id)
+    }
+
+    pub fn link(&self, base_url: &ApplicationBaseUrl) -> String {
+        format!("{}/subscriptions/unsubscribe?token={}", base_url.0, self.0)
+    }
+}
+
+impl AsRef<str> for UnsubscribeToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+fn compute_tag(subscriber_id: Uuid, hmac_secret: &HmacSecret) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(hmac_secret.0.expose_secret().as_bytes()).unwrap();
+    mac.update(b"unsubscribe:");
+    mac.update(subscriber_id.as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UnsubscribeToken;
+    use crate::startup::HmacSecret;
+    use claim::{assert_none, assert_some_eq};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn hmac_secret() -> HmacSecret {
+        HmacSecret(Secret::new(Uuid::new_v4().to_string()))
+    }
+
+    #[test]
+    fn a_generated_token_is_valid() {
+        let secret = hmac_secret();
+        let subscriber_id = Uuid::new_v4();
+
+        let token = UnsubscribeToken::generate(subscriber_id, &secret);
+
+        assert_some_eq!(
+            UnsubscribeToken::verify(token.as_ref(), &secret),
+            subscriber_id
+        );
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let token = UnsubscribeToken::generate(Uuid::new_v4(), &hmac_secret());
+
+        assert_none!(UnsubscribeToken::verify(token.as_ref(), &hmac_secret()));
+    }
+
+    #[test]
+    fn a_token_for_another_subscriber_is_rejected() {
+        let secret = hmac_secret();
+
+        let token = UnsubscribeToken::generate(Uuid::new_v4(), &secret);
+        let (_, tag) = token.as_ref().split_once('.').unwrap();
+        let forged_token = format!("{}.{}", Uuid::new_v4().simple(), tag);
+
+        assert_none!(UnsubscribeToken::verify(&forged_token, &secret));
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        let secret = hmac_secret();
+
+        for token in ["", "foobar", "foo.bar", &format!("{}.zz", Uuid::new_v4())] {
+            assert_none!(UnsubscribeToken::verify(token, &secret));
+        }
+    }
+}
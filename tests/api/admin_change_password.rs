@@ -141,6 +141,90 @@ async fn new_password_is_too_long() {
     assert!(html_page.contains("New password is too long"));
 }
 
+#[tokio::test]
+async fn new_password_cannot_be_the_username() {
+    let app = spawn_app().await;
+
+    let new_password = app.test_user.username.clone();
+
+    // Login
+    app.post_login(&LoginBody {
+        username: app.test_user.username.clone(),
+        password: app.test_user.password.clone(),
+    })
+    .await;
+
+    // Try to change the password
+    let response = app
+        .post_admin_change_password(&AdminChangePasswordBody {
+            current_password: app.test_user.password.clone(),
+            new_password: new_password.clone(),
+            new_password_check: new_password.clone(),
+        })
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    // Follow the redirect
+    let html_page = app.get_admin_change_password_html().await;
+    assert!(html_page.contains("New password can&#x27;t be your username"));
+}
+
+#[tokio::test]
+async fn new_password_is_too_easy_to_guess() {
+    let app = spawn_app().await;
+
+    let new_password = "qwertyuiop123456".to_string();
+
+    // Login
+    app.post_login(&LoginBody {
+        username: app.test_user.username.clone(),
+        password: app.test_user.password.clone(),
+    })
+    .await;
+
+    // Try to change the password
+    let response = app
+        .post_admin_change_password(&AdminChangePasswordBody {
+            current_password: app.test_user.password.clone(),
+            new_password: new_password.clone(),
+            new_password_check: new_password.clone(),
+        })
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    // Follow the redirect
+    let html_page = app.get_admin_change_password_html().await;
+    assert!(html_page.contains("New password is too easy to guess"));
+}
+
+#[tokio::test]
+async fn new_password_is_a_breached_password() {
+    let app = spawn_app().await;
+
+    let new_password = "Password1234".to_string();
+
+    // Login
+    app.post_login(&LoginBody {
+        username: app.test_user.username.clone(),
+        password: app.test_user.password.clone(),
+    })
+    .await;
+
+    // Try to change the password
+    let response = app
+        .post_admin_change_password(&AdminChangePasswordBody {
+            current_password: app.test_user.password.clone(),
+            new_password: new_password.clone(),
+            new_password_check: new_password.clone(),
+        })
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    // Follow the redirect
+    let html_page = app.get_admin_change_password_html().await;
+    assert!(html_page.contains("New password appears in a list of breached passwords"));
+}
+
 #[tokio::test]
 async fn changing_password_works() {
     let app = spawn_app().await;
@@ -185,3 +269,64 @@ async fn changing_password_works() {
         .await;
     assert_is_redirect_to(&response, "/admin/dashboard");
 }
+
+#[tokio::test]
+async fn changing_password_can_log_out_the_other_sessions() {
+    let app = spawn_app().await;
+    let new_password = Uuid::new_v4().to_string();
+
+    app.login_as(&app.test_user).await;
+    let other_client = app
+        .login_from_another_device(&app.test_user, "another-device")
+        .await;
+
+    let response = app
+        .post_admin_change_password(&[
+            ("current_password", app.test_user.password.as_str()),
+            ("new_password", &new_password),
+            ("new_password_check", &new_password),
+            ("log_out_other_sessions", "true"),
+        ])
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    let html_page = app.get_admin_change_password_html().await;
+    assert!(html_page.contains("You have been logged out everywhere else"));
+
+    let response = other_client
+        .get(format!("{}/admin/dashboard", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn changing_password_keeps_the_other_sessions_unless_asked() {
+    let app = spawn_app().await;
+    let new_password = Uuid::new_v4().to_string();
+
+    app.login_as(&app.test_user).await;
+    let other_client = app
+        .login_from_another_device(&app.test_user, "another-device")
+        .await;
+
+    let response = app
+        .post_admin_change_password(&AdminChangePasswordBody {
+            current_password: app.test_user.password.clone(),
+            new_password: new_password.clone(),
+            new_password_check: new_password.clone(),
+        })
+        .await;
+    assert_is_redirect_to(&response, "/admin/password");
+
+    let response = other_client
+        .get(format!("{}/admin/dashboard", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
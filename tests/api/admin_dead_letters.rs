@@ -0,0 +1,101 @@
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app};
+use crate::helpers::{LoginBody, SubmitNewsletterBody, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[derive(serde::Serialize)]
+struct RequeueBody {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_dead_letters() {
+    let app = spawn_app().await;
+
+    let response = app.get_admin_dead_letters().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_requeue_a_dead_letter() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_admin_requeue_dead_letter(&RequeueBody {
+            newsletter_issue_id: Uuid::new_v4(),
+            subscriber_email: "ursula@example.com".to_string(),
+        })
+        .await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn a_requeued_dead_letter_is_delivered_again() {
+    let app = spawn_app().await;
+
+    create_confirmed_subscriber(&app).await;
+
+    app.post_login(&LoginBody {
+        username: app.test_user.username.clone(),
+        password: app.test_user.password.clone(),
+    })
+    .await;
+
+    // 1) Fail the delivery until it's dead lettered
+    let record = create_dead_letter(&app).await;
+
+    let html_page = app.get_admin_dead_letters_html().await;
+    assert!(html_page.contains(&record.subscriber_email));
+
+    // 2) Requeue it
+    let response = app.post_admin_requeue_dead_letter(&record).await;
+    assert_is_redirect_to(&response, "/admin/dead_letters");
+
+    let html_page = app.get_admin_dead_letters_html().await;
+    assert!(html_page.contains("has been requeued"));
+    assert!(html_page.contains("No failed deliveries"));
+
+    // 3) Deliver it
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.dispatch_all_pending_emails().await;
+}
+
+async fn create_dead_letter(app: &TestApp) -> RequeueBody {
+    let _mock_guard = Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let newsletter_request_body = SubmitNewsletterBody {
+        title: "Newsletter title".to_string(),
+        text_content: "Newsletter body as plain text".to_string(),
+        html_content: "<p>Newsletter body as HTML</p>".to_string(),
+        idempotency_key: Uuid::new_v4(),
+    };
+
+    let response = app.post_admin_newsletters(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    app.dispatch_all_pending_emails().await;
+
+    let record = sqlx::query!(
+        "SELECT newsletter_issue_id, subscriber_email FROM issue_delivery_dead_letters"
+    )
+    .fetch_one(&app.pool)
+    .await
+    .expect("Failed to fetch the dead letter");
+
+    RequeueBody {
+        newsletter_issue_id: record.newsletter_issue_id,
+        subscriber_email: record.subscriber_email,
+    }
+}
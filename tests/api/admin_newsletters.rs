@@ -1,9 +1,6 @@
-use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with_pool};
-use crate::helpers::{ConfirmationLinks, TestApp};
-use crate::helpers::{LoginBody, SubmitNewsletterBody, SubscriptionBody};
-use fake::faker::internet::en::SafeEmail;
-use fake::faker::name::en::Name;
-use fake::Fake;
+use crate::helpers::{assert_is_redirect_to, migrate_before, spawn_app, spawn_app_with_pool};
+use crate::helpers::{create_confirmed_subscriber, create_unconfirmed_subscriber};
+use crate::helpers::{LoginBody, SubmitNewsletterBody};
 use std::time::Duration;
 use uuid::Uuid;
 use wiremock::matchers::{method, path};
@@ -112,6 +109,24 @@ async fn newsletters_returns_400_for_invalid_data() {
             },
             "missing content",
         ),
+        (
+            SubmitNewsletterBody {
+                title: "My title".to_string(),
+                text_content: "Hi {{ name".to_string(),
+                html_content: "<p>Hi</p>".to_string(),
+                idempotency_key: Uuid::new_v4(),
+            },
+            "invalid template syntax",
+        ),
+        (
+            SubmitNewsletterBody {
+                title: "My title".to_string(),
+                text_content: "Hi".to_string(),
+                html_content: "<p>Hi {{ first_name }}</p>".to_string(),
+                idempotency_key: Uuid::new_v4(),
+            },
+            "unknown template variable",
+        ),
     ];
 
     for (invalid_body, case) in test_cases {
@@ -220,42 +235,227 @@ async fn newsletter_creation_concurrent_form_submission_is_handled_gracefully(po
     // Mock verifies on Drop that we have sent the newsletter email _once_
 }
 
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    let body = SubscriptionBody {
-        name: Name().fake(),
-        email: SafeEmail().fake(),
+#[tokio::test]
+async fn failed_deliveries_are_retried() {
+    let app = spawn_app().await;
+
+    create_confirmed_subscriber(&app).await;
+
+    app.post_login(&LoginBody {
+        username: app.test_user.username.clone(),
+        password: app.test_user.password.clone(),
+    })
+    .await;
+
+    //
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = SubmitNewsletterBody {
+        title: "Newsletter title".to_string(),
+        text_content: "Newsletter body as plain text".to_string(),
+        html_content: "<p>Newsletter body as HTML</p>".to_string(),
+        idempotency_key: Uuid::new_v4(),
     };
 
-    let _mock_guard = Mock::given(path("/emails"))
+    let response = app.post_admin_newsletters(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    app.dispatch_all_pending_emails().await;
+
+    // Mock verifies on Drop that we have tried twice
+}
+
+#[tokio::test]
+async fn deliveries_failing_too_many_times_are_moved_to_the_dead_letters() {
+    let app = spawn_app().await;
+
+    create_confirmed_subscriber(&app).await;
+
+    app.post_login(&LoginBody {
+        username: app.test_user.username.clone(),
+        password: app.test_user.password.clone(),
+    })
+    .await;
+
+    //
+
+    let max_retries = app.configuration.worker.max_retries as u64;
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(max_retries + 1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = SubmitNewsletterBody {
+        title: "Newsletter title".to_string(),
+        text_content: "Newsletter body as plain text".to_string(),
+        html_content: "<p>Newsletter body as HTML</p>".to_string(),
+        idempotency_key: Uuid::new_v4(),
+    };
+
+    let response = app.post_admin_newsletters(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    app.dispatch_all_pending_emails().await;
+
+    let n_queued = sqlx::query!(r#"SELECT count(*) AS "n!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_queued, 0);
+
+    let dead_letter = sqlx::query!("SELECT n_retries FROM issue_delivery_dead_letters")
+        .fetch_one(&app.pool)
+        .await
+        .expect("Failed to fetch the dead letter");
+    assert_eq!(dead_letter.n_retries as u64, max_retries);
+}
+
+#[tokio::test]
+async fn newsletters_are_personalised_for_each_subscriber() {
+    let app = spawn_app().await;
+
+    create_confirmed_subscriber(&app).await;
+
+    app.post_login(&LoginBody {
+        username: app.test_user.username.clone(),
+        password: app.test_user.password.clone(),
+    })
+    .await;
+
+    //
+
+    Mock::given(path("/emails"))
         .and(method("POST"))
         .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
         .expect(1)
-        .mount_as_scoped(&app.email_server)
+        .mount(&app.email_server)
         .await;
 
-    app.post_subscriptions(&body)
+    let newsletter_request_body = SubmitNewsletterBody {
+        title: "Newsletter title".to_string(),
+        text_content: "Hi {{ name }}, you subscribed with {{ email }} on {{ subscribed_at }}"
+            .to_string(),
+        html_content: "<p>Hi {{ name }}</p><a href=\"{{ unsubscribe_link }}\">Leave</a>"
+            .to_string(),
+        idempotency_key: Uuid::new_v4(),
+    };
+
+    let response = app.post_admin_newsletters(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    app.dispatch_all_pending_emails().await;
+
+    let subscriber = sqlx::query!("SELECT name, email, subscribed_at FROM subscriptions")
+        .fetch_one(&app.pool)
         .await
-        .error_for_status()
         .unwrap();
 
-    let email_request = &app
+    let email_request = app
         .email_server
         .received_requests()
         .await
         .unwrap()
         .pop()
         .unwrap();
-
-    app.get_confirmation_links(email_request)
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let text = body["text"].as_str().unwrap();
+    let html = body["html"].as_str().unwrap();
+
+    let subscribed_at = subscriber.subscribed_at.date().to_string();
+    assert!(text.starts_with(&format!(
+        "Hi {}, you subscribed with {} on {}",
+        subscriber.name, subscriber.email, subscribed_at
+    )));
+
+    // The link is HTML escaped, only check the token
+    let unsubscribe_link = app.get_unsubscribe_link(&email_request);
+    assert!(html.contains(&format!(
+        "unsubscribe?{}\">Leave</a>",
+        unsubscribe_link.query().unwrap()
+    )));
 }
 
-async fn create_confirmed_subscriber(app: &TestApp) {
-    let confirmation_links = create_unconfirmed_subscriber(app).await;
+#[sqlx::test(migrations = false)]
+async fn issues_queued_before_templates_are_delivered_as_written(pool: sqlx::PgPool) {
+    // Their contents weren't written as templates, braces have no special meaning in them
+    migrate_before(&pool, 20221230094512).await;
+    let issue_id = Uuid::new_v4();
+    sqlx::query(
+        r#"
+        INSERT INTO subscriptions(id, email, name, subscribed_at, status)
+        VALUES ($1, 'ursula@example.com', 'Ursula', now(), 'confirmed')"#,
+    )
+    .bind(Uuid::new_v4())
+    .execute(&pool)
+    .await
+    .unwrap();
+    sqlx::query(
+        r#"
+        INSERT INTO newsletter_issues(id, title, text_content, html_content, status, published_at)
+        VALUES ($1, 'Old issue', 'Use {{ and }} in Jinja', '<p>Use {% raw %}</p>', 'published', now())"#,
+    )
+    .bind(issue_id)
+    .execute(&pool)
+    .await
+    .unwrap();
+    sqlx::query(
+        r#"
+        INSERT INTO issue_delivery_queue(newsletter_issue_id, subscriber_email)
+        VALUES ($1, 'ursula@example.com')"#,
+    )
+    .bind(issue_id)
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+    let app = spawn_app_with_pool(pool).await;
 
-    reqwest::get(confirmation_links.html)
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    //
+
+    app.dispatch_all_pending_emails().await;
+
+    //
+
+    let email_request = app
+        .email_server
+        .received_requests()
         .await
         .unwrap()
-        .error_for_status()
+        .pop()
         .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(body["text"]
+        .as_str()
+        .unwrap()
+        .starts_with("Use {{ and }} in Jinja"));
+    assert!(body["html"]
+        .as_str()
+        .unwrap()
+        .starts_with("<p>Use {% raw %}</p>"));
 }
@@ -0,0 +1,173 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+use crate::helpers::{create_confirmed_subscriber, create_unconfirmed_subscriber};
+use crate::helpers::{LoginBody, SubmitNewsletterBody};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn publish_newsletter(app: &TestApp) -> Uuid {
+    app.post_login(&LoginBody {
+        username: app.test_user.username.clone(),
+        password: app.test_user.password.clone(),
+    })
+    .await;
+
+    let newsletter_request_body = SubmitNewsletterBody {
+        title: "Newsletter title".to_string(),
+        text_content: "Newsletter body as plain text".to_string(),
+        html_content: "<p>Newsletter body as HTML</p>".to_string(),
+        idempotency_key: Uuid::new_v4(),
+    };
+
+    let response = app.post_admin_newsletters(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    sqlx::query!("SELECT id FROM newsletter_issues")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .id
+}
+
+struct Delivery {
+    outcome: String,
+    n_attempts: i32,
+    response_body: Option<String>,
+}
+
+async fn get_delivery(app: &TestApp, issue_id: Uuid) -> Delivery {
+    sqlx::query_as!(
+        Delivery,
+        "SELECT outcome, n_attempts, response_body FROM newsletter_deliveries WHERE newsletter_issue_id = $1",
+        issue_id,
+    )
+    .fetch_one(&app.pool)
+    .await
+    .expect("Failed to fetch the delivery")
+}
+
+#[tokio::test]
+async fn successful_deliveries_are_recorded() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("message queued"))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = publish_newsletter(&app).await;
+
+    // Before the worker runs the delivery is queued
+    let html_page = app.get_admin_newsletter_issue_html(issue_id).await;
+    assert!(html_page.contains("<th>Queued</th>\n            <td>1</td>"));
+
+    app.dispatch_all_pending_emails().await;
+
+    let delivery = get_delivery(&app, issue_id).await;
+    assert_eq!(delivery.outcome, "sent");
+    assert_eq!(delivery.n_attempts, 1);
+    assert_eq!(delivery.response_body.as_deref(), Some("message queued"));
+
+    let html_page = app.get_admin_newsletter_issue_html(issue_id).await;
+    assert!(html_page.contains("<th>Queued</th>\n            <td>0</td>"));
+    assert!(html_page.contains("Sent</a></th>\n            <td>1</td>"));
+
+    let html_page = app
+        .get_admin_newsletter_deliveries_html(issue_id, Some("sent"))
+        .await;
+    assert!(html_page.contains("message queued"));
+
+    let html_page = app
+        .get_admin_newsletter_deliveries_html(issue_id, Some("failed"))
+        .await;
+    assert!(html_page.contains("No deliveries."));
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_recorded_with_the_response_of_the_email_api() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let max_retries = app.configuration.worker.max_retries;
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500).set_body_string("mailbox unavailable"))
+        .expect(max_retries as u64 + 1)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let delivery = get_delivery(&app, issue_id).await;
+    assert_eq!(delivery.outcome, "failed");
+    assert_eq!(delivery.n_attempts, max_retries + 1);
+    assert!(delivery
+        .response_body
+        .unwrap()
+        .contains("mailbox unavailable"));
+
+    let html_page = app
+        .get_admin_newsletter_deliveries_html(issue_id, Some("failed"))
+        .await;
+    assert!(html_page.contains("mailbox unavailable"));
+}
+
+#[tokio::test]
+async fn deliveries_to_subscribers_who_are_not_confirmed_anymore_are_recorded_as_skipped() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = publish_newsletter(&app).await;
+
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+    app.dispatch_all_pending_emails().await;
+
+    let delivery = get_delivery(&app, issue_id).await;
+    assert_eq!(delivery.outcome, "skipped");
+    assert_eq!(delivery.n_attempts, 0);
+
+    let html_page = app.get_admin_newsletter_issue_html(issue_id).await;
+    assert!(html_page.contains("Skipped</a></th>\n            <td>1</td>"));
+}
+
+#[tokio::test]
+async fn unconfirmed_subscribers_have_no_delivery() {
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+
+    let issue_id = publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let html_page = app
+        .get_admin_newsletter_deliveries_html(issue_id, None)
+        .await;
+    assert!(html_page.contains("No deliveries."));
+}
+
+#[tokio::test]
+async fn filtering_deliveries_by_an_unknown_outcome_is_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let issue_id = publish_newsletter(&app).await;
+
+    let response = app
+        .get_admin_newsletter_deliveries(issue_id, Some("bounced"))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+}
@@ -0,0 +1,319 @@
+use crate::helpers::TestSendBody;
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app};
+use crate::helpers::{EditDraftBody, LoginBody, PublishDraftBody, SaveDraftBody, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn login(app: &TestApp) {
+    app.post_login(&LoginBody {
+        username: app.test_user.username.clone(),
+        password: app.test_user.password.clone(),
+    })
+    .await;
+}
+
+async fn save_draft(app: &TestApp) -> Uuid {
+    let response = app
+        .post_admin_newsletters(&SaveDraftBody {
+            title: "Draft title".to_string(),
+            text_content: "Draft body as plain text".to_string(),
+            html_content: "<p>Draft body as HTML</p>".to_string(),
+            idempotency_key: Uuid::new_v4(),
+            action: "draft",
+        })
+        .await;
+
+    let issue_id = sqlx::query!("SELECT id FROM newsletter_issues")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .id;
+
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{}", issue_id));
+
+    issue_id
+}
+
+async fn get_issue_status(app: &TestApp, issue_id: Uuid) -> String {
+    sqlx::query!(
+        "SELECT status FROM newsletter_issues WHERE id = $1",
+        issue_id
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap()
+    .status
+}
+
+#[tokio::test]
+async fn drafts_are_not_delivered() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    login(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = save_draft(&app).await;
+
+    let html_page = app.get_admin_newsletter_issue_html(issue_id).await;
+    assert!(html_page.contains("The newsletter draft has been saved"));
+    assert!(html_page.contains("Draft body as plain text"));
+
+    let html_page = app.get_admin_newsletters_html().await;
+    assert!(html_page.contains(&format!(r#"href="/admin/newsletters/{}""#, issue_id)));
+
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(get_issue_status(&app, issue_id).await, "draft");
+
+    // Mock verifies on Drop that we haven't sent the newsletter email
+}
+
+#[tokio::test]
+async fn drafts_can_be_edited() {
+    let app = spawn_app().await;
+    login(&app).await;
+
+    let issue_id = save_draft(&app).await;
+
+    let response = app
+        .post_admin_newsletter_issue(
+            issue_id,
+            &EditDraftBody {
+                title: "Edited title".to_string(),
+                text_content: "Edited body as plain text".to_string(),
+                html_content: "<p>Edited body as HTML</p>".to_string(),
+            },
+        )
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{}", issue_id));
+
+    let html_page = app.get_admin_newsletter_issue_html(issue_id).await;
+    assert!(html_page.contains("Edited title"));
+    assert!(html_page.contains("Edited body as plain text"));
+}
+
+#[tokio::test]
+async fn drafts_can_be_previewed() {
+    let app = spawn_app().await;
+    login(&app).await;
+
+    let issue_id = save_draft(&app).await;
+
+    let html_page = app.get_admin_newsletter_preview_html(issue_id).await;
+    assert!(html_page.contains("Draft title"));
+    assert!(html_page.contains("Draft body as plain text"));
+    assert!(html_page.contains("srcdoc=\"&lt;p&gt;Draft body as HTML"));
+}
+
+#[tokio::test]
+async fn a_test_email_is_only_sent_to_the_chosen_address() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    login(&app).await;
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = save_draft(&app).await;
+
+    let response = app
+        .post_admin_test_send_newsletter(
+            issue_id,
+            &TestSendBody {
+                email: "tester@example.com".to_string(),
+            },
+        )
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{}", issue_id));
+
+    let html_page = app.get_admin_newsletter_issue_html(issue_id).await;
+    assert!(html_page.contains("A test email has been sent to tester@example.com"));
+
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["to"][0]["email"], "tester@example.com");
+    assert_eq!(body["subject"], "[Test] Draft title");
+
+    assert_eq!(get_issue_status(&app, issue_id).await, "draft");
+}
+
+#[tokio::test]
+async fn a_test_email_to_an_invalid_address_is_rejected() {
+    let app = spawn_app().await;
+    login(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = save_draft(&app).await;
+
+    let response = app
+        .post_admin_test_send_newsletter(
+            issue_id,
+            &TestSendBody {
+                email: "not-an-email".to_string(),
+            },
+        )
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{}", issue_id));
+
+    let html_page = app.get_admin_newsletter_issue_html(issue_id).await;
+    assert!(html_page.contains("The test email address is invalid"));
+}
+
+#[tokio::test]
+async fn published_drafts_are_delivered_and_cannot_be_edited() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    login(&app).await;
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = save_draft(&app).await;
+
+    let response = app
+        .post_admin_publish_newsletter(
+            issue_id,
+            &PublishDraftBody {
+                send_at: "".to_string(),
+            },
+        )
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/newsletters/{}", issue_id));
+
+    let html_page = app.get_admin_newsletter_issue_html(issue_id).await;
+    assert!(html_page.contains("The newsletter issue has been published"));
+
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(get_issue_status(&app, issue_id).await, "published");
+
+    // Publishing or editing again is rejected
+    app.post_admin_publish_newsletter(
+        issue_id,
+        &PublishDraftBody {
+            send_at: "".to_string(),
+        },
+    )
+    .await;
+    let html_page = app.get_admin_newsletter_issue_html(issue_id).await;
+    assert!(html_page.contains("This newsletter issue is not a draft anymore"));
+
+    app.post_admin_newsletter_issue(
+        issue_id,
+        &EditDraftBody {
+            title: "Edited title".to_string(),
+            text_content: "Edited body as plain text".to_string(),
+            html_content: "<p>Edited body as HTML</p>".to_string(),
+        },
+    )
+    .await;
+    let html_page = app.get_admin_newsletter_issue_html(issue_id).await;
+    assert!(html_page.contains("This newsletter issue is not a draft anymore"));
+    assert!(html_page.contains("Draft title"));
+
+    app.dispatch_all_pending_emails().await;
+
+    // Mock verifies on Drop that we have sent the newsletter email once
+}
+
+#[tokio::test]
+async fn drafts_without_content_cannot_be_published() {
+    let app = spawn_app().await;
+    login(&app).await;
+
+    let issue_id = save_draft(&app).await;
+
+    app.post_admin_newsletter_issue(
+        issue_id,
+        &EditDraftBody {
+            title: "Draft title".to_string(),
+            text_content: "".to_string(),
+            html_content: "".to_string(),
+        },
+    )
+    .await;
+
+    app.post_admin_publish_newsletter(
+        issue_id,
+        &PublishDraftBody {
+            send_at: "".to_string(),
+        },
+    )
+    .await;
+
+    let html_page = app.get_admin_newsletter_issue_html(issue_id).await;
+    assert!(html_page.contains("The newsletter content is missing"));
+    assert_eq!(get_issue_status(&app, issue_id).await, "draft");
+}
+
+#[tokio::test]
+async fn unknown_newsletter_issues_return_a_404() {
+    let app = spawn_app().await;
+    login(&app).await;
+
+    let response = app.get_admin_newsletter_issue(Uuid::new_v4()).await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_a_newsletter_issue() {
+    let app = spawn_app().await;
+
+    let response = app.get_admin_newsletter_issue(Uuid::new_v4()).await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn drafts_with_an_invalid_template_cannot_be_published() {
+    let app = spawn_app().await;
+    login(&app).await;
+
+    let issue_id = save_draft(&app).await;
+
+    app.post_admin_newsletter_issue(
+        issue_id,
+        &EditDraftBody {
+            title: "Draft title".to_string(),
+            text_content: "Hi {{ name".to_string(),
+            html_content: "<p>Hi {{ name }}</p>".to_string(),
+        },
+    )
+    .await;
+
+    app.post_admin_publish_newsletter(
+        issue_id,
+        &PublishDraftBody {
+            send_at: "".to_string(),
+        },
+    )
+    .await;
+
+    let html_page = app.get_admin_newsletter_issue_html(issue_id).await;
+    assert!(html_page.contains("The newsletter content is invalid"));
+    assert_eq!(get_issue_status(&app, issue_id).await, "draft");
+}
@@ -0,0 +1,276 @@
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app};
+use crate::helpers::{LoginBody, RescheduleNewsletterBody, ScheduleNewsletterBody, TestApp};
+use time::macros::format_description;
+use time::OffsetDateTime;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn send_at(offset: time::Duration) -> String {
+    (OffsetDateTime::now_utc() + offset)
+        .format(format_description!("[year]-[month]-[day]T[hour]:[minute]"))
+        .unwrap()
+}
+
+async fn login(app: &TestApp) {
+    app.post_login(&LoginBody {
+        username: app.test_user.username.clone(),
+        password: app.test_user.password.clone(),
+    })
+    .await;
+}
+
+async fn schedule_newsletter(app: &TestApp) -> Uuid {
+    let newsletter_request_body = ScheduleNewsletterBody {
+        title: "Newsletter title".to_string(),
+        text_content: "Newsletter body as plain text".to_string(),
+        html_content: "<p>Newsletter body as HTML</p>".to_string(),
+        idempotency_key: Uuid::new_v4(),
+        send_at: send_at(time::Duration::days(1)),
+    };
+
+    let response = app.post_admin_newsletters(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    sqlx::query!("SELECT id FROM newsletter_issues")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .id
+}
+
+async fn make_issue_due(app: &TestApp, issue_id: Uuid) {
+    sqlx::query!(
+        "UPDATE newsletter_issues SET scheduled_for = now() - interval '1 minute' WHERE id = $1",
+        issue_id,
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+}
+
+async fn get_issue_status(app: &TestApp, issue_id: Uuid) -> String {
+    sqlx::query!(
+        "SELECT status FROM newsletter_issues WHERE id = $1",
+        issue_id
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap()
+    .status
+}
+
+#[tokio::test]
+async fn scheduled_newsletters_are_not_delivered_before_their_send_time() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    login(&app).await;
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = schedule_newsletter(&app).await;
+
+    let html_page = app.get_admin_newsletters_html().await;
+    assert!(html_page.contains("The newsletter issue has been scheduled"));
+
+    app.publish_all_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(get_issue_status(&app, issue_id).await, "scheduled");
+
+    let html_page = app.get_admin_scheduled_newsletters_html().await;
+    assert!(html_page.contains("Newsletter title"));
+
+    // Mock verifies on Drop that we haven't sent the newsletter email
+}
+
+#[tokio::test]
+async fn scheduled_newsletters_are_delivered_once_their_send_time_has_come() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    login(&app).await;
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = schedule_newsletter(&app).await;
+    make_issue_due(&app, issue_id).await;
+
+    app.publish_all_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(get_issue_status(&app, issue_id).await, "published");
+
+    // Publishing again is a no-op
+    app.publish_all_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+
+    // Mock verifies on Drop that we have sent the newsletter email once
+}
+
+#[tokio::test]
+async fn cancelled_newsletters_are_not_delivered() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    login(&app).await;
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = schedule_newsletter(&app).await;
+
+    let response = app.post_admin_cancel_newsletter(issue_id).await;
+    assert_is_redirect_to(&response, "/admin/newsletters/scheduled");
+
+    let html_page = app.get_admin_scheduled_newsletters_html().await;
+    assert!(html_page.contains("The newsletter issue has been cancelled"));
+    assert!(!html_page.contains("Newsletter title"));
+
+    make_issue_due(&app, issue_id).await;
+    app.publish_all_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(get_issue_status(&app, issue_id).await, "cancelled");
+
+    // Mock verifies on Drop that we haven't sent the newsletter email
+}
+
+#[tokio::test]
+async fn published_newsletters_cannot_be_cancelled() {
+    let app = spawn_app().await;
+    login(&app).await;
+
+    let issue_id = schedule_newsletter(&app).await;
+    make_issue_due(&app, issue_id).await;
+    app.publish_all_due_issues().await;
+
+    let response = app.post_admin_cancel_newsletter(issue_id).await;
+    assert_is_redirect_to(&response, "/admin/newsletters/scheduled");
+
+    let html_page = app.get_admin_scheduled_newsletters_html().await;
+    assert!(html_page.contains("This newsletter issue is not scheduled anymore"));
+
+    assert_eq!(get_issue_status(&app, issue_id).await, "published");
+}
+
+#[tokio::test]
+async fn scheduled_newsletters_can_be_rescheduled() {
+    let app = spawn_app().await;
+    login(&app).await;
+
+    let issue_id = schedule_newsletter(&app).await;
+
+    let new_send_at = send_at(time::Duration::days(7));
+    let response = app
+        .post_admin_reschedule_newsletter(
+            issue_id,
+            &RescheduleNewsletterBody {
+                send_at: new_send_at.clone(),
+            },
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters/scheduled");
+
+    let html_page = app.get_admin_scheduled_newsletters_html().await;
+    assert!(html_page.contains("The newsletter issue has been rescheduled"));
+    assert!(html_page.contains(&new_send_at));
+}
+
+#[tokio::test]
+async fn rescheduling_to_a_past_send_time_is_rejected() {
+    let app = spawn_app().await;
+    login(&app).await;
+
+    let issue_id = schedule_newsletter(&app).await;
+
+    let response = app
+        .post_admin_reschedule_newsletter(
+            issue_id,
+            &RescheduleNewsletterBody {
+                send_at: send_at(-time::Duration::days(1)),
+            },
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters/scheduled");
+
+    let html_page = app.get_admin_scheduled_newsletters_html().await;
+    assert!(html_page.contains("The send time must be in the future"));
+}
+
+#[tokio::test]
+async fn newsletters_scheduled_in_the_past_are_rejected() {
+    let app = spawn_app().await;
+    login(&app).await;
+
+    let test_cases = vec![
+        (send_at(-time::Duration::days(1)), "a past send time"),
+        ("tomorrow".to_string(), "an invalid send time"),
+    ];
+
+    for (send_at, description) in test_cases {
+        let newsletter_request_body = ScheduleNewsletterBody {
+            title: "Newsletter title".to_string(),
+            text_content: "Newsletter body as plain text".to_string(),
+            html_content: "<p>Newsletter body as HTML</p>".to_string(),
+            idempotency_key: Uuid::new_v4(),
+            send_at,
+        };
+
+        let response = app.post_admin_newsletters(&newsletter_request_body).await;
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload was {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_scheduled_newsletters() {
+    let app = spawn_app().await;
+
+    let response = app.get_admin_scheduled_newsletters().await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app.post_admin_cancel_newsletter(Uuid::new_v4()).await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn a_resubmitted_schedule_is_still_reported_as_scheduled() {
+    let app = spawn_app().await;
+    login(&app).await;
+
+    let newsletter_request_body = ScheduleNewsletterBody {
+        title: "Newsletter title".to_string(),
+        text_content: "Newsletter body as plain text".to_string(),
+        html_content: "<p>Newsletter body as HTML</p>".to_string(),
+        idempotency_key: Uuid::new_v4(),
+        send_at: send_at(time::Duration::days(1)),
+    };
+
+    let response = app.post_admin_newsletters(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let html_page = app.get_admin_newsletters_html().await;
+    assert!(html_page.contains("The newsletter issue has been scheduled"));
+
+    let response = app.post_admin_newsletters(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let html_page = app.get_admin_newsletters_html().await;
+    assert!(html_page.contains("The newsletter issue has been scheduled"));
+    assert!(!html_page.contains("The newsletter issue has been published"));
+}
@@ -0,0 +1,137 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+use uuid::Uuid;
+
+const OTHER_USER_AGENT: &str = "Mozilla/5.0 (X11; Linux x86_64) Firefox/108.0";
+
+async fn get_session_id(app: &TestApp, user_agent: &str) -> Uuid {
+    sqlx::query_scalar!("SELECT id FROM sessions WHERE user_agent = $1", user_agent)
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+}
+
+async fn is_logged_in(app: &TestApp, client: &reqwest::Client) -> bool {
+    let response = client
+        .get(format!("{}/admin/dashboard", &app.address))
+        .send()
+        .await
+        .unwrap();
+    response.status().as_u16() == 200
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_your_sessions() {
+    let app = spawn_app().await;
+
+    let response = app.get_admin_sessions().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn the_sessions_of_the_user_are_listed() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    app.login_from_another_device(&app.test_user, OTHER_USER_AGENT)
+        .await;
+
+    let html_page = app.get_admin_sessions_html().await;
+    assert!(html_page.contains(OTHER_USER_AGENT));
+    assert!(html_page.contains("127.0.0.1"));
+    assert!(html_page.contains("This device"));
+    let other_session_id = get_session_id(&app, OTHER_USER_AGENT).await;
+    assert!(html_page.contains(&format!("/admin/sessions/{}/revoke", other_session_id)));
+}
+
+#[tokio::test]
+async fn revoking_a_session_logs_it_out() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    let other_client = app
+        .login_from_another_device(&app.test_user, OTHER_USER_AGENT)
+        .await;
+    let other_session_id = get_session_id(&app, OTHER_USER_AGENT).await;
+
+    let response = app.post_admin_revoke_session(other_session_id).await;
+    assert_is_redirect_to(&response, "/admin/sessions");
+
+    let html_page = app.get_admin_sessions_html().await;
+    assert!(html_page.contains("The session has been revoked"));
+    assert!(!html_page.contains(OTHER_USER_AGENT));
+
+    assert!(!is_logged_in(&app, &other_client).await);
+    assert!(is_logged_in(&app, &app.http_client).await);
+}
+
+#[tokio::test]
+async fn the_sessions_of_other_users_cannot_be_revoked() {
+    let app = spawn_app().await;
+    let other_user = crate::helpers::TestUser::generate_with_role("owner");
+    other_user.store(&app.pool).await;
+
+    app.login_as(&app.test_user).await;
+    let other_client = app
+        .login_from_another_device(&other_user, OTHER_USER_AGENT)
+        .await;
+    let other_session_id = get_session_id(&app, OTHER_USER_AGENT).await;
+
+    let response = app.post_admin_revoke_session(other_session_id).await;
+    assert_is_redirect_to(&response, "/admin/sessions");
+
+    let html_page = app.get_admin_sessions_html().await;
+    assert!(html_page.contains("This session doesn&#x27;t exist anymore"));
+    assert!(is_logged_in(&app, &other_client).await);
+}
+
+#[tokio::test]
+async fn logging_out_everywhere_else_keeps_the_current_session() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    let other_client = app
+        .login_from_another_device(&app.test_user, OTHER_USER_AGENT)
+        .await;
+
+    let response = app.post_admin_revoke_other_sessions().await;
+    assert_is_redirect_to(&response, "/admin/sessions");
+
+    let html_page = app.get_admin_sessions_html().await;
+    assert!(html_page.contains("You have been logged out everywhere else"));
+
+    assert!(!is_logged_in(&app, &other_client).await);
+    assert!(is_logged_in(&app, &app.http_client).await);
+}
+
+#[tokio::test]
+async fn an_idle_session_is_logged_out() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+
+    let idle_timeout = time::Duration::seconds(app.configuration.session.idle_timeout_seconds);
+    sqlx::query!(
+        "UPDATE sessions SET last_seen_at = $1",
+        time::OffsetDateTime::now_utc() - idle_timeout - time::Duration::minutes(1),
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn a_session_past_its_max_lifetime_is_logged_out_even_if_active() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+
+    let max_lifetime = time::Duration::seconds(app.configuration.session.max_lifetime_seconds);
+    sqlx::query!(
+        "UPDATE sessions SET created_at = $1",
+        time::OffsetDateTime::now_utc() - max_lifetime - time::Duration::minutes(1),
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,270 @@
+use crate::helpers::{assert_is_redirect_to, create_unconfirmed_subscriber};
+use crate::helpers::{spawn_app, TestApp, TestUser};
+use time::OffsetDateTime;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn insert_subscriber(app: &TestApp, email: &str, name: &str, status: &str) -> Uuid {
+    insert_subscriber_at(app, email, name, status, OffsetDateTime::now_utc()).await
+}
+
+async fn insert_subscriber_at(
+    app: &TestApp,
+    email: &str,
+    name: &str,
+    status: &str,
+    subscribed_at: OffsetDateTime,
+) -> Uuid {
+    let subscriber_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions(id, email, name, subscribed_at, status)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        subscriber_id,
+        email,
+        name,
+        subscribed_at,
+        status,
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+    subscriber_id
+}
+
+async fn get_status(app: &TestApp, subscriber_id: Uuid) -> Option<String> {
+    sqlx::query_scalar!(
+        "SELECT status FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_optional(&app.pool)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_subscribers() {
+    let app = spawn_app().await;
+
+    let response = app.get_admin_subscribers("").await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn subscribers_can_be_filtered_by_status_and_searched() {
+    let app = spawn_app().await;
+    insert_subscriber(&app, "ursula@example.com", "Ursula Le Guin", "confirmed").await;
+    insert_subscriber(&app, "octavia@example.com", "Octavia Butler", "confirmed").await;
+    insert_subscriber(
+        &app,
+        "frank@example.com",
+        "Frank Herbert",
+        "pending_confirmation",
+    )
+    .await;
+    app.login_as(&app.test_user).await;
+
+    let html_page = app.get_admin_subscribers_html("").await;
+    assert!(html_page.contains("3 subscriber(s)"));
+
+    let html_page = app.get_admin_subscribers_html("?status=confirmed").await;
+    assert!(html_page.contains("ursula@example.com"));
+    assert!(html_page.contains("octavia@example.com"));
+    assert!(!html_page.contains("frank@example.com"));
+
+    // Both the email and the name are searched, whatever their case
+    let html_page = app.get_admin_subscribers_html("?search=le+GUIN").await;
+    assert!(html_page.contains("ursula@example.com"));
+    assert!(!html_page.contains("octavia@example.com"));
+    let html_page = app.get_admin_subscribers_html("?search=frank%40").await;
+    assert!(html_page.contains("frank@example.com"));
+    assert!(!html_page.contains("ursula@example.com"));
+
+    // Wildcards are matched literally
+    let html_page = app.get_admin_subscribers_html("?search=%25").await;
+    assert!(html_page.contains("No subscribers."));
+}
+
+#[tokio::test]
+async fn subscribers_are_sorted_and_paginated() {
+    let app = spawn_app().await;
+    let now = OffsetDateTime::now_utc();
+    for i in 0..55 {
+        insert_subscriber_at(
+            &app,
+            &format!("subscriber{:02}@example.com", i),
+            "Subscriber",
+            "confirmed",
+            now - time::Duration::minutes(i),
+        )
+        .await;
+    }
+    app.login_as(&app.test_user).await;
+
+    // Newest first by default
+    let html_page = app.get_admin_subscribers_html("").await;
+    assert!(html_page.contains("subscriber00@example.com"));
+    assert!(!html_page.contains("subscriber54@example.com"));
+    assert!(html_page.contains("Page 1 of 2"));
+    assert!(html_page.contains(r#"href="/admin/subscribers?sort=newest&amp;page=2""#));
+
+    let html_page = app.get_admin_subscribers_html("?page=2").await;
+    assert!(html_page.contains("subscriber54@example.com"));
+    assert!(!html_page.contains("subscriber00@example.com"));
+
+    // A page past the last one shows the last one
+    let html_page = app
+        .get_admin_subscribers_html("?page=9223372036854775807")
+        .await;
+    assert!(html_page.contains("subscriber54@example.com"));
+    assert!(html_page.contains("Page 2 of 2"));
+
+    let html_page = app.get_admin_subscribers_html("?sort=oldest").await;
+    let oldest = html_page.find("subscriber54@example.com").unwrap();
+    let newer = html_page.find("subscriber10@example.com").unwrap();
+    assert!(oldest < newer);
+}
+
+#[tokio::test]
+async fn a_pending_subscriber_can_be_confirmed_by_hand() {
+    let app = spawn_app().await;
+    let subscriber_id =
+        insert_subscriber(&app, "ursula@example.com", "Ursula", "pending_confirmation").await;
+    app.login_as(&app.test_user).await;
+
+    let response = app
+        .post_admin_subscriber_action(subscriber_id, "confirm", "?status=pending_confirmation")
+        .await;
+    assert_is_redirect_to(&response, "/admin/subscribers?status=pending_confirmation");
+    assert_eq!(get_status(&app, subscriber_id).await.unwrap(), "confirmed");
+
+    let html_page = app.get_admin_subscribers_html("").await;
+    assert!(html_page.contains("The subscriber has been confirmed"));
+
+    // Confirming again makes no sense
+    app.post_admin_subscriber_action(subscriber_id, "confirm", "")
+        .await;
+    let html_page = app.get_admin_subscribers_html("").await;
+    assert!(html_page.contains("Only pending subscribers can be confirmed"));
+}
+
+#[tokio::test]
+async fn a_subscriber_can_be_unsubscribed_by_hand() {
+    let app = spawn_app().await;
+    let subscriber_id = insert_subscriber(&app, "ursula@example.com", "Ursula", "confirmed").await;
+    app.login_as(&app.test_user).await;
+
+    let response = app
+        .post_admin_subscriber_action(subscriber_id, "unsubscribe", "")
+        .await;
+    assert_is_redirect_to(&response, "/admin/subscribers");
+    assert_eq!(
+        get_status(&app, subscriber_id).await.unwrap(),
+        "unsubscribed"
+    );
+
+    let html_page = app.get_admin_subscribers_html("").await;
+    assert!(html_page.contains("The subscriber has been unsubscribed"));
+}
+
+#[tokio::test]
+async fn the_confirmation_link_of_a_subscriber_changed_by_hand_cannot_be_used() {
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .id;
+    app.login_as(&app.test_user).await;
+
+    app.post_admin_subscriber_action(subscriber_id, "confirm", "")
+        .await;
+    app.post_admin_subscriber_action(subscriber_id, "unsubscribe", "")
+        .await;
+
+    // The link sent before the subscriber was confirmed by hand doesn't subscribe them again
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 410);
+    assert_eq!(
+        get_status(&app, subscriber_id).await.unwrap(),
+        "unsubscribed"
+    );
+}
+
+#[tokio::test]
+async fn a_subscriber_can_be_deleted() {
+    let app = spawn_app().await;
+    let subscriber_id = insert_subscriber(&app, "ursula@example.com", "Ursula", "confirmed").await;
+    app.login_as(&app.test_user).await;
+
+    let response = app
+        .post_admin_subscriber_action(subscriber_id, "delete", "")
+        .await;
+    assert_is_redirect_to(&response, "/admin/subscribers");
+    assert!(get_status(&app, subscriber_id).await.is_none());
+
+    let html_page = app.get_admin_subscribers_html("").await;
+    assert!(html_page.contains("The subscriber has been deleted"));
+
+    app.post_admin_subscriber_action(subscriber_id, "delete", "")
+        .await;
+    let html_page = app.get_admin_subscribers_html("").await;
+    assert!(html_page.contains("This subscriber doesn&#x27;t exist anymore"));
+}
+
+#[tokio::test]
+async fn the_confirmation_email_of_a_pending_subscriber_can_be_resent() {
+    let app = spawn_app().await;
+    let subscriber_id =
+        insert_subscriber(&app, "ursula@example.com", "Ursula", "pending_confirmation").await;
+    app.login_as(&app.test_user).await;
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_admin_subscriber_action(subscriber_id, "resend_confirmation", "")
+        .await;
+    assert_is_redirect_to(&response, "/admin/subscribers");
+
+    let html_page = app.get_admin_subscribers_html("").await;
+    assert!(html_page.contains("The confirmation email will be sent again"));
+
+    app.dispatch_pending_confirmation_emails().await;
+
+    // The new link confirms the subscriber
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    assert_eq!(get_status(&app, subscriber_id).await.unwrap(), "confirmed");
+}
+
+#[tokio::test]
+async fn viewers_can_see_the_subscribers_but_not_change_them() {
+    let app = spawn_app().await;
+    let subscriber_id = insert_subscriber(&app, "ursula@example.com", "Ursula", "confirmed").await;
+    let viewer = TestUser::generate_with_role("viewer");
+    viewer.store(&app.pool).await;
+    app.login_as(&viewer).await;
+
+    let html_page = app.get_admin_subscribers_html("").await;
+    assert!(html_page.contains("ursula@example.com"));
+    assert!(!html_page.contains("/delete"));
+
+    let response = app
+        .post_admin_subscriber_action(subscriber_id, "delete", "")
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+    assert!(get_status(&app, subscriber_id).await.is_some());
+}
@@ -0,0 +1,237 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp, TestUser};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const CSV: &str = "email,name\n\
+    ursula@example.com,Ursula Le Guin\n\
+    not-an-email,Octavia Butler\n\
+    frank@example.com,Frank Herbert\n\
+    ursula@example.com,Ursula again\n";
+
+async fn get_subscribers(app: &TestApp) -> Vec<(String, String)> {
+    sqlx::query!("SELECT email, status FROM subscriptions ORDER BY email")
+        .fetch_all(&app.pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|row| (row.email, row.status))
+        .collect()
+}
+
+#[tokio::test]
+async fn a_dry_run_reports_errors_without_importing_anything() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+
+    let response = app
+        .post_admin_import_subscribers(CSV, "send_confirmation", true)
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+
+    assert!(html_page.contains("2 subscriber(s) would be imported"));
+    assert!(html_page.contains("2 row(s) were skipped"));
+    assert!(html_page.contains("<td>3</td>"));
+    assert!(html_page.contains("not-an-email is not a valid subscriber email"));
+    assert!(html_page.contains("<td>5</td>"));
+    assert!(html_page.contains("This email address is repeated in the file"));
+
+    assert!(get_subscribers(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_are_imported_without_emails() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+
+    Mock::given(path("/emails"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_admin_import_subscribers(CSV, "confirmed", false)
+        .await;
+    app.dispatch_all_pending_emails().await;
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("2 subscriber(s) imported"));
+
+    assert_eq!(
+        get_subscribers(&app).await,
+        vec![
+            ("frank@example.com".to_string(), "confirmed".to_string()),
+            ("ursula@example.com".to_string(), "confirmed".to_string()),
+        ]
+    );
+
+    // Importing again skips the subscribers already on the list
+    let response = app
+        .post_admin_import_subscribers(CSV, "confirmed", false)
+        .await;
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("0 subscriber(s) imported"));
+    assert!(html_page.contains("This email address is already on the list"));
+}
+
+#[tokio::test]
+async fn pending_subscribers_are_imported_and_sent_a_confirmation_email() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_admin_import_subscribers(CSV, "send_confirmation", false)
+        .await;
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("2 subscriber(s) imported, pending their confirmation"));
+
+    // The emails are sent by the worker, after the report
+    assert!(app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .is_empty());
+    app.dispatch_all_pending_emails().await;
+
+    let subscribers = get_subscribers(&app).await;
+    assert_eq!(subscribers.len(), 2);
+    assert!(subscribers
+        .iter()
+        .all(|(_, status)| status == "pending_confirmation"));
+
+    // The emails have working confirmation links
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn a_file_without_the_expected_columns_is_rejected() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+
+    let response = app
+        .post_admin_import_subscribers(
+            "mail,full_name\nursula@example.com,Ursula\n",
+            "confirmed",
+            false,
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/subscribers/import");
+
+    let html_page = app.get_admin_import_subscribers_html().await;
+    assert!(html_page.contains("The CSV file must have an email and a name column"));
+}
+
+#[tokio::test]
+async fn a_file_larger_than_the_limit_is_rejected() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+
+    let mut csv = String::from("email,name\n");
+    while csv.len() <= 10 * 1024 * 1024 {
+        csv.push_str("ursula@example.com,Ursula Le Guin\n");
+    }
+
+    let response = app
+        .post_admin_import_subscribers(&csv, "confirmed", false)
+        .await;
+    assert_is_redirect_to(&response, "/admin/subscribers/import");
+
+    let html_page = app.get_admin_import_subscribers_html().await;
+    assert!(html_page.contains("The CSV file is too large"));
+    assert!(get_subscribers(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn viewers_cannot_import_subscribers() {
+    let app = spawn_app().await;
+    let viewer = TestUser::generate_with_role("viewer");
+    viewer.store(&app.pool).await;
+    app.login_as(&viewer).await;
+
+    let response = app
+        .post_admin_import_subscribers(CSV, "confirmed", false)
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+    assert!(get_subscribers(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn subscribers_are_exported_as_csv_filtered_by_status() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    app.post_admin_import_subscribers(
+        "email,name\nursula@example.com,\"Le Guin, Ursula\"\nfrank@example.com,Frank\n",
+        "confirmed",
+        false,
+    )
+    .await;
+    sqlx::query!(
+        "UPDATE subscriptions SET status = 'unsubscribed' WHERE email = 'frank@example.com'"
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let response = app.get_admin_export_subscribers("").await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/csv"));
+    let csv = response.text().await.unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], "email,name,status,subscribed_at");
+    assert!(csv.contains("ursula@example.com,\"Le Guin, Ursula\",confirmed,"));
+    assert!(csv.contains("frank@example.com,Frank,unsubscribed,"));
+
+    let response = app.get_admin_export_subscribers("?status=confirmed").await;
+    assert_eq!(
+        response.headers()["Content-Disposition"],
+        "attachment; filename=\"subscribers-confirmed.csv\""
+    );
+    let csv = response.text().await.unwrap();
+    assert_eq!(csv.lines().count(), 2);
+    assert!(!csv.contains("frank@example.com"));
+}
+
+#[tokio::test]
+async fn large_exports_are_complete() {
+    let app = spawn_app().await;
+    // More than one batch of the export
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions(id, email, name, subscribed_at, status)
+        SELECT gen_random_uuid(), 'subscriber' || i || '@example.com', 'Subscriber', now(), 'confirmed'
+        FROM generate_series(1, 1234) AS i
+        "#
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+    app.login_as(&app.test_user).await;
+
+    let csv = app
+        .get_admin_export_subscribers("")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(csv.lines().count(), 1235);
+    let unique: std::collections::HashSet<&str> = csv.lines().collect();
+    assert_eq!(unique.len(), 1235);
+}
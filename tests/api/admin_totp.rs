@@ -0,0 +1,242 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, LoginBody, TestApp};
+use secrecy::{ExposeSecret, Secret};
+use time::OffsetDateTime;
+use zero2prod::authentication::{enable_totp, TotpSecret};
+
+/// Enables the second factor of the test user, returning its secret and recovery codes.
+async fn enable_totp_for_test_user(app: &TestApp) -> (TotpSecret, Vec<Secret<String>>) {
+    let secret = TotpSecret::generate();
+    let hashing = app.configuration.password_hashing.hashing().unwrap();
+    let recovery_codes = enable_totp(&app.pool, &hashing, app.test_user.user_id, &secret)
+        .await
+        .unwrap();
+    (secret, recovery_codes)
+}
+
+async fn login_with_password(app: &TestApp) -> reqwest::Response {
+    app.post_login(&LoginBody {
+        username: app.test_user.username.clone(),
+        password: app.test_user.password.clone(),
+    })
+    .await
+}
+
+#[tokio::test]
+async fn enrolling_requires_a_valid_code_and_shows_recovery_codes() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+
+    let html_page = app.get_admin_totp_html().await;
+    assert!(html_page.contains("Two-factor authentication is disabled"));
+
+    let response = app.post_admin_totp("enroll", &[]).await;
+    assert_is_redirect_to(&response, "/admin/totp/enroll");
+
+    let html_page = app
+        .http_client
+        .get(format!("{}/admin/totp/enroll", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("otpauth://totp/zero2prod:"));
+
+    // Extract the secret shown to the user
+    let secret = html_page
+        .split("<code>")
+        .nth(1)
+        .and_then(|s| s.split("</code>").next())
+        .unwrap();
+    let secret = TotpSecret::from_base32(secret).unwrap();
+
+    // 1) A wrong code is rejected
+    let response = app.post_admin_totp("confirm", &[("code", "000000")]).await;
+    assert_is_redirect_to(&response, "/admin/totp/enroll");
+
+    let row = sqlx::query!(
+        "SELECT totp_secret FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap();
+    assert!(row.totp_secret.is_none());
+
+    // 2) The right code enables the second factor
+    let code = secret.code_at(OffsetDateTime::now_utc());
+    let response = app.post_admin_totp("confirm", &[("code", &code)]).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Keep these recovery codes somewhere safe"));
+    assert_eq!(html_page.matches("<li><code>").count(), 10);
+
+    let html_page = app.get_admin_totp_html().await;
+    assert!(html_page.contains("You have 10 unused recovery codes left"));
+
+    let row = sqlx::query!(
+        "SELECT totp_secret FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(row.totp_secret, Some(secret.to_base32()));
+}
+
+#[tokio::test]
+async fn recovery_codes_are_stored_hashed() {
+    let app = spawn_app().await;
+    let (_, recovery_codes) = enable_totp_for_test_user(&app).await;
+
+    let hashes = sqlx::query_scalar!(
+        "SELECT code_hash FROM user_recovery_codes WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_all(&app.pool)
+    .await
+    .unwrap();
+
+    assert_eq!(hashes.len(), recovery_codes.len());
+    for hash in hashes {
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(!recovery_codes
+            .iter()
+            .any(|code| hash.contains(code.expose_secret())));
+    }
+}
+
+#[tokio::test]
+async fn a_half_authenticated_session_can_not_access_the_admin_area() {
+    let app = spawn_app().await;
+    enable_totp_for_test_user(&app).await;
+
+    let response = login_with_password(&app).await;
+    assert_is_redirect_to(&response, "/login/totp");
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn login_requires_a_valid_totp_code_when_enabled() {
+    let app = spawn_app().await;
+    let (secret, _) = enable_totp_for_test_user(&app).await;
+
+    let response = login_with_password(&app).await;
+    assert_is_redirect_to(&response, "/login/totp");
+
+    // 1) A wrong code is rejected
+    let response = app.post_login_totp("000000").await;
+    assert_is_redirect_to(&response, "/login/totp");
+
+    let html_page = app
+        .http_client
+        .get(format!("{}/login/totp", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("The code is invalid"));
+
+    // 2) The right code completes the login
+    let response = app
+        .post_login_totp(&secret.code_at(OffsetDateTime::now_utc()))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn a_totp_code_can_not_be_replayed() {
+    let app = spawn_app().await;
+    let (secret, _) = enable_totp_for_test_user(&app).await;
+
+    let code = secret.code_at(OffsetDateTime::now_utc());
+
+    login_with_password(&app).await;
+    let response = app.post_login_totp(&code).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    app.post_logout().await;
+
+    login_with_password(&app).await;
+    let response = app.post_login_totp(&code).await;
+    assert_is_redirect_to(&response, "/login/totp");
+}
+
+#[tokio::test]
+async fn a_recovery_code_can_be_used_once() {
+    let app = spawn_app().await;
+    let (_, recovery_codes) = enable_totp_for_test_user(&app).await;
+    let recovery_code = recovery_codes[3].expose_secret();
+
+    login_with_password(&app).await;
+    let response = app.post_login_totp(recovery_code).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let html_page = app.get_admin_totp_html().await;
+    assert!(html_page.contains("You have 9 unused recovery codes left"));
+
+    app.post_logout().await;
+
+    login_with_password(&app).await;
+    let response = app.post_login_totp(recovery_code).await;
+    assert_is_redirect_to(&response, "/login/totp");
+}
+
+#[tokio::test]
+async fn the_second_step_requires_a_verified_password() {
+    let app = spawn_app().await;
+
+    let response = app
+        .http_client
+        .get(format!("{}/login/totp", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app.post_login_totp("000000").await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn disabling_the_second_factor_requires_the_current_password() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+    enable_totp_for_test_user(&app).await;
+
+    // 1) A wrong password is rejected
+    let response = app
+        .post_admin_totp("disable", &[("current_password", "wrong-password")])
+        .await;
+    assert_is_redirect_to(&response, "/admin/totp");
+
+    let html_page = app.get_admin_totp_html().await;
+    assert!(html_page.contains("The current password is incorrect"));
+    assert!(html_page.contains("Two-factor authentication is enabled"));
+
+    // 2) The right password disables the second factor
+    let response = app
+        .post_admin_totp(
+            "disable",
+            &[("current_password", app.test_user.password.as_str())],
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/totp");
+
+    let html_page = app.get_admin_totp_html().await;
+    assert!(html_page.contains("Two-factor authentication has been disabled"));
+
+    // 3) The password is enough to log in again
+    app.post_logout().await;
+    let response = login_with_password(&app).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
@@ -0,0 +1,402 @@
+use crate::helpers::{
+    assert_is_redirect_to, spawn_app, AcceptInvitationBody, CreateUserBody, InviteUserBody,
+    LoginBody, SubmitNewsletterBody, TestUser,
+};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn newsletter_body() -> SubmitNewsletterBody {
+    SubmitNewsletterBody {
+        title: "Newsletter title".to_string(),
+        html_content: "<p>Newsletter body as HTML</p>".to_string(),
+        text_content: "Newsletter body as plain text".to_string(),
+        idempotency_key: Uuid::new_v4(),
+    }
+}
+
+#[tokio::test]
+async fn must_be_logged_in_to_manage_users() {
+    let app = spawn_app().await;
+
+    let response = app.get_admin_users().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn a_viewer_can_see_the_dashboard_but_not_publish_a_newsletter() {
+    let app = spawn_app().await;
+
+    let viewer = TestUser::generate_with_role("viewer");
+    viewer.store(&app.pool).await;
+    app.login_as(&viewer).await;
+
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", viewer.username)));
+    assert!(!html_page.contains("Manage users"));
+
+    let response = app.get_admin_newsletters().await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app.post_admin_newsletters(&newsletter_body()).await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = app.get_admin_users().await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    // Viewers can still manage their own account
+    let response = app.post_logout().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn an_editor_can_publish_a_newsletter_but_not_manage_users() {
+    let app = spawn_app().await;
+
+    let editor = TestUser::generate_with_role("editor");
+    editor.store(&app.pool).await;
+    app.login_as(&editor).await;
+
+    let response = app.post_admin_newsletters(&newsletter_body()).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    let response = app.get_admin_users().await;
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn an_owner_can_create_a_user_who_can_then_log_in() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains("Manage users"));
+
+    let body = CreateUserBody {
+        username: "jane".to_string(),
+        password: "hunter2-on-a-windy-hill".to_string(),
+        password_check: "hunter2-on-a-windy-hill".to_string(),
+        role: "editor",
+    };
+    let response = app.post_admin_create_user(&body).await;
+    assert_is_redirect_to(&response, "/admin/users");
+
+    let html_page = app.get_admin_users_html().await;
+    assert!(html_page.contains("The user jane has been created"));
+
+    let row = sqlx::query!("SELECT role FROM users WHERE username = 'jane'")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(row.role, "editor");
+
+    // The new user can log in
+    app.post_logout().await;
+    let response = app
+        .post_login(&LoginBody {
+            username: body.username,
+            password: body.password,
+        })
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn creating_a_user_with_a_taken_username_is_rejected() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+
+    let response = app
+        .post_admin_create_user(&CreateUserBody {
+            username: app.test_user.username.clone(),
+            password: "velvet-otter-by-the-river".to_string(),
+            password_check: "velvet-otter-by-the-river".to_string(),
+            role: "viewer",
+        })
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+
+    let html_page = app.get_admin_users_html().await;
+    assert!(html_page.contains("This username is already taken"));
+}
+
+#[tokio::test]
+async fn creating_a_user_with_mismatched_passwords_is_rejected() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+
+    let response = app
+        .post_admin_create_user(&CreateUserBody {
+            username: "jane".to_string(),
+            password: "velvet-otter-by-the-river".to_string(),
+            password_check: "another-otter-by-the-river".to_string(),
+            role: "viewer",
+        })
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+
+    let html_page = app.get_admin_users_html().await;
+    assert!(html_page.contains("You entered two different new passwords"));
+
+    let n_users = sqlx::query_scalar!("SELECT COUNT(*) FROM users WHERE username = 'jane'")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(n_users, Some(0));
+}
+
+#[tokio::test]
+async fn an_owner_can_change_the_role_of_another_user() {
+    let app = spawn_app().await;
+
+    let viewer = TestUser::generate_with_role("viewer");
+    viewer.store(&app.pool).await;
+
+    app.login_as(&app.test_user).await;
+    let response = app
+        .post_admin_user_action(viewer.user_id, "role", &[("role", "editor")])
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+
+    // The new role applies to the next requests of the user
+    app.login_as(&viewer).await;
+    let response = app.post_admin_newsletters(&newsletter_body()).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+}
+
+#[tokio::test]
+async fn a_disabled_user_can_not_log_in_until_enabled_again() {
+    let app = spawn_app().await;
+
+    let viewer = TestUser::generate_with_role("viewer");
+    viewer.store(&app.pool).await;
+
+    app.login_as(&app.test_user).await;
+    let response = app
+        .post_admin_user_action(viewer.user_id, "disable", &[])
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+
+    let login_body = LoginBody {
+        username: viewer.username.clone(),
+        password: viewer.password.clone(),
+    };
+
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/login");
+
+    app.login_as(&app.test_user).await;
+    let response = app
+        .post_admin_user_action(viewer.user_id, "enable", &[])
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn the_session_of_a_disabled_user_is_rejected() {
+    let app = spawn_app().await;
+
+    let viewer = TestUser::generate_with_role("viewer");
+    viewer.store(&app.pool).await;
+    app.login_as(&viewer).await;
+
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    sqlx::query!(
+        "UPDATE users SET disabled_at = now() WHERE user_id = $1",
+        viewer.user_id
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn a_deleted_user_can_not_log_in() {
+    let app = spawn_app().await;
+
+    let viewer = TestUser::generate_with_role("viewer");
+    viewer.store(&app.pool).await;
+
+    app.login_as(&app.test_user).await;
+    let response = app
+        .post_admin_user_action(viewer.user_id, "delete", &[])
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+
+    let html_page = app.get_admin_users_html().await;
+    assert!(html_page.contains("The user has been deleted"));
+    assert!(!html_page.contains(&viewer.username));
+
+    let response = app
+        .post_login(&LoginBody {
+            username: viewer.username.clone(),
+            password: viewer.password.clone(),
+        })
+        .await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn an_owner_can_not_disable_or_delete_their_own_account() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+
+    for action in ["disable", "delete"] {
+        let response = app
+            .post_admin_user_action(app.test_user.user_id, action, &[])
+            .await;
+        assert_is_redirect_to(&response, "/admin/users");
+
+        let html_page = app.get_admin_users_html().await;
+        assert!(html_page.contains("You can&#x27;t change your own account from this page"));
+    }
+
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn an_invited_user_can_create_their_account_once() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_admin_invite_user(&InviteUserBody {
+            email: "jane@example.com".to_string(),
+            role: "viewer",
+        })
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+
+    let html_page = app.get_admin_users_html().await;
+    assert!(html_page.contains("An invitation will be sent to jane@example.com"));
+
+    app.dispatch_pending_invitations().await;
+
+    // Follow the link in the invitation email
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let invitation_link = app.get_confirmation_links(email_request).html;
+    assert_eq!(invitation_link.path(), "/invitations/accept");
+
+    app.post_logout().await;
+
+    let response = app
+        .http_client
+        .get(invitation_link.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("jane@example.com"));
+
+    let invitation_token = invitation_link
+        .query_pairs()
+        .find(|(k, _)| k == "invitation_token")
+        .map(|(_, v)| v.into_owned())
+        .unwrap();
+
+    // Only the hash of the token is stored
+    let stored = sqlx::query!("SELECT token_hash FROM user_invitations")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert!(!stored.token_hash.contains(&invitation_token));
+
+    let body = AcceptInvitationBody {
+        invitation_token,
+        username: "jane".to_string(),
+        password: "velvet-otter-by-the-river".to_string(),
+        password_check: "velvet-otter-by-the-river".to_string(),
+    };
+
+    let response = app.post_accept_invitation(&body).await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app
+        .post_login(&LoginBody {
+            username: "jane".to_string(),
+            password: "velvet-otter-by-the-river".to_string(),
+        })
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let response = app.post_admin_newsletters(&newsletter_body()).await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    // The invitation can't be used twice
+    let response = app.post_accept_invitation(&body).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app.http_client.get(invitation_link).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn a_failed_invitation_email_is_retried() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_admin_invite_user(&InviteUserBody {
+            email: "jane@example.com".to_string(),
+            role: "editor",
+        })
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+
+    app.dispatch_pending_invitations().await;
+
+    let n_invitations = sqlx::query!(r#"SELECT count(*) AS "n!" FROM user_invitations"#)
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_invitations, 1);
+
+    // Mock verifies on Drop that we have tried twice
+}
+
+#[tokio::test]
+async fn an_unknown_invitation_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_accept_invitation(&AcceptInvitationBody {
+            invitation_token: "doesnotexist".to_string(),
+            username: "jane".to_string(),
+            password: "velvet-otter-by-the-river".to_string(),
+            password_check: "velvet-otter-by-the-river".to_string(),
+        })
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+}
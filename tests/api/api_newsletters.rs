@@ -0,0 +1,166 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app};
+use serde_json::json;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn issue_body(action: &str) -> serde_json::Value {
+    json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "action": action,
+    })
+}
+
+#[tokio::test]
+async fn creating_an_issue_enqueues_its_deliveries() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let token = app.create_api_token(&app.test_user).await;
+
+    let response = app
+        .api_post(&token, "/newsletters", &issue_body("publish"))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "published");
+    assert_eq!(body["deliveries"]["queued"], 1);
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let path = format!("/newsletters/{}", body["id"].as_str().unwrap());
+    let body: serde_json::Value = app.api_get(&token, &path).await.json().await.unwrap();
+    assert_eq!(body["deliveries"]["queued"], 0);
+    assert_eq!(body["deliveries"]["sent"], 1);
+}
+
+#[tokio::test]
+async fn creating_an_issue_is_idempotent() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let token = app.create_api_token(&app.test_user).await;
+
+    let send = || {
+        reqwest::Client::new()
+            .post(format!("{}/api/v1/newsletters", &app.address))
+            .bearer_auth(&token)
+            .header("Idempotency-Key", "a-unique-key")
+            .json(&issue_body("publish"))
+            .send()
+    };
+
+    let first: serde_json::Value = send().await.unwrap().json().await.unwrap();
+    let response = send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 201);
+    let second: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(first, second);
+
+    let issues = sqlx::query_scalar!(r#"SELECT COUNT(*) as "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(issues, 1);
+}
+
+#[tokio::test]
+async fn a_draft_can_be_published_once() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let token = app.create_api_token(&app.test_user).await;
+
+    let body: serde_json::Value = app
+        .api_post(&token, "/newsletters", &issue_body("draft"))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["status"], "draft");
+    assert_eq!(body["deliveries"]["queued"], 0);
+    let path = format!("/newsletters/{}/publish", body["id"].as_str().unwrap());
+
+    let response = app.api_post(&token, &path, &json!({})).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "published");
+    assert_eq!(body["deliveries"]["queued"], 1);
+
+    let response = app.api_post(&token, &path, &json!({})).await;
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn a_draft_can_be_scheduled() {
+    let app = spawn_app().await;
+    let token = app.create_api_token(&app.test_user).await;
+
+    let body: serde_json::Value = app
+        .api_post(&token, "/newsletters", &issue_body("draft"))
+        .await
+        .json()
+        .await
+        .unwrap();
+    let path = format!("/newsletters/{}/publish", body["id"].as_str().unwrap());
+
+    let response = app
+        .api_post(&token, &path, &json!({"send_at": "2999-01-01T10:00:00Z"}))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "scheduled");
+    assert_eq!(body["scheduled_for"], "2999-01-01T10:00:00Z");
+
+    // Sending in the past makes no sense
+    let response = app
+        .api_post(
+            &token,
+            "/newsletters",
+            &json!({
+                "title": "Newsletter title",
+                "text_content": "Newsletter body as plain text",
+                "html_content": "<p>Newsletter body as HTML</p>",
+                "send_at": "2000-01-01T10:00:00Z",
+            }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn an_unknown_issue_is_not_found() {
+    let app = spawn_app().await;
+    let token = app.create_api_token(&app.test_user).await;
+    let path = format!("/newsletters/{}", uuid::Uuid::new_v4());
+
+    let response = app.api_get(&token, &path).await;
+    assert_eq!(response.status().as_u16(), 404);
+    let response = app
+        .api_post(&token, &format!("{}/publish", path), &json!({}))
+        .await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn stats_count_subscribers_issues_and_deliveries() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let token = app.create_api_token(&app.test_user).await;
+    app.api_post(&token, "/newsletters", &issue_body("draft"))
+        .await;
+    app.api_post(&token, "/newsletters", &issue_body("publish"))
+        .await;
+
+    let response = app.api_get(&token, "/stats").await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["subscribers"]["confirmed"], 1);
+    assert_eq!(body["subscribers"]["pending_confirmation"], 0);
+    assert_eq!(body["issues"]["draft"], 1);
+    assert_eq!(body["issues"]["published"], 1);
+    assert_eq!(body["deliveries"]["queued"], 1);
+}
@@ -0,0 +1,204 @@
+use crate::helpers::{create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app};
+use serde_json::json;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn subscribers_are_listed_and_filtered_by_status() {
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let token = app.create_api_token(&app.test_user).await;
+
+    let body: serde_json::Value = app
+        .api_get(&token, "/subscribers")
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["total"], 3);
+    assert_eq!(body["subscribers"].as_array().unwrap().len(), 3);
+
+    let body: serde_json::Value = app
+        .api_get(&token, "/subscribers?status=confirmed&limit=1")
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["total"], 2);
+    let subscribers = body["subscribers"].as_array().unwrap();
+    assert_eq!(subscribers.len(), 1);
+    assert_eq!(subscribers[0]["status"], "confirmed");
+}
+
+#[tokio::test]
+async fn an_invalid_page_size_is_rejected() {
+    let app = spawn_app().await;
+    let token = app.create_api_token(&app.test_user).await;
+
+    let response = app.api_get(&token, "/subscribers?limit=0").await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn invalid_query_strings_are_rejected_with_a_json_error() {
+    let app = spawn_app().await;
+    let token = app.create_api_token(&app.test_user).await;
+    let test_cases = vec![
+        ("/subscribers?status=bogus", "unknown status"),
+        ("/subscribers?limit=abc", "non numeric limit"),
+    ];
+
+    for (path, description) in test_cases {
+        let response = app.api_get(&token, path).await;
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not reject a query with a {}",
+            description
+        );
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert!(body["error"].is_string());
+    }
+}
+
+#[tokio::test]
+async fn an_invalid_subscriber_id_is_rejected_with_a_json_error() {
+    let app = spawn_app().await;
+    let token = app.create_api_token(&app.test_user).await;
+
+    let response = app.api_get(&token, "/subscribers/not-a-uuid").await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body["error"].is_string());
+}
+
+#[tokio::test]
+async fn creating_a_pending_subscriber_sends_a_confirmation_email() {
+    let app = spawn_app().await;
+    let token = app.create_api_token(&app.test_user).await;
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .api_post(
+            &token,
+            "/subscribers",
+            &json!({"email": "ursula@example.com", "name": "Ursula"}),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+    app.dispatch_pending_confirmation_emails().await;
+    let location = response.headers().get("Location").unwrap().clone();
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["email"], "ursula@example.com");
+    assert_eq!(body["status"], "pending_confirmation");
+    assert_eq!(
+        location,
+        format!("/api/v1/subscribers/{}", body["id"].as_str().unwrap()).as_str()
+    );
+
+    // The subscriber can then be fetched
+    let response = app
+        .api_get(
+            &token,
+            location.to_str().unwrap().trim_start_matches("/api/v1"),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let fetched: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(fetched, body);
+}
+
+#[tokio::test]
+async fn creating_a_confirmed_subscriber_sends_no_email() {
+    let app = spawn_app().await;
+    let token = app.create_api_token(&app.test_user).await;
+
+    Mock::given(path("/emails"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .api_post(
+            &token,
+            "/subscribers",
+            &json!({"email": "ursula@example.com", "name": "Ursula", "confirmed": true}),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+    app.dispatch_pending_confirmation_emails().await;
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "confirmed");
+}
+
+#[tokio::test]
+async fn creating_a_subscriber_twice_is_a_conflict() {
+    let app = spawn_app().await;
+    let token = app.create_api_token(&app.test_user).await;
+    let body = json!({"email": "ursula@example.com", "name": "Ursula", "confirmed": true});
+
+    let response = app.api_post(&token, "/subscribers", &body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let response = app.api_post(&token, "/subscribers", &body).await;
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn invalid_subscribers_are_rejected() {
+    let app = spawn_app().await;
+    let token = app.create_api_token(&app.test_user).await;
+    let test_cases = vec![
+        (
+            json!({"email": "not-an-email", "name": "Ursula"}),
+            "invalid email",
+        ),
+        (
+            json!({"email": "ursula@example.com", "name": ""}),
+            "empty name",
+        ),
+        (json!({"email": "ursula@example.com"}), "missing name"),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app.api_post(&token, "/subscribers", &body).await;
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not reject a subscriber with an {}",
+            description
+        );
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert!(body["error"].is_string());
+    }
+}
+
+#[tokio::test]
+async fn a_subscriber_can_be_deleted() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let token = app.create_api_token(&app.test_user).await;
+    let subscriber_id = sqlx::query_scalar!("SELECT id FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    let path = format!("/subscribers/{}", subscriber_id);
+
+    let response = app.api_delete(&token, &path).await;
+    assert_eq!(response.status().as_u16(), 204);
+
+    let response = app.api_get(&token, &path).await;
+    assert_eq!(response.status().as_u16(), 404);
+    let response = app.api_delete(&token, &path).await;
+    assert_eq!(response.status().as_u16(), 404);
+}
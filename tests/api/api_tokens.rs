@@ -0,0 +1,161 @@
+use crate::helpers::{assert_is_redirect_to, extract_api_token, spawn_app, TestUser};
+use uuid::Uuid;
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_api_tokens() {
+    let app = spawn_app().await;
+
+    let response = app.post_admin_create_api_token("CI").await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn an_api_token_is_shown_once_and_only_its_hash_is_stored() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+
+    let response = app.post_admin_create_api_token("CI").await;
+    assert_eq!(response.status().as_u16(), 200);
+    let token = extract_api_token(&response.text().await.unwrap());
+
+    let stored = sqlx::query!("SELECT name, token_hash FROM api_tokens")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(stored.name, "CI");
+    assert!(!stored.token_hash.contains(&token[4..]));
+
+    let html_page = app.get_admin_api_tokens_html().await;
+    assert!(html_page.contains("CI"));
+    assert!(!html_page.contains(&token));
+}
+
+#[tokio::test]
+async fn an_api_token_must_have_a_name() {
+    let app = spawn_app().await;
+    app.login_as(&app.test_user).await;
+
+    let response = app.post_admin_create_api_token("  ").await;
+    assert_is_redirect_to(&response, "/admin/api_tokens");
+
+    let html_page = app.get_admin_api_tokens_html().await;
+    assert!(html_page.contains("The name of the token is missing"));
+}
+
+#[tokio::test]
+async fn api_requests_without_a_valid_token_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/api/v1/subscribers", &app.address))
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        response.headers().get("WWW-Authenticate").unwrap(),
+        "Bearer"
+    );
+
+    let response = app.api_get("z2p_not-a-token", "/subscribers").await;
+    assert_eq!(response.status().as_u16(), 401);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"], "Invalid API token");
+}
+
+#[tokio::test]
+async fn a_valid_token_is_accepted_and_its_use_recorded() {
+    let app = spawn_app().await;
+    let token = app.create_api_token(&app.test_user).await;
+
+    let response = app.api_get(&token, "/subscribers").await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let last_used_at = sqlx::query_scalar!("SELECT last_used_at FROM api_tokens")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert!(last_used_at.is_some());
+
+    // It isn't recorded again right away
+    let response = app.api_get(&token, "/subscribers").await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let last_used_at_again = sqlx::query_scalar!("SELECT last_used_at FROM api_tokens")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(last_used_at_again, last_used_at);
+}
+
+#[tokio::test]
+async fn a_revoked_token_is_rejected() {
+    let app = spawn_app().await;
+    let token = app.create_api_token(&app.test_user).await;
+    let token_id = sqlx::query_scalar!("SELECT id FROM api_tokens")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+
+    app.login_as(&app.test_user).await;
+    let response = app.post_admin_revoke_api_token(token_id).await;
+    assert_is_redirect_to(&response, "/admin/api_tokens");
+    let html_page = app.get_admin_api_tokens_html().await;
+    assert!(html_page.contains("The API token has been revoked"));
+
+    let response = app.api_get(&token, "/subscribers").await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn the_tokens_of_other_users_cannot_be_revoked() {
+    let app = spawn_app().await;
+    let other_user = TestUser::generate_with_role("owner");
+    other_user.store(&app.pool).await;
+    let token = app.create_api_token(&other_user).await;
+    let token_id: Uuid = sqlx::query_scalar!("SELECT id FROM api_tokens")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+
+    app.login_as(&app.test_user).await;
+    app.post_admin_revoke_api_token(token_id).await;
+
+    let response = app.api_get(&token, "/subscribers").await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn the_tokens_of_a_disabled_user_are_rejected() {
+    let app = spawn_app().await;
+    let token = app.create_api_token(&app.test_user).await;
+
+    sqlx::query!(
+        "UPDATE users SET disabled_at = now() WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let response = app.api_get(&token, "/subscribers").await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn viewers_can_read_but_not_write_through_the_api() {
+    let app = spawn_app().await;
+    let viewer = TestUser::generate_with_role("viewer");
+    viewer.store(&app.pool).await;
+    let token = app.create_api_token(&viewer).await;
+
+    let response = app.api_get(&token, "/stats").await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app
+        .api_post(
+            &token,
+            "/subscribers",
+            &serde_json::json!({"email": "ursula@example.com", "name": "Ursula"}),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+}
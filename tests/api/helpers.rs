@@ -1,15 +1,30 @@
 use argon2::password_hash::SaltString;
 use argon2::{Argon2, PasswordHasher};
+use fake::faker::internet::en::SafeEmail;
+use fake::faker::name::en::Name;
+use fake::Fake;
 use once_cell::sync::Lazy;
-use sqlx::PgPool;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::{Connection, Executor, PgConnection, PgPool};
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
-use wiremock::MockServer;
-use zero2prod::configuration::get_configuration;
-use zero2prod::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
-use zero2prod::startup::Application;
-use zero2prod::startup::{get_connection_pool, get_tem_client};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::configuration::WorkerSettings;
+use zero2prod::configuration::{get_configuration, DatabaseSettings, Settings};
+use zero2prod::configuration::{EmailBackend, TEMSettings};
+use zero2prod::confirmation_email_queue::try_send_confirmation_email;
+use zero2prod::email_client::EmailSender;
+use zero2prod::invitation_queue::try_send_invitation;
+use zero2prod::issue_delivery_worker::{run_worker_until_stopped, try_execute_task};
+use zero2prod::issue_delivery_worker::{ExecutionOutcome, RetryPolicy};
+use zero2prod::issue_scheduler::{try_publish_scheduled_issue, SchedulingOutcome};
+use zero2prod::password_reset_queue::try_send_password_reset;
+use zero2prod::startup::{get_connection_pool, get_email_client};
+use zero2prod::startup::{Application, ApplicationBaseUrl, HmacSecret};
 use zero2prod::telemetry;
-use zero2prod::tem;
+use zero2prod::token_bucket::RateLimiter;
 
 static TRACING: Lazy<()> = Lazy::new(|| {
     let default_filter_level = "info".into();
@@ -39,10 +54,14 @@ pub struct TestApp {
     pub pool: PgPool,
 
     pub email_server: MockServer,
-    pub email_client: tem::Client,
+    pub email_client: Arc<dyn EmailSender>,
+    pub retry_policy: RetryPolicy,
     pub http_client: reqwest::Client,
 
     pub test_user: TestUser,
+    pub configuration: Settings,
+    pub shutdown: CancellationToken,
+    pub server_handle: tokio::task::JoinHandle<Result<(), anyhow::Error>>,
 }
 
 impl TestApp {
@@ -51,7 +70,19 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.http_client
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_resend_confirmation<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/subscriptions/confirm/resend", &self.address))
             .form(body)
             .send()
             .await
@@ -63,25 +94,82 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.http_client
-            .post(&format!("{}/login", &self.address))
+            .post(format!("{}/login", &self.address))
             .form(body)
             .send()
             .await
             .expect("Failed to execute request.")
     }
 
+    pub async fn login_as(&self, user: &TestUser) {
+        let response = self
+            .post_login(&LoginBody {
+                username: user.username.clone(),
+                password: user.password.clone(),
+            })
+            .await;
+        assert_is_redirect_to(&response, "/admin/dashboard");
+    }
+
+    /// Logs in with a new HTTP client, which gets a session of its own.
+    pub async fn login_from_another_device(
+        &self,
+        user: &TestUser,
+        user_agent: &str,
+    ) -> reqwest::Client {
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .cookie_store(true)
+            .user_agent(user_agent)
+            .build()
+            .unwrap();
+
+        let response = client
+            .post(format!("{}/login", &self.address))
+            .form(&LoginBody {
+                username: user.username.clone(),
+                password: user.password.clone(),
+            })
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        assert_is_redirect_to(&response, "/admin/dashboard");
+
+        client
+    }
+
+    pub async fn post_login_totp(&self, code: &str) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/login/totp", &self.address))
+            .form(&[("code", code)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn post_logout(&self) -> reqwest::Response {
         self.http_client
-            .post(&format!("{}/admin/logout", &self.address))
+            .post(format!("{}/admin/logout", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
     }
 
+    pub async fn get_home_html(&self) -> String {
+        let response = self
+            .http_client
+            .get(&self.address)
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        response.text().await.unwrap()
+    }
+
     pub async fn get_login_html(&self) -> String {
         let response = self
             .http_client
-            .get(&format!("{}/login", &self.address))
+            .get(format!("{}/login", &self.address))
             .send()
             .await
             .expect("Failed to execute request.");
@@ -91,7 +179,7 @@ impl TestApp {
 
     pub async fn get_admin_dashboard(&self) -> reqwest::Response {
         self.http_client
-            .get(&format!("{}/admin/dashboard", &self.address))
+            .get(format!("{}/admin/dashboard", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -102,9 +190,218 @@ impl TestApp {
         response.text().await.unwrap()
     }
 
+    pub async fn get_admin_sessions(&self) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/admin/sessions", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_sessions_html(&self) -> String {
+        let response = self.get_admin_sessions().await;
+        response.text().await.unwrap()
+    }
+
+    pub async fn post_admin_revoke_session(&self, session_id: Uuid) -> reqwest::Response {
+        self.http_client
+            .post(format!(
+                "{}/admin/sessions/{}/revoke",
+                &self.address, session_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_admin_revoke_other_sessions(&self) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/admin/sessions/revoke_others", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_api_tokens_html(&self) -> String {
+        self.http_client
+            .get(format!("{}/admin/api_tokens", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_admin_create_api_token(&self, name: &str) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/admin/api_tokens", &self.address))
+            .form(&[("name", name)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_admin_revoke_api_token(&self, token_id: Uuid) -> reqwest::Response {
+        self.http_client
+            .post(format!(
+                "{}/admin/api_tokens/{}/revoke",
+                &self.address, token_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Creates an API token for the user from a session of its own, and returns it.
+    pub async fn create_api_token(&self, user: &TestUser) -> String {
+        let client = self
+            .login_from_another_device(user, "API token creation")
+            .await;
+
+        let html_page = client
+            .post(format!("{}/admin/api_tokens", &self.address))
+            .form(&[("name", "Test token")])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .error_for_status()
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+
+        extract_api_token(&html_page)
+    }
+
+    pub async fn api_get(&self, token: &str, path: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/api/v1{}", &self.address, path))
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn api_post(
+        &self,
+        token: &str,
+        path: &str,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/api/v1{}", &self.address, path))
+            .bearer_auth(token)
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn api_delete(&self, token: &str, path: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .delete(format!("{}/api/v1{}", &self.address, path))
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_subscribers(&self, query: &str) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/admin/subscribers{}", &self.address, query))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_subscribers_html(&self, query: &str) -> String {
+        let response = self.get_admin_subscribers(query).await;
+        response.text().await.unwrap()
+    }
+
+    pub async fn post_admin_subscriber_action(
+        &self,
+        subscriber_id: Uuid,
+        action: &str,
+        query: &str,
+    ) -> reqwest::Response {
+        self.http_client
+            .post(format!(
+                "{}/admin/subscribers/{}/{}{}",
+                &self.address, subscriber_id, action, query
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_import_subscribers_html(&self) -> String {
+        self.http_client
+            .get(format!("{}/admin/subscribers/import", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// Uploads a CSV file with the import form, built by hand since it is multipart.
+    pub async fn post_admin_import_subscribers(
+        &self,
+        csv: &str,
+        mode: &str,
+        dry_run: bool,
+    ) -> reqwest::Response {
+        let boundary = "----zero2prod-test-boundary";
+
+        let mut body = format!(
+            "--{boundary}\r\n\
+            Content-Disposition: form-data; name=\"mode\"\r\n\r\n\
+            {mode}\r\n"
+        );
+        if dry_run {
+            body.push_str(&format!(
+                "--{boundary}\r\n\
+                Content-Disposition: form-data; name=\"dry_run\"\r\n\r\n\
+                on\r\n"
+            ));
+        }
+        body.push_str(&format!(
+            "--{boundary}\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"subscribers.csv\"\r\n\
+            Content-Type: text/csv\r\n\r\n\
+            {csv}\r\n\
+            --{boundary}--\r\n"
+        ));
+
+        self.http_client
+            .post(format!("{}/admin/subscribers/import", &self.address))
+            .header(
+                "Content-Type",
+                format!("multipart/form-data; boundary={}", boundary),
+            )
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_export_subscribers(&self, query: &str) -> reqwest::Response {
+        self.http_client
+            .get(format!(
+                "{}/admin/subscribers/export{}",
+                &self.address, query
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn get_admin_change_password(&self) -> reqwest::Response {
         self.http_client
-            .get(&format!("{}/admin/password", &self.address))
+            .get(format!("{}/admin/password", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -120,7 +417,7 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.http_client
-            .post(&format!("{}/admin/password", &self.address))
+            .post(format!("{}/admin/password", &self.address))
             .form(body)
             .send()
             .await
@@ -129,7 +426,7 @@ impl TestApp {
 
     pub async fn get_admin_newsletters(&self) -> reqwest::Response {
         self.http_client
-            .get(&format!("{}/admin/newsletters", &self.address))
+            .get(format!("{}/admin/newsletters", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -145,7 +442,296 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.http_client
-            .post(&format!("{}/admin/newsletters", &self.address))
+            .post(format!("{}/admin/newsletters", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_dead_letters(&self) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/admin/dead_letters", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_dead_letters_html(&self) -> String {
+        let response = self.get_admin_dead_letters().await;
+        response.text().await.unwrap()
+    }
+
+    pub async fn post_admin_requeue_dead_letter<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/admin/dead_letters/requeue", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_newsletter_issue(&self, issue_id: Uuid) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/admin/newsletters/{}", &self.address, issue_id))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_newsletter_issue_html(&self, issue_id: Uuid) -> String {
+        let response = self.get_admin_newsletter_issue(issue_id).await;
+        response.text().await.unwrap()
+    }
+
+    pub async fn post_admin_newsletter_issue<Body>(
+        &self,
+        issue_id: Uuid,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/admin/newsletters/{}", &self.address, issue_id))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_newsletter_deliveries(
+        &self,
+        issue_id: Uuid,
+        outcome: Option<&str>,
+    ) -> reqwest::Response {
+        let mut url = format!(
+            "{}/admin/newsletters/{}/deliveries",
+            &self.address, issue_id
+        );
+        if let Some(outcome) = outcome {
+            url = format!("{}?outcome={}", url, outcome);
+        }
+
+        self.http_client
+            .get(url)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_newsletter_deliveries_html(
+        &self,
+        issue_id: Uuid,
+        outcome: Option<&str>,
+    ) -> String {
+        let response = self
+            .get_admin_newsletter_deliveries(issue_id, outcome)
+            .await;
+        response.text().await.unwrap()
+    }
+
+    pub async fn get_admin_newsletter_preview_html(&self, issue_id: Uuid) -> String {
+        self.http_client
+            .get(format!(
+                "{}/admin/newsletters/{}/preview",
+                &self.address, issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_admin_test_send_newsletter<Body>(
+        &self,
+        issue_id: Uuid,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!(
+                "{}/admin/newsletters/{}/test",
+                &self.address, issue_id
+            ))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_admin_publish_newsletter<Body>(
+        &self,
+        issue_id: Uuid,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!(
+                "{}/admin/newsletters/{}/publish",
+                &self.address, issue_id
+            ))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_scheduled_newsletters(&self) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/admin/newsletters/scheduled", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_scheduled_newsletters_html(&self) -> String {
+        let response = self.get_admin_scheduled_newsletters().await;
+        response.text().await.unwrap()
+    }
+
+    pub async fn post_admin_reschedule_newsletter<Body>(
+        &self,
+        issue_id: Uuid,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!(
+                "{}/admin/newsletters/{}/reschedule",
+                &self.address, issue_id
+            ))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_admin_cancel_newsletter(&self, issue_id: Uuid) -> reqwest::Response {
+        self.http_client
+            .post(format!(
+                "{}/admin/newsletters/{}/cancel",
+                &self.address, issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_totp_html(&self) -> String {
+        self.http_client
+            .get(format!("{}/admin/totp", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// Posts to one of the two-factor authentication actions: `enroll`, `confirm` or `disable`.
+    pub async fn post_admin_totp(&self, action: &str, body: &[(&str, &str)]) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/admin/totp/{}", &self.address, action))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_users(&self) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/admin/users", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_users_html(&self) -> String {
+        let response = self.get_admin_users().await;
+        response.text().await.unwrap()
+    }
+
+    pub async fn post_admin_create_user<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/admin/users", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_admin_invite_user<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/admin/users/invite", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Posts to one of the per-user actions: `role`, `disable`, `enable` or `delete`.
+    pub async fn post_admin_user_action(
+        &self,
+        user_id: Uuid,
+        action: &str,
+        body: &[(&str, &str)],
+    ) -> reqwest::Response {
+        self.http_client
+            .post(format!(
+                "{}/admin/users/{}/{}",
+                &self.address, user_id, action
+            ))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_accept_invitation<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/invitations/accept", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_password_reset_request(&self, email: &str) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/password_reset", &self.address))
+            .form(&[("email", email)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_password_reset<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/password_reset/confirm", &self.address))
             .form(body)
             .send()
             .await
@@ -181,11 +767,128 @@ impl TestApp {
         }
     }
 
+    pub fn get_unsubscribe_link(&self, email_request: &wiremock::Request) -> reqwest::Url {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+
+        let header = body["additional_headers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|header| header["key"] == "List-Unsubscribe")
+            .expect("No List-Unsubscribe header");
+
+        let raw_link = header["value"]
+            .as_str()
+            .unwrap()
+            .trim_start_matches('<')
+            .trim_end_matches('>');
+
+        let mut unsubscribe_link = reqwest::Url::parse(raw_link).unwrap();
+        assert_eq!(unsubscribe_link.host_str().unwrap(), "127.0.0.1");
+        unsubscribe_link.set_port(Some(self.port)).unwrap();
+
+        unsubscribe_link
+    }
+
+    pub async fn publish_all_due_issues(&self) {
+        loop {
+            let result = try_publish_scheduled_issue(&self.pool).await.unwrap();
+            if let SchedulingOutcome::NothingDue = result {
+                break;
+            }
+        }
+    }
+
+    pub fn spawn_worker(
+        &self,
+        settings: WorkerSettings,
+        shutdown: CancellationToken,
+    ) -> tokio::task::JoinHandle<Result<(), anyhow::Error>> {
+        tokio::spawn(run_worker_until_stopped(
+            self.pool.clone(),
+            self.email_client.clone(),
+            settings,
+            ApplicationBaseUrl(self.configuration.application.base_url.clone()),
+            HmacSecret(self.configuration.application.hmac_secret.clone()),
+            shutdown,
+        ))
+    }
+
+    /// Sends the confirmation emails only, leaving the issues and the password resets queued.
+    pub async fn dispatch_pending_confirmation_emails(&self) {
+        let base_url = ApplicationBaseUrl(self.configuration.application.base_url.clone());
+
+        loop {
+            let result = try_send_confirmation_email(
+                &self.pool,
+                self.email_client.as_ref(),
+                &self.retry_policy,
+                &RateLimiter::unlimited(),
+                &CancellationToken::new(),
+                &base_url,
+            )
+            .await
+            .unwrap();
+            if let ExecutionOutcome::EmptyQueue = result {
+                break;
+            }
+        }
+    }
+
+    /// Sends the invitations only, leaving the other emails queued.
+    pub async fn dispatch_pending_invitations(&self) {
+        let base_url = ApplicationBaseUrl(self.configuration.application.base_url.clone());
+
+        loop {
+            let result = try_send_invitation(
+                &self.pool,
+                self.email_client.as_ref(),
+                &self.retry_policy,
+                &RateLimiter::unlimited(),
+                &CancellationToken::new(),
+                &base_url,
+            )
+            .await
+            .unwrap();
+            if let ExecutionOutcome::EmptyQueue = result {
+                break;
+            }
+        }
+    }
+
     pub async fn dispatch_all_pending_emails(&self) {
+        let base_url = ApplicationBaseUrl(self.configuration.application.base_url.clone());
+
+        loop {
+            let result = try_send_password_reset(
+                &self.pool,
+                self.email_client.as_ref(),
+                &RateLimiter::unlimited(),
+                &CancellationToken::new(),
+                &base_url,
+            )
+            .await
+            .unwrap();
+            if let ExecutionOutcome::EmptyQueue = result {
+                break;
+            }
+        }
+
+        self.dispatch_pending_invitations().await;
+        self.dispatch_pending_confirmation_emails().await;
+
         loop {
-            let result = try_execute_task(&self.pool, &self.email_client)
-                .await
-                .unwrap();
+            let result = try_execute_task(
+                &self.pool,
+                self.email_client.as_ref(),
+                &self.retry_policy,
+                &RateLimiter::unlimited(),
+                &CancellationToken::new(),
+                &base_url,
+                &HmacSecret(self.configuration.application.hmac_secret.clone()),
+            )
+            .await
+            .unwrap();
             if let ExecutionOutcome::EmptyQueue = result {
                 break;
             }
@@ -193,14 +896,58 @@ impl TestApp {
     }
 }
 
+/// Spawns the application backed by a brand new database, isolated from every other test.
 pub async fn spawn_app() -> TestApp {
-    let configuration = get_configuration().expect("Failed to read configuration");
+    let mut configuration = get_configuration().expect("Failed to read configuration");
+    configuration.database.name = Uuid::new_v4().to_string();
 
-    let pool = get_connection_pool(&configuration.database).await;
+    let pool = configure_database(&configuration.database).await;
 
     spawn_app_with_pool(pool).await
 }
 
+async fn configure_database(configuration: &DatabaseSettings) -> PgPool {
+    let maintenance_settings = DatabaseSettings {
+        name: "postgres".to_string(),
+        ..configuration.clone()
+    };
+
+    let mut connection =
+        PgConnection::connect(maintenance_settings.connection_string().expose_secret())
+            .await
+            .expect("Failed to connect to PostgreSQL");
+    connection
+        .execute(format!(r#"CREATE DATABASE "{}";"#, configuration.name).as_str())
+        .await
+        .expect("Failed to create database");
+
+    let pool = get_connection_pool(configuration).await;
+    sqlx::migrate!("./migrations")
+        .run(&pool)
+        .await
+        .expect("Failed to migrate the database");
+
+    pool
+}
+
+/// Runs the migrations older than `version`, so that a test can seed the data a migration sees.
+pub async fn migrate_before(pool: &PgPool, version: i64) {
+    let migrator = sqlx::migrate!("./migrations");
+    let migrator = sqlx::migrate::Migrator {
+        migrations: migrator
+            .iter()
+            .filter(|migration| migration.version < version)
+            .cloned()
+            .collect(),
+        ..migrator
+    };
+
+    migrator
+        .run(pool)
+        .await
+        .expect("Failed to migrate the database");
+}
+
 pub async fn spawn_app_with_pool(pool: sqlx::PgPool) -> TestApp {
     Lazy::force(&TRACING);
 
@@ -210,22 +957,33 @@ pub async fn spawn_app_with_pool(pool: sqlx::PgPool) -> TestApp {
 
     let mut configuration = get_configuration().expect("Failed to read configuration");
     configuration.application.port = 0;
-    configuration.tem.base_url = email_server.uri();
+    configuration.email.backend = EmailBackend::Tem;
+    configuration.email.tem = Some(TEMSettings {
+        base_url: email_server.uri(),
+        auth_key: Secret::new(Uuid::new_v4().to_string()),
+        project_id: Uuid::new_v4().to_string(),
+    });
+
+    // Retry failed deliveries right away so that tests don't have to wait
+    configuration.worker.retry_base_delay_milliseconds = 0;
+    configuration.worker.retry_max_delay_milliseconds = 0;
 
     // Build the stuff needed for the test harness
     let test_app_pool = pool.clone();
-    let test_app_email_client = get_tem_client(&configuration.tem);
+    let test_app_email_client = get_email_client(&configuration.email);
+    let test_app_retry_policy = configuration.worker.retry_policy();
 
     // Build the application
     let app_pool = pool.clone();
-    let app_email_client = get_tem_client(&configuration.tem);
+    let app_email_client = get_email_client(&configuration.email);
 
-    let app = Application::build_with_pool(configuration, app_pool, app_email_client)
+    let app = Application::build_with_pool(configuration.clone(), app_pool, app_email_client)
         .await
         .expect("Failed to build application");
     let app_port = app.port;
 
-    let _ = tokio::spawn(app.run_until_stopped());
+    let shutdown = CancellationToken::new();
+    let server_handle = tokio::spawn(app.run_until_stopped(shutdown.clone()));
 
     //
 
@@ -241,8 +999,12 @@ pub async fn spawn_app_with_pool(pool: sqlx::PgPool) -> TestApp {
         pool: test_app_pool,
         email_server,
         email_client: test_app_email_client,
+        retry_policy: test_app_retry_policy,
         http_client,
         test_user: TestUser::generate(),
+        configuration,
+        shutdown,
+        server_handle,
     };
 
     test_app.test_user.store(&test_app.pool).await;
@@ -253,19 +1015,28 @@ pub async fn spawn_app_with_pool(pool: sqlx::PgPool) -> TestApp {
 pub struct TestUser {
     pub user_id: Uuid,
     pub username: String,
+    pub email: String,
     pub password: String,
+    pub role: &'static str,
 }
 
 impl TestUser {
     fn generate() -> Self {
+        Self::generate_with_role("owner")
+    }
+
+    pub fn generate_with_role(role: &'static str) -> Self {
+        let username = Uuid::new_v4().to_string();
         Self {
             user_id: Uuid::new_v4(),
-            username: Uuid::new_v4().to_string(),
+            email: format!("{}@example.com", username),
+            username,
             password: Uuid::new_v4().to_string(),
+            role,
         }
     }
 
-    async fn store(&self, pool: &sqlx::PgPool) {
+    pub async fn store(&self, pool: &sqlx::PgPool) {
         let salt = SaltString::generate(&mut rand::thread_rng());
 
         let hasher = Argon2::new(
@@ -281,12 +1052,14 @@ impl TestUser {
 
         sqlx::query!(
             r#"
-            INSERT INTO users(user_id, username, password_hash)
-            VALUES ($1, $2, $3)
+            INSERT INTO users(user_id, username, email, password_hash, role)
+            VALUES ($1, $2, $3, $4, $5)
             "#,
             self.user_id,
             self.username,
+            self.email,
             password_hash,
+            self.role,
         )
         .execute(pool)
         .await
@@ -294,6 +1067,56 @@ impl TestUser {
     }
 }
 
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    let body = SubscriptionBody {
+        name: Name().fake(),
+        email: SafeEmail().fake(),
+    };
+
+    let _mock_guard = Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(&body)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_pending_confirmation_emails().await;
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+
+    app.get_confirmation_links(email_request)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    let confirmation_links = create_unconfirmed_subscriber(app).await;
+
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+/// Extracts the API token shown once on the page rendered after its creation.
+pub fn extract_api_token(html_page: &str) -> String {
+    let start = html_page.find("z2p_").expect("No API token in the page");
+    html_page[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+        .collect()
+}
+
 pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
     assert_eq!(
         response.status().as_u16(),
@@ -317,6 +1140,35 @@ pub struct LoginBody {
     pub password: String,
 }
 
+#[derive(serde::Serialize)]
+pub struct CreateUserBody {
+    pub username: String,
+    pub password: String,
+    pub password_check: String,
+    pub role: &'static str,
+}
+
+#[derive(serde::Serialize)]
+pub struct InviteUserBody {
+    pub email: String,
+    pub role: &'static str,
+}
+
+#[derive(serde::Serialize)]
+pub struct AcceptInvitationBody {
+    pub invitation_token: String,
+    pub username: String,
+    pub password: String,
+    pub password_check: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct PasswordResetBody {
+    pub reset_token: String,
+    pub new_password: String,
+    pub new_password_check: String,
+}
+
 #[derive(serde::Serialize)]
 pub struct AdminChangePasswordBody {
     pub current_password: String,
@@ -331,3 +1183,43 @@ pub struct SubmitNewsletterBody {
     pub text_content: String,
     pub idempotency_key: Uuid,
 }
+
+#[derive(serde::Serialize)]
+pub struct SaveDraftBody {
+    pub title: String,
+    pub html_content: String,
+    pub text_content: String,
+    pub idempotency_key: Uuid,
+    pub action: &'static str,
+}
+
+#[derive(serde::Serialize)]
+pub struct EditDraftBody {
+    pub title: String,
+    pub html_content: String,
+    pub text_content: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct TestSendBody {
+    pub email: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct PublishDraftBody {
+    pub send_at: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct ScheduleNewsletterBody {
+    pub title: String,
+    pub html_content: String,
+    pub text_content: String,
+    pub idempotency_key: Uuid,
+    pub send_at: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct RescheduleNewsletterBody {
+    pub send_at: String,
+}
@@ -0,0 +1,162 @@
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app, TestApp};
+use crate::helpers::{LoginBody, SubmitNewsletterBody};
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn publish_newsletter(app: &TestApp) {
+    app.post_login(&LoginBody {
+        username: app.test_user.username.clone(),
+        password: app.test_user.password.clone(),
+    })
+    .await;
+
+    let newsletter_request_body = SubmitNewsletterBody {
+        title: "Newsletter title".to_string(),
+        text_content: "Newsletter body as plain text".to_string(),
+        html_content: "<p>Newsletter body as HTML</p>".to_string(),
+        idempotency_key: Uuid::new_v4(),
+    };
+    let response = app.post_admin_newsletters(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+}
+
+async fn wait_for_empty_queue(app: &TestApp) {
+    loop {
+        let n_queued = sqlx::query!(r#"SELECT count(*) AS "n!" FROM issue_delivery_queue"#)
+            .fetch_one(&app.pool)
+            .await
+            .unwrap()
+            .n;
+        if n_queued == 0 {
+            return;
+        }
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+#[tokio::test]
+async fn concurrent_consumers_deliver_each_email_once() {
+    let app = spawn_app().await;
+    for _ in 0..10 {
+        create_confirmed_subscriber(&app).await;
+    }
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(50)))
+        .expect(10)
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+
+    let mut settings = app.configuration.worker.clone();
+    settings.concurrency = 4;
+    settings.max_emails_per_second = None;
+
+    let shutdown = CancellationToken::new();
+    let worker = app.spawn_worker(settings, shutdown.clone());
+
+    tokio::time::timeout(Duration::from_secs(10), wait_for_empty_queue(&app))
+        .await
+        .expect("The queue was not emptied");
+
+    shutdown.cancel();
+    worker.await.unwrap().unwrap();
+
+    let n_sent = sqlx::query!(
+        r#"SELECT count(*) AS "n!" FROM newsletter_deliveries WHERE outcome = 'sent'"#
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap()
+    .n;
+    assert_eq!(n_sent, 10);
+
+    // Mock verifies on Drop that we have sent each newsletter email once
+}
+
+#[tokio::test]
+async fn deliveries_are_rate_limited() {
+    let app = spawn_app().await;
+    for _ in 0..5 {
+        create_confirmed_subscriber(&app).await;
+    }
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(5)
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+
+    let mut settings = app.configuration.worker.clone();
+    settings.concurrency = 4;
+    settings.max_emails_per_second = Some(10.0);
+    settings.rate_limit_burst = 1;
+
+    let started_at = Instant::now();
+
+    let shutdown = CancellationToken::new();
+    let worker = app.spawn_worker(settings, shutdown.clone());
+
+    tokio::time::timeout(Duration::from_secs(10), wait_for_empty_queue(&app))
+        .await
+        .expect("The queue was not emptied");
+
+    // The first email uses the initial token, the next four wait 100ms each
+    assert!(started_at.elapsed() >= Duration::from_millis(400));
+
+    shutdown.cancel();
+    worker.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn skipped_deliveries_do_not_count_towards_the_rate_limit() {
+    let app = spawn_app().await;
+    for _ in 0..4 {
+        create_confirmed_subscriber(&app).await;
+    }
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(&app).await;
+
+    // Everyone but one subscriber leaves before the worker runs
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'unsubscribed'
+        WHERE id <> (SELECT id FROM subscriptions LIMIT 1)
+        "#
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let mut settings = app.configuration.worker.clone();
+    settings.concurrency = 1;
+    settings.max_emails_per_second = Some(0.5);
+    settings.rate_limit_burst = 1;
+
+    let shutdown = CancellationToken::new();
+    let worker = app.spawn_worker(settings, shutdown.clone());
+
+    // Each skipped delivery would otherwise wait 2s for a token
+    tokio::time::timeout(Duration::from_secs(1), wait_for_empty_queue(&app))
+        .await
+        .expect("The queue was not emptied");
+
+    shutdown.cancel();
+    worker.await.unwrap().unwrap();
+}
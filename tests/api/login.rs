@@ -1,4 +1,7 @@
 use crate::helpers::{assert_is_redirect_to, spawn_app, LoginBody};
+use argon2::password_hash::SaltString;
+use argon2::{Argon2, PasswordHasher};
+use zero2prod::authentication::purge_forgotten_login_failures;
 
 #[tokio::test]
 async fn an_error_flash_message_is_set_on_failure() {
@@ -44,3 +47,222 @@ async fn redirect_to_admin_dashboard_after_login_success() {
     let html_page = app.get_admin_dashboard_html().await;
     assert!(html_page.contains(&format!("Welcome {}", &app.test_user.username)));
 }
+
+const THROTTLED_HTML: &str = "Too many failed login attempts, try again later";
+
+fn wrong_password(username: &str) -> LoginBody {
+    LoginBody {
+        username: username.to_string(),
+        password: "wrong-password".to_string(),
+    }
+}
+
+#[tokio::test]
+async fn the_right_password_is_refused_after_too_many_failures() {
+    let app = spawn_app().await;
+
+    for _ in 0..3 {
+        app.post_login(&wrong_password(&app.test_user.username))
+            .await;
+    }
+
+    let response = app
+        .post_login(&LoginBody {
+            username: app.test_user.username.clone(),
+            password: app.test_user.password.clone(),
+        })
+        .await;
+    assert_is_redirect_to(&response, "/login");
+
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(THROTTLED_HTML));
+}
+
+#[tokio::test]
+async fn an_unknown_username_is_throttled_the_same_way() {
+    let app = spawn_app().await;
+
+    let mut messages = Vec::new();
+    for username in [
+        app.test_user.username.clone(),
+        "random-username".to_string(),
+    ] {
+        let mut username_messages = Vec::new();
+        for _ in 0..4 {
+            app.post_login(&wrong_password(&username)).await;
+            let html_page = app.get_login_html().await;
+            username_messages.push(html_page.contains(THROTTLED_HTML));
+        }
+        messages.push(username_messages);
+    }
+
+    assert_eq!(messages[0], vec![false, false, false, true]);
+    assert_eq!(messages[0], messages[1]);
+}
+
+#[tokio::test]
+async fn a_lockout_is_written_to_the_audit_log() {
+    let app = spawn_app().await;
+
+    // One failure away from the lockout, and the last one is old enough not to delay the next
+    sqlx::query!(
+        r#"
+        INSERT INTO login_failures(counter, key, n_failures, last_failure_at, locked_until)
+        VALUES ('username', $1, 9, now() - interval '2 minutes', NULL)
+        "#,
+        app.test_user.username
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    app.post_login(&wrong_password(&app.test_user.username))
+        .await;
+
+    let row = sqlx::query!(
+        "SELECT n_failures, locked_until FROM login_failures WHERE counter = 'username' AND key = $1",
+        app.test_user.username
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(row.n_failures, 10);
+    assert!(row.locked_until.is_some());
+
+    let rows = sqlx::query!("SELECT event, username, client_ip FROM audit_log")
+        .fetch_all(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].event, "login_lockout");
+    assert_eq!(rows[0].username.as_ref(), Some(&app.test_user.username));
+    assert_eq!(rows[0].client_ip.as_deref(), Some("127.0.0.1"));
+}
+
+#[tokio::test]
+async fn throttled_attempts_do_not_count_as_failures() {
+    let app = spawn_app().await;
+
+    for _ in 0..20 {
+        app.post_login(&wrong_password(&app.test_user.username))
+            .await;
+    }
+
+    let row = sqlx::query!(
+        "SELECT n_failures, locked_until FROM login_failures WHERE counter = 'username' AND key = $1",
+        app.test_user.username
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(row.n_failures, 3);
+    assert!(row.locked_until.is_none());
+}
+
+#[tokio::test]
+async fn forgotten_login_failures_are_pruned() {
+    let app = spawn_app().await;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO login_failures(counter, key, n_failures, last_failure_at, locked_until)
+        VALUES
+            ('username', 'old', 2, now() - interval '2 hours', NULL),
+            ('username', 'lockout-over', 10, now() - interval '20 minutes', now() - interval '5 minutes')
+        "#
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    app.post_login(&wrong_password(&app.test_user.username))
+        .await;
+
+    let purged =
+        purge_forgotten_login_failures(&app.pool, &app.configuration.login_protection.policy())
+            .await
+            .unwrap();
+    assert_eq!(purged, 2);
+
+    let keys: Vec<String> = sqlx::query!("SELECT key FROM login_failures")
+        .fetch_all(&app.pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|row| row.key)
+        .collect();
+    assert_eq!(keys.len(), 2);
+    assert!(keys.contains(&app.test_user.username));
+    assert!(keys.contains(&"127.0.0.1".to_string()));
+}
+
+#[tokio::test]
+async fn a_successful_login_resets_the_failures_of_the_username() {
+    let app = spawn_app().await;
+
+    let body = LoginBody {
+        username: app.test_user.username.clone(),
+        password: app.test_user.password.clone(),
+    };
+
+    for _ in 0..2 {
+        for _ in 0..2 {
+            app.post_login(&wrong_password(&app.test_user.username))
+                .await;
+        }
+
+        let response = app.post_login(&body).await;
+        assert_is_redirect_to(&response, "/admin/dashboard");
+
+        app.post_logout().await;
+    }
+}
+
+#[tokio::test]
+async fn a_legacy_password_hash_is_upgraded_on_login() {
+    let app = spawn_app().await;
+
+    // An Argon2i hash with weaker parameters than the current settings
+    let legacy_hash = Argon2::new(
+        argon2::Algorithm::Argon2i,
+        argon2::Version::V0x10,
+        argon2::Params::new(4096, 1, 1, None).unwrap(),
+    )
+    .hash_password(
+        app.test_user.password.as_bytes(),
+        &SaltString::generate(&mut rand::thread_rng()),
+    )
+    .unwrap()
+    .to_string();
+
+    sqlx::query!(
+        "UPDATE users SET password_hash = $1 WHERE user_id = $2",
+        legacy_hash,
+        app.test_user.user_id
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let body = LoginBody {
+        username: app.test_user.username.clone(),
+        password: app.test_user.password.clone(),
+    };
+
+    let response = app.post_login(&body).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let password_hash = sqlx::query_scalar!(
+        "SELECT password_hash FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap();
+    assert!(password_hash.starts_with("$argon2id$v=19$m=15000,t=2,p=1$"));
+
+    // The upgraded hash still matches the password
+    app.post_logout().await;
+    let response = app.post_login(&body).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
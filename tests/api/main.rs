@@ -1,8 +1,24 @@
 mod admin_change_password;
 mod admin_dashboard;
+mod admin_dead_letters;
 mod admin_newsletters;
+mod admin_newsletters_deliveries;
+mod admin_newsletters_issue;
+mod admin_newsletters_scheduled;
+mod admin_sessions;
+mod admin_subscribers;
+mod admin_subscribers_csv;
+mod admin_totp;
+mod admin_users;
+mod api_newsletters;
+mod api_subscribers;
+mod api_tokens;
 mod health_check;
 mod helpers;
+mod issue_delivery_worker;
 mod login;
+mod password_reset;
+mod shutdown;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
@@ -0,0 +1,353 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, LoginBody, PasswordResetBody, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::password_reset_queue::purge_expired_password_reset_tokens;
+
+const RESET_LINK_SENT_HTML: &str =
+    "If an account exists for this email address, a link to reset its password has been sent";
+
+/// Requests a password reset for the test user and returns the link from the email.
+async fn request_reset_link(app: &TestApp) -> reqwest::Url {
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_password_reset_request(&app.test_user.email).await;
+    assert_is_redirect_to(&response, "/login");
+
+    // The link is sent by the worker
+    assert!(app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .is_empty());
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    app.get_confirmation_links(email_request).html
+}
+
+fn reset_token(reset_link: &reqwest::Url) -> String {
+    reset_link
+        .query_pairs()
+        .find(|(k, _)| k == "reset_token")
+        .map(|(_, v)| v.into_owned())
+        .unwrap()
+}
+
+fn reset_body(reset_link: &reqwest::Url, new_password: &str) -> PasswordResetBody {
+    PasswordResetBody {
+        reset_token: reset_token(reset_link),
+        new_password: new_password.to_string(),
+        new_password_check: new_password.to_string(),
+    }
+}
+
+#[tokio::test]
+async fn a_user_can_reset_their_password_with_the_emailed_link() {
+    let app = spawn_app().await;
+
+    let reset_link = request_reset_link(&app).await;
+    assert_eq!(reset_link.path(), "/password_reset/confirm");
+
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(RESET_LINK_SENT_HTML));
+
+    let response = app
+        .http_client
+        .get(reset_link.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app
+        .post_password_reset(&reset_body(&reset_link, "amber-kettle-sings-at-dawn"))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("Your password has been reset, you can now log in"));
+
+    // The old password doesn't work anymore, the new one does
+    let response = app
+        .post_login(&LoginBody {
+            username: app.test_user.username.clone(),
+            password: app.test_user.password.clone(),
+        })
+        .await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app
+        .post_login(&LoginBody {
+            username: app.test_user.username.clone(),
+            password: "amber-kettle-sings-at-dawn".to_string(),
+        })
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn a_reset_link_can_only_be_used_once() {
+    let app = spawn_app().await;
+    let reset_link = request_reset_link(&app).await;
+
+    let response = app
+        .post_password_reset(&reset_body(&reset_link, "amber-kettle-sings-at-dawn"))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app
+        .post_password_reset(&reset_body(&reset_link, "another-kettle-sings-at-dusk"))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app.http_client.get(reset_link).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn an_expired_reset_link_is_rejected() {
+    let app = spawn_app().await;
+    let reset_link = request_reset_link(&app).await;
+
+    sqlx::query!("UPDATE password_reset_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+    let response = app
+        .http_client
+        .get(reset_link.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app
+        .post_password_reset(&reset_body(&reset_link, "amber-kettle-sings-at-dawn"))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn a_new_reset_link_invalidates_the_previous_ones() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    for _ in 0..2 {
+        app.post_password_reset_request(&app.test_user.email).await;
+        app.dispatch_all_pending_emails().await;
+    }
+
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let first_link = app.get_confirmation_links(&email_requests[0]).html;
+    let second_link = app.get_confirmation_links(&email_requests[1]).html;
+
+    let response = app.http_client.get(first_link).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app.http_client.get(second_link).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn expired_reset_tokens_are_purged() {
+    let app = spawn_app().await;
+    request_reset_link(&app).await;
+
+    let purged = purge_expired_password_reset_tokens(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(purged, 0);
+
+    sqlx::query!("UPDATE password_reset_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+    let purged = purge_expired_password_reset_tokens(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(purged, 1);
+}
+
+#[tokio::test]
+async fn the_new_password_is_validated() {
+    let app = spawn_app().await;
+    let reset_link = request_reset_link(&app).await;
+
+    let response = app
+        .post_password_reset(&PasswordResetBody {
+            reset_token: reset_token(&reset_link),
+            new_password: "amber-kettle-sings-at-dawn".to_string(),
+            new_password_check: "another-kettle-sings-at-dusk".to_string(),
+        })
+        .await;
+    assert_is_redirect_to(
+        &response,
+        &format!(
+            "/password_reset/confirm?reset_token={}",
+            reset_token(&reset_link)
+        ),
+    );
+
+    let response = app.http_client.get(reset_link).send().await.unwrap();
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("You entered two different new passwords"));
+}
+
+#[tokio::test]
+async fn an_unknown_email_gets_the_same_answer_without_an_email() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_password_reset_request("nobody@example.com").await;
+    assert_is_redirect_to(&response, "/login");
+
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(RESET_LINK_SENT_HTML));
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn password_reset_requests_are_throttled_per_email_address() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(3)
+        .mount(&app.email_server)
+        .await;
+
+    for _ in 0..3 {
+        let response = app.post_password_reset_request(&app.test_user.email).await;
+        assert_is_redirect_to(&response, "/login");
+    }
+
+    let response = app.post_password_reset_request(&app.test_user.email).await;
+    assert_is_redirect_to(&response, "/password_reset");
+    let response = app
+        .http_client
+        .get(format!("{}/password_reset", &app.address))
+        .send()
+        .await
+        .unwrap();
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Too many password resets have been requested"));
+
+    // Another address can still ask for a link
+    let response = app.post_password_reset_request("nobody@example.com").await;
+    assert_is_redirect_to(&response, "/login");
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn password_reset_requests_are_throttled_per_client_ip_address() {
+    let app = spawn_app().await;
+
+    for i in 0..20 {
+        let response = app
+            .post_password_reset_request(&format!("nobody{}@example.com", i))
+            .await;
+        assert_is_redirect_to(&response, "/login");
+    }
+
+    let response = app.post_password_reset_request(&app.test_user.email).await;
+    assert_is_redirect_to(&response, "/password_reset");
+}
+
+#[tokio::test]
+async fn resetting_the_password_logs_the_user_out_everywhere() {
+    let app = spawn_app().await;
+
+    // Log in from another device
+    let other_client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
+    let response = other_client
+        .post(format!("{}/login", &app.address))
+        .form(&LoginBody {
+            username: app.test_user.username.clone(),
+            password: app.test_user.password.clone(),
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let reset_link = request_reset_link(&app).await;
+    let response = app
+        .post_password_reset(&reset_body(&reset_link, "amber-kettle-sings-at-dawn"))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = other_client
+        .get(format!("{}/admin/dashboard", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+
+    let n_sessions = sqlx::query_scalar!(
+        "SELECT COUNT(*) FROM sessions WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(n_sessions, Some(0));
+}
+
+#[tokio::test]
+async fn a_breached_password_is_rejected() {
+    let app = spawn_app().await;
+    let reset_link = request_reset_link(&app).await;
+
+    let response = app
+        .post_password_reset(&reset_body(&reset_link, "Password1234"))
+        .await;
+    assert_is_redirect_to(
+        &response,
+        &format!(
+            "/password_reset/confirm?reset_token={}",
+            reset_token(&reset_link)
+        ),
+    );
+
+    // The link can still be used with a better password
+    let response = app
+        .http_client
+        .get(reset_link.clone())
+        .send()
+        .await
+        .unwrap();
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("New password appears in a list of breached passwords"));
+
+    let response = app
+        .post_password_reset(&reset_body(&reset_link, "amber-kettle-sings-at-dawn"))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,157 @@
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app};
+use crate::helpers::{LoginBody, SubmitNewsletterBody};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn the_worker_finishes_the_current_delivery_before_stopping() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    app.post_login(&LoginBody {
+        username: app.test_user.username.clone(),
+        password: app.test_user.password.clone(),
+    })
+    .await;
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(500)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = SubmitNewsletterBody {
+        title: "Newsletter title".to_string(),
+        text_content: "Newsletter body as plain text".to_string(),
+        html_content: "<p>Newsletter body as HTML</p>".to_string(),
+        idempotency_key: Uuid::new_v4(),
+    };
+    let response = app.post_admin_newsletters(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    let shutdown = CancellationToken::new();
+    let worker = app.spawn_worker(app.configuration.worker.clone(), shutdown.clone());
+
+    // Wait until the email is being sent, then ask the worker to stop
+    while app.email_server.received_requests().await.unwrap().len() < 2 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    shutdown.cancel();
+
+    tokio::time::timeout(Duration::from_secs(5), worker)
+        .await
+        .expect("The worker did not stop")
+        .unwrap()
+        .unwrap();
+
+    let n_queued = sqlx::query!(r#"SELECT count(*) AS "n!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_queued, 0);
+
+    let delivery = sqlx::query!("SELECT outcome FROM newsletter_deliveries")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.outcome, "sent");
+}
+
+#[tokio::test]
+async fn a_worker_waiting_for_the_rate_limiter_stops_right_away() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+
+    app.post_login(&LoginBody {
+        username: app.test_user.username.clone(),
+        password: app.test_user.password.clone(),
+    })
+    .await;
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = SubmitNewsletterBody {
+        title: "Newsletter title".to_string(),
+        text_content: "Newsletter body as plain text".to_string(),
+        html_content: "<p>Newsletter body as HTML</p>".to_string(),
+        idempotency_key: Uuid::new_v4(),
+    };
+    let response = app.post_admin_newsletters(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // The second email can't be sent before a long time
+    let mut settings = app.configuration.worker.clone();
+    settings.max_emails_per_second = Some(0.001);
+    settings.rate_limit_burst = 1;
+    let shutdown = CancellationToken::new();
+    let worker = app.spawn_worker(settings, shutdown.clone());
+
+    while app.email_server.received_requests().await.unwrap().len() < 3 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    shutdown.cancel();
+
+    tokio::time::timeout(Duration::from_millis(500), worker)
+        .await
+        .expect("The worker did not stop")
+        .unwrap()
+        .unwrap();
+
+    // The delivery which was waiting is still queued
+    let n_queued = sqlx::query!(r#"SELECT count(*) AS "n!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_queued, 1);
+}
+
+#[tokio::test]
+async fn an_idle_worker_stops_right_away() {
+    let app = spawn_app().await;
+
+    let shutdown = CancellationToken::new();
+    let worker = app.spawn_worker(app.configuration.worker.clone(), shutdown.clone());
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    shutdown.cancel();
+
+    tokio::time::timeout(Duration::from_millis(500), worker)
+        .await
+        .expect("The worker did not stop")
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn the_api_stops_accepting_connections_after_shutdown() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/health_check", app.address))
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    app.shutdown.cancel();
+
+    tokio::time::timeout(Duration::from_secs(5), app.server_handle)
+        .await
+        .expect("The API did not stop")
+        .unwrap()
+        .unwrap();
+
+    let result = reqwest::get(format!("{}/health_check", app.address)).await;
+    assert!(result.is_err());
+}
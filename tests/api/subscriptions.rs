@@ -116,6 +116,42 @@ async fn subscribe_sends_a_confirmation_email_for_valid_data() {
     //
 
     let _ = app.post_subscriptions(&body).await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn subscribe_leaves_the_confirmation_email_to_the_worker() {
+    let app = spawn_app().await;
+
+    let body = SubscriptionBody {
+        name: Name().fake(),
+        email: SafeEmail().fake(),
+    };
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    //
+
+    app.post_subscriptions(&body)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    //
+
+    assert!(app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .is_empty());
+
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(app.email_server.received_requests().await.unwrap().len(), 1);
 }
 
 #[tokio::test]
@@ -136,6 +172,7 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
     //
 
     let _ = app.post_subscriptions(&body).await;
+    app.dispatch_all_pending_emails().await;
 
     //
 
@@ -168,4 +205,278 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error(pool: sqlx::PgPool)
     let response = app.post_subscriptions(&body).await;
 
     assert_eq!(response.status().as_u16(), 500);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Something went wrong"));
+}
+
+#[tokio::test]
+async fn subscribing_again_while_pending_resends_a_confirmation_email() {
+    let app = spawn_app().await;
+
+    let body = SubscriptionBody {
+        name: Name().fake(),
+        email: SafeEmail().fake(),
+    };
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(&body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+
+    // The first confirmation email is older than the resend interval
+    sqlx::query!(
+        "UPDATE subscriptions SET subscribed_at = now() - interval '1 day' WHERE email = $1",
+        &body.email
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    //
+
+    let response = app.post_subscriptions(&body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+
+    //
+
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let first_links = app.get_confirmation_links(&email_requests[0]);
+    let second_links = app.get_confirmation_links(&email_requests[1]);
+    assert_ne!(first_links.html, second_links.html);
+
+    // The new link confirms the subscriber
+    reqwest::get(second_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let saved = sqlx::query!(
+        "SELECT status FROM subscriptions WHERE email = $1",
+        &body.email
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn confirmation_emails_are_not_resent_more_often_than_the_resend_interval() {
+    let app = spawn_app().await;
+
+    let body = SubscriptionBody {
+        name: Name().fake(),
+        email: SafeEmail().fake(),
+    };
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(&body)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    sqlx::query!(
+        "UPDATE subscriptions SET subscribed_at = now() - interval '1 day' WHERE email = $1",
+        &body.email
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    //
+
+    // Only the first of these resends a confirmation email
+    for _ in 0..3 {
+        let response = app.post_subscriptions(&body).await;
+        assert_eq!(response.status().as_u16(), 200);
+        app.dispatch_all_pending_emails().await;
+    }
+}
+
+#[tokio::test]
+async fn subscribing_again_when_confirmed_sends_nothing() {
+    let app = spawn_app().await;
+
+    let body = SubscriptionBody {
+        name: Name().fake(),
+        email: SafeEmail().fake(),
+    };
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(&body)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    //
+
+    let response = app.post_subscriptions(&body).await;
+    app.dispatch_all_pending_emails().await;
+
+    //
+
+    assert_eq!(response.status().as_u16(), 200);
+
+    let saved = sqlx::query!(
+        "SELECT status FROM subscriptions WHERE email = $1",
+        &body.email
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribing_does_not_reveal_whether_the_address_is_on_the_list() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let pending = SubscriptionBody {
+        name: Name().fake(),
+        email: SafeEmail().fake(),
+    };
+    app.post_subscriptions(&pending)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let new = SubscriptionBody {
+        name: Name().fake(),
+        email: SafeEmail().fake(),
+    };
+
+    //
+
+    let first_response = app.post_subscriptions(&new).await;
+    let second_response = app.post_subscriptions(&pending).await;
+
+    //
+
+    assert_eq!(first_response.status(), second_response.status());
+    assert_eq!(
+        first_response.text().await.unwrap(),
+        second_response.text().await.unwrap()
+    );
+}
+
+#[tokio::test]
+async fn the_home_page_has_a_signup_form() {
+    let app = spawn_app().await;
+
+    let html_page = app.get_home_html().await;
+
+    assert!(html_page.contains(r#"<form class="login" action="/subscriptions" method="POST">"#));
+    assert!(html_page.contains(r#"name="name""#));
+    assert!(html_page.contains(r#"name="email""#));
+}
+
+#[tokio::test]
+async fn subscribe_shows_a_page_asking_to_check_the_inbox() {
+    let app = spawn_app().await;
+
+    let body = SubscriptionBody {
+        name: Name().fake(),
+        email: SafeEmail().fake(),
+    };
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    //
+
+    let response = app.post_subscriptions(&body).await;
+
+    //
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Check your inbox"));
+}
+
+#[tokio::test]
+async fn invalid_signups_show_the_form_again_with_what_is_wrong() {
+    let app = spawn_app().await;
+
+    let test_cases = vec![
+        (
+            vec![("name", ""), ("email", "ursula@example.com")],
+            "Please enter your name.",
+        ),
+        (
+            vec![
+                ("name", "Ursula <Le Guin>"),
+                ("email", "ursula@example.com"),
+            ],
+            "Your name can&#x27;t be longer than 256 characters",
+        ),
+        (
+            vec![("email", "ursula@example.com")],
+            "Please enter your name.",
+        ),
+        (
+            vec![("name", "Ursula"), ("email", "")],
+            "Please enter your email address.",
+        ),
+        (
+            vec![("name", "Ursula"), ("email", "ursula.example.com")],
+            "This doesn&#x27;t look like a valid email address",
+        ),
+    ];
+
+    for (body, message) in test_cases {
+        let response = app.post_subscriptions(&body).await;
+
+        assert_eq!(response.status().as_u16(), 400);
+        let html_page = response.text().await.unwrap();
+        assert!(
+            html_page.contains(message),
+            "The form of {:?} doesn't say {:?}",
+            body,
+            message
+        );
+        assert!(html_page.contains(r#"action="/subscriptions""#));
+
+        // What was submitted is kept
+        for (_, value) in body.iter().filter(|(_, value)| value.len() > 3) {
+            let value = value.replace('<', "&lt;").replace('>', "&gt;");
+            assert!(html_page.contains(&format!(r#"value="{}""#, value)));
+        }
+    }
 }
@@ -1,9 +1,12 @@
-use crate::helpers::{spawn_app, SubscriptionBody};
+use crate::helpers::{create_unconfirmed_subscriber, spawn_app, SubscriptionBody};
+use crate::helpers::{migrate_before, spawn_app_with_pool};
 use fake::faker::internet::en::SafeEmail;
 use fake::faker::name::en::Name;
 use fake::Fake;
+use uuid::Uuid;
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
+use zero2prod::subscription_purge::purge_expired_subscribers;
 
 #[tokio::test]
 async fn confirmations_without_token_are_rejected_with_a_400() {
@@ -32,6 +35,7 @@ async fn the_link_returned_by_subscribe_returns_a_200_if_called() {
         .await;
 
     let _ = app.post_subscriptions(&body).await;
+    app.dispatch_all_pending_emails().await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
     let confirmation_links = app.get_confirmation_links(email_request);
 
@@ -40,6 +44,8 @@ async fn the_link_returned_by_subscribe_returns_a_200_if_called() {
     let response = reqwest::get(confirmation_links.html).await.unwrap();
 
     assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Your subscription is confirmed"));
 }
 
 #[tokio::test]
@@ -58,6 +64,7 @@ async fn clicking_on_confirmation_link_confirms_a_subscriber() {
         .await;
 
     let _ = app.post_subscriptions(&body).await;
+    app.dispatch_all_pending_emails().await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
     let confirmation_links = app.get_confirmation_links(email_request);
 
@@ -81,3 +88,277 @@ async fn clicking_on_confirmation_link_confirms_a_subscriber() {
     assert_eq!(saved.name, body.name);
     assert_eq!(saved.status, "confirmed");
 }
+
+#[tokio::test]
+async fn confirmations_with_an_unknown_token_are_rejected_with_a_401() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(&format!(
+        "{}/subscriptions/confirm?subscription_token=notavalidtoken",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("This confirmation link is invalid"));
+}
+
+#[tokio::test]
+async fn a_confirmation_link_can_only_be_used_once() {
+    let app = spawn_app().await;
+
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    reqwest::get(confirmation_links.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    //
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    //
+
+    assert_eq!(response.status().as_u16(), 410);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("This confirmation link has already been used"));
+    assert!(html_page.contains("Send me a new link"));
+}
+
+#[sqlx::test(migrations = false)]
+async fn the_old_links_of_an_unsubscribed_subscriber_are_rejected_with_a_410(pool: sqlx::PgPool) {
+    // Tokens could still be used after unsubscribing before they were marked as consumed
+    migrate_before(&pool, 20221227091530).await;
+    let subscriber_id = Uuid::new_v4();
+    sqlx::query(
+        r#"
+        INSERT INTO subscriptions(id, email, name, subscribed_at, status)
+        VALUES ($1, 'ursula@example.com', 'Ursula', now(), 'unsubscribed')"#,
+    )
+    .bind(subscriber_id)
+    .execute(&pool)
+    .await
+    .unwrap();
+    sqlx::query(
+        r#"
+        INSERT INTO subscription_tokens(subscription_token, subscriber_id)
+        VALUES ('oldtoken', $1)"#,
+    )
+    .bind(subscriber_id)
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+    let app = spawn_app_with_pool(pool).await;
+
+    //
+
+    let response = reqwest::get(format!(
+        "{}/subscriptions/confirm?subscription_token=oldtoken",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    //
+
+    assert_eq!(response.status().as_u16(), 410);
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn expired_confirmation_links_are_rejected() {
+    let app = spawn_app().await;
+
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '8 days'")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+    //
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    //
+
+    assert_eq!(response.status().as_u16(), 410);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("This confirmation link has expired"));
+    assert!(html_page.contains("Send me a new link"));
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn a_new_link_can_be_requested_from_an_expired_one() {
+    let app = spawn_app().await;
+
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '8 days'")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+    sqlx::query!("UPDATE subscriptions SET subscribed_at = now() - interval '8 days'")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+    let expired_token = token_of(&confirmation_links.html);
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    //
+
+    let response = app
+        .post_resend_confirmation(&serde_json::json!({
+            "subscription_token": expired_token,
+        }))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    //
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("a new confirmation link is on its way"));
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let new_links = app.get_confirmation_links(&email_request);
+    reqwest::get(new_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_do_not_get_a_new_link() {
+    let app = spawn_app().await;
+
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    reqwest::get(confirmation_links.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    sqlx::query!("UPDATE subscriptions SET subscribed_at = now() - interval '1 day'")
+        .execute(&app.pool)
+        .await
+        .unwrap();
+    let used_token = token_of(&confirmation_links.html);
+
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    //
+
+    let response = app
+        .post_resend_confirmation(&serde_json::json!({
+            "subscription_token": used_token,
+        }))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    //
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("a new confirmation link is on its way"));
+}
+
+#[tokio::test]
+async fn pending_subscribers_are_purged_after_the_confirmation_window() {
+    let app = spawn_app().await;
+
+    create_unconfirmed_subscriber(&app).await;
+    let expired_links = create_unconfirmed_subscriber(&app).await;
+    let confirmed_links = create_unconfirmed_subscriber(&app).await;
+    reqwest::get(confirmed_links.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // The recent pending subscriber is the only one to keep its signup date
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions SET subscribed_at = now() - interval '8 days'
+        WHERE id IN (
+            SELECT subscriber_id FROM subscription_tokens WHERE subscription_token = ANY($1)
+        )
+        "#,
+        &[
+            token_of(&expired_links.html),
+            token_of(&confirmed_links.html),
+        ][..],
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    //
+
+    let purged = purge_expired_subscribers(&app.pool, time::Duration::days(7))
+        .await
+        .unwrap();
+
+    //
+
+    assert_eq!(purged, 1);
+
+    let statuses: Vec<String> = sqlx::query!("SELECT status FROM subscriptions ORDER BY status")
+        .fetch_all(&app.pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|row| row.status)
+        .collect();
+    assert_eq!(statuses, vec!["confirmed", "pending_confirmation"]);
+
+    // The tokens of the purged subscriber are gone with it
+    let response = reqwest::get(expired_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+fn token_of(confirmation_link: &reqwest::Url) -> String {
+    confirmation_link
+        .query_pairs()
+        .find(|(key, _)| key == "subscription_token")
+        .unwrap()
+        .1
+        .to_string()
+}
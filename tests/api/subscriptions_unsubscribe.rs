@@ -0,0 +1,182 @@
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, spawn_app};
+use crate::helpers::{LoginBody, SubmitNewsletterBody, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn publish_newsletter(app: &TestApp) {
+    app.post_login(&LoginBody {
+        username: app.test_user.username.clone(),
+        password: app.test_user.password.clone(),
+    })
+    .await;
+
+    let newsletter_request_body = SubmitNewsletterBody {
+        title: "Newsletter title".to_string(),
+        text_content: "Newsletter body as plain text".to_string(),
+        html_content: "<p>Newsletter body as HTML</p>".to_string(),
+        idempotency_key: Uuid::new_v4(),
+    };
+
+    let response = app.post_admin_newsletters(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+}
+
+async fn deliver_newsletter_and_get_unsubscribe_link(app: &TestApp) -> reqwest::Url {
+    Mock::given(path("/emails"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    publish_newsletter(app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+
+    app.get_unsubscribe_link(&email_request)
+}
+
+async fn get_subscriber_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.pool)
+        .await
+        .expect("Failed to fetch saved subscription.")
+        .status
+}
+
+#[tokio::test]
+async fn newsletter_issues_contain_an_unsubscribe_link() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let unsubscribe_link = deliver_newsletter_and_get_unsubscribe_link(&app).await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+
+    let link = unsubscribe_link.query().unwrap();
+    assert!(body["html"].as_str().unwrap().contains(link));
+    assert!(body["text"].as_str().unwrap().contains(link));
+    assert!(body["additional_headers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|header| header["key"] == "List-Unsubscribe-Post"
+            && header["value"] == "List-Unsubscribe=One-Click"));
+}
+
+#[tokio::test]
+async fn opening_the_unsubscribe_link_does_not_unsubscribe() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let unsubscribe_link = deliver_newsletter_and_get_unsubscribe_link(&app).await;
+
+    let response = reqwest::get(unsubscribe_link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("<form"));
+
+    assert_eq!(get_subscriber_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn a_one_click_unsubscribe_request_unsubscribes_the_subscriber() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let unsubscribe_link = deliver_newsletter_and_get_unsubscribe_link(&app).await;
+
+    let response = reqwest::Client::new()
+        .post(unsubscribe_link)
+        .form(&[("List-Unsubscribe", "One-Click")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    assert_eq!(get_subscriber_status(&app).await, "unsubscribed");
+}
+
+#[tokio::test]
+async fn unsubscribe_requests_with_an_invalid_token_are_rejected_with_a_401() {
+    let app = spawn_app().await;
+
+    let url = format!(
+        "{}/subscriptions/unsubscribe?token={}.deadbeef",
+        app.address,
+        Uuid::new_v4().simple()
+    );
+
+    let response = reqwest::get(&url).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = reqwest::Client::new().post(&url).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn newsletters_are_not_delivered_to_unsubscribed_subscribers() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let unsubscribe_link = deliver_newsletter_and_get_unsubscribe_link(&app).await;
+
+    // Publish an issue while still subscribed, then unsubscribe before it is delivered
+    publish_newsletter(&app).await;
+
+    reqwest::Client::new()
+        .post(unsubscribe_link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    publish_newsletter(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Mock verifies on Drop that we have sent the first newsletter email only
+}
+
+#[tokio::test]
+async fn a_pending_confirmation_link_cannot_subscribe_again_after_unsubscribing() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // A link which hasn't been used, like one sent again before confirming with the first one
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_tokens(subscription_token, subscriber_id, created_at)
+        SELECT 'pendingtoken', id, now() FROM subscriptions"#
+    )
+    .execute(&app.pool)
+    .await
+    .unwrap();
+
+    let unsubscribe_link = deliver_newsletter_and_get_unsubscribe_link(&app).await;
+    reqwest::Client::new()
+        .post(unsubscribe_link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let response = reqwest::get(format!(
+        "{}/subscriptions/confirm?subscription_token=pendingtoken",
+        app.address
+    ))
+    .await
+    .unwrap();
+    assert_eq!(response.status().as_u16(), 410);
+
+    assert_eq!(get_subscriber_status(&app).await, "unsubscribed");
+}